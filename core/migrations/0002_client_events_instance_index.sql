@@ -0,0 +1,2 @@
+-- console scrollback is looked up per instance
+CREATE INDEX IF NOT EXISTS ClientEventsInstanceId ON ClientEvents (instance_id);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS GlobalSettingsChanges (
+    id                  INTEGER     PRIMARY KEY     AUTOINCREMENT,
+    change_value        TEXT        NOT NULL,
+    setting             TEXT        NOT NULL,
+    snowflake           BIGINT      NOT NULL,
+    caused_by_user_id   TEXT
+);
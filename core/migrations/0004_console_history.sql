@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS ConsoleHistory (
+    id                  INTEGER     PRIMARY KEY     AUTOINCREMENT,
+    instance_id         TEXT        NOT NULL,
+    command             TEXT        NOT NULL,
+    redacted            BOOLEAN     NOT NULL,
+    user_id             TEXT,
+    user_name           TEXT,
+    snowflake           BIGINT      NOT NULL
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS MonitorSamples (
+    id                  INTEGER     PRIMARY KEY     AUTOINCREMENT,
+    instance_id         TEXT        NOT NULL,
+    timestamp           BIGINT      NOT NULL,
+    cpu_usage           REAL,
+    memory_usage        BIGINT,
+    player_count        INTEGER
+);
+
+-- history is always read per instance over a time range
+CREATE INDEX IF NOT EXISTS MonitorSamplesInstanceTime ON MonitorSamples (instance_id, timestamp);
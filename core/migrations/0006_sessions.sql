@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS Sessions (
+    id                  TEXT        PRIMARY KEY,
+    user_id             TEXT        NOT NULL,
+    created_at          BIGINT      NOT NULL,
+    last_seen           BIGINT      NOT NULL,
+    expires_at          BIGINT      NOT NULL,
+    ip                  TEXT,
+    user_agent          TEXT
+);
@@ -0,0 +1,2 @@
+-- events are searched and pruned by time, which the snowflake encodes
+CREATE INDEX IF NOT EXISTS ClientEventsSnowflake ON ClientEvents (snowflake);
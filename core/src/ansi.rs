@@ -0,0 +1,254 @@
+//! ANSI escape sequences in console output, either stripped or turned into styled spans the
+//! frontend can render
+//!
+//! The decoder keeps its state between calls, so a style carries over to the next line and an
+//! escape sequence split across two reads is still recognized
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{7}';
+
+/// What is done with the ANSI escape sequences a server prints
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum AnsiHandling {
+    /// Kept in the output as printed
+    #[default]
+    Raw,
+    /// Removed from the output
+    Strip,
+    /// Removed from the output and sent as styled spans alongside it
+    Parse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum AnsiColor {
+    /// One of the 256 palette colors, 0 to 15 are the standard and bright colors
+    Indexed {
+        index: u8,
+    },
+    Rgb {
+        r: u8,
+        g: u8,
+        b: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct AnsiStyle {
+    pub foreground: Option<AnsiColor>,
+    pub background: Option<AnsiColor>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct AnsiSpan {
+    pub text: String,
+    pub style: AnsiStyle,
+}
+
+#[derive(Debug, Clone, Default)]
+enum ParseState {
+    #[default]
+    Text,
+    /// After an ESC
+    Escape,
+    /// Inside a control sequence, `ESC [` followed by its parameters so far
+    Csi(String),
+    /// Inside an operating system command, `ESC ]`, until BEL or `ESC \`
+    Osc { escape: bool },
+}
+
+/// Reads the color after a `38` or `48` parameter, `5;n` or `2;r;g;b`
+fn extended_color<'a>(params: &mut impl Iterator<Item = &'a str>) -> Option<AnsiColor> {
+    let mut next = || params.next().and_then(|param| param.parse::<u8>().ok());
+    match next()? {
+        5 => Some(AnsiColor::Indexed { index: next()? }),
+        2 => Some(AnsiColor::Rgb {
+            r: next()?,
+            g: next()?,
+            b: next()?,
+        }),
+        _ => None,
+    }
+}
+
+impl AnsiStyle {
+    /// Applies the parameters of a Select Graphic Rendition sequence
+    fn apply_sgr(&mut self, params: &str) {
+        let mut params = params.split(|c: char| c == ';' || c == ':');
+        while let Some(param) = params.next() {
+            let code = if param.is_empty() {
+                0
+            } else {
+                match param.parse::<u8>() {
+                    Ok(code) => code,
+                    Err(_) => continue,
+                }
+            };
+            match code {
+                0 => *self = AnsiStyle::default(),
+                1 => self.bold = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.foreground = Some(AnsiColor::Indexed { index: code - 30 }),
+                38 => self.foreground = extended_color(&mut params),
+                39 => self.foreground = None,
+                40..=47 => self.background = Some(AnsiColor::Indexed { index: code - 40 }),
+                48 => self.background = extended_color(&mut params),
+                49 => self.background = None,
+                90..=97 => self.foreground = Some(AnsiColor::Indexed { index: code - 82 }),
+                100..=107 => self.background = Some(AnsiColor::Indexed { index: code - 92 }),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnsiDecoder {
+    state: ParseState,
+    style: AnsiStyle,
+}
+
+impl AnsiDecoder {
+    /// The text of `input` in spans of the same style, without escape sequences
+    pub fn decode(&mut self, input: &str) -> Vec<AnsiSpan> {
+        let mut spans: Vec<AnsiSpan> = Vec::new();
+        let mut text = String::new();
+        let flush = |text: &mut String, spans: &mut Vec<AnsiSpan>, style: AnsiStyle| {
+            if text.is_empty() {
+                return;
+            }
+            match spans.last_mut() {
+                Some(last) if last.style == style => last.text.push_str(text),
+                _ => spans.push(AnsiSpan {
+                    text: text.clone(),
+                    style,
+                }),
+            }
+            text.clear();
+        };
+        for c in input.chars() {
+            self.state = match std::mem::take(&mut self.state) {
+                ParseState::Text if c == ESC => ParseState::Escape,
+                ParseState::Text => {
+                    text.push(c);
+                    ParseState::Text
+                }
+                ParseState::Escape => match c {
+                    '[' => ParseState::Csi(String::new()),
+                    ']' => ParseState::Osc { escape: false },
+                    ESC => ParseState::Escape,
+                    // two character sequences like `ESC c` have nothing to show
+                    _ => ParseState::Text,
+                },
+                ParseState::Csi(mut params) => match c {
+                    // final byte
+                    '@'..='~' => {
+                        if c == 'm' {
+                            flush(&mut text, &mut spans, self.style);
+                            self.style.apply_sgr(&params);
+                        }
+                        ParseState::Text
+                    }
+                    // parameter and intermediate bytes
+                    ' '..='?' => {
+                        params.push(c);
+                        ParseState::Csi(params)
+                    }
+                    // not a valid sequence, drop it
+                    _ => ParseState::Text,
+                },
+                ParseState::Osc { escape } => match c {
+                    BEL => ParseState::Text,
+                    '\\' if escape => ParseState::Text,
+                    _ => ParseState::Osc { escape: c == ESC },
+                },
+            };
+        }
+        flush(&mut text, &mut spans, self.style);
+        spans
+    }
+
+    /// `input` without escape sequences
+    pub fn strip(&mut self, input: &str) -> String {
+        self.decode(input)
+            .into_iter()
+            .map(|span| span.text)
+            .collect()
+    }
+}
+
+#[test]
+fn test_decode_colors() {
+    let mut decoder = AnsiDecoder::default();
+    let spans = decoder.decode("\u{1b}[32mDone\u{1b}[0m (1.2s)! \u{1b}[1;38;5;208mhi\n");
+    assert_eq!(
+        spans,
+        vec![
+            AnsiSpan {
+                text: "Done".to_string(),
+                style: AnsiStyle {
+                    foreground: Some(AnsiColor::Indexed { index: 2 }),
+                    ..Default::default()
+                },
+            },
+            AnsiSpan {
+                text: " (1.2s)! ".to_string(),
+                style: AnsiStyle::default(),
+            },
+            AnsiSpan {
+                text: "hi\n".to_string(),
+                style: AnsiStyle {
+                    foreground: Some(AnsiColor::Indexed { index: 208 }),
+                    bold: true,
+                    ..Default::default()
+                },
+            },
+        ]
+    );
+    // the style carries over to the next line
+    assert!(decoder.decode("more")[0].style.bold);
+}
+
+#[test]
+fn test_split_escape_sequence() {
+    let mut decoder = AnsiDecoder::default();
+    assert_eq!(decoder.strip("[INFO] \u{1b}[3"), "[INFO] ");
+    let spans = decoder.decode("1mError\u{1b}]0;title\u{7}\u{1b}[K!");
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].text, "Error!");
+    assert_eq!(
+        spans[0].style.foreground,
+        Some(AnsiColor::Indexed { index: 1 })
+    );
+}
+
+#[test]
+fn test_rgb_and_bright_colors() {
+    let mut style = AnsiStyle::default();
+    style.apply_sgr("38;2;255;128;0;103");
+    assert_eq!(
+        style.foreground,
+        Some(AnsiColor::Rgb {
+            r: 255,
+            g: 128,
+            b: 0
+        })
+    );
+    assert_eq!(style.background, Some(AnsiColor::Indexed { index: 11 }));
+    style.apply_sgr("");
+    assert_eq!(style, AnsiStyle::default());
+}
@@ -1,6 +1,10 @@
 pub mod hashed_password;
 pub mod jwt_token;
+pub mod password_policy;
 pub mod permission;
+pub mod session;
+pub mod totp;
 pub mod user;
 pub mod user_id;
 pub mod user_secrets;
+pub mod viewer_token;
@@ -0,0 +1,170 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+/// Passwords rejected regardless of the policy's other requirements, one per line
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// Longest minimum length the policy accepts, argon2 doesn't care but users would
+pub const MAX_MIN_LENGTH: usize = 128;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Reject passwords from a bundled list of commonly used passwords
+    pub reject_common: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_uppercase: false,
+            require_lowercase: false,
+            require_digit: false,
+            require_symbol: false,
+            reject_common: true,
+        }
+    }
+}
+
+/// A requirement of the policy a password doesn't meet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum PasswordRequirement {
+    MinLength { min_length: usize },
+    Uppercase,
+    Lowercase,
+    Digit,
+    Symbol,
+    NotCommon,
+}
+
+impl Display for PasswordRequirement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordRequirement::MinLength { min_length } => {
+                write!(f, "at least {} characters", min_length)
+            }
+            PasswordRequirement::Uppercase => write!(f, "an uppercase letter"),
+            PasswordRequirement::Lowercase => write!(f, "a lowercase letter"),
+            PasswordRequirement::Digit => write!(f, "a digit"),
+            PasswordRequirement::Symbol => write!(f, "a symbol"),
+            PasswordRequirement::NotCommon => write!(f, "not a commonly used password"),
+        }
+    }
+}
+
+/// The requirements a rejected password didn't meet, the error response lists them under
+/// `unmet_requirements`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicyViolation {
+    pub unmet_requirements: Vec<PasswordRequirement>,
+}
+
+impl Display for PasswordPolicyViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let unmet: Vec<String> = self
+            .unmet_requirements
+            .iter()
+            .map(|requirement| requirement.to_string())
+            .collect();
+        write!(f, "The password must be {}", unmet.join(", "))
+    }
+}
+
+fn is_common(password: &str) -> bool {
+    COMMON_PASSWORDS
+        .lines()
+        .any(|common| common.eq_ignore_ascii_case(password))
+}
+
+impl PasswordPolicy {
+    pub fn unmet_requirements(&self, password: &str) -> Vec<PasswordRequirement> {
+        let mut unmet = Vec::new();
+        if password.chars().count() < self.min_length {
+            unmet.push(PasswordRequirement::MinLength {
+                min_length: self.min_length,
+            });
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            unmet.push(PasswordRequirement::Uppercase);
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            unmet.push(PasswordRequirement::Lowercase);
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            unmet.push(PasswordRequirement::Digit);
+        }
+        if self.require_symbol && password.chars().all(char::is_alphanumeric) {
+            unmet.push(PasswordRequirement::Symbol);
+        }
+        if self.reject_common && is_common(password) {
+            unmet.push(PasswordRequirement::NotCommon);
+        }
+        unmet
+    }
+
+    pub fn check(&self, password: &str) -> Result<(), Error> {
+        let unmet_requirements = self.unmet_requirements(password);
+        if unmet_requirements.is_empty() {
+            return Ok(());
+        }
+        Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: color_eyre::Report::msg(PasswordPolicyViolation { unmet_requirements }),
+        })
+    }
+}
+
+#[test]
+fn test_password_policy() {
+    let policy = PasswordPolicy::default();
+    assert!(policy.check("correct horse battery staple").is_ok());
+    assert_eq!(
+        policy.unmet_requirements("Password"),
+        vec![PasswordRequirement::NotCommon]
+    );
+    assert_eq!(
+        policy.unmet_requirements("hunter2"),
+        vec![
+            PasswordRequirement::MinLength { min_length: 8 },
+            PasswordRequirement::NotCommon
+        ]
+    );
+
+    let strict = PasswordPolicy {
+        min_length: 12,
+        require_uppercase: true,
+        require_lowercase: true,
+        require_digit: true,
+        require_symbol: true,
+        reject_common: true,
+    };
+    assert!(strict.check("Tr0ub4dor&3-horse").is_ok());
+    assert_eq!(
+        strict.unmet_requirements("lowercase only here"),
+        vec![PasswordRequirement::Uppercase, PasswordRequirement::Digit]
+    );
+
+    let error = strict.check("short").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::BadRequest);
+    assert_eq!(
+        error
+            .source
+            .downcast_ref::<PasswordPolicyViolation>()
+            .unwrap()
+            .unmet_requirements
+            .len(),
+        4
+    );
+}
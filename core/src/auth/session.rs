@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::user_id::UserId;
+
+/// Longest user agent kept, the rest is cut off
+const MAX_USER_AGENT_LEN: usize = 256;
+
+/// A login, its id is in the claims of the tokens issued for it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct Session {
+    pub id: String,
+    pub uid: UserId,
+    pub created_at: i64,
+    /// Updated as the session's token is used, persisted every few minutes
+    pub last_seen: i64,
+    pub expires_at: i64,
+    /// Address the login came from
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// A session as listed to its user
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct SessionInfo {
+    #[serde(flatten)]
+    pub session: Session,
+    /// The session of the token the list was requested with
+    pub current: bool,
+}
+
+pub fn truncate_user_agent(user_agent: &str) -> String {
+    user_agent.chars().take(MAX_USER_AGENT_LEN).collect()
+}
@@ -0,0 +1,181 @@
+//! Time-based one-time passwords (RFC 6238) for two-factor login, with single use
+//! recovery codes for when the authenticator is lost
+
+use color_eyre::eyre::eyre;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::secrets::SecretStore;
+
+const ISSUER: &str = "Lodestone";
+const SECRET_LEN: usize = 20;
+const STEP_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Codes from the step before and after are accepted too, to allow for clock drift
+const SKEW_STEPS: i64 = 1;
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// A user's second factor as stored in the users file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TotpEnrollment {
+    /// Base32 secret, sealed with the secret store key
+    secret: String,
+    /// Only enforced at login once a code from the authenticator was verified
+    pub confirmed: bool,
+    /// SHA-256 of the unused recovery codes
+    recovery_codes: Vec<String>,
+    /// The step of the last accepted code, so a code can't be replayed
+    last_used_step: i64,
+}
+
+/// Returned once on enrollment, the secret and recovery codes can't be read back later
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct TotpEnrollmentInfo {
+    /// `otpauth://` URI to show as a QR code
+    pub otpauth_uri: String,
+    /// For entering into an authenticator by hand
+    pub secret: String,
+    pub recovery_codes: Vec<String>,
+}
+
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    code % 10u32.pow(DIGITS)
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+fn new_recovery_code() -> String {
+    let mut bytes = [0u8; 10];
+    OsRng.fill_bytes(&mut bytes);
+    let chars: String = bytes
+        .iter()
+        .map(|b| RECOVERY_CODE_ALPHABET[*b as usize % RECOVERY_CODE_ALPHABET.len()] as char)
+        .collect();
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+/// The step `code` is valid for at `now`, if any
+fn matching_step(secret: &[u8], code: &str, now: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let step = now / STEP_SECS;
+    (step - SKEW_STEPS..=step + SKEW_STEPS)
+        .filter(|step| *step >= 0)
+        .find(|step| hotp(secret, *step as u64) == code)
+}
+
+fn invalid_code() -> Error {
+    Error {
+        kind: ErrorKind::Unauthorized,
+        source: eyre!("Invalid two-factor code"),
+    }
+}
+
+impl TotpEnrollment {
+    /// A new unconfirmed enrollment for `account`
+    pub fn new(account: &str, secrets: &SecretStore) -> Result<(Self, TotpEnrollmentInfo), Error> {
+        let mut secret = [0u8; SECRET_LEN];
+        OsRng.fill_bytes(&mut secret);
+        let secret = BASE32_NOPAD.encode(&secret);
+        let recovery_codes: Vec<String> =
+            (0..RECOVERY_CODES).map(|_| new_recovery_code()).collect();
+        let otpauth_uri = format!(
+            "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECS}",
+            issuer = ISSUER,
+            account = url::form_urlencoded::byte_serialize(account.as_bytes()).collect::<String>(),
+        );
+        Ok((
+            Self {
+                secret: secrets.seal(&secret)?,
+                confirmed: false,
+                recovery_codes: recovery_codes
+                    .iter()
+                    .map(|code| hash_recovery_code(code))
+                    .collect(),
+                last_used_step: -1,
+            },
+            TotpEnrollmentInfo {
+                otpauth_uri,
+                secret,
+                recovery_codes,
+            },
+        ))
+    }
+
+    /// Accepts a current code that wasn't used before, or an unused recovery code which is
+    /// then used up
+    pub fn verify(&mut self, code: &str, now: i64, secrets: &SecretStore) -> Result<(), Error> {
+        let secret = BASE32_NOPAD
+            .decode(secrets.unseal(&self.secret)?.as_bytes())
+            .map_err(|_| eyre!("Stored TOTP secret is corrupted"))?;
+        if let Some(step) = matching_step(&secret, code, now) {
+            if step <= self.last_used_step {
+                return Err(invalid_code());
+            }
+            self.last_used_step = step;
+            return Ok(());
+        }
+        let hash = hash_recovery_code(code);
+        match self
+            .recovery_codes
+            .iter()
+            .position(|stored| *stored == hash)
+        {
+            Some(index) => {
+                self.recovery_codes.remove(index);
+                Ok(())
+            }
+            None => Err(invalid_code()),
+        }
+    }
+}
+
+#[test]
+fn test_totp() {
+    // RFC 6238 appendix B, SHA1
+    let secret = b"12345678901234567890";
+    assert_eq!(hotp(secret, 59 / 30), 94287082 % 1_000_000);
+    assert_eq!(hotp(secret, 1111111109 / 30), 7081804 % 1_000_000);
+    assert_eq!(hotp(secret, 2000000000 / 30), 69279037 % 1_000_000);
+
+    assert_eq!(matching_step(secret, "287082", 59), Some(1));
+    // a code from the previous step is still accepted
+    assert_eq!(matching_step(secret, "287082", 89), Some(1));
+    assert_eq!(matching_step(secret, "287082", 150), None);
+    assert_eq!(matching_step(secret, "28708", 59), None);
+
+    assert_eq!(
+        hash_recovery_code("ABCDE-fghjk"),
+        hash_recovery_code("abcdefghjk")
+    );
+    let code = new_recovery_code();
+    assert_eq!(code.len(), 11);
+}
@@ -1,7 +1,12 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+};
 
 use argon2::{Argon2, PasswordVerifier};
 use color_eyre::eyre::{eyre, Context};
+use dashmap::DashMap;
 use jsonwebtoken::{Algorithm, Validation};
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
@@ -12,21 +17,30 @@ use crate::{
     error::{Error, ErrorKind},
     event_broadcaster::EventBroadcaster,
     events::{CausedBy, Event, EventInner, UserEvent, UserEventInner},
+    secrets::SecretStore,
     types::{InstanceUuid, Snowflake},
+    util::rand_alphanumeric,
 };
 
 use super::{
     hashed_password::{hash_password, HashedPassword},
     jwt_token::JwtToken,
+    password_policy::PasswordPolicy,
     permission::UserPermission,
+    session::Session,
+    totp::{TotpEnrollment, TotpEnrollmentInfo},
     user_id::UserId,
     user_secrets::UserSecret,
+    viewer_token::{self, MintedViewerToken, StoredViewerToken, ViewerToken},
 };
 
 #[derive(Deserialize, Serialize)]
 pub struct Claim {
     pub uid: UserId,
     pub exp: usize,
+    /// The session the token belongs to, tokens from before sessions have none and are rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
 }
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct User {
@@ -37,6 +51,8 @@ pub struct User {
     pub is_admin: bool,
     pub permissions: UserPermission,
     pub secret: UserSecret,
+    #[serde(default)]
+    pub totp: Option<TotpEnrollment>,
 }
 
 impl User {
@@ -55,8 +71,14 @@ impl User {
             is_admin,
             permissions,
             secret: UserSecret::default(),
+            totp: None,
         }
     }
+    /// Whether login asks for a second factor
+    pub fn two_factor_enabled(&self) -> bool {
+        self.totp.as_ref().map_or(false, |totp| totp.confirmed)
+    }
+
     fn get_permission_level(&self) -> u8 {
         if self.is_owner {
             u8::MAX
@@ -252,17 +274,19 @@ impl User {
             // TODO!,
             EventInner::ProgressionEvent(_progression_event) => true,
             EventInner::PlayitggRunnerEvent(_playitgg_runner_event) => true,
+            // a batch may cover instances the user can't see, those users should use the per instance monitor
+            EventInner::MonitorEvent(monitor_event) => monitor_event
+                .reports
+                .keys()
+                .all(|uuid| self.can_perform_action(&UserAction::ViewInstance(uuid.clone()))),
         }
     }
 
-    pub fn create_jwt(&self) -> Result<JwtToken, Error> {
-        let exp = chrono::Utc::now()
-            .checked_add_signed(chrono::Duration::days(60))
-            .ok_or_else(|| eyre!("Failed to create JWT token"))?
-            .timestamp();
+    fn create_session_jwt(&self, exp: i64, sid: String) -> Result<JwtToken, Error> {
         let claim = Claim {
             uid: self.uid.clone(),
             exp: exp as usize,
+            sid: Some(sid),
         };
 
         JwtToken::new(claim, self.secret.clone())
@@ -324,6 +348,7 @@ pub struct PublicUser {
     pub is_owner: bool,
     pub is_admin: bool,
     pub permissions: UserPermission,
+    pub two_factor_enabled: bool,
 }
 
 impl From<&User> for PublicUser {
@@ -334,6 +359,7 @@ impl From<&User> for PublicUser {
             is_owner: user.is_owner,
             is_admin: user.is_admin,
             permissions: user.permissions.clone(),
+            two_factor_enabled: user.two_factor_enabled(),
         }
     }
 }
@@ -341,6 +367,7 @@ impl From<&User> for PublicUser {
 impl From<User> for PublicUser {
     fn from(user: User) -> Self {
         PublicUser {
+            two_factor_enabled: user.two_factor_enabled(),
             uid: user.uid,
             username: user.username,
             is_owner: user.is_owner,
@@ -350,11 +377,33 @@ impl From<User> for PublicUser {
     }
 }
 
+/// A login that passed the password and waits for the second factor
+#[derive(Clone)]
+struct TotpChallenge {
+    uid: UserId,
+    expires_at: i64,
+    failed_attempts: u32,
+    /// Checked against the password policy with the password, which isn't kept
+    password_update_required: bool,
+}
+
+/// Matches the expiry of the tokens issued on login
+const SESSION_TTL_SECS: i64 = 60 * 24 * 60 * 60;
+/// How long the second step of a login can take
+const TOTP_CHALLENGE_TTL_SECS: i64 = 5 * 60;
+/// Wrong codes before the login has to start over
+const MAX_TOTP_ATTEMPTS: u32 = 5;
+
 #[derive(Clone)]
 pub struct UsersManager {
     event_broadcaster: EventBroadcaster,
     users: HashMap<UserId, User>,
     path_to_users: PathBuf,
+    viewer_tokens: HashMap<String, StoredViewerToken>,
+    password_policy: PasswordPolicy,
+    totp_challenges: HashMap<String, TotpChallenge>,
+    /// Shared so a token's use can be recorded while holding only a read lock
+    sessions: Arc<DashMap<String, Session>>,
 }
 
 impl UsersManager {
@@ -367,8 +416,26 @@ impl UsersManager {
             event_broadcaster,
             users,
             path_to_users,
+            viewer_tokens: HashMap::new(),
+            password_policy: PasswordPolicy::default(),
+            totp_challenges: HashMap::new(),
+            sessions: Arc::new(DashMap::new()),
         }
     }
+
+    /// Kept in sync with the global settings
+    pub fn set_password_policy(&mut self, password_policy: PasswordPolicy) {
+        self.password_policy = password_policy;
+    }
+
+    /// Rejects a new password that doesn't meet the password policy
+    pub fn check_password(&self, password: &str) -> Result<(), Error> {
+        self.password_policy.check(password)
+    }
+
+    fn path_to_viewer_tokens(&self) -> PathBuf {
+        self.path_to_users.with_file_name("viewer_tokens.json")
+    }
     pub async fn load_users(&mut self) -> Result<(), Error> {
         if tokio::fs::OpenOptions::new()
             .read(true)
@@ -405,9 +472,91 @@ impl UsersManager {
             .context("Failed to deserialize user json")?;
             self.users = users;
         }
+        if let Ok(content) = tokio::fs::read_to_string(self.path_to_viewer_tokens()).await {
+            self.viewer_tokens =
+                serde_json::from_str(&content).context("Failed to deserialize viewer tokens")?;
+        }
         Ok(())
     }
 
+    async fn write_viewer_tokens(&self) -> Result<(), Error> {
+        crate::util::fs::write_all(
+            self.path_to_viewer_tokens(),
+            serde_json::to_string(&self.viewer_tokens)
+                .context("Failed to serialize viewer tokens")?,
+        )
+        .await
+    }
+
+    /// Creates a read-only token for `instances`, its secret is only ever returned here
+    pub async fn mint_viewer_token(
+        &mut self,
+        label: String,
+        instances: HashSet<InstanceUuid>,
+        created_by: UserId,
+        now: i64,
+        expires_at: Option<i64>,
+    ) -> Result<MintedViewerToken, Error> {
+        let (minted, stored) = viewer_token::mint(label, instances, created_by, now, expires_at);
+        self.viewer_tokens.insert(stored.info.id.clone(), stored);
+        if let Err(e) = self.write_viewer_tokens().await {
+            self.viewer_tokens.remove(&minted.info.id);
+            return Err(e);
+        }
+        Ok(minted)
+    }
+
+    /// Tokens that have not expired yet
+    pub fn viewer_tokens(&self, now: i64) -> Vec<ViewerToken> {
+        let mut tokens: Vec<ViewerToken> = self
+            .viewer_tokens
+            .values()
+            .filter(|stored| !stored.info.is_expired(now))
+            .map(|stored| stored.info.clone())
+            .collect();
+        tokens.sort_by_key(|token| token.created_at);
+        tokens
+    }
+
+    pub fn get_viewer_token(&self, id: &str) -> Option<ViewerToken> {
+        self.viewer_tokens.get(id).map(|stored| stored.info.clone())
+    }
+
+    /// Revokes the token, along with any that already expired
+    pub async fn revoke_viewer_token(&mut self, id: &str, now: i64) -> Result<(), Error> {
+        if self.viewer_tokens.remove(id).is_none() {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Viewer token not found"),
+            });
+        }
+        self.viewer_tokens
+            .retain(|_, stored| !stored.info.is_expired(now));
+        self.write_viewer_tokens().await
+    }
+
+    /// The viewer token `token` is, if it is one that can still view `uuid`
+    ///
+    /// Its creator must still exist and be allowed to view `uuid`, checked on every use so
+    /// a token never outlives the permissions it was minted with
+    pub fn try_auth_viewer(
+        &self,
+        token: &str,
+        uuid: &InstanceUuid,
+        now: i64,
+    ) -> Option<ViewerToken> {
+        let (id, secret) = viewer_token::parse(token)?;
+        let stored = self.viewer_tokens.get(id)?;
+        if !viewer_token::secret_matches(stored, secret) || !stored.info.can_view(uuid, now) {
+            return None;
+        }
+        let creator = self.users.get(&stored.info.created_by)?;
+        if !creator.can_perform_action(&UserAction::ViewInstance(uuid.clone())) {
+            return None;
+        }
+        Some(stored.info.clone())
+    }
+
     async fn write_to_file(&self) -> Result<(), Error> {
         let mut file = tokio::fs::File::create(&self.path_to_users)
             .await
@@ -461,9 +610,26 @@ impl UsersManager {
         uid: impl AsRef<UserId>,
         caused_by: CausedBy,
     ) -> Result<Option<User>, Error> {
+        if self.is_last_owner(uid.as_ref()) {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("Cannot delete the last owner, transfer ownership first"),
+            });
+        }
         let user = self.users.remove(uid.as_ref());
         match self.write_to_file().await {
             Ok(()) => {
+                self.sessions
+                    .retain(|_, session| session.uid != *uid.as_ref());
+                // a deleted user's viewer tokens stop working with their account
+                let tokens_before = self.viewer_tokens.len();
+                self.viewer_tokens
+                    .retain(|_, stored| stored.info.created_by != *uid.as_ref());
+                if self.viewer_tokens.len() != tokens_before {
+                    if let Err(e) = self.write_viewer_tokens().await {
+                        warn!("Failed to revoke the viewer tokens of a deleted user: {e}");
+                    }
+                }
                 if let Some(_user) = user.as_ref() {
                     self.event_broadcaster.send(Event {
                         event_inner: EventInner::UserEvent(UserEvent {
@@ -503,6 +669,9 @@ impl UsersManager {
         if let Some(user) = self.users.get_mut(uid.as_ref()) {
             user.secret = UserSecret::default();
         }
+        // the new secret already invalidates every token, this drops them from the list
+        self.sessions
+            .retain(|_, session| session.uid != *uid.as_ref());
 
         match self.write_to_file().await {
             Ok(_) => {
@@ -600,6 +769,7 @@ impl UsersManager {
                     source: eyre!("Credential mismatch"),
                 })?;
         }
+        self.check_password(&password)?;
         if let Some(user) = self.users.get_mut(uid.as_ref()) {
             user.hashed_psw = hash_password(password);
         }
@@ -674,16 +844,200 @@ impl UsersManager {
         }
     }
 
+    /// Whether `uid` is the only owner left
+    fn is_last_owner(&self, uid: &UserId) -> bool {
+        self.users.get(uid).map_or(false, |user| user.is_owner)
+            && self.users.values().filter(|user| user.is_owner).count() <= 1
+    }
+
+    /// Sets the roles of users and persists them at once, sending a `RoleChanged` event for
+    /// each user whose roles changed
+    async fn set_roles(
+        &mut self,
+        roles: Vec<(UserId, bool, bool)>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let mut previous = Vec::new();
+        for (uid, is_owner, is_admin) in &roles {
+            let user = self.users.get_mut(uid).ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("User id not found"),
+            })?;
+            previous.push((uid.clone(), user.is_owner, user.is_admin));
+            user.is_owner = *is_owner;
+            user.is_admin = *is_admin;
+        }
+        if !self.users.values().any(|user| user.is_owner) {
+            self.restore_roles(previous);
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("Cannot remove the last owner, transfer ownership first"),
+            });
+        }
+        if let Err(e) = self.write_to_file().await {
+            self.restore_roles(previous);
+            return Err(e);
+        }
+        for ((uid, is_owner, is_admin), (_, was_owner, was_admin)) in
+            roles.into_iter().zip(previous)
+        {
+            if (is_owner, is_admin) == (was_owner, was_admin) {
+                continue;
+            }
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::UserEvent(UserEvent {
+                    user_id: uid,
+                    user_event_inner: UserEventInner::RoleChanged { is_owner, is_admin },
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by: caused_by.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn restore_roles(&mut self, roles: Vec<(UserId, bool, bool)>) {
+        for (uid, is_owner, is_admin) in roles {
+            if let Some(user) = self.users.get_mut(&uid) {
+                user.is_owner = is_owner;
+                user.is_admin = is_admin;
+            }
+        }
+    }
+
+    /// Grants or revokes the owner and admin roles of a user, there is always at least one
+    /// owner left
+    pub async fn set_role(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        is_owner: bool,
+        is_admin: bool,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        self.set_roles(
+            vec![(uid.as_ref().to_owned(), is_owner, is_admin)],
+            caused_by,
+        )
+        .await
+    }
+
+    /// Makes `to` an owner, and unless `keep_owner` demotes `from` to admin
+    pub async fn transfer_ownership(
+        &mut self,
+        from: impl AsRef<UserId>,
+        to: impl AsRef<UserId>,
+        keep_owner: bool,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        if from == to {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("You already own this server"),
+            });
+        }
+        if !self.users.get(from).map_or(false, |user| user.is_owner) {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Only an owner can transfer ownership"),
+            });
+        }
+        let mut roles = vec![(to.to_owned(), true, true)];
+        if !keep_owner {
+            roles.push((from.to_owned(), false, true));
+        }
+        self.set_roles(roles, caused_by).await
+    }
+
     pub fn try_auth(&self, token: &str) -> Option<User> {
-        let claimed_uid = decode_no_verify(token)?;
+        let claimed_uid = decode_no_verify(token)?.uid;
         let claimed_requester = self.users.get(&claimed_uid)?;
-        let requester_uid = decode_token(token, &claimed_requester.secret)?;
-        if claimed_uid != requester_uid {
+        let claim = decode_token(token, &claimed_requester.secret)?;
+        if claimed_uid != claim.uid {
             return None;
         }
+        // every token belongs to a session, a token of a revoked session is rejected even
+        // though its signature is fine
+        match self.sessions.get_mut(&claim.sid?) {
+            Some(mut session) if session.uid == claim.uid => {
+                session.last_seen = chrono::Utc::now().timestamp();
+            }
+            _ => return None,
+        }
         Some(claimed_requester.to_owned())
     }
 
+    /// Starts a session for `user` and issues its token
+    pub fn create_session(
+        &self,
+        user: &User,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        now: i64,
+    ) -> Result<(JwtToken, Session), Error> {
+        let session = Session {
+            id: rand_alphanumeric(32),
+            uid: user.uid.clone(),
+            created_at: now,
+            last_seen: now,
+            expires_at: now + SESSION_TTL_SECS,
+            ip,
+            user_agent,
+        };
+        let token = user.create_session_jwt(session.expires_at, session.id.clone())?;
+        self.sessions.insert(session.id.clone(), session.clone());
+        Ok((token, session))
+    }
+
+    /// Sessions read back from the database on startup, expired ones are dropped
+    pub fn restore_sessions(&self, sessions: Vec<Session>, now: i64) {
+        for session in sessions {
+            if session.expires_at > now && self.users.contains_key(&session.uid) {
+                self.sessions.insert(session.id.clone(), session);
+            }
+        }
+    }
+
+    /// Every live session, expired ones are dropped
+    pub fn sessions(&self, now: i64) -> Vec<Session> {
+        self.sessions.retain(|_, session| session.expires_at > now);
+        self.sessions
+            .iter()
+            .map(|session| session.value().clone())
+            .collect()
+    }
+
+    /// Sessions of `uid`, most recently used first
+    pub fn sessions_of(&self, uid: &UserId, now: i64) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self
+            .sessions(now)
+            .into_iter()
+            .filter(|session| session.uid == *uid)
+            .collect();
+        sessions.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        sessions
+    }
+
+    /// The session `token` belongs to, if it has one
+    pub fn session_id(token: &str) -> Option<String> {
+        decode_no_verify(token)?.sid
+    }
+
+    /// Logs out one session of `uid`
+    pub fn revoke_session(&self, uid: &UserId, sid: &str) -> Result<Session, Error> {
+        match self
+            .sessions
+            .remove_if(sid, |_, session| session.uid == *uid)
+        {
+            Some((_, session)) => Ok(session),
+            None => Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Session not found"),
+            }),
+        }
+    }
+
     pub fn try_auth_or_err(&self, token: &str) -> Result<User, Error> {
         self.try_auth(token).ok_or_else(|| Error {
             kind: ErrorKind::Unauthorized,
@@ -696,6 +1050,17 @@ impl UsersManager {
         username: impl AsRef<str>,
         password: impl AsRef<str>,
     ) -> Result<JwtToken, Error> {
+        let user = self.verify_credentials(username, password)?;
+        let (token, _) = self.create_session(&user, None, None, chrono::Utc::now().timestamp())?;
+        Ok(token)
+    }
+
+    /// The user with `username` if `password` is theirs
+    pub fn verify_credentials(
+        &self,
+        username: impl AsRef<str>,
+        password: impl AsRef<str>,
+    ) -> Result<User, Error> {
         let user = self.get_user_by_username(username).ok_or_else(|| Error {
             kind: ErrorKind::Unauthorized,
             source: eyre!("Credential mismatch"),
@@ -709,22 +1074,215 @@ impl UsersManager {
                 kind: ErrorKind::Unauthorized,
                 source: eyre!("Credential mismatch"),
             })?;
-        user.create_jwt()
+        Ok(user)
+    }
+
+    fn send_user_event(&self, uid: &UserId, inner: UserEventInner, caused_by: CausedBy) {
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::UserEvent(UserEvent {
+                user_id: uid.clone(),
+                user_event_inner: inner,
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+    }
+
+    /// Persists a change to the second factor of `uid`, or reverts it if it can't be saved
+    async fn write_totp(
+        &mut self,
+        uid: &UserId,
+        totp: Option<TotpEnrollment>,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let old_totp = std::mem::replace(&mut user.totp, totp);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid) {
+                user.totp = old_totp;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Checks a code, or a recovery code, against the enrollment of `uid`
+    async fn verify_totp(
+        &mut self,
+        uid: &UserId,
+        code: &str,
+        now: i64,
+        secrets: &SecretStore,
+    ) -> Result<(), Error> {
+        let mut totp = self
+            .users
+            .get(uid)
+            .and_then(|user| user.totp.clone())
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Two-factor authentication is not set up"),
+            })?;
+        totp.verify(code, now, secrets)?;
+        // the used step or recovery code must be saved, or it could be used again
+        self.write_totp(uid, Some(totp)).await
+    }
+
+    /// Starts setting up two-factor authentication, it is enforced once confirmed with a code
+    pub async fn enroll_totp(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        secrets: &SecretStore,
+    ) -> Result<TotpEnrollmentInfo, Error> {
+        let user = self.get_user(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        if user.two_factor_enabled() {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("Two-factor authentication is already enabled, disable it first"),
+            });
+        }
+        let (totp, info) = TotpEnrollment::new(&user.username, secrets)?;
+        self.write_totp(uid.as_ref(), Some(totp)).await?;
+        Ok(info)
+    }
+
+    pub async fn confirm_totp(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        code: &str,
+        now: i64,
+        secrets: &SecretStore,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let uid = uid.as_ref();
+        let mut totp = match self.get_user(uid).and_then(|user| user.totp) {
+            Some(totp) if !totp.confirmed => totp,
+            Some(_) => {
+                return Err(Error {
+                    kind: ErrorKind::Conflict,
+                    source: eyre!("Two-factor authentication is already enabled"),
+                })
+            }
+            None => {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Two-factor authentication is not set up"),
+                })
+            }
+        };
+        totp.verify(code, now, secrets)?;
+        totp.confirmed = true;
+        self.write_totp(uid, Some(totp)).await?;
+        self.send_user_event(uid, UserEventInner::TwoFactorEnabled, caused_by);
+        Ok(())
+    }
+
+    /// Turns two-factor authentication off, `code` is required unless an owner or admin
+    /// resets it for someone who lost their authenticator
+    pub async fn disable_totp(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        code: Option<&str>,
+        now: i64,
+        secrets: &SecretStore,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let uid = uid.as_ref();
+        let was_enabled = self
+            .get_user(uid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("User id not found"),
+            })?
+            .two_factor_enabled();
+        if let Some(code) = code {
+            self.verify_totp(uid, code, now, secrets).await?;
+        }
+        self.write_totp(uid, None).await?;
+        if was_enabled {
+            self.send_user_event(uid, UserEventInner::TwoFactorDisabled, caused_by);
+        }
+        Ok(())
+    }
+
+    /// Starts the second step of a login, returning the challenge to answer with a code
+    pub fn start_totp_challenge(
+        &mut self,
+        uid: &UserId,
+        password_update_required: bool,
+        now: i64,
+    ) -> String {
+        self.totp_challenges
+            .retain(|_, challenge| challenge.expires_at > now);
+        let challenge = rand_alphanumeric(32);
+        self.totp_challenges.insert(
+            challenge.clone(),
+            TotpChallenge {
+                uid: uid.clone(),
+                expires_at: now + TOTP_CHALLENGE_TTL_SECS,
+                failed_attempts: 0,
+                password_update_required,
+            },
+        );
+        challenge
+    }
+
+    /// Finishes a login with the code for `challenge`, which can only be used once. Also
+    /// returns whether the password failed the password policy
+    pub async fn complete_totp_challenge(
+        &mut self,
+        challenge: &str,
+        code: &str,
+        now: i64,
+        secrets: &SecretStore,
+    ) -> Result<(User, bool), Error> {
+        let pending = match self.totp_challenges.get(challenge) {
+            Some(pending) if pending.expires_at > now => pending.clone(),
+            _ => {
+                self.totp_challenges.remove(challenge);
+                return Err(Error {
+                    kind: ErrorKind::Unauthorized,
+                    source: eyre!("Login expired, sign in again"),
+                });
+            }
+        };
+        if let Err(e) = self.verify_totp(&pending.uid, code, now, secrets).await {
+            match self.totp_challenges.get_mut(challenge) {
+                Some(pending) if pending.failed_attempts + 1 < MAX_TOTP_ATTEMPTS => {
+                    pending.failed_attempts += 1
+                }
+                _ => {
+                    self.totp_challenges.remove(challenge);
+                }
+            }
+            return Err(e);
+        }
+        self.totp_challenges.remove(challenge);
+        let user = self.get_user(&pending.uid).ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("User not found"),
+        })?;
+        Ok((user, pending.password_update_required))
     }
 }
 
-fn decode_token(token: &str, jwt_secret: &UserSecret) -> Option<UserId> {
+fn decode_token(token: &str, jwt_secret: &UserSecret) -> Option<Claim> {
     match jsonwebtoken::decode::<Claim>(
         token,
         &jsonwebtoken::DecodingKey::from_secret(jwt_secret.as_ref().as_bytes()),
         &Validation::new(Algorithm::HS512),
     ) {
-        Ok(t) => Some(t.claims.uid),
+        Ok(t) => Some(t.claims),
         Err(_) => None,
     }
 }
 
-fn decode_no_verify(token: &str) -> Option<UserId> {
+fn decode_no_verify(token: &str) -> Option<Claim> {
     let mut no_verify = Validation::new(Algorithm::HS512);
     no_verify.insecure_disable_signature_validation();
     match jsonwebtoken::decode::<Claim>(
@@ -732,7 +1290,7 @@ fn decode_no_verify(token: &str) -> Option<UserId> {
         &jsonwebtoken::DecodingKey::from_secret("noverify".as_bytes()),
         &no_verify,
     ) {
-        Ok(t) => Some(t.claims.uid),
+        Ok(t) => Some(t.claims),
         Err(_) => None,
     }
 }
@@ -796,13 +1354,30 @@ mod tests {
             .change_password(
                 &test_user1.uid,
                 Some("12345"),
-                "54321".to_string(),
+                "a longer passphrase".to_string(),
                 CausedBy::System,
             )
             .await
             .unwrap();
 
-        users_manager.login("test_user1", "54321").unwrap();
+        users_manager
+            .login("test_user1", "a longer passphrase")
+            .unwrap();
+
+        // new passwords must meet the password policy
+        assert_eq!(
+            users_manager
+                .change_password(
+                    &test_user1.uid,
+                    Some("a longer passphrase"),
+                    "54321".to_string(),
+                    CausedBy::System,
+                )
+                .await
+                .unwrap_err()
+                .kind,
+            ErrorKind::BadRequest
+        );
     }
 
     #[tokio::test]
@@ -861,4 +1436,173 @@ mod tests {
 
         assert!(users_manager.get_user_by_username("test_user1").is_some());
     }
+
+    #[tokio::test]
+    async fn test_transfer_ownership() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_transfer_ownership")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            true,
+            UserPermission::default(),
+        );
+        let successor = User::new(
+            "successor".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        users_manager
+            .add_user(owner.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        users_manager
+            .add_user(successor.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        // the only owner can't be deleted or demoted
+        assert_eq!(
+            users_manager
+                .delete_user(&owner.uid, CausedBy::System)
+                .await
+                .unwrap_err()
+                .kind,
+            ErrorKind::Conflict
+        );
+        assert!(users_manager
+            .set_role(&owner.uid, false, true, CausedBy::System)
+            .await
+            .is_err());
+        assert!(users_manager.get_user(&owner.uid).unwrap().is_owner);
+
+        assert_eq!(
+            users_manager
+                .transfer_ownership(&successor.uid, &owner.uid, false, CausedBy::System)
+                .await
+                .unwrap_err()
+                .kind,
+            ErrorKind::PermissionDenied
+        );
+        users_manager
+            .transfer_ownership(&owner.uid, &successor.uid, false, CausedBy::System)
+            .await
+            .unwrap();
+        let old_owner = users_manager.get_user(&owner.uid).unwrap();
+        assert!(!old_owner.is_owner && old_owner.is_admin);
+        assert!(users_manager.get_user(&successor.uid).unwrap().is_owner);
+
+        users_manager
+            .delete_user(&owner.uid, CausedBy::System)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_sessions").unwrap().into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let user = User::new(
+            "user".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        users_manager
+            .add_user(user.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let (laptop, laptop_session) = users_manager
+            .create_session(&user, Some("10.0.0.2".to_string()), None, now)
+            .unwrap();
+        let (phone, _) = users_manager
+            .create_session(&user, None, None, now)
+            .unwrap();
+        assert!(users_manager.try_auth(&laptop).is_some());
+        assert_eq!(
+            UsersManager::session_id(&laptop),
+            Some(laptop_session.id.clone())
+        );
+        assert_eq!(users_manager.sessions_of(&user.uid, now).len(), 2);
+
+        // another user can't revoke the session
+        assert_eq!(
+            users_manager
+                .revoke_session(&UserId::default(), &laptop_session.id)
+                .unwrap_err()
+                .kind,
+            ErrorKind::NotFound
+        );
+        users_manager
+            .revoke_session(&user.uid, &laptop_session.id)
+            .unwrap();
+        assert!(users_manager.try_auth(&laptop).is_none());
+        assert!(users_manager.try_auth(&phone).is_some());
+
+        // a restored session is accepted again, an expired one is dropped
+        users_manager.restore_sessions(vec![laptop_session.clone()], now);
+        assert!(users_manager.try_auth(&laptop).is_some());
+        assert!(users_manager.sessions(laptop_session.expires_at).is_empty());
+
+        users_manager
+            .logout_user(&user.uid, CausedBy::System)
+            .await
+            .unwrap();
+        assert!(users_manager.sessions_of(&user.uid, now).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_viewer_token_follows_creator_permissions() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_viewer_token")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let uuid = InstanceUuid::from("INSTANCE_1".to_string());
+        let mut permissions = UserPermission::default();
+        permissions.can_view_instance.insert(uuid.clone());
+        let user = User::new("user".to_string(), "12345", false, false, permissions);
+        users_manager
+            .add_user(user.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let minted = users_manager
+            .mint_viewer_token(
+                "Overlay".to_string(),
+                HashSet::from([uuid.clone()]),
+                user.uid.clone(),
+                now,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(users_manager
+            .try_auth_viewer(&minted.token, &uuid, now)
+            .is_some());
+
+        // the creator losing the permission disables the token
+        users_manager
+            .update_permissions(&user.uid, UserPermission::default(), CausedBy::System)
+            .await
+            .unwrap();
+        assert!(users_manager
+            .try_auth_viewer(&minted.token, &uuid, now)
+            .is_none());
+    }
 }
@@ -0,0 +1,125 @@
+//! Read-only tokens scoped to a few instances, for public dashboards and stream overlays
+//!
+//! A viewer token is not a user: it can only see the console output and status of its
+//! instances, and every control endpoint keeps rejecting it
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ts_rs::TS;
+
+use crate::{types::InstanceUuid, util::rand_alphanumeric};
+
+use super::user_id::UserId;
+
+const TOKEN_PREFIX: &str = "lsv";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ViewerToken {
+    pub id: String,
+    pub label: String,
+    pub instances: HashSet<InstanceUuid>,
+    pub created_by: UserId,
+    pub created_at: i64,
+    /// Unix time after which the token stops working, `None` for no expiry
+    pub expires_at: Option<i64>,
+}
+
+/// Only the hash of the secret is kept, the token is shown once when minted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct StoredViewerToken {
+    #[serde(flatten)]
+    pub info: ViewerToken,
+    pub secret_hash: String,
+}
+
+impl ViewerToken {
+    pub fn can_view(&self, uuid: &InstanceUuid, now: i64) -> bool {
+        !self.is_expired(now) && self.instances.contains(uuid)
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.map(|at| now >= at).unwrap_or(false)
+    }
+}
+
+/// A newly minted token and the only time its secret is readable
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct MintedViewerToken {
+    pub token: String,
+    pub info: ViewerToken,
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+pub(super) fn mint(
+    label: String,
+    instances: HashSet<InstanceUuid>,
+    created_by: UserId,
+    created_at: i64,
+    expires_at: Option<i64>,
+) -> (MintedViewerToken, StoredViewerToken) {
+    let id = rand_alphanumeric(12);
+    let secret = rand_alphanumeric(32);
+    let info = ViewerToken {
+        id: id.clone(),
+        label,
+        instances,
+        created_by,
+        created_at,
+        expires_at,
+    };
+    (
+        MintedViewerToken {
+            token: format!("{TOKEN_PREFIX}_{id}_{secret}"),
+            info: info.clone(),
+        },
+        StoredViewerToken {
+            info,
+            secret_hash: hash_secret(&secret),
+        },
+    )
+}
+
+/// The id and secret of a viewer token, `None` for any other kind of token
+pub(super) fn parse(token: &str) -> Option<(&str, &str)> {
+    let mut parts = token.splitn(3, '_');
+    if parts.next()? != TOKEN_PREFIX {
+        return None;
+    }
+    Some((parts.next()?, parts.next()?))
+}
+
+pub(super) fn secret_matches(stored: &StoredViewerToken, secret: &str) -> bool {
+    stored.secret_hash == hash_secret(secret)
+}
+
+#[test]
+fn test_viewer_token() {
+    let uuid = InstanceUuid::from("INSTANCE_1".to_string());
+    let (minted, stored) = mint(
+        "Overlay".to_string(),
+        HashSet::from([uuid.clone()]),
+        UserId::default(),
+        0,
+        Some(100),
+    );
+    let (id, secret) = parse(&minted.token).unwrap();
+    assert_eq!(id, minted.info.id);
+    assert!(secret_matches(&stored, secret));
+    assert!(!secret_matches(&stored, "guess"));
+    // the secret itself is never stored
+    assert!(!serde_json::to_string(&stored).unwrap().contains(secret));
+    assert!(parse("eyJhbGciOi.jwt.token").is_none());
+
+    assert!(minted.info.can_view(&uuid, 50));
+    assert!(!minted
+        .info
+        .can_view(&InstanceUuid::from("INSTANCE_2".to_string()), 50));
+    assert!(!minted.info.can_view(&uuid, 100));
+}
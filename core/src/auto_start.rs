@@ -0,0 +1,207 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use ts_rs::TS;
+
+use crate::disk_usage::guard_instance_disk_space;
+use crate::events::CausedBy;
+use crate::traits::{t_configurable::TConfigurable, t_server::TServer};
+use crate::types::InstanceUuid;
+use crate::AppState;
+
+/// How long to wait for an instance others depend on to finish starting
+const DEPENDENCY_START_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Where an instance goes in the boot sequence when lodestone starts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct AutoStartOrder {
+    /// Within a tier, instances with a higher priority are started first
+    #[serde(default)]
+    pub start_priority: i32,
+    /// Instances that must be running before this one is started
+    #[serde(default)]
+    pub depends_on: Vec<InstanceUuid>,
+}
+
+/// Groups instances into tiers so that every instance comes after all of its
+/// dependencies, sorted by priority within each tier.
+///
+/// Dependencies outside of `instances` are ignored. Instances that are part of
+/// (or depend on) a dependency cycle are returned separately
+pub fn auto_start_tiers(
+    instances: &[(InstanceUuid, AutoStartOrder)],
+) -> (Vec<Vec<InstanceUuid>>, Vec<InstanceUuid>) {
+    let known: HashSet<&InstanceUuid> = instances.iter().map(|(uuid, _)| uuid).collect();
+    let priorities: HashMap<&InstanceUuid, i32> = instances
+        .iter()
+        .map(|(uuid, order)| (uuid, order.start_priority))
+        .collect();
+    let mut remaining: HashMap<&InstanceUuid, HashSet<&InstanceUuid>> = instances
+        .iter()
+        .map(|(uuid, order)| {
+            (
+                uuid,
+                order
+                    .depends_on
+                    .iter()
+                    .filter(|dep| *dep != uuid && known.contains(dep))
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let mut tiers = Vec::new();
+    loop {
+        let mut tier: Vec<&InstanceUuid> = remaining
+            .iter()
+            .filter(|(_, deps)| deps.is_empty())
+            .map(|(uuid, _)| *uuid)
+            .collect();
+        if tier.is_empty() {
+            break;
+        }
+        for uuid in &tier {
+            remaining.remove(uuid);
+        }
+        for deps in remaining.values_mut() {
+            for uuid in &tier {
+                deps.remove(uuid);
+            }
+        }
+        tier.sort_by(|a, b| {
+            priorities[b]
+                .cmp(&priorities[a])
+                .then_with(|| a.to_string().cmp(&b.to_string()))
+        });
+        tiers.push(tier.into_iter().cloned().collect());
+    }
+    let mut cyclic: Vec<InstanceUuid> = remaining.into_keys().cloned().collect();
+    cyclic.sort_by_key(|uuid| uuid.to_string());
+    (tiers, cyclic)
+}
+
+/// Starts every auto start instance in dependency order, waiting `delay`
+/// between each start
+pub async fn run_auto_start(state: AppState, delay: Duration) {
+    let mut instances = Vec::new();
+    for entry in state.instances.iter() {
+        if entry.value().auto_start().await {
+            instances.push((entry.key().clone(), entry.value().auto_start_order().await));
+        }
+    }
+    let depended_on: HashSet<InstanceUuid> = instances
+        .iter()
+        .flat_map(|(_, order)| order.depends_on.iter().cloned())
+        .collect();
+    let (tiers, cyclic) = auto_start_tiers(&instances);
+    if !cyclic.is_empty() {
+        error!(
+            "Not auto starting instances {} because their dependencies form a cycle",
+            cyclic
+                .iter()
+                .map(|uuid| uuid.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let mut first = true;
+    for uuid in tiers.into_iter().flatten() {
+        let instance = match state.instances.get(&uuid) {
+            Some(v) => v.value().clone(),
+            None => continue,
+        };
+        if !first && !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        first = false;
+        info!("Auto starting instance {}", instance.name().await);
+        if let Err(e) = guard_instance_disk_space(
+            &instance,
+            state.global_settings.lock().await.min_free_disk_space_mb(),
+            &state.event_broadcaster,
+        )
+        .await
+        {
+            error!(
+                "Not auto starting instance {}: {}",
+                instance.name().await,
+                e
+            );
+            continue;
+        }
+        // instances others depend on must be fully up before moving on
+        let block = depended_on.contains(&uuid);
+        let start = instance.start(CausedBy::System, block);
+        let result = if block {
+            match tokio::time::timeout(DEPENDENCY_START_TIMEOUT, start).await {
+                Ok(v) => v,
+                Err(_) => {
+                    warn!(
+                        "Instance {} took too long to start, starting its dependents anyway",
+                        instance.name().await
+                    );
+                    Ok(())
+                }
+            }
+        } else {
+            start.await
+        };
+        if let Err(e) = result {
+            error!(
+                "Failed to start instance {}: {:?}",
+                instance.name().await,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uuid(s: &str) -> InstanceUuid {
+        InstanceUuid::from(s.to_string())
+    }
+
+    fn order(start_priority: i32, depends_on: &[&str]) -> AutoStartOrder {
+        AutoStartOrder {
+            start_priority,
+            depends_on: depends_on.iter().map(|s| uuid(s)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_auto_start_tiers() {
+        let (tiers, cyclic) = auto_start_tiers(&[
+            (uuid("backend_a"), order(0, &["proxy"])),
+            (uuid("backend_b"), order(5, &["proxy", "missing"])),
+            (uuid("proxy"), order(0, &[])),
+            (uuid("standalone"), order(1, &[])),
+        ]);
+        assert!(cyclic.is_empty());
+        assert_eq!(
+            tiers,
+            vec![
+                vec![uuid("standalone"), uuid("proxy")],
+                vec![uuid("backend_b"), uuid("backend_a")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_auto_start_tiers_cycle() {
+        let (tiers, cyclic) = auto_start_tiers(&[
+            (uuid("a"), order(0, &["b"])),
+            (uuid("b"), order(0, &["a"])),
+            (uuid("c"), order(0, &["a"])),
+            (uuid("d"), order(0, &[])),
+        ]);
+        assert_eq!(tiers, vec![vec![uuid("d")]]);
+        assert_eq!(cyclic, vec![uuid("a"), uuid("b"), uuid("c")]);
+    }
+}
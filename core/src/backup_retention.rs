@@ -0,0 +1,329 @@
+//! Grandfather-father-son retention for backup archives: the newest backup of each of the
+//! last few hours, days, weeks and months is kept, everything else is pruned
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use chrono::{Datelike, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::timezone::local_secs;
+
+/// Format of the timestamp at the end of a backup archive name, in the core's timezone
+pub const BACKUP_TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+const HOUR: i64 = 60 * 60;
+const DAY: i64 = 24 * HOUR;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct BackupPolicy {
+    /// A backup is taken when the newest one is older than this, `None` to only back up by hand
+    pub interval_hours: Option<u32>,
+    /// The newest backup of each of this many hours is kept
+    pub hourly: Option<u32>,
+    /// The newest backup of each of this many days is kept
+    pub daily: Option<u32>,
+    /// The newest backup of each of this many weeks, starting on Monday, is kept
+    pub weekly: Option<u32>,
+    /// The newest backup of each of this many months is kept
+    pub monthly: Option<u32>,
+}
+
+impl BackupPolicy {
+    /// Nothing is pruned when no tier is set
+    pub fn is_unlimited(&self) -> bool {
+        self.hourly.is_none()
+            && self.daily.is_none()
+            && self.weekly.is_none()
+            && self.monthly.is_none()
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.interval_hours == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The backup interval must be at least one hour"),
+            });
+        }
+        if [self.hourly, self.daily, self.weekly, self.monthly].contains(&Some(0)) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A retention tier must keep at least one backup, unset it instead"),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct BackupPruneReport {
+    pub kept: u32,
+    pub deleted: u32,
+    pub reclaimed_bytes: u64,
+}
+
+impl BackupPruneReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "Backup pruning kept {} and deleted {} backup(s), reclaiming {:.1} MiB",
+            self.kept,
+            self.deleted,
+            self.reclaimed_bytes as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BackupFile {
+    path: PathBuf,
+    size: u64,
+    /// When the backup was taken, in seconds since the epoch
+    created: i64,
+}
+
+/// The time in a `<name>-<timestamp>.zip` archive name, which survives the archive being
+/// copied around unlike its modification time
+pub fn timestamp_from_name(path: &Path, tz: Tz) -> Option<i64> {
+    let stem = path.file_stem()?.to_str()?;
+    let timestamp = stem.get(stem.len().checked_sub(19)?..)?;
+    let naive = NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP_FORMAT).ok()?;
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.timestamp())
+}
+
+fn collect_backups(dir: &Path, tz: Tz) -> Vec<BackupFile> {
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(_) => return Vec::new(),
+    };
+    let mut backups = Vec::new();
+    for entry in read_dir.filter_map(Result::ok) {
+        let path = entry.path();
+        let metadata = match entry.metadata() {
+            Ok(v) if v.is_file() => v,
+            _ => continue,
+        };
+        if path.extension().and_then(|ext| ext.to_str()) != Some("zip") {
+            continue;
+        }
+        let created = timestamp_from_name(&path, tz).unwrap_or_else(|| {
+            metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0)
+        });
+        backups.push(BackupFile {
+            path,
+            size: metadata.len(),
+            created,
+        });
+    }
+    backups
+}
+
+/// When the newest backup in `dir` was taken
+pub fn newest_backup_time(dir: &Path, tz: Tz) -> Option<i64> {
+    collect_backups(dir, tz)
+        .into_iter()
+        .map(|backup| backup.created)
+        .max()
+}
+
+fn month_bucket(created: i64, tz: Tz) -> i64 {
+    match tz.timestamp_opt(created, 0).single() {
+        Some(time) => time.year() as i64 * 12 + time.month0() as i64,
+        None => 0,
+    }
+}
+
+/// Marks the newest backup of each of the `count` newest buckets as kept, `created` is
+/// sorted newest first
+fn keep_per_bucket(
+    created: &[i64],
+    count: Option<u32>,
+    bucket: impl Fn(i64) -> i64,
+    kept: &mut HashSet<usize>,
+) {
+    let count = match count {
+        Some(count) => count as usize,
+        None => return,
+    };
+    let mut seen = HashSet::new();
+    for (index, created) in created.iter().enumerate() {
+        if seen.len() >= count {
+            break;
+        }
+        if seen.insert(bucket(*created)) {
+            kept.insert(index);
+        }
+    }
+}
+
+/// The backups no tier of `policy` keeps, buckets are hours, days, weeks and months in `tz`.
+/// The newest backup is always kept
+pub fn select_for_deletion<T>(
+    mut backups: Vec<T>,
+    created: impl Fn(&T) -> i64,
+    policy: &BackupPolicy,
+    tz: Tz,
+) -> Vec<T> {
+    if policy.is_unlimited() || backups.is_empty() {
+        return Vec::new();
+    }
+    backups.sort_by_key(|backup| std::cmp::Reverse(created(backup)));
+    let times: Vec<i64> = backups.iter().map(&created).collect();
+    let mut kept = HashSet::from([0]);
+    keep_per_bucket(
+        &times,
+        policy.hourly,
+        |t| local_secs(tz, t).div_euclid(HOUR),
+        &mut kept,
+    );
+    keep_per_bucket(
+        &times,
+        policy.daily,
+        |t| local_secs(tz, t).div_euclid(DAY),
+        &mut kept,
+    );
+    // the epoch was a Thursday, shift it so weeks start on Monday
+    keep_per_bucket(
+        &times,
+        policy.weekly,
+        |t| (local_secs(tz, t).div_euclid(DAY) + 3).div_euclid(7),
+        &mut kept,
+    );
+    keep_per_bucket(&times, policy.monthly, |t| month_bucket(t, tz), &mut kept);
+    backups
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !kept.contains(index))
+        .map(|(_, backup)| backup)
+        .collect()
+}
+
+fn prune_backups_blocking(dir: &Path, policy: &BackupPolicy, tz: Tz) -> BackupPruneReport {
+    let backups = collect_backups(dir, tz);
+    let total = backups.len() as u32;
+    let mut report = BackupPruneReport::default();
+    for backup in select_for_deletion(backups, |backup| backup.created, policy, tz) {
+        match std::fs::remove_file(&backup.path) {
+            Ok(_) => {
+                report.deleted += 1;
+                report.reclaimed_bytes += backup.size;
+            }
+            Err(e) => error!("Failed to remove {}: {e}", backup.path.display()),
+        }
+    }
+    report.kept = total - report.deleted;
+    report
+}
+
+/// Deletes the `.zip` backups in `dir` that `policy` no longer keeps
+pub async fn prune_backups(
+    dir: &Path,
+    policy: &BackupPolicy,
+    tz: Tz,
+) -> Result<BackupPruneReport, Error> {
+    let dir = dir.to_owned();
+    let policy = policy.clone();
+    Ok(
+        tokio::task::spawn_blocking(move || prune_backups_blocking(&dir, &policy, tz))
+            .await
+            .context("Backup pruning task panicked")?,
+    )
+}
+
+#[test]
+fn test_select_for_deletion_over_a_month() {
+    // Monday 2023-01-02 00:00 UTC, then a backup every hour for 30 days
+    let start = 1672617600;
+    let backups: Vec<BackupFile> = (0..30 * 24)
+        .map(|i| BackupFile {
+            path: PathBuf::from(i.to_string()),
+            size: 1,
+            created: start + i * HOUR,
+        })
+        .collect();
+    let policy = BackupPolicy {
+        interval_hours: Some(1),
+        hourly: Some(24),
+        daily: Some(7),
+        weekly: Some(4),
+        monthly: None,
+    };
+    let deleted: HashSet<i64> =
+        select_for_deletion(backups.clone(), |backup| backup.created, &policy, Tz::UTC)
+            .into_iter()
+            .map(|backup| (backup.created - start) / HOUR)
+            .collect();
+    let mut survivors: Vec<i64> = (0..30 * 24).filter(|i| !deleted.contains(i)).collect();
+    survivors.sort();
+
+    // the whole last day hourly, the end of each of the last 7 days, and the end of each
+    // of the last 4 weeks (the last one being the partial week of Jan 30-31)
+    let mut expected: Vec<i64> = (696..720).collect();
+    expected.extend([575, 599, 623, 647, 671, 695]);
+    expected.extend([335, 503]);
+    expected.sort();
+    assert_eq!(survivors, expected);
+
+    let monthly = BackupPolicy {
+        monthly: Some(12),
+        ..Default::default()
+    };
+    let deleted = select_for_deletion(backups.clone(), |backup| backup.created, &monthly, Tz::UTC);
+    assert_eq!(deleted.len(), 30 * 24 - 1);
+    assert!(select_for_deletion(
+        backups,
+        |backup| backup.created,
+        &BackupPolicy::default(),
+        Tz::UTC
+    )
+    .is_empty());
+}
+
+#[test]
+fn test_select_for_deletion_in_timezone() {
+    // 2023-01-02 at 22:00 and 23:30 UTC, the second is already Jan 3 in Berlin
+    let backups = vec![1672696800, 1672702200];
+    let daily = BackupPolicy {
+        daily: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(
+        select_for_deletion(backups.clone(), |t| *t, &daily, Tz::UTC),
+        vec![1672696800]
+    );
+    assert!(select_for_deletion(backups, |t| *t, &daily, chrono_tz::Europe::Berlin).is_empty());
+}
+
+#[test]
+fn test_timestamp_from_name() {
+    let naive =
+        NaiveDateTime::parse_from_str("2023-05-01_12-30-00", BACKUP_TIMESTAMP_FORMAT).unwrap();
+    assert_eq!(
+        timestamp_from_name(
+            Path::new("backups/my-world-2023-05-01_12-30-00.zip"),
+            chrono_tz::Europe::Berlin
+        ),
+        chrono_tz::Europe::Berlin
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|time| time.timestamp())
+    );
+    assert_eq!(
+        timestamp_from_name(Path::new("backups/manual.zip"), Tz::UTC),
+        None
+    );
+}
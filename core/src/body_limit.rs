@@ -0,0 +1,53 @@
+//! Request body size policy: small bodies everywhere except the routes that take uploads,
+//! which lift the limit with their own `DefaultBodyLimit` layer
+
+use axum::{
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::eyre;
+
+use crate::error::{Error, ErrorKind};
+
+/// Largest JSON, text or form body accepted by routes without a limit of their own
+pub const DEFAULT_BODY_LIMIT: usize = 1024 * 1024;
+
+fn describe_limit(limit: usize) -> String {
+    if limit >= 1024 * 1024 && limit % (1024 * 1024) == 0 {
+        format!("{} MiB", limit / (1024 * 1024))
+    } else if limit >= 1024 && limit % 1024 == 0 {
+        format!("{} KiB", limit / 1024)
+    } else {
+        format!("{} bytes", limit)
+    }
+}
+
+/// Turns axum's plain text "length limit exceeded" rejection into an API error that states
+/// `limit`, the `DefaultBodyLimit` the routes behind this middleware were given
+pub async fn payload_too_large_middleware<B>(
+    State(limit): State<usize>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let response = next.run(request).await;
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE {
+        return response;
+    }
+    Error {
+        kind: ErrorKind::PayloadTooLarge,
+        source: eyre!(
+            "Request body is too large, this endpoint accepts at most {}",
+            describe_limit(limit)
+        ),
+    }
+    .into_response()
+}
+
+#[test]
+fn test_describe_limit() {
+    assert_eq!(describe_limit(DEFAULT_BODY_LIMIT), "1 MiB");
+    assert_eq!(describe_limit(64 * 1024), "64 KiB");
+    assert_eq!(describe_limit(1500), "1500 bytes");
+}
@@ -0,0 +1,114 @@
+//! Branding the frontend shows in place of Lodestone's, so a host can white-label the panel
+//! without forking it
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct Branding {
+    /// Shown in place of "Lodestone"
+    pub display_name: String,
+    /// http(s) URL of the logo, `None` for Lodestone's
+    pub logo_url: Option<String>,
+    /// `#rrggbb`
+    pub accent_color: String,
+    /// Where users are sent for help, `None` hides the link
+    pub support_url: Option<String>,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            display_name: "Lodestone".to_string(),
+            logo_url: None,
+            accent_color: "#1D8EB2".to_string(),
+            support_url: Some("https://www.lodestone.cc/".to_string()),
+        }
+    }
+}
+
+fn validate_url(url: &str, what: &str) -> Result<(), Error> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid {what}: {e}"),
+    })?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The {what} must be an http or https URL"),
+        });
+    }
+    Ok(())
+}
+
+impl Branding {
+    pub fn validate(&self) -> Result<(), Error> {
+        let display_name = self.display_name.trim();
+        if display_name.is_empty()
+            || display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH
+            || display_name.chars().any(char::is_control)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "The display name must be 1 to {MAX_DISPLAY_NAME_LENGTH} characters without line breaks"
+                ),
+            });
+        }
+        let color = self.accent_color.as_bytes();
+        if color.len() != 7 || color[0] != b'#' || !color[1..].iter().all(u8::is_ascii_hexdigit) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "The accent color must be a hex color like #1D8EB2, got {}",
+                    self.accent_color
+                ),
+            });
+        }
+        if let Some(logo_url) = &self.logo_url {
+            validate_url(logo_url, "logo URL")?;
+        }
+        if let Some(support_url) = &self.support_url {
+            validate_url(support_url, "support link")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_validate_branding() {
+    assert!(Branding::default().validate().is_ok());
+    let branded = Branding {
+        display_name: "Acme Hosting".to_string(),
+        logo_url: Some("https://cdn.acme.example/logo.svg".to_string()),
+        accent_color: "#ff6600".to_string(),
+        support_url: None,
+    };
+    assert!(branded.validate().is_ok());
+    for accent_color in ["ff6600", "#f60", "#gg6600", "#ff66001"] {
+        assert!(Branding {
+            accent_color: accent_color.to_string(),
+            ..branded.clone()
+        }
+        .validate()
+        .is_err());
+    }
+    assert!(Branding {
+        logo_url: Some("javascript:alert(1)".to_string()),
+        ..branded.clone()
+    }
+    .validate()
+    .is_err());
+    assert!(Branding {
+        display_name: " ".to_string(),
+        ..branded
+    }
+    .validate()
+    .is_err());
+}
@@ -0,0 +1,155 @@
+//! Resource limits of the cgroup lodestone runs in, so that inside a container the
+//! container's limits are used instead of the host's
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// cgroup v1 reports "no limit" as a huge number rounded down to the page size
+const V1_UNLIMITED_THRESHOLD: u64 = 1 << 62;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct CgroupLimits {
+    /// Bytes, `None` if unlimited
+    pub memory_limit: Option<u64>,
+    /// Bytes currently charged to the cgroup
+    pub memory_usage: Option<u64>,
+    /// Number of CPUs worth of time the cgroup may use, `None` if unlimited
+    pub cpu_limit: Option<f64>,
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// `memory.max`, either `max` or a number of bytes
+fn parse_v2_memory_max(content: &str) -> Option<u64> {
+    content.trim().parse().ok()
+}
+
+/// `cpu.max`, `$MAX $PERIOD` where `$MAX` may be `max`
+fn parse_v2_cpu_max(content: &str) -> Option<f64> {
+    let mut parts = content.split_whitespace();
+    let quota: f64 = parts.next()?.parse().ok()?;
+    let period: f64 = parts.next()?.parse().ok()?;
+    (period > 0.0).then(|| quota / period)
+}
+
+fn parse_v1_memory_limit(content: &str) -> Option<u64> {
+    content
+        .trim()
+        .parse()
+        .ok()
+        .filter(|limit| *limit < V1_UNLIMITED_THRESHOLD)
+}
+
+/// `cpu.cfs_quota_us` is -1 when unlimited
+fn parse_v1_cpu(quota: &str, period: &str) -> Option<f64> {
+    let quota: i64 = quota.trim().parse().ok()?;
+    let period: i64 = period.trim().parse().ok()?;
+    (quota > 0 && period > 0).then(|| quota as f64 / period as f64)
+}
+
+/// Reads the limits of the cgroup mounted at `root`, trying the unified (v2) hierarchy first
+pub fn read_cgroup_limits(root: &Path) -> Option<CgroupLimits> {
+    if let Some(memory_max) = read_trimmed(&root.join("memory.max")) {
+        return Some(CgroupLimits {
+            memory_limit: parse_v2_memory_max(&memory_max),
+            memory_usage: read_trimmed(&root.join("memory.current")).and_then(|s| s.parse().ok()),
+            cpu_limit: read_trimmed(&root.join("cpu.max")).and_then(|s| parse_v2_cpu_max(&s)),
+        });
+    }
+    let memory_limit = read_trimmed(&root.join("memory/memory.limit_in_bytes"))?;
+    Some(CgroupLimits {
+        memory_limit: parse_v1_memory_limit(&memory_limit),
+        memory_usage: read_trimmed(&root.join("memory/memory.usage_in_bytes"))
+            .and_then(|s| s.parse().ok()),
+        cpu_limit: match (
+            read_trimmed(&root.join("cpu/cpu.cfs_quota_us")),
+            read_trimmed(&root.join("cpu/cpu.cfs_period_us")),
+        ) {
+            (Some(quota), Some(period)) => parse_v1_cpu(&quota, &period),
+            _ => None,
+        },
+    })
+}
+
+/// Limits of the cgroup lodestone runs in, `None` if there is none (e.g. not on Linux)
+pub fn cgroup_limits() -> Option<CgroupLimits> {
+    if cfg!(target_os = "linux") {
+        read_cgroup_limits(Path::new(CGROUP_ROOT))
+    } else {
+        None
+    }
+}
+
+/// Total and available memory, limited by the cgroup if its limit is lower than the host's
+pub fn effective_memory(
+    limits: Option<&CgroupLimits>,
+    host_total: u64,
+    host_available: u64,
+) -> (u64, u64) {
+    match limits {
+        Some(CgroupLimits {
+            memory_limit: Some(limit),
+            memory_usage,
+            ..
+        }) if *limit < host_total => {
+            let available = limit.saturating_sub(memory_usage.unwrap_or(0));
+            (*limit, available.min(host_available))
+        }
+        _ => (host_total, host_available),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limits() {
+        assert_eq!(parse_v2_memory_max("max\n"), None);
+        assert_eq!(parse_v2_memory_max("2147483648\n"), Some(2147483648));
+        assert_eq!(parse_v2_cpu_max("max 100000"), None);
+        assert_eq!(parse_v2_cpu_max("150000 100000"), Some(1.5));
+        assert_eq!(parse_v1_memory_limit("9223372036854771712"), None);
+        assert_eq!(parse_v1_memory_limit("1073741824"), Some(1073741824));
+        assert_eq!(parse_v1_cpu("-1", "100000"), None);
+        assert_eq!(parse_v1_cpu("200000", "100000"), Some(2.0));
+    }
+
+    #[test]
+    fn test_read_cgroup_v2() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("memory.max"), "4294967296\n").unwrap();
+        std::fs::write(root.path().join("memory.current"), "1073741824\n").unwrap();
+        std::fs::write(root.path().join("cpu.max"), "200000 100000\n").unwrap();
+        let limits = read_cgroup_limits(root.path()).unwrap();
+        assert_eq!(limits.memory_limit, Some(4294967296));
+        assert_eq!(limits.cpu_limit, Some(2.0));
+
+        let gib = 1024 * 1024 * 1024;
+        assert_eq!(
+            effective_memory(Some(&limits), 16 * gib, 12 * gib),
+            (4 * gib, 3 * gib)
+        );
+        // a limit above the host's memory doesn't constrain anything
+        assert_eq!(
+            effective_memory(Some(&limits), 2 * gib, gib),
+            (2 * gib, gib)
+        );
+    }
+
+    #[test]
+    fn test_no_cgroup() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(read_cgroup_limits(root.path()), None);
+        assert_eq!(effective_memory(None, 100, 50), (100, 50));
+    }
+}
@@ -0,0 +1,262 @@
+//! Console commands each role may send, so staff without the owner role can be kept from
+//! commands like `stop` or `op`
+//!
+//! The files a server keeps the result of some commands in, like `ops.json` for `op`, are held
+//! to the same rules so a denied command can't be worked around by editing the file
+
+use std::path::Path;
+
+use color_eyre::eyre::eyre;
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::user::User,
+    error::{Error, ErrorKind},
+};
+
+/// Files in the instance root and the commands that change them
+const COMMAND_FILES: &[(&str, &[&str])] = &[
+    ("ops.json", &["op", "deop"]),
+    ("whitelist.json", &["whitelist"]),
+    ("banned-players.json", &["ban", "pardon"]),
+    ("banned-ips.json", &["ban-ip", "pardon-ip"]),
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum CommandRule {
+    /// Matches the command if it starts with these words, case insensitive, so `op` matches
+    /// `op Steve` but not `openinv`
+    Prefix { prefix: String },
+    /// Matches the command if the regex matches anywhere in it, the command is lowercased and
+    /// without the leading slash and `minecraft:` namespace
+    Regex { pattern: String },
+}
+
+impl CommandRule {
+    /// `command` is already normalized
+    fn matches(&self, command: &str) -> bool {
+        match self {
+            CommandRule::Prefix { prefix } => {
+                let prefix = normalize(prefix);
+                command == prefix || command.starts_with(&format!("{prefix} "))
+            }
+            CommandRule::Regex { pattern } => Regex::new(pattern)
+                .and_then(|regex| regex.is_match(command))
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Without the leading slash, the `minecraft:` namespace, surrounding whitespace and runs of
+/// spaces, lowercased
+fn normalize(command: &str) -> String {
+    let command = command
+        .trim()
+        .trim_start_matches('/')
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    match command.strip_prefix("minecraft:") {
+        Some(command) => command.to_string(),
+        None => command,
+    }
+}
+
+/// The normalized command, followed by the ones it runs through `execute … run`, so
+/// `execute as @s run op Steve` can't get around a rule for `op`
+fn nested_commands(command: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut command = normalize(command);
+    loop {
+        let inner = if command.starts_with("execute ") {
+            command
+                .split_once(" run ")
+                .map(|(_, inner)| normalize(inner))
+        } else {
+            None
+        };
+        commands.push(command);
+        match inner {
+            Some(inner) => command = inner,
+            None => return commands,
+        }
+    }
+}
+
+/// A command is allowed if it matches an allow rule, or there are none, and no deny rule
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct CommandFilter {
+    pub allow: Vec<CommandRule>,
+    pub deny: Vec<CommandRule>,
+}
+
+impl CommandFilter {
+    /// Whether `command` and every command it runs through `execute` are allowed
+    pub fn is_allowed(&self, command: &str) -> bool {
+        nested_commands(command).iter().all(|command| {
+            (self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches(command)))
+                && !self.deny.iter().any(|rule| rule.matches(command))
+        })
+    }
+}
+
+/// The filter of each role, owners can always send any command
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct CommandFilters {
+    pub admin: CommandFilter,
+    /// Users that are neither owner nor admin
+    pub user: CommandFilter,
+}
+
+impl CommandFilters {
+    pub fn validate(&self) -> Result<(), Error> {
+        for rule in [&self.admin, &self.user]
+            .into_iter()
+            .flat_map(|filter| filter.allow.iter().chain(filter.deny.iter()))
+        {
+            match rule {
+                CommandRule::Regex { pattern } => {
+                    if let Err(e) = Regex::new(pattern) {
+                        return Err(Error {
+                            kind: ErrorKind::BadRequest,
+                            source: eyre!("Invalid command filter regex {pattern}: {e}"),
+                        });
+                    }
+                }
+                CommandRule::Prefix { prefix } => {
+                    if normalize(prefix).is_empty() {
+                        return Err(Error {
+                            kind: ErrorKind::BadRequest,
+                            source: eyre!("A command filter prefix can't be empty"),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn filter_of(&self, user: &User) -> Option<&CommandFilter> {
+        if user.is_owner {
+            None
+        } else if user.is_admin {
+            Some(&self.admin)
+        } else {
+            Some(&self.user)
+        }
+    }
+
+    pub fn check_command(&self, user: &User, command: &str) -> Result<(), Error> {
+        match self.filter_of(user) {
+            Some(filter) if !filter.is_allowed(command) => Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Your role isn't allowed to run this command"),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a change to the file at `relative_path` in an instance against the commands
+    /// that change the same file
+    pub fn check_file(&self, user: &User, relative_path: &Path) -> Result<(), Error> {
+        let filter = match self.filter_of(user) {
+            Some(filter) => filter,
+            None => return Ok(()),
+        };
+        let file_name = match relative_path.to_str() {
+            Some(file_name) => file_name.trim_start_matches(['/', '\\']),
+            None => return Ok(()),
+        };
+        match COMMAND_FILES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(file_name))
+        {
+            Some((name, commands)) if !commands.iter().all(|c| filter.is_allowed(c)) => {
+                Err(Error {
+                    kind: ErrorKind::PermissionDenied,
+                    source: eyre!(
+                        "Your role isn't allowed to run {}, so it can't change {name} either",
+                        commands.join(" or ")
+                    ),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_command_filter() {
+    let filter = CommandFilter {
+        allow: Vec::new(),
+        deny: vec![
+            CommandRule::Prefix {
+                prefix: "/op".to_string(),
+            },
+            CommandRule::Regex {
+                pattern: r"^(stop|restart)\b".to_string(),
+            },
+        ],
+    };
+    assert!(!filter.is_allowed("op Steve"));
+    assert!(!filter.is_allowed("/OP   Steve"));
+    assert!(filter.is_allowed("openinv Steve"));
+    assert!(!filter.is_allowed(" /stop"));
+    assert!(filter.is_allowed("stopsound Steve"));
+    assert!(!filter.is_allowed("/minecraft:op Steve"));
+    assert!(!filter.is_allowed("execute as @a run op Steve"));
+    assert!(!filter.is_allowed("execute as @a at @s run execute run minecraft:stop"));
+    assert!(filter.is_allowed("execute as @a run say hi"));
+
+    let allow_list = CommandFilter {
+        allow: vec![CommandRule::Prefix {
+            prefix: "say".to_string(),
+        }],
+        deny: Vec::new(),
+    };
+    assert!(allow_list.is_allowed("say hi"));
+    assert!(!allow_list.is_allowed("list"));
+}
+
+#[test]
+fn test_command_filter_files() {
+    let filters = CommandFilters {
+        admin: CommandFilter::default(),
+        user: CommandFilter {
+            allow: Vec::new(),
+            deny: vec![CommandRule::Prefix {
+                prefix: "deop".to_string(),
+            }],
+        },
+    };
+    let user = |is_owner, is_admin| {
+        User::new(
+            "test".to_string(),
+            "password",
+            is_owner,
+            is_admin,
+            crate::auth::permission::UserPermission::default(),
+        )
+    };
+    let ops = Path::new("ops.json");
+    assert!(filters.check_file(&user(false, false), ops).is_err());
+    assert!(filters.check_file(&user(false, true), ops).is_ok());
+    assert!(filters.check_file(&user(true, false), ops).is_ok());
+    assert!(filters
+        .check_file(&user(false, false), Path::new("whitelist.json"))
+        .is_ok());
+    // only the one in the instance root is read by the server
+    assert!(filters
+        .check_file(&user(false, false), Path::new("world/ops.json"))
+        .is_ok());
+    assert!(filters
+        .check_command(&user(false, false), "deop Steve")
+        .is_err());
+}
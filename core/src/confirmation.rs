@@ -0,0 +1,75 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::eyre;
+use dashmap::DashMap;
+
+use crate::{
+    auth::user_id::UserId,
+    error::{Error, ErrorKind},
+    util::rand_alphanumeric,
+};
+
+/// How long a user has to confirm a destructive operation after asking for it
+pub const CONFIRMATION_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Single use tokens a destructive operation has to be confirmed with, so it takes a
+/// deliberate second request instead of one misclick
+///
+/// A token is only valid for the user and the action it was issued for
+#[derive(Debug, Clone, Default)]
+pub struct ConfirmationTokens {
+    entries: Arc<DashMap<String, (Instant, UserId, String)>>,
+}
+
+impl ConfirmationTokens {
+    pub fn issue(&self, user: &UserId, action: &str) -> String {
+        self.entries
+            .retain(|_, (issued_at, _, _)| issued_at.elapsed() < CONFIRMATION_TTL);
+        let token = rand_alphanumeric(32);
+        self.entries.insert(
+            token.clone(),
+            (Instant::now(), user.clone(), action.to_owned()),
+        );
+        token
+    }
+
+    /// Uses up `token`, failing if it wasn't issued to `user` for `action` or has expired
+    pub fn redeem(&self, user: &UserId, action: &str, token: &str) -> Result<(), Error> {
+        match self
+            .entries
+            .remove_if(token, |_, (_, issued_to, issued_for)| {
+                issued_to == user && issued_for == action
+            }) {
+            Some((_, (issued_at, _, _))) if issued_at.elapsed() < CONFIRMATION_TTL => Ok(()),
+            _ => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid or expired confirmation token, request a new one"),
+            }),
+        }
+    }
+}
+
+#[test]
+fn test_confirmation_tokens() {
+    let tokens = ConfirmationTokens::default();
+    let user = UserId::from("user".to_string());
+    let other = UserId::from("other".to_string());
+
+    let token = tokens.issue(&user, "world_reset:INSTANCE_1");
+    assert!(tokens
+        .redeem(&other, "world_reset:INSTANCE_1", &token)
+        .is_err());
+    assert!(tokens
+        .redeem(&user, "world_reset:INSTANCE_2", &token)
+        .is_err());
+    assert!(tokens
+        .redeem(&user, "world_reset:INSTANCE_1", &token)
+        .is_ok());
+    // single use
+    assert!(tokens
+        .redeem(&user, "world_reset:INSTANCE_1", &token)
+        .is_err());
+}
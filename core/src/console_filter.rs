@@ -0,0 +1,186 @@
+//! Named regex filters over console output, applied when the console is read or streamed
+//! so the stored output stays complete
+
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::eyre;
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{Event, EventInner, InstanceEventInner},
+};
+
+const MAX_PRESETS: usize = 32;
+const MAX_PRESET_NAME_LEN: usize = 32;
+const MAX_PATTERNS: usize = 16;
+const MAX_PATTERN_LEN: usize = 512;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ConsoleFilter {
+    /// A line is shown only if it matches one of these, every line if empty
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// A line matching any of these is hidden, even if it is included
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// Console filter presets of an instance by name, e.g. "hide-chat" or "errors-only"
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(transparent)]
+#[ts(export)]
+pub struct ConsoleFilters {
+    pub presets: BTreeMap<String, ConsoleFilter>,
+}
+
+impl ConsoleFilters {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.presets.len() > MAX_PRESETS {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "An instance can have at most {} console filters",
+                    MAX_PRESETS
+                ),
+            });
+        }
+        for (name, filter) in &self.presets {
+            if name.is_empty()
+                || name.len() > MAX_PRESET_NAME_LEN
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Invalid console filter name {}, use up to {} letters, digits, - and _",
+                        name,
+                        MAX_PRESET_NAME_LEN
+                    ),
+                });
+            }
+            CompiledConsoleFilter::new(filter).map_err(|e| Error {
+                kind: e.kind,
+                source: e
+                    .source
+                    .wrap_err(format!("Invalid console filter {}", name)),
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn compile(&self, name: &str) -> Result<CompiledConsoleFilter, Error> {
+        let filter = self.presets.get(name).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Console filter {} not found", name),
+        })?;
+        CompiledConsoleFilter::new(filter)
+    }
+}
+
+pub struct CompiledConsoleFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<Vec<Regex>, Error> {
+    if patterns.len() > MAX_PATTERNS {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "A console filter can have at most {} patterns",
+                MAX_PATTERNS
+            ),
+        });
+    }
+    patterns
+        .iter()
+        .map(|pattern| {
+            if pattern.len() > MAX_PATTERN_LEN {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Console filter patterns can be at most {} bytes",
+                        MAX_PATTERN_LEN
+                    ),
+                });
+            }
+            Regex::new(pattern).map_err(|e| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid pattern {}: {}", pattern, e),
+            })
+        })
+        .collect()
+}
+
+impl CompiledConsoleFilter {
+    pub fn new(filter: &ConsoleFilter) -> Result<Self, Error> {
+        Ok(Self {
+            include: compile_patterns(&filter.include)?,
+            exclude: compile_patterns(&filter.exclude)?,
+        })
+    }
+
+    /// A pattern that fails to run, e.g. by backtracking too much, doesn't match
+    fn matches_any(patterns: &[Regex], line: &str) -> bool {
+        patterns.iter().any(|re| re.is_match(line).unwrap_or(false))
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        (self.include.is_empty() || Self::matches_any(&self.include, line))
+            && !Self::matches_any(&self.exclude, line)
+    }
+
+    /// Whether a console event passes the filter, events other than console lines always do
+    pub fn is_event_match(&self, event: &Event) -> bool {
+        let instance_event = match &event.event_inner {
+            EventInner::InstanceEvent(instance_event) => instance_event,
+            _ => return true,
+        };
+        match &instance_event.instance_event_inner {
+            InstanceEventInner::InstanceOutput { message, .. }
+            | InstanceEventInner::SystemMessage { message } => self.is_match(message),
+            InstanceEventInner::PlayerMessage {
+                player,
+                player_message,
+            } => self.is_match(&format!("<{}> {}", player, player_message)),
+            _ => true,
+        }
+    }
+}
+
+#[test]
+fn test_console_filter() {
+    let hide_chat = CompiledConsoleFilter::new(&ConsoleFilter {
+        include: vec![],
+        exclude: vec![r"<\w+> ".to_string()],
+    })
+    .unwrap();
+    assert!(!hide_chat.is_match("[12:00:00] [Server thread/INFO]: <Steve> hello"));
+    assert!(hide_chat.is_match("[12:00:00] [Server thread/INFO]: Steve joined the game"));
+
+    let errors_only = CompiledConsoleFilter::new(&ConsoleFilter {
+        include: vec!["/(WARN|ERROR)\\]".to_string()],
+        exclude: vec!["Can't keep up".to_string()],
+    })
+    .unwrap();
+    assert!(errors_only.is_match("[12:00:00] [Server thread/ERROR]: Failed to save chunk"));
+    assert!(!errors_only.is_match("[12:00:00] [Server thread/INFO]: Done (3.2s)!"));
+    assert!(!errors_only.is_match("[12:00:00] [Server thread/WARN]: Can't keep up!"));
+
+    let mut filters = ConsoleFilters::default();
+    filters.presets.insert(
+        "broken".to_string(),
+        ConsoleFilter {
+            include: vec!["(".to_string()],
+            exclude: vec![],
+        },
+    );
+    assert!(filters.validate().is_err());
+    assert!(filters.compile("missing").is_err());
+}
@@ -0,0 +1,97 @@
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{field, info_span, Instrument};
+
+use crate::{util::rand_alphanumeric, AppState};
+
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+/// Correlation id of the request currently being handled, if any
+pub fn current_correlation_id() -> Option<String> {
+    CORRELATION_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Instance uuid targeted by a request, taken from a `/instance/:uuid/...` path
+fn instance_uuid_from_path(path: &str) -> Option<&str> {
+    let mut segments = path.split('/');
+    segments.find(|segment| *segment == "instance")?;
+    segments
+        .next()
+        .filter(|segment| segment.starts_with("INSTANCE_"))
+}
+
+/// Assigns every request a correlation id and runs it inside a span carrying
+/// the id, the authenticated user and the target instance, so all logs of one
+/// operation can be tied together
+///
+/// A client supplied `x-correlation-id` is reused if it looks sane
+pub async fn correlation_id_middleware<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let correlation_id = request
+        .headers()
+        .get(CORRELATION_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| {
+            !v.is_empty()
+                && v.len() <= 64
+                && v.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        .map(|v| v.to_owned())
+        .unwrap_or_else(|| rand_alphanumeric(16));
+
+    let span = info_span!(
+        "request",
+        correlation_id = %correlation_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        user = field::Empty,
+        instance = field::Empty,
+    );
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if let Some(token) = token {
+        if let Some(user) = state.users_manager.read().await.try_auth(token) {
+            span.record("user", field::display(&user.username));
+        }
+    }
+    if let Some(instance_uuid) = instance_uuid_from_path(request.uri().path()) {
+        span.record("instance", instance_uuid);
+    }
+
+    let mut response = CORRELATION_ID
+        .scope(correlation_id.clone(), next.run(request).instrument(span))
+        .await;
+    if let Ok(v) = HeaderValue::from_str(&correlation_id) {
+        response.headers_mut().insert(CORRELATION_ID_HEADER, v);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::instance_uuid_from_path;
+
+    #[test]
+    fn test_instance_uuid_from_path() {
+        assert_eq!(
+            instance_uuid_from_path("/api/v1/instance/INSTANCE_abc/console"),
+            Some("INSTANCE_abc")
+        );
+        assert_eq!(instance_uuid_from_path("/api/v1/instance/list"), None);
+        assert_eq!(instance_uuid_from_path("/api/v1/system/ram"), None);
+    }
+}
@@ -0,0 +1,94 @@
+//! Exclusive lock on the data directory, so two cores never share one
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use fs3::FileExt;
+use tracing::{error, warn};
+
+use crate::error::{Error, ErrorKind};
+
+const LOCK_FILE_NAME: &str = "lodestone.lock";
+
+/// Held for as long as the core runs. The OS drops the lock when the process dies, so a
+/// crashed core never blocks the next start
+pub struct DataDirLock {
+    file: File,
+    path: PathBuf,
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+impl DataDirLock {
+    /// Locks `data_dir` and records our PID, failing if another process holds the lock
+    pub fn acquire(data_dir: &Path) -> Result<Self, Error> {
+        let path = data_dir.join(LOCK_FILE_NAME);
+        // not truncated before locking, the PID of a running holder must survive
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .context(format!("Failed to open lock file {}", path.display()))?;
+        if file.try_lock_exclusive().is_err() {
+            let holder = read_pid(&mut file)
+                .map(|pid| format!(" (PID {pid})"))
+                .unwrap_or_default();
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!(
+                    "Another instance of lodestone{} is using {}, stop it before starting another one",
+                    holder,
+                    data_dir.display()
+                ),
+            });
+        }
+        if let Some(pid) = read_pid(&mut file) {
+            warn!(
+                "Lodestone (PID {pid}) did not shut down cleanly last time, taking over its lock"
+            );
+        }
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| write!(file, "{}", std::process::id()))
+            .and_then(|_| file.flush())
+            .context(format!("Failed to write lock file {}", path.display()))?;
+        Ok(Self { file, path })
+    }
+
+    /// Releases the lock on a clean shutdown, leaving no PID behind
+    pub fn release(self) {
+        if let Err(e) = self.file.set_len(0) {
+            error!("Failed to clear lock file {}: {}", self.path.display(), e);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+#[test]
+fn test_data_dir_lock() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let lock = DataDirLock::acquire(data_dir.path()).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(data_dir.path().join(LOCK_FILE_NAME)).unwrap(),
+        std::process::id().to_string()
+    );
+    lock.release();
+    assert!(
+        std::fs::read_to_string(data_dir.path().join(LOCK_FILE_NAME))
+            .unwrap()
+            .is_empty()
+    );
+    // a lock left behind by a crashed process is taken over
+    std::fs::write(data_dir.path().join(LOCK_FILE_NAME), "999999").unwrap();
+    DataDirLock::acquire(data_dir.path()).unwrap().release();
+}
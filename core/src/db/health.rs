@@ -0,0 +1,167 @@
+//! Whether the database can be used. Lodestone keeps managing instances without it, the
+//! features backed by it (event persistence and the various histories) are off until the
+//! database can be opened again
+use std::{
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use tracing::{error, info};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+use super::migrations::{migrate, MigrationError};
+
+/// How long a query waits for a connection, short so a broken database doesn't hang requests
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct DbStatus {
+    pub available: bool,
+    /// Why the database is unavailable
+    pub error: Option<String>,
+    pub unavailable_since: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DbHealth {
+    status: Arc<Mutex<DbStatus>>,
+}
+
+impl DbHealth {
+    pub fn status(&self) -> DbStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.status.lock().unwrap().available
+    }
+
+    /// Errors if the database is unavailable, for features that can't work without it
+    pub fn ensure_available(&self) -> Result<(), Error> {
+        let status = self.status();
+        if status.available {
+            return Ok(());
+        }
+        Err(Error {
+            kind: ErrorKind::ServiceUnavailable,
+            source: eyre!(
+                "The database is unavailable: {}",
+                status.error.unwrap_or_else(|| "not opened yet".to_string())
+            ),
+        })
+    }
+
+    /// Returns whether the database was unavailable before
+    pub fn mark_available(&self) -> bool {
+        let mut status = self.status.lock().unwrap();
+        let was_unavailable = !status.available;
+        *status = DbStatus {
+            available: true,
+            error: None,
+            unavailable_since: None,
+        };
+        was_unavailable
+    }
+
+    pub fn mark_unavailable(&self, error: &Error) {
+        let mut status = self.status.lock().unwrap();
+        if status.available {
+            error!(
+                "The database became unavailable, history and event persistence are disabled: {}",
+                error
+            );
+        }
+        status.available = false;
+        status.error = Some(error.source.to_string());
+        status.unavailable_since = status
+            .unavailable_since
+            .or_else(|| Some(chrono::Utc::now().timestamp()));
+    }
+}
+
+/// A pool that only connects once it is used, so a locked or corrupt database file doesn't
+/// stop lodestone from starting
+pub fn lazy_pool(path: &Path) -> Result<SqlitePool, Error> {
+    let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path.display()))
+        .context("Failed to create sqlite connection options")?
+        .create_if_missing(true);
+    Ok(SqlitePoolOptions::new()
+        .acquire_timeout(ACQUIRE_TIMEOUT)
+        .connect_lazy_with(options))
+}
+
+/// Opens and migrates the database, marking it unavailable if it can't be read
+///
+/// Only a failed migration is an error, lodestone must not run against a schema it doesn't know
+pub async fn open(pool: &SqlitePool, health: &DbHealth) -> Result<(), Error> {
+    match migrate(pool).await {
+        Ok(_) => {
+            health.mark_available();
+            Ok(())
+        }
+        Err(MigrationError::Unavailable(e)) => {
+            error!(
+                "Failed to open the database, continuing without history and event persistence: {}",
+                e
+            );
+            health.mark_unavailable(&e);
+            Ok(())
+        }
+        Err(MigrationError::Failed(e)) => Err(e),
+    }
+}
+
+/// Tries to open the database again while it is unavailable
+pub async fn reconnect_task(pool: SqlitePool, health: DbHealth) {
+    let mut interval = tokio::time::interval(RECONNECT_INTERVAL);
+    loop {
+        interval.tick().await;
+        if health.is_available() {
+            continue;
+        }
+        match migrate(&pool).await {
+            Ok(_) => {
+                if health.mark_available() {
+                    info!("The database is available again");
+                }
+            }
+            Err(MigrationError::Unavailable(e)) => health.mark_unavailable(&e),
+            // stays unavailable, the schema is left as it was before the failed migration
+            Err(MigrationError::Failed(e)) => {
+                error!("{:?}", e.source);
+                health.mark_unavailable(&e);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_db_health_transitions() {
+    let health = DbHealth::default();
+    assert!(health.ensure_available().is_err());
+    health.mark_unavailable(&Error {
+        kind: ErrorKind::Internal,
+        source: eyre!("database is locked"),
+    });
+    let since = health.status().unavailable_since;
+    assert!(since.is_some());
+    health.mark_unavailable(&Error {
+        kind: ErrorKind::Internal,
+        source: eyre!("still locked"),
+    });
+    // the first failure is kept as the start of the outage
+    assert_eq!(health.status().unavailable_since, since);
+    assert_eq!(health.status().error.as_deref(), Some("still locked"));
+    assert!(health.mark_available());
+    assert!(!health.mark_available());
+    assert!(health.ensure_available().is_ok());
+}
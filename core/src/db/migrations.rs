@@ -0,0 +1,254 @@
+//! Versioned schema migrations, applied in order when lodestone opens the database
+//!
+//! A migration is never edited once released, a schema change is a new file in `migrations`
+//! added to the end of [`MIGRATIONS`]. Each one runs in a transaction together with recording
+//! its version, so a failed migration leaves the schema as it was before it
+use color_eyre::eyre::{eyre, Context};
+use sqlx::{sqlite::SqlitePool, Row};
+use tracing::info;
+
+use crate::error::{Error, ErrorKind};
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// Databases created before migrations existed have some of these tables already, so every
+/// migration up to 6 only creates what is missing
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "client events",
+        sql: include_str!("../../migrations/0001_client_events.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "client events instance index",
+        sql: include_str!("../../migrations/0002_client_events_instance_index.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "global settings changes",
+        sql: include_str!("../../migrations/0003_global_settings_changes.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "console history",
+        sql: include_str!("../../migrations/0004_console_history.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "monitor samples",
+        sql: include_str!("../../migrations/0005_monitor_samples.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "sessions",
+        sql: include_str!("../../migrations/0006_sessions.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "client events snowflake index",
+        sql: include_str!("../../migrations/0007_client_events_snowflake_index.sql"),
+    },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    /// The database couldn't be opened or read, nothing was changed
+    Unavailable(Error),
+    /// A migration failed and was rolled back, the schema is at the last version that applied
+    Failed(Error),
+}
+
+/// Applies the migrations the database doesn't have yet, returns how many were applied
+pub async fn migrate(pool: &SqlitePool) -> Result<u32, MigrationError> {
+    run_migrations(pool, MIGRATIONS).await
+}
+
+async fn applied_versions(pool: &SqlitePool) -> Result<Vec<i64>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS SchemaMigrations (
+            version             INTEGER     PRIMARY KEY,
+            description         TEXT        NOT NULL,
+            applied_at          BIGINT      NOT NULL
+        );
+        "#,
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to create migrations table")?;
+    Ok(sqlx::query("SELECT version FROM SchemaMigrations")
+        .fetch_all(&mut connection)
+        .await
+        .context("Failed to read applied migrations")?
+        .iter()
+        .map(|row| row.get("version"))
+        .collect())
+}
+
+async fn apply(pool: &SqlitePool, migration: &Migration) -> Result<(), Error> {
+    let mut transaction = pool.begin().await.context("Failed to start transaction")?;
+    sqlx::Executor::execute(&mut transaction, migration.sql)
+        .await
+        .context("Failed to run migration")?;
+    sqlx::query(
+        r#"
+INSERT INTO SchemaMigrations
+(version, description, applied_at)
+VALUES
+(?1, ?2, ?3)
+        "#,
+    )
+    .bind(migration.version)
+    .bind(migration.description)
+    .bind(chrono::Utc::now().timestamp())
+    .execute(&mut transaction)
+    .await
+    .context("Failed to record migration")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    Ok(())
+}
+
+async fn run_migrations(
+    pool: &SqlitePool,
+    migrations: &[Migration],
+) -> Result<u32, MigrationError> {
+    let applied = applied_versions(pool)
+        .await
+        .map_err(MigrationError::Unavailable)?;
+    let latest = migrations.iter().map(|m| m.version).max().unwrap_or(0);
+    if let Some(newer) = applied.iter().find(|version| **version > latest) {
+        return Err(MigrationError::Failed(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "The database is at schema version {newer}, newer than the {latest} this version of lodestone supports. Upgrade lodestone or restore an older database"
+            ),
+        }));
+    }
+    let mut count = 0;
+    for migration in migrations {
+        if applied.contains(&migration.version) {
+            continue;
+        }
+        apply(pool, migration).await.map_err(|e| {
+            MigrationError::Failed(Error {
+                kind: ErrorKind::Internal,
+                source: e.source.wrap_err(format!(
+                    "Database migration {} ({}) failed and was rolled back",
+                    migration.version, migration.description
+                )),
+            })
+        })?;
+        info!(
+            "Applied database migration {} ({})",
+            migration.version, migration.description
+        );
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// A migrated database in a temporary directory, removed when the directory is dropped
+#[cfg(test)]
+pub async fn test_pool() -> (tempdir::TempDir, SqlitePool) {
+    let dir = tempdir::TempDir::new("lodestone_db_test").unwrap();
+    let pool = super::health::lazy_pool(&dir.path().join("test.db")).unwrap();
+    migrate(&pool).await.unwrap();
+    (dir, pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn tables(pool: &SqlitePool) -> Vec<String> {
+        sqlx::query("SELECT name FROM sqlite_master WHERE type IN ('table', 'index') ORDER BY name")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("name"))
+            .filter(|name: &String| !name.starts_with("sqlite_"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_migrate_fresh_database() {
+        let (_dir, pool) = test_pool().await;
+        assert_eq!(
+            tables(&pool).await,
+            vec![
+                "ClientEvents",
+                "ClientEventsInstanceId",
+                "ClientEventsSnowflake",
+                "ConsoleHistory",
+                "GlobalSettingsChanges",
+                "MonitorSamples",
+                "MonitorSamplesInstanceTime",
+                "SchemaMigrations",
+                "Sessions",
+            ]
+        );
+        // running again is a no-op
+        assert_eq!(migrate(&pool).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_older_schema() {
+        let dir = tempdir::TempDir::new("lodestone_db_test").unwrap();
+        let pool = super::super::health::lazy_pool(&dir.path().join("test.db")).unwrap();
+        // a database from before migrations, with only the original events table
+        sqlx::Executor::execute(&pool, MIGRATIONS[0].sql)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO ClientEvents (event_value, details, snowflake, level) VALUES ('{}', 'old', 1, 'Info')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert_eq!(migrate(&pool).await.unwrap(), MIGRATIONS.len() as u32);
+        assert!(tables(&pool)
+            .await
+            .contains(&"ClientEventsInstanceId".to_string()));
+        let details: String = sqlx::query("SELECT details FROM ClientEvents")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get("details");
+        assert_eq!(details, "old");
+    }
+
+    #[tokio::test]
+    async fn test_failed_migration_is_rolled_back() {
+        let (_dir, pool) = test_pool().await;
+        let broken = [Migration {
+            version: MIGRATIONS.len() as i64 + 1,
+            description: "broken",
+            sql: "CREATE TABLE Half (id INTEGER); INSERT INTO Missing VALUES (1);",
+        }];
+        assert!(matches!(
+            run_migrations(&pool, &broken).await,
+            Err(MigrationError::Failed(_))
+        ));
+        assert!(!tables(&pool).await.contains(&"Half".to_string()));
+
+        // an older lodestone must not run against the newer schema
+        assert!(matches!(
+            run_migrations(&pool, &MIGRATIONS[..2]).await,
+            Err(MigrationError::Failed(_))
+        ));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod health;
+pub mod migrations;
 pub mod read;
 pub mod types;
 pub mod write;
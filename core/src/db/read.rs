@@ -1,9 +1,18 @@
 use crate::{
-    error::Error, events::EventQuery, output_types::ClientEvent, prelude::LODESTONE_EPOCH_MIL,
+    auth::{session::Session, user_id::UserId},
+    error::Error,
+    events::{Event, EventQuery},
+    global_settings::GlobalSettingsChange,
+    output_types::ClientEvent,
+    prelude::LODESTONE_EPOCH_MIL,
+    types::{InstanceUuid, Snowflake},
 };
 
+use super::types::{ConsoleHistoryEntry, MonitorSample};
+
 use color_eyre::eyre::Context;
-use sqlx::sqlite::SqlitePool;
+use futures::{Stream, StreamExt};
+use sqlx::{sqlite::SqlitePool, Row};
 use tracing::error;
 
 // TODO clean up all unwraps
@@ -68,15 +77,190 @@ FROM ClientEvents"#
     Ok(filtered)
 }
 
+/// Returns the most recent global settings changes, newest first
+pub async fn get_global_settings_history(
+    pool: &SqlitePool,
+    limit: u32,
+) -> Result<Vec<GlobalSettingsChange>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let rows = sqlx::query(
+        r#"
+SELECT
+change_value
+FROM GlobalSettingsChanges
+ORDER BY id DESC
+LIMIT ($1)"#,
+    )
+    .bind(limit)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch global settings history")?;
+    let mut changes = Vec::new();
+    for row in rows {
+        let value: String = row.get("change_value");
+        if let Ok(change) = serde_json::from_str(&value) {
+            changes.push(change);
+        } else {
+            error!("Failed to parse global settings change: {}", value);
+        }
+    }
+    Ok(changes)
+}
+
+/// Returns the most recent console commands of an instance, newest first
+pub async fn get_console_history(
+    pool: &SqlitePool,
+    instance_id: &InstanceUuid,
+    limit: u32,
+) -> Result<Vec<ConsoleHistoryEntry>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let rows = sqlx::query(
+        r#"
+SELECT
+command, redacted, user_id, user_name, snowflake
+FROM ConsoleHistory
+WHERE instance_id = ($1)
+ORDER BY id DESC
+LIMIT ($2)"#,
+    )
+    .bind(instance_id.to_string())
+    .bind(limit)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch console history")?;
+    Ok(rows
+        .into_iter()
+        .map(|row| ConsoleHistoryEntry {
+            instance_id: instance_id.clone(),
+            command: row.get("command"),
+            redacted: row.get("redacted"),
+            user_id: row.get::<Option<String>, _>("user_id").map(UserId::from),
+            user_name: row.get("user_name"),
+            snowflake: row.get::<Snowflake, _>("snowflake"),
+        })
+        .collect())
+}
+
+/// Returns the most recent console output of an instance stored in the events table, oldest first
+///
+/// `before` pages further back, only events older than that snowflake are returned
+pub async fn get_console_output(
+    pool: &SqlitePool,
+    instance_id: &InstanceUuid,
+    limit: u32,
+    before: Option<Snowflake>,
+) -> Result<Vec<Event>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let rows = sqlx::query(
+        r#"
+SELECT
+event_value
+FROM ClientEvents
+WHERE instance_id = ($1)
+AND json_extract(event_value, '$.event_inner.instance_event_inner.type') IN ('InstanceOutput', 'PlayerMessage', 'SystemMessage')
+AND ($2 IS NULL OR snowflake < $2)
+ORDER BY id DESC
+LIMIT ($3)"#,
+    )
+    .bind(instance_id.to_string())
+    .bind(before)
+    .bind(limit)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch console output")?;
+    let mut events = Vec::new();
+    for row in rows.into_iter().rev() {
+        let value: String = row.get("event_value");
+        if let Ok(event) = serde_json::from_str(&value) {
+            events.push(event);
+        } else {
+            error!("Failed to parse console event: {}", value);
+        }
+    }
+    Ok(events)
+}
+
+/// Monitor samples of an instance with `from <= timestamp <= to`, oldest first
+///
+/// Rows are read as the stream is polled, so long ranges are never held in memory at once
+pub fn stream_monitor_samples<'a>(
+    pool: &'a SqlitePool,
+    instance_id: &InstanceUuid,
+    from: i64,
+    to: i64,
+) -> impl Stream<Item = Result<MonitorSample, Error>> + 'a {
+    let instance = instance_id.clone();
+    sqlx::query(
+        r#"
+SELECT
+timestamp, cpu_usage, memory_usage, player_count
+FROM MonitorSamples
+WHERE instance_id = ($1) AND timestamp >= ($2) AND timestamp <= ($3)
+ORDER BY timestamp ASC"#,
+    )
+    .bind(instance_id.to_string())
+    .bind(from)
+    .bind(to)
+    .fetch(pool)
+    .map(move |row| {
+        let row = row.context("Failed to fetch monitor samples")?;
+        Ok(MonitorSample {
+            instance_id: instance.clone(),
+            timestamp: row.get("timestamp"),
+            cpu_usage: row.get("cpu_usage"),
+            memory_usage: row.get::<Option<i64>, _>("memory_usage").map(|m| m as u64),
+            player_count: row.get("player_count"),
+        })
+    })
+}
+
+/// Sessions that haven't expired by `now`
+pub async fn get_sessions(pool: &SqlitePool, now: i64) -> Result<Vec<Session>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let rows = sqlx::query(
+        r#"
+SELECT
+id, user_id, created_at, last_seen, expires_at, ip, user_agent
+FROM Sessions
+WHERE expires_at > ($1)"#,
+    )
+    .bind(now)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch sessions")?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Session {
+            id: row.get("id"),
+            uid: UserId::from(row.get::<String, _>("user_id")),
+            created_at: row.get("created_at"),
+            last_seen: row.get("last_seen"),
+            expires_at: row.get("expires_at"),
+            ip: row.get("ip"),
+            user_agent: row.get("user_agent"),
+        })
+        .collect())
+}
+
 #[cfg(test)]
 #[allow(unused_imports)]
 mod tests {
     use std::{path::PathBuf, str::FromStr};
 
-    use sqlx::{sqlite::SqliteConnectOptions, Pool, Sqlite};
-
     use crate::{
-        db::write::init_client_events_table,
+        db::migrations::test_pool,
         events::{CausedBy, EventInner, EventLevel, FSEvent, FSOperation, FSTarget},
         types::Snowflake,
     };
@@ -85,19 +269,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_search() {
-        let pool: Pool<Sqlite> = Pool::connect_with(
-            SqliteConnectOptions::from_str("sqlite://test.db")
-                .unwrap()
-                .create_if_missing(true),
-        )
-        .await
-        .unwrap();
-        let drop_result = sqlx::query!(r#"DROP TABLE IF EXISTS ClientEvents"#)
-            .execute(&pool)
-            .await;
-        assert!(drop_result.is_ok());
-        let init_result = init_client_events_table(&pool).await;
-        assert!(init_result.is_ok());
+        let (_dir, pool) = test_pool().await;
 
         let snowflake = Snowflake::new();
         let _dummy_event_1 = ClientEvent {
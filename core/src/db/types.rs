@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use ts_rs::TS;
 
 use crate::{
     auth::user_id::UserId,
@@ -8,6 +9,30 @@ use crate::{
     types::{InstanceUuid, Snowflake},
 };
 
+/// A command sent to an instance's console, as stored in the command history
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct ConsoleHistoryEntry {
+    pub instance_id: InstanceUuid,
+    pub command: String,
+    pub redacted: bool,
+    pub user_id: Option<UserId>,
+    pub user_name: Option<String>,
+    pub snowflake: Snowflake,
+}
+
+/// An instance's resource usage averaged over one sampling interval, as stored for history
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct MonitorSample {
+    pub instance_id: InstanceUuid,
+    /// Unix time, in seconds, at the end of the interval
+    pub timestamp: i64,
+    pub cpu_usage: Option<f32>,
+    pub memory_usage: Option<u64>,
+    pub player_count: Option<u32>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ClientEventRow {
     pub event_value: Value,
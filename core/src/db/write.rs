@@ -1,61 +1,122 @@
 use crate::{
+    auth::session::Session,
     error::Error,
-    events::{Event, EventInner, ProgressionEventInner},
+    event_broadcaster::EventBroadcaster,
+    event_retention::EventRetention,
+    events::{CausedBy, Event, EventInner, ProgressionEventInner},
+    global_settings::GlobalSettingsChange,
     output_types::ClientEvent,
+    prelude::LODESTONE_EPOCH_MIL,
 };
 
+use std::time::Duration;
+
 use color_eyre::eyre::Context;
-use sqlx::sqlite::SqlitePool;
+use sqlx::{sqlite::SqlitePool, Row};
 use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
 use tracing::{error, warn};
 
-use super::types::ClientEventRow;
+use super::health::DbHealth;
+use super::types::{ClientEventRow, ConsoleHistoryEntry, MonitorSample};
 
 // TODO clean up all unwraps
 
-pub async fn write_event_to_db_task(mut event_receiver: Receiver<Event>, sqlite_pool: SqlitePool) {
-    let init_result = init_client_events_table(&sqlite_pool).await;
-    if let Err(error) = init_result.as_ref() {
-        warn!("Failed to initialize client events table: {}", error);
+/// Events are written in batches of at most this many, each in one transaction
+const EVENT_BATCH_SIZE: usize = 256;
+/// Buffered events are written at least this often
+const EVENT_FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The events worth storing, monitor reports and progress updates are only kept in memory
+fn persisted(event: Event) -> Option<ClientEvent> {
+    // monitor reports are kept in the monitor buffer, storing every tick would bloat the db
+    if event.is_event_monitor_report() {
+        return None;
+    }
+    let client_event: ClientEvent = event.into();
+    if let EventInner::ProgressionEvent(pe) = &client_event.event_inner {
+        if let ProgressionEventInner::ProgressionUpdate { .. } = pe.progression_event_inner() {
+            return None;
+        }
+    }
+    Some(client_event)
+}
+
+async fn flush_events(
+    sqlite_pool: &SqlitePool,
+    db_health: &DbHealth,
+    batch: &mut Vec<ClientEvent>,
+) {
+    if batch.is_empty() {
         return;
     }
+    // events are only kept in memory while the database is unavailable
+    if db_health.is_available() {
+        if let Err(e) = write_client_events(sqlite_pool, batch).await {
+            error!(
+                "Error inserting {} event(s) into database: {}",
+                batch.len(),
+                e
+            );
+            db_health.mark_unavailable(&e);
+        }
+    }
+    batch.clear();
+}
 
+/// Stores events in batches until `shutdown` is cancelled, then writes what is left
+pub async fn write_event_to_db_task(
+    mut event_receiver: Receiver<Event>,
+    event_broadcaster: EventBroadcaster,
+    sqlite_pool: SqlitePool,
+    db_health: DbHealth,
+    shutdown: CancellationToken,
+) {
+    let mut batch = Vec::with_capacity(EVENT_BATCH_SIZE);
+    let mut flush_interval = tokio::time::interval(EVENT_FLUSH_INTERVAL);
+    flush_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
     loop {
-        let result = event_receiver.recv().await;
-        if let Err(error) = result.as_ref() {
-            match error {
-                RecvError::Lagged(_) => {
-                    warn!("Event buffer lagged");
+        let event = tokio::select! {
+            result = event_receiver.recv() => match result {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    event_broadcaster.record_lag("Event database writer", skipped);
                     continue;
                 }
-                RecvError::Closed => {
+                Err(RecvError::Closed) => {
                     warn!("Event buffer closed");
                     break;
                 }
-            }
-        }
-
-        let client_event: ClientEvent = result.unwrap().into();
-        if let EventInner::ProgressionEvent(pe) = &client_event.event_inner {
-            if let ProgressionEventInner::ProgressionUpdate { .. } = pe.progression_event_inner() {
+            },
+            _ = flush_interval.tick() => {
+                flush_events(&sqlite_pool, &db_health, &mut batch).await;
                 continue;
             }
+            _ = shutdown.cancelled() => break,
+        };
+        if let Some(client_event) = persisted(event) {
+            batch.push(client_event);
         }
-        let insertion_result = write_client_event(&sqlite_pool, client_event).await;
-        if let Err(e) = insertion_result.as_ref() {
-            error!("Error inserting into database: {}", e);
-            break;
+        if batch.len() >= EVENT_BATCH_SIZE {
+            flush_events(&sqlite_pool, &db_health, &mut batch).await;
+            flush_interval.reset();
         }
     }
+    // events sent right before shutdown are still in the channel
+    while let Ok(event) = event_receiver.try_recv() {
+        if let Some(client_event) = persisted(event) {
+            batch.push(client_event);
+        }
+    }
+    flush_events(&sqlite_pool, &db_health, &mut batch).await;
 }
 
-async fn write_client_event(pool: &SqlitePool, client_event: ClientEvent) -> Result<i64, Error> {
-    let mut connection = pool
-        .acquire()
-        .await
-        .context("Failed to aquire db connection")?;
-
-    let row = ClientEventRow::from(&client_event);
+async fn insert_client_event(
+    connection: &mut sqlx::SqliteConnection,
+    client_event: &ClientEvent,
+) -> Result<i64, Error> {
+    let row = ClientEventRow::from(client_event);
     let id = sqlx::query!(
         r#"
 INSERT INTO ClientEvents
@@ -70,6 +131,55 @@ VALUES
         row.caused_by_user_id,
         row.instance_id,
     )
+    .execute(connection)
+    .await
+    .context("Failed to write to DB")?
+    .last_insert_rowid();
+    Ok(id)
+}
+
+/// Writes `client_events` in one transaction, so either all of them are stored or none
+async fn write_client_events(
+    pool: &SqlitePool,
+    client_events: &[ClientEvent],
+) -> Result<(), Error> {
+    let mut transaction = pool.begin().await.context("Failed to start transaction")?;
+    for client_event in client_events {
+        insert_client_event(&mut transaction, client_event).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    Ok(())
+}
+
+pub async fn write_global_settings_change(
+    pool: &SqlitePool,
+    change: &GlobalSettingsChange,
+) -> Result<i64, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+
+    let caused_by_user_id = if let CausedBy::User { user_id, .. } = &change.caused_by {
+        Some(user_id.to_string())
+    } else {
+        None
+    };
+    let id = sqlx::query(
+        r#"
+INSERT INTO GlobalSettingsChanges
+(change_value, setting, snowflake, caused_by_user_id)
+VALUES
+(?1, ?2, ?3, ?4)
+        "#,
+    )
+    .bind(serde_json::to_string(change).context("Failed to serialize settings change")?)
+    .bind(&change.setting)
+    .bind(change.snowflake)
+    .bind(caused_by_user_id)
     .execute(&mut connection)
     .await
     .context("Failed to write to DB")?
@@ -77,29 +187,256 @@ VALUES
     Ok(id)
 }
 
-pub async fn init_client_events_table(pool: &SqlitePool) -> Result<(), Error> {
+/// Appends a command to an instance's history, dropping the oldest entries beyond `cap`
+pub async fn write_console_history_entry(
+    pool: &SqlitePool,
+    entry: &ConsoleHistoryEntry,
+    cap: u32,
+) -> Result<(), Error> {
     let mut connection = pool
         .acquire()
         .await
         .context("Failed to aquire db connection")?;
 
-    sqlx::query!(
+    sqlx::query(
         r#"
-        CREATE TABLE IF NOT EXISTS ClientEvents (
-            id                  INTEGER     PRIMARY KEY     AUTOINCREMENT,
-            event_value         TEXT        NOT NULL,
-            details             TEXT        NOT NULL,
-            snowflake           BIGINT      NOT NULL,
-            level               VARCHAR(20) NOT NULL,
-            caused_by_user_id   TEXT,
-            instance_id         TEXT
-        );
-        "#
+INSERT INTO ConsoleHistory
+(instance_id, command, redacted, user_id, user_name, snowflake)
+VALUES
+(?1, ?2, ?3, ?4, ?5, ?6)
+        "#,
+    )
+    .bind(entry.instance_id.to_string())
+    .bind(&entry.command)
+    .bind(entry.redacted)
+    .bind(entry.user_id.as_ref().map(|id| id.to_string()))
+    .bind(&entry.user_name)
+    .bind(entry.snowflake)
+    .execute(&mut connection)
+    .await
+    .context("Failed to write to DB")?;
+
+    sqlx::query(
+        r#"
+DELETE FROM ConsoleHistory
+WHERE instance_id = ?1 AND id NOT IN (
+    SELECT id FROM ConsoleHistory WHERE instance_id = ?1 ORDER BY id DESC LIMIT ?2
+)
+        "#,
+    )
+    .bind(entry.instance_id.to_string())
+    .bind(cap)
+    .execute(&mut connection)
+    .await
+    .context("Failed to trim console history")?;
+    Ok(())
+}
+
+pub async fn write_monitor_sample(pool: &SqlitePool, sample: &MonitorSample) -> Result<(), Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+
+    sqlx::query(
+        r#"
+INSERT INTO MonitorSamples
+(instance_id, timestamp, cpu_usage, memory_usage, player_count)
+VALUES
+(?1, ?2, ?3, ?4, ?5)
+        "#,
     )
+    .bind(sample.instance_id.to_string())
+    .bind(sample.timestamp)
+    .bind(sample.cpu_usage)
+    // sqlite has no unsigned 64 bit integer
+    .bind(sample.memory_usage.map(|m| m as i64))
+    .bind(sample.player_count)
     .execute(&mut connection)
     .await
-    .context("Failed to create table")?;
+    .context("Failed to write to DB")?;
+    Ok(())
+}
+
+/// Merges the samples older than `downsample_before` into one per instance and hour,
+/// and deletes the ones older than `delete_before`
+pub async fn compact_monitor_samples(
+    pool: &SqlitePool,
+    downsample_before: i64,
+    delete_before: i64,
+) -> Result<(), Error> {
+    let mut transaction = pool.begin().await.context("Failed to start transaction")?;
+
+    sqlx::query("DELETE FROM MonitorSamples WHERE timestamp < ?1")
+        .bind(delete_before)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete old monitor samples")?;
+
+    // rows merged by an earlier run are alone in their hour and are left as is
+    let last_id: i64 = sqlx::query("SELECT COALESCE(MAX(id), 0) AS last_id FROM MonitorSamples")
+        .fetch_one(&mut transaction)
+        .await
+        .context("Failed to read monitor samples")?
+        .get("last_id");
+    sqlx::query(
+        r#"
+INSERT INTO MonitorSamples
+(instance_id, timestamp, cpu_usage, memory_usage, player_count)
+SELECT instance_id, MAX(timestamp), AVG(cpu_usage), CAST(AVG(memory_usage) AS INTEGER), CAST(ROUND(AVG(player_count)) AS INTEGER)
+FROM MonitorSamples
+WHERE id <= ?1 AND timestamp < ?2
+GROUP BY instance_id, timestamp / 3600
+HAVING COUNT(*) > 1
+        "#,
+    )
+    .bind(last_id)
+    .bind(downsample_before)
+    .execute(&mut transaction)
+    .await
+    .context("Failed to downsample monitor samples")?;
+    sqlx::query(
+        r#"
+DELETE FROM MonitorSamples
+WHERE id <= ?1 AND timestamp < ?2 AND (instance_id, timestamp / 3600) IN (
+    SELECT instance_id, timestamp / 3600 FROM MonitorSamples
+    WHERE id <= ?1 AND timestamp < ?2
+    GROUP BY instance_id, timestamp / 3600
+    HAVING COUNT(*) > 1
+)
+        "#,
+    )
+    .bind(last_id)
+    .bind(downsample_before)
+    .execute(&mut transaction)
+    .await
+    .context("Failed to downsample monitor samples")?;
 
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    Ok(())
+}
+
+/// The smallest snowflake generated at `timestamp`, in seconds since the epoch
+fn snowflake_at(timestamp: i64) -> i64 {
+    (timestamp * 1000 - LODESTONE_EPOCH_MIL.with(|p| *p)).max(0) << 22
+}
+
+/// Deletes the events past their retention at `now`, returns how many were deleted
+pub async fn prune_client_events(
+    pool: &SqlitePool,
+    retention: &EventRetention,
+    now: i64,
+) -> Result<u64, Error> {
+    let before = |days: Option<u32>| days.map(|days| snowflake_at(now - days as i64 * 86400));
+    let mut transaction = pool.begin().await.context("Failed to start transaction")?;
+    let mut deleted = 0;
+
+    // console output is told apart by its type, the rest by level
+    const IS_CONSOLE: &str = "json_extract(event_value, '$.event_inner.instance_event_inner.type') IN ('InstanceOutput', 'PlayerMessage', 'SystemMessage')";
+    if let Some(before) = before(retention.console_days) {
+        deleted += sqlx::query(&format!(
+            "DELETE FROM ClientEvents WHERE snowflake < ?1 AND {IS_CONSOLE}"
+        ))
+        .bind(before)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete console events")?
+        .rows_affected();
+    }
+    if let Some(before) = before(retention.warning_days) {
+        deleted += sqlx::query(&format!(
+            "DELETE FROM ClientEvents WHERE snowflake < ?1 AND level IN ('Warning', 'Error') AND NOT COALESCE({IS_CONSOLE}, 0)"
+        ))
+        .bind(before)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete warning events")?
+        .rows_affected();
+    }
+    if let Some(before) = before(retention.info_days) {
+        deleted += sqlx::query(&format!(
+            "DELETE FROM ClientEvents WHERE snowflake < ?1 AND level NOT IN ('Warning', 'Error') AND NOT COALESCE({IS_CONSOLE}, 0)"
+        ))
+        .bind(before)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete info events")?
+        .rows_affected();
+    }
+    if let Some(max_rows) = retention.max_rows {
+        deleted += sqlx::query(
+            r#"
+DELETE FROM ClientEvents
+WHERE id <= (SELECT id FROM ClientEvents ORDER BY id DESC LIMIT 1 OFFSET ?1)
+        "#,
+        )
+        // sqlite has no unsigned 64 bit integer
+        .bind(max_rows.min(i64::MAX as u64) as i64)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete events over the limit")?
+        .rows_affected();
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    Ok(deleted)
+}
+
+pub async fn write_session(pool: &SqlitePool, session: &Session) -> Result<(), Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    insert_session(&mut connection, session).await
+}
+
+async fn insert_session(
+    connection: &mut sqlx::SqliteConnection,
+    session: &Session,
+) -> Result<(), Error> {
+    sqlx::query(
+        r#"
+INSERT OR REPLACE INTO Sessions
+(id, user_id, created_at, last_seen, expires_at, ip, user_agent)
+VALUES
+(?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        "#,
+    )
+    .bind(&session.id)
+    .bind(session.uid.to_string())
+    .bind(session.created_at)
+    .bind(session.last_seen)
+    .bind(session.expires_at)
+    .bind(&session.ip)
+    .bind(&session.user_agent)
+    .execute(connection)
+    .await
+    .context("Failed to write session to DB")?;
+    Ok(())
+}
+
+/// Replaces the stored sessions with `sessions`, dropping the revoked and expired ones
+pub async fn sync_sessions(pool: &SqlitePool, sessions: &[Session]) -> Result<(), Error> {
+    let mut transaction = pool.begin().await.context("Failed to start transaction")?;
+
+    sqlx::query("DELETE FROM Sessions")
+        .execute(&mut transaction)
+        .await
+        .context("Failed to clear sessions")?;
+    for session in sessions {
+        insert_session(&mut transaction, session).await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
     Ok(())
 }
 
@@ -109,10 +446,13 @@ pub async fn init_client_events_table(pool: &SqlitePool) -> Result<(), Error> {
 mod tests {
     use std::{path::PathBuf, str::FromStr};
 
-    use sqlx::{sqlite::SqliteConnectOptions, Pool};
+    use futures::TryStreamExt;
 
     use crate::{
-        events::{CausedBy, EventLevel, FSEvent, FSOperation, FSTarget},
+        db::migrations::test_pool,
+        events::{
+            CausedBy, EventLevel, FSEvent, FSOperation, FSTarget, InstanceEvent, InstanceEventInner,
+        },
         types::Snowflake,
     };
 
@@ -120,19 +460,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_write() {
-        let pool = Pool::connect_with(
-            SqliteConnectOptions::from_str("sqlite://test.db")
-                .unwrap()
-                .create_if_missing(true),
-        )
-        .await
-        .unwrap();
-        let drop_result = sqlx::query!(r#"DROP TABLE IF EXISTS ClientEvents"#)
-            .execute(&pool)
-            .await;
-        assert!(drop_result.is_ok());
-        let init_result = init_client_events_table(&pool).await;
-        assert!(init_result.is_ok());
+        let (_dir, pool) = test_pool().await;
         let snowflake = Snowflake::new();
         let dummy_event = ClientEvent {
             event_inner: EventInner::FSEvent(FSEvent {
@@ -144,7 +472,7 @@ mod tests {
             level: EventLevel::Info,
             caused_by: CausedBy::System,
         };
-        let write_result = write_client_event(&pool, dummy_event.clone()).await;
+        let write_result = write_client_events(&pool, &[dummy_event.clone()]).await;
         assert!(write_result.is_ok());
 
         let row_result = sqlx::query!(
@@ -166,4 +494,274 @@ mod tests {
         assert_eq!(row.caused_by_user_id, None);
         assert_eq!(row.instance_id, None);
     }
+
+    #[tokio::test]
+    async fn test_write_global_settings_change() {
+        let (_dir, pool) = test_pool().await;
+        let change = GlobalSettingsChange::new("safe_mode", true, false, CausedBy::System);
+        write_global_settings_change(&pool, &change).await.unwrap();
+        let change = GlobalSettingsChange::new("core_name", "a", "b", CausedBy::System);
+        write_global_settings_change(&pool, &change).await.unwrap();
+
+        let history = crate::db::read::get_global_settings_history(&pool, 10)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].setting, "core_name");
+        assert_eq!(history[0].new_value, serde_json::json!("b"));
+        assert_eq!(history[1].setting, "safe_mode");
+        assert_eq!(history[1].old_value, serde_json::json!(true));
+    }
+
+    #[tokio::test]
+    async fn test_console_history() {
+        let (_dir, pool) = test_pool().await;
+        let instance_id = crate::types::InstanceUuid::from("INSTANCE_test".to_string());
+        for i in 0..5 {
+            let entry = ConsoleHistoryEntry {
+                instance_id: instance_id.clone(),
+                command: format!("say {i}"),
+                redacted: false,
+                user_id: None,
+                user_name: None,
+                snowflake: Snowflake::new(),
+            };
+            write_console_history_entry(&pool, &entry, 3).await.unwrap();
+        }
+
+        let history = crate::db::read::get_console_history(&pool, &instance_id, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|e| e.command.as_str())
+                .collect::<Vec<_>>(),
+            vec!["say 4", "say 3", "say 2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_console_output() {
+        let (_dir, pool) = test_pool().await;
+        let instance_id = crate::types::InstanceUuid::from("INSTANCE_test".to_string());
+        for i in 0..3 {
+            let event = Event::new_instance_output(
+                instance_id.clone(),
+                "test".to_string(),
+                format!("line {i}"),
+            );
+            write_client_events(&pool, &[event.into()]).await.unwrap();
+        }
+        let warning = Event::new_instance_warning(
+            instance_id.clone(),
+            "test".to_string(),
+            "not console output".to_string(),
+        );
+        write_client_events(&pool, &[warning.into()]).await.unwrap();
+
+        let lines = |events: Vec<Event>| {
+            events
+                .into_iter()
+                .filter_map(|event| match event.event_inner {
+                    EventInner::InstanceEvent(InstanceEvent {
+                        instance_event_inner: InstanceEventInner::InstanceOutput { message, .. },
+                        ..
+                    }) => Some(message),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let recent = crate::db::read::get_console_output(&pool, &instance_id, 2, None)
+            .await
+            .unwrap();
+        let oldest_shown = recent[0].snowflake;
+        assert_eq!(lines(recent), vec!["line 1", "line 2"]);
+        let older =
+            crate::db::read::get_console_output(&pool, &instance_id, 10, Some(oldest_shown))
+                .await
+                .unwrap();
+        assert_eq!(lines(older), vec!["line 0"]);
+    }
+
+    #[tokio::test]
+    async fn test_monitor_samples() {
+        let (_dir, pool) = test_pool().await;
+        let instance_id = crate::types::InstanceUuid::from("INSTANCE_test".to_string());
+        for timestamp in [60, 120, 180] {
+            let sample = MonitorSample {
+                instance_id: instance_id.clone(),
+                timestamp,
+                cpu_usage: Some(12.5),
+                memory_usage: Some(2 << 30),
+                player_count: None,
+            };
+            write_monitor_sample(&pool, &sample).await.unwrap();
+        }
+
+        let samples: Vec<MonitorSample> =
+            crate::db::read::stream_monitor_samples(&pool, &instance_id, 100, 180)
+                .try_collect()
+                .await
+                .unwrap();
+        assert_eq!(
+            samples.iter().map(|s| s.timestamp).collect::<Vec<_>>(),
+            vec![120, 180]
+        );
+        assert_eq!(samples[0].memory_usage, Some(2 << 30));
+        assert_eq!(samples[0].player_count, None);
+    }
+
+    #[tokio::test]
+    async fn test_compact_monitor_samples() {
+        let (_dir, pool) = test_pool().await;
+        let instance_id = crate::types::InstanceUuid::from("INSTANCE_test".to_string());
+        // two hours of minutely samples, then a recent one
+        for timestamp in (0..2 * 3600).step_by(60).chain([10 * 3600]) {
+            let sample = MonitorSample {
+                instance_id: instance_id.clone(),
+                timestamp,
+                cpu_usage: Some(if timestamp < 3600 { 10.0 } else { 20.0 }),
+                memory_usage: Some(100),
+                player_count: Some(1),
+            };
+            write_monitor_sample(&pool, &sample).await.unwrap();
+        }
+
+        compact_monitor_samples(&pool, 5 * 3600, 0).await.unwrap();
+        // running again must not change the merged rows
+        compact_monitor_samples(&pool, 5 * 3600, 0).await.unwrap();
+        let samples: Vec<MonitorSample> =
+            crate::db::read::stream_monitor_samples(&pool, &instance_id, 0, i64::MAX)
+                .try_collect()
+                .await
+                .unwrap();
+        assert_eq!(
+            samples
+                .iter()
+                .map(|s| (s.timestamp, s.cpu_usage))
+                .collect::<Vec<_>>(),
+            vec![
+                (3540, Some(10.0)),
+                (7140, Some(20.0)),
+                (10 * 3600, Some(20.0))
+            ]
+        );
+
+        compact_monitor_samples(&pool, 5 * 3600, 5 * 3600)
+            .await
+            .unwrap();
+        let remaining: Vec<MonitorSample> =
+            crate::db::read::stream_monitor_samples(&pool, &instance_id, 0, i64::MAX)
+                .try_collect()
+                .await
+                .unwrap();
+        assert_eq!(remaining.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_prune_client_events() {
+        let (_dir, pool) = test_pool().await;
+        let instance_id = crate::types::InstanceUuid::from("INSTANCE_test".to_string());
+        let now = chrono::Utc::now().timestamp();
+        let day = 86400;
+        // (age in days, event), all written with the snowflake of their age
+        let events = vec![
+            (
+                3,
+                Event::new_instance_output(instance_id.clone(), "test".to_string(), "old".into()),
+            ),
+            (
+                1,
+                Event::new_instance_output(instance_id.clone(), "test".to_string(), "new".into()),
+            ),
+            (
+                3,
+                Event::new_instance_warning(instance_id.clone(), "test".to_string(), "warn".into()),
+            ),
+            (
+                3,
+                Event::new_system_message(instance_id.clone(), "test".to_string(), "sys".into()),
+            ),
+        ];
+        for (age, event) in events {
+            let mut client_event: ClientEvent = event.into();
+            client_event.snowflake =
+                serde_json::from_value(serde_json::json!(snowflake_at(now - age * day) + 1))
+                    .unwrap();
+            write_client_events(&pool, &[client_event]).await.unwrap();
+        }
+        let count = |pool: SqlitePool| async move {
+            sqlx::query("SELECT COUNT(*) AS count FROM ClientEvents")
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .get::<i64, _>("count")
+        };
+
+        let keep_warnings = EventRetention {
+            console_days: Some(2),
+            warning_days: Some(7),
+            info_days: Some(2),
+            max_rows: None,
+        };
+        // the old console line and system message go, the warning is kept longer
+        assert_eq!(
+            prune_client_events(&pool, &keep_warnings, now)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(count(pool.clone()).await, 2);
+
+        let capped = EventRetention {
+            console_days: None,
+            warning_days: None,
+            info_days: None,
+            max_rows: Some(1),
+        };
+        assert_eq!(prune_client_events(&pool, &capped, now).await.unwrap(), 1);
+        // the newest event is the one kept
+        let level: String = sqlx::query("SELECT level FROM ClientEvents")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get("level");
+        assert_eq!(level, "Warning");
+    }
+
+    #[tokio::test]
+    async fn test_write_event_to_db_task_flushes_on_shutdown() {
+        let (_dir, pool) = test_pool().await;
+        let db_health = DbHealth::default();
+        db_health.mark_available();
+        let (event_broadcaster, _rx) = EventBroadcaster::new(4096);
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(write_event_to_db_task(
+            event_broadcaster.subscribe(),
+            event_broadcaster.clone(),
+            pool.clone(),
+            db_health,
+            shutdown.clone(),
+        ));
+        let instance_id = crate::types::InstanceUuid::from("INSTANCE_test".to_string());
+        // more than a batch, so one is written on size and the rest on shutdown
+        let count = EVENT_BATCH_SIZE + 10;
+        for i in 0..count {
+            event_broadcaster.send(Event::new_instance_output(
+                instance_id.clone(),
+                "test".to_string(),
+                format!("line {i}"),
+            ));
+        }
+        shutdown.cancel();
+        task.await.unwrap();
+
+        let stored: i64 = sqlx::query("SELECT COUNT(*) AS count FROM ClientEvents")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get("count");
+        assert_eq!(stored, count as i64);
+    }
 }
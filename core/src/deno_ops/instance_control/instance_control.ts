@@ -41,7 +41,7 @@ export function getInstanceState(instanceUuid: string): Promise<InstanceState> {
 }
 
 export function sendCommand(command: string, instanceUuid: string): Promise<void> {
-    return core.opAsync("send_command", instanceUuid, command);
+    return core.opAsync("send_command", instanceUuid, command, getCurrentTaskPid());
 }
 
 export function monitorInstance(instanceUuid: string): Promise<PerformanceReport> {
@@ -105,11 +105,11 @@ export function isRconAvailable(instanceUuid: string): Promise<boolean> {
 }
 
 export function trySendRconCommand(command: string, instanceUuid: string): Promise<string | null> {
-    return core.opAsync("try_send_rcon_command", instanceUuid, command);
+    return core.opAsync("try_send_rcon_command", instanceUuid, command, getCurrentTaskPid());
 }
 
 export function sendRconCommand(command: string, instanceUuid: string): Promise<string> {
-    return core.opAsync("send_rcon_command", instanceUuid, command);
+    return core.opAsync("send_rcon_command", instanceUuid, command, getCurrentTaskPid());
 }
 
 export function waitTillRconAvailable(instanceUuid: string): Promise<void> {
@@ -7,6 +7,7 @@ use deno_core::{
 
 use crate::{
     events::CausedBy,
+    handlers::instance_server::check_command_filter,
     macro_executor::MacroPID,
     prelude::app_state,
     traits::{
@@ -99,10 +100,13 @@ async fn kill_instance(
     instance_uuid: InstanceUuid,
     task_pid: MacroPID,
 ) -> Result<(), anyhow::Error> {
-    let instance = app_state()
+    let state = app_state();
+    let instance = state
         .instances
         .get(&instance_uuid)
-        .ok_or(anyhow::anyhow!("Instance not found"))?;
+        .ok_or(anyhow::anyhow!("Instance not found"))?
+        .clone();
+    state.instance_states.request_stop(&instance_uuid);
     instance
         .kill(CausedBy::Macro {
             macro_pid: task_pid,
@@ -121,12 +125,40 @@ async fn get_instance_state(instance_uuid: InstanceUuid) -> Result<State, anyhow
     Ok(instance.state().await)
 }
 
+/// Holds a command from a macro to the command filter of the user that started it, or the
+/// macro that started it. Macros lodestone starts itself, like hooks, are not filtered
+async fn check_macro_command(
+    instance_uuid: &InstanceUuid,
+    task_pid: MacroPID,
+    command: &str,
+) -> Result<(), anyhow::Error> {
+    let state = app_state();
+    let mut caller = state.macro_executor.caller(task_pid);
+    // a macro is always started by one with a lower pid, so this ends
+    while let Some(CausedBy::Macro { macro_pid }) = caller {
+        caller = state.macro_executor.caller(macro_pid);
+    }
+    if let Some(CausedBy::User { user_id, .. }) = caller {
+        let user = state
+            .users_manager
+            .read()
+            .await
+            .get_user(&user_id)
+            .ok_or(anyhow::anyhow!(
+                "The user who started this macro no longer exists"
+            ))?;
+        check_command_filter(state, &user, instance_uuid, command).await?;
+    }
+    Ok(())
+}
+
 #[op]
 async fn send_command(
     instance_uuid: InstanceUuid,
     command: String,
     task_pid: MacroPID,
 ) -> Result<(), anyhow::Error> {
+    check_macro_command(&instance_uuid, task_pid, &command).await?;
     let instance = app_state()
         .instances
         .get(&instance_uuid)
@@ -312,7 +344,9 @@ async fn is_rcon_available(instance_uuid: InstanceUuid) -> Result<bool, anyhow::
 async fn try_send_rcon_command(
     instance_uuid: InstanceUuid,
     command: String,
+    task_pid: MacroPID,
 ) -> Result<Option<String>, anyhow::Error> {
+    check_macro_command(&instance_uuid, task_pid, &command).await?;
     let instance = app_state()
         .instances
         .get(&instance_uuid)
@@ -329,7 +363,9 @@ async fn try_send_rcon_command(
 async fn send_rcon_command(
     instance_uuid: InstanceUuid,
     command: String,
+    task_pid: MacroPID,
 ) -> Result<String, anyhow::Error> {
+    check_macro_command(&instance_uuid, task_pid, &command).await?;
     let instance = app_state()
         .instances
         .get(&instance_uuid)
@@ -0,0 +1,129 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use color_eyre::eyre::eyre;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sysinfo::{DiskExt, RefreshKind, System, SystemExt};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::EventBroadcaster;
+use crate::events::Event;
+use crate::prelude::GameInstance;
+use crate::traits::t_configurable::TConfigurable;
+use crate::types::InstanceUuid;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct DiskSpace {
+    pub total: u64,
+    pub free: u64,
+}
+
+/// Free and total space of the filesystem holding `path`
+///
+/// Picks the disk with the longest mount point that is a prefix of `path`
+pub fn disk_space_of(path: impl AsRef<Path>) -> Option<DiskSpace> {
+    let path = path
+        .as_ref()
+        .canonicalize()
+        .unwrap_or_else(|_| path.as_ref().to_owned());
+    let mut sys = System::new_with_specifics(RefreshKind::new().with_disks_list());
+    sys.refresh_disks();
+    sys.disks()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| DiskSpace {
+            total: disk.total_space(),
+            free: disk.available_space(),
+        })
+}
+
+/// Refuse with `InsufficientDiskSpace` when the filesystem holding the instance
+/// has less than `min_free_mb` left, and warn once it drops below twice that
+pub async fn guard_instance_disk_space(
+    instance: &GameInstance,
+    min_free_mb: u64,
+    event_broadcaster: &EventBroadcaster,
+) -> Result<(), Error> {
+    let path = instance.path().await;
+    let disk_space = match tokio::task::spawn_blocking(move || disk_space_of(path))
+        .await
+        .ok()
+        .flatten()
+    {
+        Some(v) => v,
+        // can't tell, don't block the user
+        None => return Ok(()),
+    };
+    let min_free = min_free_mb.saturating_mul(1024 * 1024);
+    let free_mb = disk_space.free / 1024 / 1024;
+    if disk_space.free < min_free {
+        return Err(Error {
+            kind: ErrorKind::InsufficientDiskSpace,
+            source: eyre!(
+                "Only {free_mb} MB of disk space left, at least {min_free_mb} MB is required"
+            ),
+        });
+    }
+    if disk_space.free < min_free.saturating_mul(2) {
+        event_broadcaster.send(Event::new_instance_warning(
+            instance.uuid().await,
+            instance.name().await,
+            format!("Disk space is running low, only {free_mb} MB left"),
+        ));
+    }
+    Ok(())
+}
+
+/// Total size in bytes of all files under `path`
+pub fn dir_size(path: impl AsRef<Path>) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct InstanceSize {
+    pub size: u64,
+    pub last_updated: i64,
+}
+
+/// Walking a world directory is expensive, so instance sizes are computed
+/// periodically in the background and served from here
+#[derive(Clone, Default)]
+pub struct InstanceSizeCache {
+    sizes: Arc<DashMap<InstanceUuid, InstanceSize>>,
+}
+
+impl InstanceSizeCache {
+    pub fn get(&self, uuid: &InstanceUuid) -> Option<InstanceSize> {
+        self.sizes.get(uuid).map(|v| *v)
+    }
+
+    pub async fn refresh(&self, instances: &DashMap<InstanceUuid, GameInstance>) {
+        let mut paths = Vec::new();
+        for entry in instances.iter() {
+            paths.push((entry.key().clone(), entry.value().path().await));
+        }
+        self.sizes.retain(|uuid, _| instances.contains_key(uuid));
+        for (uuid, path) in paths {
+            if let Ok(size) = tokio::task::spawn_blocking(move || dir_size(path)).await {
+                self.sizes.insert(
+                    uuid,
+                    InstanceSize {
+                        size,
+                        last_updated: chrono::Utc::now().timestamp(),
+                    },
+                );
+            }
+        }
+    }
+}
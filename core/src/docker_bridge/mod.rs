@@ -165,6 +165,10 @@ impl DockerBridge {
                 player_count: None,
                 max_player_count: None,
                 player_list: None,
+                tags: Default::default(),
+                notes: Default::default(),
+                restart_required: false,
+                deleted_at: None,
             };
             ret.push(instance);
         }
@@ -9,9 +9,12 @@ use serde_json::json;
 use thiserror::Error;
 use ts_rs::TS;
 
+use crate::auth::password_policy::PasswordPolicyViolation;
 use crate::error;
+use crate::startup_diagnostics::StartupDiagnostics;
 
-#[derive(Debug, Clone, Deserialize, Serialize, TS)]
+/// Category of an error, each kind maps to exactly one HTTP status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, TS)]
 #[ts(export)]
 pub enum ErrorKind {
     NotFound,
@@ -19,6 +22,12 @@ pub enum ErrorKind {
     BadRequest,
     PermissionDenied,
     Unauthorized,
+    Conflict,
+    InsufficientDiskSpace,
+    InsufficientMemory,
+    PayloadTooLarge,
+    /// A dependency like the database can't be reached right now
+    ServiceUnavailable,
     External,
     Internal,
 }
@@ -58,8 +67,13 @@ impl Display for ErrorKind {
             ErrorKind::BadRequest => write!(f, "Bad Request"),
             ErrorKind::PermissionDenied => write!(f, "Permission Denied"),
             ErrorKind::Unauthorized => write!(f, "Unauthorized"),
+            ErrorKind::Conflict => write!(f, "Conflict"),
+            ErrorKind::InsufficientDiskSpace => write!(f, "Insufficient Disk Space"),
+            ErrorKind::InsufficientMemory => write!(f, "Insufficient Memory"),
+            ErrorKind::PayloadTooLarge => write!(f, "Payload Too Large"),
+            ErrorKind::ServiceUnavailable => write!(f, "Service Unavailable"),
             ErrorKind::Internal => write!(f, "Internal Error"),
-            ErrorKind::External => write!(f, "External Error")
+            ErrorKind::External => write!(f, "External Error"),
         }
     }
 }
@@ -87,19 +101,57 @@ fn test_error_serialization() {
     assert_eq!(json, r#"{"kind":"NotFound","causes":["Test"]}"#);
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
-        let status = match self.kind {
+impl ErrorKind {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
             ErrorKind::NotFound => StatusCode::NOT_FOUND,
             ErrorKind::UnsupportedOperation => StatusCode::NOT_IMPLEMENTED,
             ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
             ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
             ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::Conflict => StatusCode::CONFLICT,
+            ErrorKind::InsufficientDiskSpace => StatusCode::INSUFFICIENT_STORAGE,
+            ErrorKind::InsufficientMemory => StatusCode::PRECONDITION_FAILED,
+            ErrorKind::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorKind::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
             ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
             ErrorKind::External => StatusCode::BAD_GATEWAY,
-        };
-        (status, json!(self).to_string()).into_response()
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        let mut body = json!(self);
+        // lets users quote the id of the failed request in bug reports
+        if let Some(correlation_id) = crate::correlation::current_correlation_id() {
+            body["correlation_id"] = json!(correlation_id);
+        }
+        // a rejected password says which requirements it didn't meet
+        if let Some(violation) = self.source.downcast_ref::<PasswordPolicyViolation>() {
+            body["unmet_requirements"] = json!(violation.unmet_requirements);
+        }
+        // a failed start says which startup checks failed
+        if let Some(diagnostics) = self.source.downcast_ref::<StartupDiagnostics>() {
+            body["diagnostics"] = json!(diagnostics);
+        }
+        (self.kind.status_code(), body.to_string()).into_response()
+    }
+}
+
+#[test]
+fn test_error_status_code() {
+    let response = Error {
+        kind: ErrorKind::Conflict,
+        source: Report::msg("Test"),
     }
+    .into_response();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(ErrorKind::NotFound.status_code(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        ErrorKind::PermissionDenied.status_code(),
+        StatusCode::FORBIDDEN
+    );
 }
 
 impl From<Report> for Error {
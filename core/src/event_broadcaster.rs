@@ -1,7 +1,10 @@
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use tokio::sync::broadcast::{Receiver, Sender};
-use tracing::error;
+use tracing::{error, warn};
+use ts_rs::TS;
 
 use crate::{
     events::{Event, EventInner, InstanceEvent, InstanceEventInner},
@@ -12,6 +15,25 @@ use crate::{
 #[derive(Debug, Clone)]
 pub struct EventBroadcaster {
     event_tx: Sender<Event>,
+    capacity: usize,
+    lag_counters: Arc<LagCounters>,
+}
+
+#[derive(Debug, Default)]
+struct LagCounters {
+    lag_count: AtomicU64,
+    skipped_events: AtomicU64,
+}
+
+/// How often subscribers fell behind the event channel and missed events
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, TS)]
+#[ts(export)]
+pub struct EventLagReport {
+    pub capacity: usize,
+    /// Times a subscriber lagged since lodestone started
+    pub lag_count: u64,
+    /// Events dropped for lagging subscribers since lodestone started
+    pub skipped_events: u64,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -30,7 +52,31 @@ pub struct PlayerChange {
 impl EventBroadcaster {
     pub fn new(capacity: usize) -> (Self, Receiver<Event>) {
         let (event_tx, rx) = tokio::sync::broadcast::channel(capacity);
-        (Self { event_tx }, rx)
+        (
+            Self {
+                event_tx,
+                capacity,
+                lag_counters: Arc::new(LagCounters::default()),
+            },
+            rx,
+        )
+    }
+
+    /// To be called by subscribers that got `RecvError::Lagged`
+    pub fn record_lag(&self, subscriber: &str, skipped: u64) {
+        warn!("{subscriber} lagged behind the event channel, {skipped} events were skipped");
+        self.lag_counters.lag_count.fetch_add(1, Ordering::Relaxed);
+        self.lag_counters
+            .skipped_events
+            .fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    pub fn lag_report(&self) -> EventLagReport {
+        EventLagReport {
+            capacity: self.capacity,
+            lag_count: self.lag_counters.lag_count.load(Ordering::Relaxed),
+            skipped_events: self.lag_counters.skipped_events.load(Ordering::Relaxed),
+        }
     }
 
     pub fn send(&self, event: Event) {
@@ -64,7 +110,7 @@ impl EventBroadcaster {
     pub async fn next_instance_output(&self, instance_uuid: &InstanceUuid) -> String {
         loop {
             let instance_event = self.next_instance_event(instance_uuid).await;
-            if let InstanceEventInner::InstanceOutput { message } =
+            if let InstanceEventInner::InstanceOutput { message, .. } =
                 instance_event.instance_event_inner
             {
                 return message;
@@ -0,0 +1,107 @@
+//! Deletes stored events once they are past their retention, so the events table doesn't grow
+//! without bound on a busy core
+
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tokio::sync::Mutex;
+use tracing::{error, info};
+use ts_rs::TS;
+
+use crate::{
+    db::{health::DbHealth, write::prune_client_events},
+    error::{Error, ErrorKind},
+    global_settings::GlobalSettings,
+};
+
+/// How long stored events are kept, by kind. `None` keeps them forever
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct EventRetention {
+    /// Days console output, player chat and system messages are kept
+    pub console_days: Option<u32>,
+    /// Days warnings and errors are kept
+    pub warning_days: Option<u32>,
+    /// Days all other events are kept
+    pub info_days: Option<u32>,
+    /// Events kept at most, the oldest are deleted first regardless of kind
+    pub max_rows: Option<u64>,
+}
+
+impl Default for EventRetention {
+    fn default() -> Self {
+        Self {
+            console_days: Some(14),
+            warning_days: Some(180),
+            info_days: Some(60),
+            max_rows: Some(1_000_000),
+        }
+    }
+}
+
+impl EventRetention {
+    pub fn validate(&self) -> Result<(), Error> {
+        if [self.console_days, self.warning_days, self.info_days].contains(&Some(0)) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Events must be kept for at least one day, unset it to keep them forever"
+                ),
+            });
+        }
+        if self.max_rows == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("At least one event must be kept, unset the limit to keep them all"),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Applies the event retention every hour
+pub async fn prune_events_task(
+    global_settings: Arc<Mutex<GlobalSettings>>,
+    sqlite_pool: SqlitePool,
+    db_health: DbHealth,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        if !db_health.is_available() {
+            continue;
+        }
+        let retention = global_settings.lock().await.event_retention();
+        match prune_client_events(&sqlite_pool, &retention, chrono::Utc::now().timestamp()).await {
+            Ok(0) => {}
+            Ok(deleted) => info!("Deleted {deleted} event(s) past their retention"),
+            Err(e) => error!("Failed to prune events: {e}"),
+        }
+    }
+}
+
+#[test]
+fn test_validate_event_retention() {
+    assert!(EventRetention::default().validate().is_ok());
+    let forever = EventRetention {
+        console_days: None,
+        warning_days: None,
+        info_days: None,
+        max_rows: None,
+    };
+    assert!(forever.validate().is_ok());
+    assert!(EventRetention {
+        console_days: Some(0),
+        ..Default::default()
+    }
+    .validate()
+    .is_err());
+    assert!(EventRetention {
+        max_rows: Some(0),
+        ..Default::default()
+    }
+    .validate()
+    .is_err());
+}
@@ -1,15 +1,24 @@
 #![allow(clippy::enum_variant_names)]
 
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::{
+    ansi::AnsiSpan,
     auth::{permission::UserPermission, user_id::UserId},
     macro_executor::MacroPID,
     output_types::ClientEvent,
-    traits::{t_macro::ExitStatus, t_player::Player, t_server::State, InstanceInfo},
+    traits::{
+        t_macro::ExitStatus,
+        t_player::Player,
+        t_server::{MonitorReport, State},
+        InstanceInfo,
+    },
     types::{InstanceUuid, Snowflake, TimeRange},
 };
 
@@ -106,6 +115,9 @@ pub enum InstanceEventInner {
     },
     InstanceOutput {
         message: String,
+        /// The styled text of `message`, when the instance parses ANSI escape sequences
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        spans: Option<Vec<AnsiSpan>>,
     },
     SystemMessage {
         message: String,
@@ -120,6 +132,20 @@ pub enum InstanceEventInner {
         player: String,
         player_message: String,
     },
+    PlayerJoined {
+        player: String,
+    },
+    PlayerLeft {
+        player: String,
+    },
+    PlayerDeath {
+        player: String,
+        death_message: String,
+    },
+    InstanceRenamed {
+        old_name: String,
+        new_name: String,
+    },
 }
 
 impl AsRef<InstanceEventInner> for InstanceEventInner {
@@ -151,6 +177,21 @@ pub enum UserEventInner {
     PermissionChanged {
         new_permissions: Box<UserPermission>,
     },
+    /// The owner account was created through first time setup
+    SetupCompleted,
+    /// The user was granted or lost the owner or admin role, e.g. by an ownership transfer
+    RoleChanged {
+        is_owner: bool,
+        is_admin: bool,
+    },
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    /// The user's role isn't allowed to send a command
+    CommandDenied {
+        instance_uuid: InstanceUuid,
+        /// The command, or the file whose change was denied in its place
+        attempt: String,
+    },
 }
 
 impl AsRef<UserEventInner> for UserEventInner {
@@ -217,8 +258,16 @@ pub enum ProgressionEndValue {
 #[ts(export)]
 #[serde(tag = "type")]
 pub enum ProgressionStartValue {
-    InstanceCreation { instance_uuid: InstanceUuid },
-    InstanceDelete { instance_uuid: InstanceUuid },
+    InstanceCreation {
+        instance_uuid: InstanceUuid,
+    },
+    InstanceDelete {
+        instance_uuid: InstanceUuid,
+    },
+    /// Any other operation on an existing instance
+    InstanceOperation {
+        instance_uuid: InstanceUuid,
+    },
 }
 
 // the backend will keep exactly 1 copy of ProgressionStart, and 1 copy of ProgressionUpdate OR ProgressionEnd
@@ -277,6 +326,13 @@ pub struct FSEvent {
     pub target: FSTarget,
 }
 
+/// Monitor reports of every instance that changed materially during one tick of the monitor task
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[ts(export)]
+pub struct MonitorEvent {
+    pub reports: HashMap<InstanceUuid, MonitorReport>,
+}
+
 pub fn new_fs_event(operation: FSOperation, target: FSTarget, caused_by: CausedBy) -> Event {
     Event {
         details: "".to_string(),
@@ -331,6 +387,7 @@ pub enum EventInner {
     FSEvent(FSEvent),
     ProgressionEvent(ProgressionEvent),
     PlayitggRunnerEvent(PlayitggRunnerEvent),
+    MonitorEvent(MonitorEvent),
 }
 
 impl AsRef<EventInner> for EventInner {
@@ -418,6 +475,9 @@ impl Event {
             _ => false,
         }
     }
+    pub fn is_event_monitor_report(&self) -> bool {
+        matches!(self.event_inner, EventInner::MonitorEvent(_))
+    }
     pub fn try_player_message(&self) -> Option<(String, String)> {
         match &self.event_inner {
             EventInner::InstanceEvent(instance_event) => match &instance_event.instance_event_inner
@@ -456,7 +516,10 @@ impl Event {
             event_inner: EventInner::InstanceEvent(InstanceEvent {
                 instance_uuid,
                 instance_name,
-                instance_event_inner: InstanceEventInner::InstanceOutput { message: output },
+                instance_event_inner: InstanceEventInner::InstanceOutput {
+                    message: output,
+                    spans: None,
+                },
             }),
             caused_by: CausedBy::System,
         }
@@ -483,6 +546,61 @@ impl Event {
         }
     }
 
+    pub fn new_player_joined(
+        instance_uuid: InstanceUuid,
+        instance_name: String,
+        player: String,
+    ) -> Event {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner: InstanceEventInner::PlayerJoined { player },
+            }),
+            caused_by: CausedBy::System,
+        }
+    }
+
+    pub fn new_player_left(
+        instance_uuid: InstanceUuid,
+        instance_name: String,
+        player: String,
+    ) -> Event {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner: InstanceEventInner::PlayerLeft { player },
+            }),
+            caused_by: CausedBy::System,
+        }
+    }
+
+    pub fn new_player_death(
+        instance_uuid: InstanceUuid,
+        instance_name: String,
+        player: String,
+        death_message: String,
+    ) -> Event {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner: InstanceEventInner::PlayerDeath {
+                    player,
+                    death_message,
+                },
+            }),
+            caused_by: CausedBy::System,
+        }
+    }
+
     pub fn new_system_message(
         instance_uuid: InstanceUuid,
         instance_name: String,
@@ -502,6 +620,82 @@ impl Event {
         }
     }
 
+    pub fn new_setup_completed(owner_uid: UserId) -> Event {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::UserEvent(UserEvent {
+                user_id: owner_uid,
+                user_event_inner: UserEventInner::SetupCompleted,
+            }),
+            caused_by: CausedBy::System,
+        }
+    }
+
+    pub fn new_command_denied(
+        user_id: UserId,
+        instance_uuid: InstanceUuid,
+        attempt: String,
+        caused_by: CausedBy,
+    ) -> Event {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::UserEvent(UserEvent {
+                user_id,
+                user_event_inner: UserEventInner::CommandDenied {
+                    instance_uuid,
+                    attempt,
+                },
+            }),
+            caused_by,
+        }
+    }
+
+    pub fn new_monitor_event(reports: HashMap<InstanceUuid, MonitorReport>) -> Event {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::MonitorEvent(MonitorEvent { reports }),
+            caused_by: CausedBy::System,
+        }
+    }
+
+    pub fn new_instance_warning(
+        instance_uuid: InstanceUuid,
+        instance_name: String,
+        message: String,
+    ) -> Event {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner: InstanceEventInner::InstanceWarning { message },
+            }),
+            caused_by: CausedBy::System,
+        }
+    }
+
+    pub fn new_instance_renamed(
+        instance_uuid: InstanceUuid,
+        old_name: String,
+        new_name: String,
+        caused_by: CausedBy,
+    ) -> Event {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name: new_name.clone(),
+                instance_event_inner: InstanceEventInner::InstanceRenamed { old_name, new_name },
+            }),
+            caused_by,
+        }
+    }
+
     pub fn new_instance_state_transition(
         instance_uuid: InstanceUuid,
         instance_name: String,
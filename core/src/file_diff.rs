@@ -0,0 +1,116 @@
+//! Unified diffs between text files, e.g. a config file and its copy in another instance
+
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    types::InstanceUuid,
+};
+
+/// Longest diff returned, the rest is cut off
+const MAX_DIFF_BYTES: usize = 256 * 1024;
+
+const CONTEXT_LINES: usize = 3;
+
+/// What a file is compared against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffTarget {
+    /// The file at the same path in another instance, `instance:<uuid>`
+    Instance(InstanceUuid),
+    /// A file in the instance trash, `trash:<id>`
+    Trash(String),
+}
+
+impl std::str::FromStr for DiffTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("instance", uuid)) if !uuid.is_empty() => {
+                Ok(DiffTarget::Instance(InstanceUuid::from(uuid.to_string())))
+            }
+            Some(("trash", id)) if !id.is_empty() => Ok(DiffTarget::Trash(id.to_string())),
+            _ => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Expected instance:<uuid> or trash:<id> to compare against"),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct FileDiff {
+    /// Unified diff from the compared file to the current one, empty if they are the same
+    pub diff: String,
+    pub truncated: bool,
+}
+
+/// Reads a text file, refusing binary files and files over `max_bytes`
+pub async fn read_text(path: &Path, max_bytes: u64) -> Result<String, Error> {
+    let metadata = tokio::fs::metadata(path).await.map_err(|_| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("File {} not found", path.display()),
+    })?;
+    if !metadata.is_file() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} is not a file", path.display()),
+        });
+    }
+    if metadata.len() > max_bytes {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} is too large to diff", path.display()),
+        });
+    }
+    let content = tokio::fs::read(path)
+        .await
+        .context(format!("Failed to read {}", path.display()))?;
+    match String::from_utf8(content) {
+        Ok(content) if !content.contains('\0') => Ok(content),
+        _ => Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} is not a text file", path.display()),
+        }),
+    }
+}
+
+pub fn unified_diff(old_name: &str, old: &str, new_name: &str, new: &str) -> FileDiff {
+    let mut diff = TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(old_name, new_name)
+        .to_string();
+    let truncated = diff.len() > MAX_DIFF_BYTES;
+    if truncated {
+        // cut at the end of a line
+        let end = diff[..MAX_DIFF_BYTES].rfind('\n').map_or(0, |i| i + 1);
+        diff.truncate(end);
+    }
+    FileDiff { diff, truncated }
+}
+
+#[test]
+fn test_unified_diff() {
+    let old = "motd=A Minecraft Server\nmax-players=20\npvp=true\n";
+    let new = "motd=A Minecraft Server\nmax-players=50\npvp=true\n";
+    let diff = unified_diff("a/server.properties", old, "b/server.properties", new);
+    assert!(!diff.truncated);
+    assert!(diff
+        .diff
+        .starts_with("--- a/server.properties\n+++ b/server.properties\n"));
+    assert!(diff.diff.contains("-max-players=20\n+max-players=50\n"));
+    assert!(unified_diff("a", old, "b", old).diff.is_empty());
+
+    assert_eq!(
+        "instance:INSTANCE_1".parse::<DiffTarget>().unwrap(),
+        DiffTarget::Instance(InstanceUuid::from("INSTANCE_1".to_string()))
+    );
+    assert!("backup:1".parse::<DiffTarget>().is_err());
+}
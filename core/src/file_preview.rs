@@ -0,0 +1,176 @@
+//! Guesses how a file should be shown, from its extension and first few bytes
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// Bytes read from the start of a file to sniff its type and show as a preview
+pub const PREVIEW_HEAD_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum FileKind {
+    Text,
+    Json,
+    Yaml,
+    Image,
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct FilePreview {
+    pub kind: FileKind,
+    /// Only for images
+    pub mime_type: Option<String>,
+    /// Only for text files
+    pub encoding: Option<TextEncoding>,
+    pub size: u64,
+    /// Start of the file, only for text files
+    pub head: Option<String>,
+    /// The file is too large to be edited in the browser
+    pub too_large_to_edit: bool,
+}
+
+fn image_mime_type(head: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n".as_slice(), "image/png"),
+        (b"\xff\xd8\xff".as_slice(), "image/jpeg"),
+        (b"GIF87a".as_slice(), "image/gif"),
+        (b"GIF89a".as_slice(), "image/gif"),
+        (b"\x00\x00\x01\x00".as_slice(), "image/x-icon"),
+    ];
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+        .map(|(_, mime_type)| *mime_type)
+}
+
+/// `None` if `head` doesn't look like text
+fn text_encoding(head: &[u8]) -> Option<TextEncoding> {
+    if head.starts_with(b"\xff\xfe") {
+        return Some(TextEncoding::Utf16Le);
+    }
+    if head.starts_with(b"\xfe\xff") {
+        return Some(TextEncoding::Utf16Be);
+    }
+    if head.contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => Some(TextEncoding::Utf8),
+        // the head may end in the middle of a character
+        Err(e) if e.error_len().is_none() => Some(TextEncoding::Utf8),
+        Err(_) => None,
+    }
+}
+
+fn decode_head(head: &[u8], encoding: TextEncoding) -> String {
+    match encoding {
+        TextEncoding::Utf8 => {
+            String::from_utf8_lossy(head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head)).to_string()
+        }
+        TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+            let units: Vec<u16> = head[2..]
+                .chunks_exact(2)
+                .map(|pair| match encoding {
+                    TextEncoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+    }
+}
+
+/// Detects the kind of the file at `path` from its first bytes, content wins over the extension
+pub fn preview(path: &Path, head: &[u8], size: u64, max_edit_size: u64) -> FilePreview {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    if let Some(mime_type) = image_mime_type(head) {
+        return FilePreview {
+            kind: FileKind::Image,
+            mime_type: Some(mime_type.to_string()),
+            encoding: None,
+            size,
+            head: None,
+            too_large_to_edit: true,
+        };
+    }
+    let encoding = match text_encoding(head) {
+        Some(encoding) => encoding,
+        None => {
+            return FilePreview {
+                kind: FileKind::Binary,
+                mime_type: None,
+                encoding: None,
+                size,
+                head: None,
+                too_large_to_edit: true,
+            }
+        }
+    };
+    let kind = match extension.as_deref() {
+        Some("json") | Some("json5") | Some("mcmeta") => FileKind::Json,
+        Some("yml") | Some("yaml") => FileKind::Yaml,
+        _ => FileKind::Text,
+    };
+    FilePreview {
+        kind,
+        mime_type: None,
+        encoding: Some(encoding),
+        size,
+        head: Some(decode_head(head, encoding)),
+        too_large_to_edit: size > max_edit_size,
+    }
+}
+
+#[test]
+fn test_preview() {
+    let png = preview(
+        Path::new("server-icon.png"),
+        b"\x89PNG\r\n\x1a\n\0\0",
+        2048,
+        1024,
+    );
+    assert_eq!(png.kind, FileKind::Image);
+    assert_eq!(png.mime_type.as_deref(), Some("image/png"));
+
+    let config = preview(
+        Path::new("config.yml"),
+        b"settings:\n  debug: false\n",
+        25,
+        1024,
+    );
+    assert_eq!(config.kind, FileKind::Yaml);
+    assert_eq!(config.encoding, Some(TextEncoding::Utf8));
+    assert!(!config.too_large_to_edit);
+
+    let log = preview(Path::new("latest.log"), "caf\u{e9}".as_bytes(), 4096, 1024);
+    assert_eq!(log.kind, FileKind::Text);
+    assert!(log.too_large_to_edit);
+
+    let utf16 = preview(Path::new("notes.txt"), b"\xff\xfeh\0i\0", 6, 1024);
+    assert_eq!(utf16.encoding, Some(TextEncoding::Utf16Le));
+    assert_eq!(utf16.head.as_deref(), Some("hi"));
+
+    // a region file named like a text file is still binary
+    let region = preview(Path::new("r.0.0.txt"), b"\0\0\x02\x01\xff", 8192, 1024);
+    assert_eq!(region.kind, FileKind::Binary);
+}
@@ -0,0 +1,224 @@
+//! Files deleted through the instance file handlers are moved to a `.trash` directory
+//! at the root of the instance, and can be restored until the retention period runs out
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::util::{fs, rand_alphanumeric, resolve_path_conflict, scoped_join_win_safe};
+
+pub const TRASH_DIR_NAME: &str = ".trash";
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct TrashedFile {
+    pub id: String,
+    /// Where the file was, relative to the instance root
+    pub path: String,
+    pub is_dir: bool,
+    pub deleted_at: i64,
+}
+
+pub fn trash_dir(root: &Path) -> PathBuf {
+    root.join(TRASH_DIR_NAME)
+}
+
+/// Each trashed file is kept as `.trash/<id>/<file name>`, described by `.trash/<id>.json`
+fn entry_paths(root: &Path, id: &str) -> Result<(PathBuf, PathBuf), Error> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid trash entry id"),
+        });
+    }
+    let trash_dir = trash_dir(root);
+    Ok((trash_dir.join(id), trash_dir.join(format!("{id}.json"))))
+}
+
+fn is_expired(deleted_at: i64, now: i64, retention_days: u32) -> bool {
+    now - deleted_at >= retention_days as i64 * 24 * 60 * 60
+}
+
+/// Moves `path`, a file or directory inside the instance at `root`, to the instance's trash
+pub async fn move_to_trash(root: &Path, path: &Path) -> Result<TrashedFile, Error> {
+    let relative_path = path
+        .strip_prefix(root)
+        .context("Path is not inside the instance")?;
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| eyre!("Path has no file name"))?;
+    let id = rand_alphanumeric(16);
+    let (entry_dir, entry_file) = entry_paths(root, &id)?;
+    let trashed = TrashedFile {
+        id,
+        path: relative_path.to_string_lossy().to_string(),
+        is_dir: path.is_dir(),
+        deleted_at: chrono::Utc::now().timestamp(),
+    };
+    fs::create_dir_all(&entry_dir).await?;
+    fs::rename(path, entry_dir.join(file_name)).await?;
+    fs::write_all(&entry_file, serde_json::to_string_pretty(&trashed).unwrap()).await?;
+    Ok(trashed)
+}
+
+/// Files in the instance's trash, most recently deleted first
+pub async fn list_trash(root: &Path) -> Result<Vec<TrashedFile>, Error> {
+    let trash_dir = trash_dir(root);
+    if !trash_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    let mut read_dir = tokio::fs::read_dir(&trash_dir)
+        .await
+        .context("Failed to read trash directory")?;
+    while let Some(entry) = read_dir
+        .next_entry()
+        .await
+        .context("Failed to read trash directory")?
+    {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        match fs::read_to_string(&path).await.and_then(|s| {
+            Ok(serde_json::from_str::<TrashedFile>(&s).context("Invalid trash entry")?)
+        }) {
+            Ok(trashed) => entries.push(trashed),
+            Err(e) => error!("Skipping trash entry {}: {e}", path.display()),
+        }
+    }
+    entries.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    Ok(entries)
+}
+
+/// A trashed file and where its content is kept, for reading it without restoring it
+pub async fn trashed_file(root: &Path, id: &str) -> Result<(TrashedFile, PathBuf), Error> {
+    let (entry_dir, entry_file) = entry_paths(root, id)?;
+    let trashed: TrashedFile = match fs::read_to_string(&entry_file).await {
+        Ok(s) => serde_json::from_str(&s).context("Invalid trash entry")?,
+        Err(_) => {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Trash entry not found"),
+            })
+        }
+    };
+    let file_name = Path::new(&trashed.path)
+        .file_name()
+        .ok_or_else(|| eyre!("Trash entry has no file name"))?;
+    let path = entry_dir.join(file_name);
+    Ok((trashed, path))
+}
+
+/// Moves a trashed file back to where it was, returning where it ended up
+///
+/// Gets a suffixed name if something else took its place in the meantime
+pub async fn restore_from_trash(root: &Path, id: &str) -> Result<PathBuf, Error> {
+    let (entry_dir, entry_file) = entry_paths(root, id)?;
+    let trashed: TrashedFile = match fs::read_to_string(&entry_file).await {
+        Ok(s) => serde_json::from_str(&s).context("Invalid trash entry")?,
+        Err(_) => {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Trash entry not found"),
+            })
+        }
+    };
+    let destination = scoped_join_win_safe(root, &trashed.path)?;
+    if destination.starts_with(trash_dir(root)) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Trash entry points into the trash"),
+        });
+    }
+    let file_name = destination
+        .file_name()
+        .ok_or_else(|| eyre!("Trash entry has no file name"))?;
+    let source = entry_dir.join(file_name);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let destination = resolve_path_conflict(destination, None);
+    fs::rename(&source, &destination).await?;
+    fs::remove_dir_all(&entry_dir).await?;
+    fs::remove_file(&entry_file).await?;
+    Ok(destination)
+}
+
+/// Permanently deletes files that have been in the trash longer than `retention_days`
+pub async fn sweep_trash(root: &Path, retention_days: u32) {
+    let entries = match list_trash(root).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to sweep trash of {}: {e}", root.display());
+            return;
+        }
+    };
+    let now = chrono::Utc::now().timestamp();
+    for trashed in entries {
+        if !is_expired(trashed.deleted_at, now, retention_days) {
+            continue;
+        }
+        if let Ok((entry_dir, entry_file)) = entry_paths(root, &trashed.id) {
+            let res = match fs::remove_dir_all(&entry_dir).await {
+                Ok(_) => fs::remove_file(&entry_file).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                error!("Failed to empty {} from the trash: {e}", trashed.path);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_is_expired() {
+    let day = 24 * 60 * 60;
+    assert!(!is_expired(0, 6 * day, 7));
+    assert!(is_expired(0, 7 * day, 7));
+}
+
+#[tokio::test]
+async fn test_trash_and_restore() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    std::fs::create_dir_all(root.join("world/region")).unwrap();
+    std::fs::write(root.join("world/region/r.0.0.mca"), "region").unwrap();
+
+    let trashed = move_to_trash(root, &root.join("world/region/r.0.0.mca"))
+        .await
+        .unwrap();
+    assert!(!root.join("world/region/r.0.0.mca").exists());
+    let entries = list_trash(root).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        PathBuf::from(&entries[0].path),
+        PathBuf::from("world/region/r.0.0.mca")
+    );
+
+    // a new file took its place
+    std::fs::write(root.join("world/region/r.0.0.mca"), "new").unwrap();
+    let restored = restore_from_trash(root, &trashed.id).await.unwrap();
+    assert_ne!(restored, root.join("world/region/r.0.0.mca"));
+    assert_eq!(std::fs::read_to_string(restored).unwrap(), "region");
+    assert!(list_trash(root).await.unwrap().is_empty());
+    assert!(restore_from_trash(root, "../escape").await.is_err());
+
+    // a forged entry can't restore into the trash itself
+    std::fs::write(root.join("server.properties"), "motd").unwrap();
+    let trashed = move_to_trash(root, &root.join("server.properties"))
+        .await
+        .unwrap();
+    let mut forged = trashed.clone();
+    forged.path = format!("{TRASH_DIR_NAME}/server.properties");
+    std::fs::write(
+        trash_dir(root).join(format!("{}.json", trashed.id)),
+        serde_json::to_string(&forged).unwrap(),
+    )
+    .unwrap();
+    assert!(restore_from_trash(root, &trashed.id).await.is_err());
+}
@@ -0,0 +1,696 @@
+//! A hostname based reverse proxy for Minecraft instances.
+//!
+//! The gateway listens on a single port (25564 by default) and reads the handshake packet
+//! of every incoming connection. The server address the client connected with is looked up
+//! in the route table, and the connection is then proxied to the port of the matching instance.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{eyre, Context},
+    Report,
+};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    implementations::minecraft::util::read_properties_from_path,
+    prelude::GameInstance,
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+};
+
+/// A handshake packet is tiny, anything larger than this is not a Minecraft client
+const MAX_HANDSHAKE_LENGTH: i32 = 1024;
+
+/// Login start only carries a name and a uuid, status and ping packets are even smaller
+const MAX_PRE_LOGIN_PACKET_LENGTH: i32 = 1024;
+
+/// Clients that haven't finished the handshake and login start by then are dropped, so idle
+/// sockets can't pile up
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Instance ports are allocated upwards from 25565, so the gateway stays just below them
+const DEFAULT_LISTEN_PORT: u16 = 25564;
+
+/// Peak concurrent connections are tracked in one minute buckets over this many minutes
+const PEAK_WINDOW_MINUTES: usize = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct GatewayConfig {
+    pub enabled: bool,
+    pub listen_port: u16,
+    /// Maps a hostname (e.g. `survival.example.com`) to the instance it should be routed to
+    pub routes: HashMap<String, InstanceUuid>,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_port: DEFAULT_LISTEN_PORT,
+            routes: HashMap::new(),
+        }
+    }
+}
+
+/// While enabled, the gateway answers status pings with `message` as the MOTD and
+/// disconnects players with it, except for allowed players and the instance's operators.
+///
+/// Players are let through by the name their client sends before logging in, which only
+/// the backend checks afterwards and only in online mode. Offline mode instances therefore
+/// let nobody through, anyone could claim an allowed name.
+///
+/// Only connections going through the gateway are affected
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct MaintenanceMode {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub message: String,
+    /// Players (case insensitive) that may still join, in addition to the ops in `ops.json`
+    #[serde(default)]
+    pub allowed_players: BTreeSet<String>,
+}
+
+impl MaintenanceMode {
+    fn message(&self) -> &str {
+        if self.message.is_empty() {
+            "Server is under maintenance"
+        } else {
+            &self.message
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    pub protocol_version: i32,
+    pub server_address: String,
+    pub server_port: u16,
+    pub next_state: i32,
+}
+
+pub(crate) fn read_var_int(buf: &[u8], cursor: &mut usize) -> Option<i32> {
+    let mut value: i32 = 0;
+    for i in 0..5 {
+        let byte = *buf.get(*cursor)?;
+        *cursor += 1;
+        value |= ((byte & 0x7F) as i32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+pub(crate) fn write_var_int(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7F == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+pub(crate) fn read_string<'a>(buf: &'a [u8], cursor: &mut usize) -> Option<&'a str> {
+    let length = read_var_int(buf, cursor)?;
+    if length < 0 {
+        return None;
+    }
+    let end = cursor.checked_add(length as usize)?;
+    let s = std::str::from_utf8(buf.get(*cursor..end)?).ok()?;
+    *cursor = end;
+    Some(s)
+}
+
+/// Builds a length-prefixed packet carrying a single string field
+fn string_packet(packet_id: i32, s: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_var_int(&mut body, packet_id);
+    write_var_int(&mut body, s.len() as i32);
+    body.extend_from_slice(s.as_bytes());
+    let mut packet = Vec::new();
+    write_var_int(&mut packet, body.len() as i32);
+    packet.extend_from_slice(&body);
+    packet
+}
+
+/// Parses the player name out of the body of a login start packet
+pub fn parse_login_start(packet: &[u8]) -> Option<String> {
+    let mut cursor = 0;
+    if read_var_int(packet, &mut cursor)? != 0x00 {
+        return None;
+    }
+    Some(read_string(packet, &mut cursor)?.to_owned())
+}
+
+/// Parses the body of a handshake packet, without the length prefix
+pub fn parse_handshake(packet: &[u8]) -> Option<Handshake> {
+    let mut cursor = 0;
+    let packet_id = read_var_int(packet, &mut cursor)?;
+    if packet_id != 0x00 {
+        return None;
+    }
+    let protocol_version = read_var_int(packet, &mut cursor)?;
+    let server_address = read_string(packet, &mut cursor)?;
+    let server_port = u16::from_be_bytes([*packet.get(cursor)?, *packet.get(cursor + 1)?]);
+    cursor += 2;
+    let next_state = read_var_int(packet, &mut cursor)?;
+    Some(Handshake {
+        protocol_version,
+        server_address: normalize_hostname(server_address),
+        server_port,
+        next_state,
+    })
+}
+
+/// Forge appends `\0FML\0` markers and some clients append a trailing dot,
+/// strip those so the address can be matched against the route table
+fn normalize_hostname(address: &str) -> String {
+    address
+        .split('\0')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('.')
+        .to_lowercase()
+}
+
+/// Reads a length-prefixed packet from the stream.
+///
+/// Returns the raw bytes read (so they can be replayed to the backend) and the packet body
+pub(crate) async fn read_packet(
+    stream: &mut TcpStream,
+    max_length: i32,
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let mut raw = Vec::new();
+    let mut length: i32 = 0;
+    for i in 0..3 {
+        let byte = stream
+            .read_u8()
+            .await
+            .context("Failed to read packet length")?;
+        raw.push(byte);
+        length |= ((byte & 0x7F) as i32) << (7 * i);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    if length <= 0 || length > max_length {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid packet length {}", length),
+        });
+    }
+    let mut packet = vec![0; length as usize];
+    stream
+        .read_exact(&mut packet)
+        .await
+        .context("Failed to read packet")?;
+    raw.extend_from_slice(&packet);
+    Ok((raw, packet))
+}
+
+/// Fails if `future`, reading from a client that hasn't been proxied yet, takes longer than
+/// [`HANDSHAKE_TIMEOUT`]
+async fn before_handshake_timeout<T>(
+    future: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, future)
+        .await
+        .map_err(|_| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Client did not finish the handshake in time"),
+        })?
+}
+
+/// Reads the handshake packet from the stream.
+///
+/// Returns the raw bytes read (so they can be replayed to the backend) and the parsed handshake
+async fn read_handshake(stream: &mut TcpStream) -> Result<(Vec<u8>, Handshake), Error> {
+    let (raw, packet) = read_packet(stream, MAX_HANDSHAKE_LENGTH).await?;
+    let handshake = parse_handshake(&packet).ok_or_else(|| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Malformed handshake packet"),
+    })?;
+    Ok((raw, handshake))
+}
+
+/// Names of the operators in the instance's `ops.json`, lowercased
+async fn read_operators(path_to_instance: &Path) -> BTreeSet<String> {
+    #[derive(Deserialize)]
+    struct Operator {
+        name: String,
+    }
+    let ops: Vec<Operator> = match tokio::fs::read(path_to_instance.join("ops.json")).await {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+    ops.into_iter().map(|op| op.name.to_lowercase()).collect()
+}
+
+/// Whether the instance's `server.properties` has `online-mode` on, the default when unset
+pub(crate) async fn is_online_mode(path_to_instance: &Path) -> bool {
+    read_properties_from_path(&path_to_instance.join("server.properties"))
+        .await
+        .ok()
+        .and_then(|properties| properties.get("online-mode").cloned())
+        .map_or(true, |online_mode| online_mode.trim() != "false")
+}
+
+/// Answers a server list ping with the maintenance message as the MOTD
+async fn answer_maintenance_status(client: &mut TcpStream, message: &str) -> Result<(), Error> {
+    // status request, has no fields
+    before_handshake_timeout(read_packet(client, MAX_PRE_LOGIN_PACKET_LENGTH)).await?;
+    let status = serde_json::json!({
+        "version": { "name": "Maintenance", "protocol": -1 },
+        "players": { "max": 0, "online": 0 },
+        "description": { "text": message },
+    });
+    client
+        .write_all(&string_packet(0x00, &status.to_string()))
+        .await
+        .context("Failed to send status response")?;
+    // the ping packet is echoed back as the pong
+    if let Ok((raw, _)) =
+        before_handshake_timeout(read_packet(client, MAX_PRE_LOGIN_PACKET_LENGTH)).await
+    {
+        client
+            .write_all(&raw)
+            .await
+            .context("Failed to send pong")?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS, Default)]
+#[ts(export)]
+pub struct GatewayStatsReport {
+    pub active_connections: u32,
+    pub total_connections: u64,
+    /// Bytes sent from clients to the instance
+    pub bytes_in: u64,
+    /// Bytes sent from the instance to clients
+    pub bytes_out: u64,
+    /// Highest number of concurrent connections in the last few minutes
+    pub peak_connections: u32,
+}
+
+/// Connection counters of a single backend instance.
+///
+/// Everything is an atomic so the proxy loop never has to take a lock
+#[derive(Default)]
+pub struct BackendStats {
+    active_connections: AtomicU32,
+    total_connections: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    peak_bucket_minute: [AtomicI64; PEAK_WINDOW_MINUTES],
+    peak_bucket_value: [AtomicU32; PEAK_WINDOW_MINUTES],
+}
+
+fn current_minute() -> i64 {
+    chrono::Utc::now().timestamp() / 60
+}
+
+impl BackendStats {
+    fn record_peak(&self, active: u32) {
+        let minute = current_minute();
+        let bucket = minute as usize % PEAK_WINDOW_MINUTES;
+        let bucket_minute = self.peak_bucket_minute[bucket].load(Ordering::Relaxed);
+        if bucket_minute != minute
+            && self.peak_bucket_minute[bucket]
+                .compare_exchange(bucket_minute, minute, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            // the bucket is stale, start counting this minute from scratch
+            self.peak_bucket_value[bucket].store(0, Ordering::Relaxed);
+        }
+        self.peak_bucket_value[bucket].fetch_max(active, Ordering::Relaxed);
+    }
+
+    fn connection_opened(&self) {
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+        let active = self.active_connections.fetch_add(1, Ordering::Relaxed) + 1;
+        self.record_peak(active);
+    }
+
+    fn connection_closed(&self) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> GatewayStatsReport {
+        let minute = current_minute();
+        let active_connections = self.active_connections.load(Ordering::Relaxed);
+        let peak_connections = (0..PEAK_WINDOW_MINUTES)
+            .filter(|i| {
+                minute - self.peak_bucket_minute[*i].load(Ordering::Relaxed)
+                    < PEAK_WINDOW_MINUTES as i64
+            })
+            .map(|i| self.peak_bucket_value[i].load(Ordering::Relaxed))
+            .max()
+            .unwrap_or(0)
+            .max(active_connections);
+        GatewayStatsReport {
+            active_connections,
+            total_connections: self.total_connections.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            peak_connections,
+        }
+    }
+}
+
+async fn copy_counted<R, W>(
+    mut reader: R,
+    mut writer: W,
+    counter: &AtomicU64,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; 8192];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n]).await?;
+        counter.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    writer.shutdown().await
+}
+
+async fn stop_listener(listener_handle: &mut Option<JoinHandle<()>>) {
+    if let Some(handle) = listener_handle.take() {
+        handle.abort();
+        // wait for the old listener to be dropped so the port is freed
+        let _ = handle.await;
+    }
+}
+
+/// Binds the port of `config`, `None` if the gateway is disabled
+async fn bind_listener(config: &GatewayConfig) -> Result<Option<TcpListener>, Error> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let listener = TcpListener::bind(("0.0.0.0", config.listen_port))
+        .await
+        .context(format!(
+            "Failed to bind gateway to port {}",
+            config.listen_port
+        ))?;
+    info!("Gateway listening on port {}", config.listen_port);
+    Ok(Some(listener))
+}
+
+#[derive(Clone)]
+pub struct Gateway {
+    config: Arc<Mutex<GatewayConfig>>,
+    path_to_config: PathBuf,
+    instances: Arc<DashMap<InstanceUuid, GameInstance>>,
+    listener_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    stats: Arc<DashMap<InstanceUuid, Arc<BackendStats>>>,
+}
+
+impl Gateway {
+    pub async fn new(
+        path_to_config: PathBuf,
+        instances: Arc<DashMap<InstanceUuid, GameInstance>>,
+    ) -> Result<Self, Error> {
+        let config = match tokio::fs::read(&path_to_config).await {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(config) => config,
+                Err(e) => {
+                    error!(
+                        "Failed to parse gateway config at {}, using the defaults: {}",
+                        path_to_config.display(),
+                        e
+                    );
+                    GatewayConfig::default()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => GatewayConfig::default(),
+            Err(e) => {
+                return Err(Report::new(e)
+                    .wrap_err(format!(
+                        "Failed to read gateway config at {}",
+                        path_to_config.display()
+                    ))
+                    .into())
+            }
+        };
+        Ok(Self {
+            config: Arc::new(Mutex::new(config)),
+            path_to_config,
+            instances,
+            listener_handle: Arc::new(Mutex::new(None)),
+            stats: Arc::new(DashMap::new()),
+        })
+    }
+
+    pub fn stats(&self, uuid: &InstanceUuid) -> Option<GatewayStatsReport> {
+        self.stats.get(uuid).map(|stats| stats.report())
+    }
+
+    pub fn all_stats(&self) -> HashMap<InstanceUuid, GatewayStatsReport> {
+        self.stats
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().report()))
+            .collect()
+    }
+
+    pub async fn config(&self) -> GatewayConfig {
+        self.config.lock().await.clone()
+    }
+
+    /// Switches to `config`, which is only saved once its port could be bound. On failure the
+    /// previous config stays in effect
+    pub async fn set_config(&self, config: GatewayConfig) -> Result<(), Error> {
+        let serialized =
+            serde_json::to_string_pretty(&config).context("Failed to serialize gateway config")?;
+        let mut listener_handle = self.listener_handle.lock().await;
+        stop_listener(&mut listener_handle).await;
+        let result = match bind_listener(&config).await {
+            Ok(listener) => crate::util::fs::write_all(&self.path_to_config, serialized)
+                .await
+                .map(|_| listener),
+            Err(e) => Err(e),
+        };
+        let listener = match result {
+            Ok(listener) => listener,
+            Err(e) => {
+                // dropping a listener that was bound for the new config frees its port
+                if let Ok(Some(listener)) = bind_listener(&self.config().await).await {
+                    listener_handle.replace(self.spawn_listener(listener));
+                }
+                return Err(e);
+            }
+        };
+        *self.config.lock().await = config;
+        if let Some(listener) = listener {
+            listener_handle.replace(self.spawn_listener(listener));
+        }
+        Ok(())
+    }
+
+    /// (Re)starts the listener with the current config, stopping it if the gateway is disabled
+    pub async fn restart_listener(&self) -> Result<(), Error> {
+        let mut listener_handle = self.listener_handle.lock().await;
+        stop_listener(&mut listener_handle).await;
+        if let Some(listener) = bind_listener(&self.config().await).await? {
+            listener_handle.replace(self.spawn_listener(listener));
+        }
+        Ok(())
+    }
+
+    fn spawn_listener(&self, listener: TcpListener) -> JoinHandle<()> {
+        let gateway = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("Gateway failed to accept connection: {}", e);
+                        continue;
+                    }
+                };
+                let gateway = gateway.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = gateway.handle_connection(stream).await {
+                        debug!("Gateway connection from {} closed: {}", addr, e);
+                    }
+                });
+            }
+        })
+    }
+
+    async fn backend(&self, hostname: &str) -> Option<(InstanceUuid, GameInstance)> {
+        let uuid = self.config.lock().await.routes.get(hostname)?.clone();
+        let instance = self.instances.get(&uuid)?.value().clone();
+        Some((uuid, instance))
+    }
+
+    async fn handle_connection(&self, mut client: TcpStream) -> Result<(), Error> {
+        let (mut raw_handshake, handshake) =
+            before_handshake_timeout(read_handshake(&mut client)).await?;
+        let (uuid, instance) = match self.backend(&handshake.server_address).await {
+            Some(v) => v,
+            None => {
+                warn!(
+                    "Gateway received connection for unknown host {}",
+                    handshake.server_address
+                );
+                return Err(Error {
+                    kind: ErrorKind::NotFound,
+                    source: eyre!("No route for host {}", handshake.server_address),
+                });
+            }
+        };
+        let maintenance = instance.maintenance().await;
+        if maintenance.enabled {
+            match handshake.next_state {
+                1 => return answer_maintenance_status(&mut client, maintenance.message()).await,
+                2 => {
+                    let (raw_login, login) = before_handshake_timeout(read_packet(
+                        &mut client,
+                        MAX_PRE_LOGIN_PACKET_LENGTH,
+                    ))
+                    .await?;
+                    let player = parse_login_start(&login)
+                        .ok_or_else(|| Error {
+                            kind: ErrorKind::BadRequest,
+                            source: eyre!("Malformed login start packet"),
+                        })?
+                        .to_lowercase();
+                    let path_to_instance = instance.path().await;
+                    // the name is only verified by the backend in online mode
+                    let allowed = is_online_mode(&path_to_instance).await
+                        && (maintenance
+                            .allowed_players
+                            .iter()
+                            .any(|name| name.to_lowercase() == player)
+                            || read_operators(&path_to_instance).await.contains(&player));
+                    if !allowed {
+                        let reason = serde_json::json!({ "text": maintenance.message() });
+                        client
+                            .write_all(&string_packet(0x00, &reason.to_string()))
+                            .await
+                            .context("Failed to send disconnect")?;
+                        return Ok(());
+                    }
+                    raw_handshake.extend_from_slice(&raw_login);
+                }
+                _ => {}
+            }
+        }
+        let port = instance.port().await;
+        let mut backend = TcpStream::connect(("127.0.0.1", port as u16))
+            .await
+            .context(format!("Failed to connect to backend on port {}", port))?;
+        backend
+            .write_all(&raw_handshake)
+            .await
+            .context("Failed to forward handshake to backend")?;
+        let stats = self.stats.entry(uuid).or_default().value().clone();
+        stats
+            .bytes_in
+            .fetch_add(raw_handshake.len() as u64, Ordering::Relaxed);
+        stats.connection_opened();
+        let (client_read, client_write) = client.split();
+        let (backend_read, backend_write) = backend.split();
+        let result = tokio::try_join!(
+            copy_counted(client_read, backend_write, &stats.bytes_in),
+            copy_counted(backend_read, client_write, &stats.bytes_out),
+        );
+        stats.connection_closed();
+        result.context("Gateway proxy error")?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_handshake() {
+    // handshake body for protocol 763, "Mc.Example.com.", port 25565, next state 2
+    let mut packet = vec![0x00, 0xFB, 0x05, 15];
+    packet.extend_from_slice(b"Mc.Example.com.");
+    packet.extend_from_slice(&25565_u16.to_be_bytes());
+    packet.push(0x02);
+    assert_eq!(
+        parse_handshake(&packet),
+        Some(Handshake {
+            protocol_version: 763,
+            server_address: "mc.example.com".to_string(),
+            server_port: 25565,
+            next_state: 2,
+        })
+    );
+
+    let mut forge_packet = vec![0x00, 0xFB, 0x05, 19];
+    forge_packet.extend_from_slice(b"mc.example.com\0FML\0");
+    forge_packet.extend_from_slice(&25565_u16.to_be_bytes());
+    forge_packet.push(0x02);
+    assert_eq!(
+        parse_handshake(&forge_packet).unwrap().server_address,
+        "mc.example.com"
+    );
+
+    assert_eq!(parse_handshake(&[0x01, 0x00]), None);
+    assert_eq!(parse_handshake(&[0x00, 0xFB, 0x05, 40, b'a']), None);
+}
+
+#[test]
+fn test_login_start_and_string_packet() {
+    let mut login = vec![0x00, 5];
+    login.extend_from_slice(b"Steve");
+    // trailing player uuid sent by newer clients
+    login.extend_from_slice(&[0; 16]);
+    assert_eq!(parse_login_start(&login), Some("Steve".to_string()));
+    assert_eq!(parse_login_start(&[0x01, 0x00]), None);
+
+    let message = "a".repeat(200);
+    let packet = string_packet(0x00, &message);
+    let mut cursor = 0;
+    assert_eq!(
+        read_var_int(&packet, &mut cursor),
+        Some(packet.len() as i32 - 2)
+    );
+    assert_eq!(read_var_int(&packet, &mut cursor), Some(0x00));
+    assert_eq!(read_string(&packet, &mut cursor), Some(message.as_str()));
+}
+
+#[tokio::test]
+async fn test_is_online_mode() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    // unset, the server's default applies
+    assert!(is_online_mode(root).await);
+    std::fs::write(root.join("server.properties"), "motd=hi\nonline-mode=false\n").unwrap();
+    assert!(!is_online_mode(root).await);
+    std::fs::write(root.join("server.properties"), "online-mode=true\n").unwrap();
+    assert!(is_online_mode(root).await);
+}
@@ -1,11 +1,53 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
-use color_eyre::eyre::Context;
+use chrono_tz::Tz;
+use color_eyre::eyre::{eyre, Context};
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
+use tokio::sync::watch;
 use ts_rs::TS;
 
-use crate::{error::Error, event_broadcaster::EventBroadcaster};
+use crate::{
+    auth::password_policy::PasswordPolicy,
+    branding::Branding,
+    command_filter::CommandFilters,
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    event_retention::EventRetention,
+    events::CausedBy,
+    offsite_backup::OffsiteBackupConfig,
+    types::Snowflake,
+    webhooks::Webhook,
+};
+
+/// A single mutation of the global settings, persisted for auditing
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct GlobalSettingsChange {
+    pub setting: String,
+    pub old_value: serde_json::Value,
+    pub new_value: serde_json::Value,
+    pub caused_by: CausedBy,
+    pub snowflake: Snowflake,
+}
+
+impl GlobalSettingsChange {
+    pub fn new(
+        setting: impl Into<String>,
+        old_value: impl Serialize,
+        new_value: impl Serialize,
+        caused_by: CausedBy,
+    ) -> Self {
+        Self {
+            setting: setting.into(),
+            old_value: serde_json::to_value(old_value).unwrap_or_default(),
+            new_value: serde_json::to_value(new_value).unwrap_or_default(),
+            caused_by,
+            snowflake: Snowflake::default(),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, TS)]
 #[ts(export)]
@@ -15,75 +57,429 @@ pub struct GlobalSettingsData {
     pub domain: Option<String>,
     #[serde(default)]
     pub playit_enabled: bool,
+    /// Incremented on every change, used to detect concurrent modifications
+    #[serde(default)]
+    pub version: u64,
+    /// Instances won't start and exports won't be created when the free space
+    /// on their filesystem is below this many megabytes
+    #[serde(default = "default_min_free_disk_space_mb")]
+    pub min_free_disk_space_mb: u64,
+    /// Memory in megabytes that must stay free on the host after an instance
+    /// is started with its maximum memory
+    #[serde(default = "default_memory_safety_margin_mb")]
+    pub memory_safety_margin_mb: u64,
+    /// Origins allowed to call the API from a browser, `*` allows any origin.
+    /// Applied on restart
+    #[serde(default = "default_cors_allowed_origins")]
+    pub cors_allowed_origins: Vec<String>,
+    /// Request headers allowed in cross origin requests. Applied on restart
+    #[serde(default = "default_cors_allowed_headers")]
+    pub cors_allowed_headers: Vec<String>,
+    /// Seconds to wait between starting each auto start instance
+    #[serde(default)]
+    pub auto_start_delay_secs: u64,
+    #[serde(default)]
+    pub restart_warnings: RestartWarnings,
+    /// Console commands matching any of these regexes are stored redacted in the command history
+    #[serde(default = "default_console_redact_patterns")]
+    pub console_redact_patterns: Vec<String>,
+    /// How much a monitor report must change before it is broadcast as an event
+    #[serde(default)]
+    pub monitor_event_threshold: MonitorEventThreshold,
+    /// Number of events the event channel holds for slow subscribers before they miss some.
+    /// Overridden by `LODESTONE_EVENT_CHANNEL_CAPACITY`. Applied on restart
+    #[serde(default = "default_event_channel_capacity")]
+    pub event_channel_capacity: usize,
+    /// Seconds a create request's `Idempotency-Key` is remembered for
+    #[serde(default = "default_idempotency_key_ttl_secs")]
+    pub idempotency_key_ttl_secs: u64,
+    /// Days deleted instances and instance files stay in the trash and can be restored
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32,
+    /// Files larger than this are shown as too large to edit in the browser
+    #[serde(default = "default_max_inline_edit_bytes")]
+    pub max_inline_edit_bytes: u64,
+    /// Seconds between monitor history samples, each averaging the live reports in between
+    #[serde(default = "default_monitor_sample_interval_secs")]
+    pub monitor_sample_interval_secs: u64,
+    /// Days monitor history is kept. Samples older than a day are merged into hourly averages
+    #[serde(default = "default_monitor_history_retention_days")]
+    pub monitor_history_retention_days: u32,
+    /// Values `${NAME}` references in macro arguments and launch arguments are replaced with
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    /// Webhook endpoints, each can be checked with `POST /global_settings/webhooks/:id/test`
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    /// Whether instance hooks may run shell commands, macro hooks are always allowed
+    #[serde(default)]
+    pub allow_shell_hooks: bool,
+    /// Compress API responses for clients that accept gzip or brotli. Applied on restart
+    #[serde(default = "default_compress_responses")]
+    pub compress_responses: bool,
+    /// Applied to new passwords, existing passwords keep working but are flagged at login
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+    /// How long saving stays turned off while a running instance is backed up,
+    /// it is turned back on after this even if the backup isn't done
+    #[serde(default = "default_hot_backup_timeout_secs")]
+    pub hot_backup_timeout_secs: u64,
+    /// Forward the ports of running instances through the router with UPnP
+    #[serde(default)]
+    pub upnp_port_forwarding: bool,
+    /// Where backup archives are uploaded to after they are taken
+    #[serde(default)]
+    pub offsite_backup: OffsiteBackupConfig,
+    /// How long stored events are kept, checked every hour
+    #[serde(default)]
+    pub event_retention: EventRetention,
+    /// Console commands admins and users may send, owners can send any command
+    #[serde(default)]
+    pub command_filters: CommandFilters,
+    /// IANA name of the timezone calendar based scheduling like backup retention uses, the
+    /// machine's timezone unless set
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    /// Shown by the frontend in place of Lodestone's name, logo and colors
+    #[serde(default)]
+    pub branding: Branding,
 }
 
-impl Default for GlobalSettingsData {
+/// In-game countdown sent with `/say` before a restart with countdown
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct RestartWarnings {
+    /// Seconds before the restart at which a warning is sent
+    pub offsets_secs: Vec<u64>,
+    /// `{time}` is replaced with the remaining time, e.g. "5 minutes"
+    pub message_template: String,
+    /// Restart right away once no players are online instead of finishing the countdown
+    pub restart_when_empty: bool,
+}
+
+impl Default for RestartWarnings {
     fn default() -> Self {
         Self {
-            core_name: format!("{}'s Lodestone Core", whoami::realname()),
-            safe_mode: true,
-            domain: None,
-            playit_enabled: true,
+            offsets_secs: vec![300, 60, 10],
+            message_template: "Server restarting in {time}".to_string(),
+            restart_when_empty: true,
         }
     }
 }
 
-pub struct GlobalSettings {
-    path_to_global_settings: PathBuf,
-    _event_broadcaster: EventBroadcaster,
-    global_settings_data: GlobalSettingsData,
+impl RestartWarnings {
+    pub fn render(&self, remaining_secs: u64) -> String {
+        let time = match remaining_secs {
+            1 => "1 second".to_string(),
+            60 => "1 minute".to_string(),
+            secs if secs >= 60 && secs % 60 == 0 => format!("{} minutes", secs / 60),
+            secs => format!("{secs} seconds"),
+        };
+        self.message_template.replace("{time}", &time)
+    }
 }
 
-impl GlobalSettings {
-    pub fn new(
-        path_to_global_settings: PathBuf,
-        _event_broadcaster: EventBroadcaster,
-        global_settings_data: GlobalSettingsData,
-    ) -> Self {
+/// Changes smaller than these are treated as jitter and not broadcast as monitor events
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct MonitorEventThreshold {
+    /// Absolute change in CPU usage, in percentage points
+    pub cpu_percent: f32,
+    /// Change in memory usage relative to the last broadcast report, in percent
+    pub memory_percent: f32,
+}
+
+impl Default for MonitorEventThreshold {
+    fn default() -> Self {
         Self {
-            path_to_global_settings,
-            _event_broadcaster,
-            global_settings_data,
+            cpu_percent: 5.0,
+            memory_percent: 5.0,
         }
     }
-    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+}
+
+fn default_idempotency_key_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_trash_retention_days() -> u32 {
+    7
+}
+
+fn default_hot_backup_timeout_secs() -> u64 {
+    600
+}
+
+fn default_max_inline_edit_bytes() -> u64 {
+    2 * 1024 * 1024
+}
+
+fn default_monitor_sample_interval_secs() -> u64 {
+    60
+}
+
+fn default_monitor_history_retention_days() -> u32 {
+    30
+}
+
+fn default_event_channel_capacity() -> usize {
+    4096
+}
+
+fn default_min_free_disk_space_mb() -> u64 {
+    1024
+}
+
+fn default_memory_safety_margin_mb() -> u64 {
+    512
+}
+
+fn default_compress_responses() -> bool {
+    true
+}
+
+fn default_cors_allowed_origins() -> Vec<String> {
+    vec![
+        "http://localhost:3000".to_string(),
+        "http://127.0.0.1:3000".to_string(),
+        "tauri://localhost".to_string(),
+        "https://tauri.localhost".to_string(),
+    ]
+}
+
+fn default_console_redact_patterns() -> Vec<String> {
+    vec![
+        r"(?i)password".to_string(),
+        r"(?i)^/?(login|register|changepassword|authme)\b".to_string(),
+    ]
+}
+
+fn default_timezone() -> String {
+    crate::timezone::local_timezone().name().to_string()
+}
+
+fn default_cors_allowed_headers() -> Vec<String> {
+    vec![
+        "origin".to_string(),
+        "content-type".to_string(),
+        "authorization".to_string(),
+        "x-correlation-id".to_string(),
+        "idempotency-key".to_string(),
+        "if-match".to_string(),
+    ]
+}
+
+/// Declares `GlobalSettingsPatch` with an optional field for each listed setting, and the
+/// step applying it, so a setting only has to be listed here to be patchable. A field can
+/// give a conversion from the patch value to the setting with `=> convert`
+macro_rules! global_settings_patch {
+    ($($(#[$attr:meta])* $field:ident: $ty:ty $(=> $convert:expr)?,)*) => {
+        /// A partial update of the global settings.
+        ///
+        /// `version` must match the current version of the settings, otherwise the patch is
+        /// rejected
+        #[derive(Serialize, Deserialize, Clone, Debug, Default, TS)]
+        #[serde(deny_unknown_fields)]
+        #[ts(export)]
+        pub struct GlobalSettingsPatch {
+            pub version: u64,
+            $($(#[$attr])* pub $field: Option<$ty>,)*
+        }
+
+        impl GlobalSettingsPatch {
+            /// Sets the fields present in the patch, returning a change for each
+            fn apply_to(
+                self,
+                data: &mut GlobalSettingsData,
+                caused_by: &CausedBy,
+            ) -> Vec<GlobalSettingsChange> {
+                let mut changes = Vec::new();
+                $(
+                    if let Some(value) = self.$field {
+                        $(let value = ($convert)(value);)?
+                        changes.push(GlobalSettingsChange::new(
+                            stringify!($field),
+                            &data.$field,
+                            &value,
+                            caused_by.clone(),
+                        ));
+                        data.$field = value;
+                    }
+                )*
+                changes
+            }
+        }
+    };
+}
+
+global_settings_patch! {
+    core_name: String,
+    safe_mode: bool,
+    /// An empty string clears the domain
+    domain: String => |domain: String| if domain.is_empty() { None } else { Some(domain) },
+    playit_enabled: bool,
+    min_free_disk_space_mb: u64,
+    memory_safety_margin_mb: u64,
+    cors_allowed_origins: Vec<String>,
+    cors_allowed_headers: Vec<String>,
+    auto_start_delay_secs: u64,
+    restart_warnings: RestartWarnings,
+    console_redact_patterns: Vec<String>,
+    monitor_event_threshold: MonitorEventThreshold,
+    event_channel_capacity: usize,
+    idempotency_key_ttl_secs: u64,
+    trash_retention_days: u32,
+    max_inline_edit_bytes: u64,
+    monitor_sample_interval_secs: u64,
+    monitor_history_retention_days: u32,
+    variables: BTreeMap<String, String>,
+    webhooks: Vec<Webhook>,
+    allow_shell_hooks: bool,
+    compress_responses: bool,
+    password_policy: PasswordPolicy,
+    hot_backup_timeout_secs: u64,
+    upnp_port_forwarding: bool,
+    offsite_backup: OffsiteBackupConfig,
+    event_retention: EventRetention,
+    command_filters: CommandFilters,
+    timezone: String,
+    branding: Branding,
+}
+
+impl Default for GlobalSettingsData {
+    fn default() -> Self {
+        Self {
+            core_name: format!("{}'s Lodestone Core", whoami::realname()),
+            safe_mode: true,
+            domain: None,
+            playit_enabled: true,
+            version: 0,
+            min_free_disk_space_mb: default_min_free_disk_space_mb(),
+            memory_safety_margin_mb: default_memory_safety_margin_mb(),
+            cors_allowed_origins: default_cors_allowed_origins(),
+            cors_allowed_headers: default_cors_allowed_headers(),
+            auto_start_delay_secs: 0,
+            restart_warnings: RestartWarnings::default(),
+            console_redact_patterns: default_console_redact_patterns(),
+            monitor_event_threshold: MonitorEventThreshold::default(),
+            event_channel_capacity: default_event_channel_capacity(),
+            idempotency_key_ttl_secs: default_idempotency_key_ttl_secs(),
+            trash_retention_days: default_trash_retention_days(),
+            max_inline_edit_bytes: default_max_inline_edit_bytes(),
+            monitor_sample_interval_secs: default_monitor_sample_interval_secs(),
+            monitor_history_retention_days: default_monitor_history_retention_days(),
+            variables: BTreeMap::new(),
+            webhooks: Vec::new(),
+            allow_shell_hooks: false,
+            compress_responses: default_compress_responses(),
+            password_policy: PasswordPolicy::default(),
+            hot_backup_timeout_secs: default_hot_backup_timeout_secs(),
+            upnp_port_forwarding: false,
+            offsite_backup: OffsiteBackupConfig::default(),
+            event_retention: EventRetention::default(),
+            command_filters: CommandFilters::default(),
+            timezone: default_timezone(),
+            branding: Branding::default(),
+        }
+    }
+}
+
+impl GlobalSettingsData {
+    /// Reads the settings without a `GlobalSettings`, for values needed before it can be built
+    pub async fn read_from_file(path_to_global_settings: &Path) -> Result<Self, Error> {
         if tokio::fs::OpenOptions::new()
             .read(true)
             .create(true)
             .write(true)
-            .open(&self.path_to_global_settings)
+            .open(path_to_global_settings)
             .await
             .context(format!(
                 "Failed to open global settings file at {}",
-                self.path_to_global_settings.display()
+                path_to_global_settings.display()
             ))?
             .metadata()
             .await
             .context(format!(
                 "Failed to get metadata for global settings file at {}",
-                self.path_to_global_settings.display()
+                path_to_global_settings.display()
             ))?
             .len()
             == 0
         {
-            self.global_settings_data = GlobalSettingsData::default();
+            Ok(GlobalSettingsData::default())
         } else {
-            self.global_settings_data = serde_json::from_slice(
-                &tokio::fs::read(&self.path_to_global_settings)
-                    .await
-                    .context(format!(
+            Ok(
+                serde_json::from_slice(&tokio::fs::read(path_to_global_settings).await.context(
+                    format!(
                         "Failed to read global settings file at {}",
-                        self.path_to_global_settings.display()
-                    ))?,
+                        path_to_global_settings.display()
+                    ),
+                )?)
+                .context(format!(
+                    "Failed to parse global settings file at {}",
+                    path_to_global_settings.display()
+                ))?,
             )
-            .context(format!(
-                "Failed to parse global settings file at {}",
-                self.path_to_global_settings.display()
-            ))?;
         }
+    }
+
+    /// The settings as shown to users who aren't owners or admins, without webhook URLs which
+    /// can carry tokens, variables, or where offsite backups go and with which key
+    pub fn redacted(&self) -> Self {
+        let mut data = self.clone();
+        for webhook in &mut data.webhooks {
+            webhook.url.clear();
+        }
+        data.variables.clear();
+        data.offsite_backup = OffsiteBackupConfig {
+            enabled: data.offsite_backup.enabled,
+            retention: data.offsite_backup.retention,
+            ..Default::default()
+        };
+        data
+    }
+}
+
+pub struct GlobalSettings {
+    path_to_global_settings: PathBuf,
+    _event_broadcaster: EventBroadcaster,
+    global_settings_data: GlobalSettingsData,
+    /// The parsed timezone, for readers that can't wait for the settings lock
+    timezone: watch::Sender<Tz>,
+}
+
+impl GlobalSettings {
+    pub fn new(
+        path_to_global_settings: PathBuf,
+        _event_broadcaster: EventBroadcaster,
+        global_settings_data: GlobalSettingsData,
+    ) -> Self {
+        let (timezone, _) = watch::channel(Tz::UTC);
+        let global_settings = Self {
+            path_to_global_settings,
+            _event_broadcaster,
+            global_settings_data,
+            timezone,
+        };
+        global_settings.publish_timezone();
+        global_settings
+    }
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        self.global_settings_data =
+            GlobalSettingsData::read_from_file(&self.path_to_global_settings).await?;
+        self.publish_timezone();
         Ok(())
     }
-    async fn write_to_file(&self) -> Result<(), Error> {
+    fn publish_timezone(&self) {
+        self.timezone.send_replace(self.timezone());
+    }
+    /// Follows [`Self::timezone`] without holding the settings lock
+    pub fn subscribe_timezone(&self) -> watch::Receiver<Tz> {
+        self.timezone.subscribe()
+    }
+    async fn write_to_file(&mut self) -> Result<(), Error> {
+        let mut global_settings_data = self.global_settings_data.clone();
+        global_settings_data.version += 1;
         let mut file = tokio::fs::File::create(&self.path_to_global_settings)
             .await
             .context(format!(
@@ -91,7 +487,7 @@ impl GlobalSettings {
                 self.path_to_global_settings.display()
             ))?;
         file.write_all(
-            serde_json::to_string_pretty(&self.global_settings_data)
+            serde_json::to_string_pretty(&global_settings_data)
                 .context("Failed to serialize global settings data")?
                 .as_bytes(),
         )
@@ -100,6 +496,7 @@ impl GlobalSettings {
             "Failed to write to global settings file at {}",
             self.path_to_global_settings.display()
         ))?;
+        self.global_settings_data.version = global_settings_data.version;
         Ok(())
     }
     pub async fn set_core_name(&mut self, name: String) -> Result<(), Error> {
@@ -165,6 +562,179 @@ impl GlobalSettings {
     pub fn playit_enabled(&self) -> bool {
         self.global_settings_data.playit_enabled
     }
+
+    pub async fn set_min_free_disk_space_mb(
+        &mut self,
+        min_free_disk_space_mb: u64,
+    ) -> Result<(), Error> {
+        let old_min_free_disk_space_mb = self.global_settings_data.min_free_disk_space_mb;
+        self.global_settings_data.min_free_disk_space_mb = min_free_disk_space_mb;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.min_free_disk_space_mb = old_min_free_disk_space_mb;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn min_free_disk_space_mb(&self) -> u64 {
+        self.global_settings_data.min_free_disk_space_mb
+    }
+
+    pub async fn set_memory_safety_margin_mb(
+        &mut self,
+        memory_safety_margin_mb: u64,
+    ) -> Result<(), Error> {
+        let old_memory_safety_margin_mb = self.global_settings_data.memory_safety_margin_mb;
+        self.global_settings_data.memory_safety_margin_mb = memory_safety_margin_mb;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.memory_safety_margin_mb = old_memory_safety_margin_mb;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn memory_safety_margin_mb(&self) -> u64 {
+        self.global_settings_data.memory_safety_margin_mb
+    }
+
+    pub fn cors_allowed_origins(&self) -> Vec<String> {
+        self.global_settings_data.cors_allowed_origins.clone()
+    }
+
+    pub fn cors_allowed_headers(&self) -> Vec<String> {
+        self.global_settings_data.cors_allowed_headers.clone()
+    }
+
+    pub fn auto_start_delay_secs(&self) -> u64 {
+        self.global_settings_data.auto_start_delay_secs
+    }
+
+    pub fn restart_warnings(&self) -> RestartWarnings {
+        self.global_settings_data.restart_warnings.clone()
+    }
+
+    pub fn console_redact_patterns(&self) -> Vec<String> {
+        self.global_settings_data.console_redact_patterns.clone()
+    }
+
+    pub fn monitor_event_threshold(&self) -> MonitorEventThreshold {
+        self.global_settings_data.monitor_event_threshold.clone()
+    }
+
+    pub fn event_channel_capacity(&self) -> usize {
+        self.global_settings_data.event_channel_capacity
+    }
+
+    pub fn idempotency_key_ttl_secs(&self) -> u64 {
+        self.global_settings_data.idempotency_key_ttl_secs
+    }
+
+    pub fn trash_retention_days(&self) -> u32 {
+        self.global_settings_data.trash_retention_days
+    }
+
+    pub fn max_inline_edit_bytes(&self) -> u64 {
+        self.global_settings_data.max_inline_edit_bytes
+    }
+
+    pub fn monitor_sample_interval_secs(&self) -> u64 {
+        self.global_settings_data.monitor_sample_interval_secs
+    }
+
+    pub fn monitor_history_retention_days(&self) -> u32 {
+        self.global_settings_data.monitor_history_retention_days
+    }
+
+    pub fn variables(&self) -> BTreeMap<String, String> {
+        self.global_settings_data.variables.clone()
+    }
+
+    pub fn webhooks(&self) -> Vec<Webhook> {
+        self.global_settings_data.webhooks.clone()
+    }
+
+    pub fn allow_shell_hooks(&self) -> bool {
+        self.global_settings_data.allow_shell_hooks
+    }
+
+    pub fn compress_responses(&self) -> bool {
+        self.global_settings_data.compress_responses
+    }
+
+    pub fn password_policy(&self) -> PasswordPolicy {
+        self.global_settings_data.password_policy.clone()
+    }
+
+    pub fn hot_backup_timeout_secs(&self) -> u64 {
+        self.global_settings_data.hot_backup_timeout_secs
+    }
+
+    pub fn upnp_port_forwarding(&self) -> bool {
+        self.global_settings_data.upnp_port_forwarding
+    }
+
+    pub fn offsite_backup(&self) -> OffsiteBackupConfig {
+        self.global_settings_data.offsite_backup.clone()
+    }
+
+    pub fn event_retention(&self) -> EventRetention {
+        self.global_settings_data.event_retention.clone()
+    }
+
+    pub fn command_filters(&self) -> CommandFilters {
+        self.global_settings_data.command_filters.clone()
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.global_settings_data
+            .timezone
+            .parse()
+            .unwrap_or(Tz::UTC)
+    }
+
+    pub fn branding(&self) -> Branding {
+        self.global_settings_data.branding.clone()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.global_settings_data.version
+    }
+
+    /// Applies all fields of the patch at once, either all of them are applied or none are.
+    ///
+    /// Returns the changes that were made
+    pub async fn apply_patch(
+        &mut self,
+        patch: GlobalSettingsPatch,
+        caused_by: CausedBy,
+    ) -> Result<Vec<GlobalSettingsChange>, Error> {
+        if patch.version != self.global_settings_data.version {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!(
+                    "Global settings were modified (version {}), expected version {}",
+                    self.global_settings_data.version,
+                    patch.version
+                ),
+            });
+        }
+        let old_data = self.global_settings_data.clone();
+        let changes = patch.apply_to(&mut self.global_settings_data, &caused_by);
+        match self.write_to_file().await {
+            Ok(_) => {
+                self.publish_timezone();
+                Ok(changes)
+            }
+            Err(e) => {
+                self.global_settings_data = old_data;
+                Err(e)
+            }
+        }
+    }
 }
 
 impl AsRef<GlobalSettingsData> for GlobalSettings {
@@ -232,4 +802,115 @@ mod tests {
 
         assert_eq!(global_settings.core_name(), "test_core_name");
     }
+
+    #[tokio::test]
+    async fn test_apply_patch() {
+        use super::*;
+        use std::path::PathBuf;
+
+        let temp_dir = tempdir::TempDir::new("test_apply_patch").unwrap();
+        let (event_broadcaster, _) = EventBroadcaster::new(10);
+        let mut global_settings = GlobalSettings::new(
+            PathBuf::from(temp_dir.path()).join("global_settings.json"),
+            event_broadcaster,
+            GlobalSettingsData::default(),
+        );
+        global_settings.load_from_file().await.unwrap();
+        let version = global_settings.version();
+        let timezone = global_settings.subscribe_timezone();
+
+        let changes = global_settings
+            .apply_patch(
+                GlobalSettingsPatch {
+                    version,
+                    core_name: Some("patched".to_string()),
+                    safe_mode: Some(false),
+                    domain: Some(String::new()),
+                    timezone: Some("Europe/Berlin".to_string()),
+                    ..Default::default()
+                },
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+        assert_eq!(changes.len(), 4);
+        assert_eq!(global_settings.core_name(), "patched");
+        assert_eq!(*timezone.borrow(), chrono_tz::Europe::Berlin);
+        assert!(!global_settings.safe_mode());
+        assert_eq!(global_settings.as_ref().domain, None);
+        assert_eq!(global_settings.version(), version + 1);
+
+        // a patch based on the stale version is rejected and nothing is applied
+        let result = global_settings
+            .apply_patch(
+                GlobalSettingsPatch {
+                    version,
+                    core_name: Some("stale".to_string()),
+                    ..Default::default()
+                },
+                CausedBy::System,
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error {
+                kind: ErrorKind::Conflict,
+                ..
+            })
+        ));
+        assert_eq!(global_settings.core_name(), "patched");
+    }
+
+    #[test]
+    fn test_patch_rejects_unknown_fields() {
+        use super::GlobalSettingsPatch;
+        let patch: GlobalSettingsPatch =
+            serde_json::from_str(r#"{"version": 1, "core_name": "patched"}"#).unwrap();
+        assert_eq!(patch.core_name.as_deref(), Some("patched"));
+        assert!(patch.safe_mode.is_none());
+        assert!(serde_json::from_str::<GlobalSettingsPatch>(
+            r#"{"version": 1, "core_nmae": "patched"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_redacted() {
+        use super::GlobalSettingsData;
+        use crate::{offsite_backup::OffsiteBackupConfig, webhooks::Webhook};
+        let data = GlobalSettingsData {
+            webhooks: vec![Webhook {
+                id: "a".to_string(),
+                name: "discord".to_string(),
+                url: "https://discord.com/api/webhooks/1/token".to_string(),
+            }],
+            variables: [("RCON_PASSWORD".to_string(), "hunter2".to_string())].into(),
+            offsite_backup: OffsiteBackupConfig {
+                enabled: true,
+                endpoint: "https://s3.eu-central-1.amazonaws.com".to_string(),
+                bucket: "backups".to_string(),
+                access_key_id: "AKIA".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let redacted = data.redacted();
+        assert_eq!(redacted.webhooks[0].name, "discord");
+        assert!(redacted.webhooks[0].url.is_empty());
+        assert!(redacted.variables.is_empty());
+        assert!(redacted.offsite_backup.enabled);
+        assert!(redacted.offsite_backup.endpoint.is_empty());
+        assert!(redacted.offsite_backup.bucket.is_empty());
+        assert!(redacted.offsite_backup.access_key_id.is_empty());
+    }
+
+    #[test]
+    fn test_render_restart_warning() {
+        use super::RestartWarnings;
+        let warnings = RestartWarnings::default();
+        assert_eq!(warnings.render(300), "Server restarting in 5 minutes");
+        assert_eq!(warnings.render(60), "Server restarting in 1 minute");
+        assert_eq!(warnings.render(90), "Server restarting in 90 seconds");
+        assert_eq!(warnings.render(1), "Server restarting in 1 second");
+    }
 }
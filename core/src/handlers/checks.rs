@@ -1,6 +1,23 @@
+use std::path::Path as StdPath;
+use std::time::Duration;
+
+use crate::error::{Error, ErrorKind};
+use crate::implementations::minecraft::util::install_jre;
+use crate::java_runtimes::java_binary;
+use crate::prelude::path_to_binaries;
 use crate::traits::t_configurable::TConfigurable;
+use crate::util::dont_spawn_terminal;
 use crate::{port_manager::PortStatus, AppState};
-use axum::{extract::Path, routing::get, Json, Router};
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use serde::Serialize;
+use tracing::{info, warn};
+use ts_rs::TS;
 /// Check the status of a port
 /// Note: this function is not cheap
 pub async fn get_port_status(
@@ -24,9 +41,121 @@ pub async fn is_name_in_use(
     Json(false)
 }
 
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct DependencyStatus {
+    pub name: String,
+    pub was_healthy: bool,
+    pub repaired: bool,
+    pub error: Option<String>,
+}
+
+/// Check that a downloaded JRE exists, is executable and actually runs
+async fn verify_jre(jre_dir: &StdPath) -> Result<(), Error> {
+    let java = java_binary(jre_dir);
+    let metadata = tokio::fs::metadata(&java)
+        .await
+        .context(format!("{} is missing", java.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(eyre!("{} is not executable", java.display()).into());
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+    let status = tokio::time::timeout(
+        Duration::from_secs(30),
+        dont_spawn_terminal(tokio::process::Command::new(&java).arg("-version"))
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .stdin(std::process::Stdio::null())
+            .status(),
+    )
+    .await
+    .context(format!("{} -version timed out", java.display()))?
+    .context(format!("Failed to run {}", java.display()))?;
+    if !status.success() {
+        return Err(eyre!("{} -version exited with {}", java.display(), status).into());
+    }
+    Ok(())
+}
+
+async fn redownload_jre(path_to_java: &StdPath, major_version: u64) -> Result<(), Error> {
+    let jre_dir = install_jre(path_to_java, major_version, &|_| {}, None).await?;
+    verify_jre(&jre_dir).await
+}
+
+/// Verify every downloaded runtime and re-download the ones that are broken,
+/// e.g. quarantined by an antivirus or truncated mid-download
+pub async fn repair_dependencies(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<DependencyStatus>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the owner can repair dependencies"),
+        });
+    }
+    let path_to_java = path_to_binaries().join("java");
+    let mut major_versions = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(&path_to_java).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if let Some(major_version) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("jre"))
+                .and_then(|v| v.parse::<u64>().ok())
+            {
+                major_versions.push(major_version);
+            }
+        }
+    }
+    major_versions.sort_unstable();
+
+    let mut ret = Vec::new();
+    for major_version in major_versions {
+        let name = format!("jre{major_version}");
+        let status = match verify_jre(&path_to_java.join(&name)).await {
+            Ok(_) => DependencyStatus {
+                name,
+                was_healthy: true,
+                repaired: false,
+                error: None,
+            },
+            Err(e) => {
+                warn!("{name} is broken, re-downloading: {e}");
+                match redownload_jre(&path_to_java, major_version).await {
+                    Ok(_) => {
+                        info!("Repaired {name}");
+                        DependencyStatus {
+                            name,
+                            was_healthy: false,
+                            repaired: true,
+                            error: None,
+                        }
+                    }
+                    Err(e) => DependencyStatus {
+                        name,
+                        was_healthy: false,
+                        repaired: false,
+                        error: Some(e.to_string()),
+                    },
+                }
+            }
+        };
+        ret.push(status);
+    }
+    Ok(Json(ret))
+}
+
 pub fn get_checks_routes(state: AppState) -> Router {
     Router::new()
         .route("/check/port/:port", get(get_port_status))
         .route("/check/name/:name", get(is_name_in_use))
+        .route("/checks/dependencies/repair", post(repair_dependencies))
         .with_state(state)
 }
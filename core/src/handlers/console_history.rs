@@ -0,0 +1,161 @@
+use axum::{
+    extract::{Path, Query},
+    routing::get,
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use tracing::error;
+
+use crate::{
+    auth::user::UserAction,
+    db::{
+        read::get_console_history, types::ConsoleHistoryEntry, write::write_console_history_entry,
+    },
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    prelude::GameInstance,
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+/// Commands kept per instance, older ones are dropped
+const CONSOLE_HISTORY_CAP: u32 = 500;
+
+/// Replaces everything but the command name if the command matches any of `patterns`
+fn redact_command(command: &str, patterns: &[String]) -> Option<String> {
+    let sensitive = patterns.iter().any(|pattern| {
+        fancy_regex::Regex::new(pattern)
+            .ok()
+            .and_then(|re| re.is_match(command).ok())
+            .unwrap_or(false)
+    });
+    sensitive.then(|| {
+        let name = command.split_whitespace().next().unwrap_or_default();
+        format!("{name} [redacted]")
+    })
+}
+
+/// Stores a command a user sent to an instance in its history
+pub(crate) async fn record_console_command(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    command: &str,
+    caused_by: &CausedBy,
+) {
+    let patterns = state.global_settings.lock().await.console_redact_patterns();
+    let redacted = redact_command(command, &patterns);
+    let (user_id, user_name) = match caused_by {
+        CausedBy::User { user_id, user_name } => (Some(user_id.clone()), Some(user_name.clone())),
+        _ => (None, None),
+    };
+    let entry = ConsoleHistoryEntry {
+        instance_id: uuid.clone(),
+        redacted: redacted.is_some(),
+        command: redacted.unwrap_or_else(|| command.to_string()),
+        user_id,
+        user_name,
+        snowflake: Snowflake::default(),
+    };
+    if !state.db_health.is_available() {
+        return;
+    }
+    if let Err(e) =
+        write_console_history_entry(&state.sqlite_pool, &entry, CONSOLE_HISTORY_CAP).await
+    {
+        error!("Failed to record console command: {}", e);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ConsoleHistoryQuery {
+    limit: Option<u32>,
+}
+
+pub async fn get_instance_console_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<ConsoleHistoryQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ConsoleHistoryEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessConsole(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let limit = query
+        .limit
+        .unwrap_or(CONSOLE_HISTORY_CAP)
+        .min(CONSOLE_HISTORY_CAP);
+    state.db_health.ensure_available()?;
+    Ok(Json(
+        get_console_history(&state.sqlite_pool, &uuid, limit).await?,
+    ))
+}
+
+/// Command names for autocomplete, asked from the running server
+pub async fn get_instance_known_commands(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessConsole(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .value()
+        .clone();
+    match instance {
+        GameInstance::MinecraftInstance(instance) => {
+            Ok(Json(instance.known_commands().await.map_err(|e| {
+                Error {
+                    kind: ErrorKind::UnsupportedOperation,
+                    source: e.source.wrap_err("Listing commands requires RCON"),
+                }
+            })?))
+        }
+        _ => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Listing commands is only supported for Minecraft instances"),
+        }),
+    }
+}
+
+pub fn get_console_history_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/console/history",
+            get(get_instance_console_history),
+        )
+        .route(
+            "/instance/:uuid/console/commands",
+            get(get_instance_known_commands),
+        )
+        .with_state(state)
+}
+
+#[test]
+fn test_redact_command() {
+    let patterns = vec![
+        r"(?i)password".to_string(),
+        r"(?i)^/?(login|register)\b".to_string(),
+    ];
+    assert_eq!(
+        redact_command("login hunter2", &patterns),
+        Some("login [redacted]".to_string())
+    );
+    assert_eq!(
+        redact_command("lp user Steve meta set Password abc", &patterns),
+        Some("lp [redacted]".to_string())
+    );
+    assert_eq!(redact_command("say hello", &patterns), None);
+}
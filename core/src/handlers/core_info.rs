@@ -1,9 +1,72 @@
 use std::env;
 
-use crate::{prelude::VERSION, AppState};
+use crate::{
+    branding::Branding,
+    db::health::DbStatus,
+    prelude::{lodestone_path, VERSION},
+    timezone::utc_offset_secs,
+    AppState,
+};
 use axum::{routing::get, Json, Router};
 use serde::{Deserialize, Serialize};
 use sysinfo::{CpuExt, DiskExt, System, SystemExt};
+use ts_rs::TS;
+
+use super::instance_setup_configs::{available_game_types, HandlerGameType};
+
+/// Bumped whenever an endpoint changes in a way an older frontend can't handle
+pub const API_REVISION: u32 = 1;
+
+#[derive(Serialize, Deserialize, TS, Clone, Copy, PartialEq, Eq)]
+#[ts(export)]
+pub enum CoreFeature {
+    Tls,
+    Gateway,
+    InstanceExport,
+    InstanceImport,
+    Tasks,
+    TaskCancellation,
+    Peers,
+    MonitorHistory,
+    LogCleanup,
+}
+
+/// What this core supports, so a frontend can hide what an older core can't do
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct Capabilities {
+    api_revision: u32,
+    /// Git commit the core was built from, set through `LODESTONE_BUILD_COMMIT` at build time
+    build_commit: Option<String>,
+    game_types: Vec<HandlerGameType>,
+    /// Features this core supports, with Tls and Gateway only listed while enabled
+    features: Vec<CoreFeature>,
+}
+
+async fn capabilities(state: &AppState) -> Capabilities {
+    let mut features = vec![
+        CoreFeature::InstanceExport,
+        CoreFeature::InstanceImport,
+        CoreFeature::Tasks,
+        CoreFeature::TaskCancellation,
+        CoreFeature::Peers,
+        CoreFeature::MonitorHistory,
+        CoreFeature::LogCleanup,
+    ];
+    let tls = lodestone_path().join("tls");
+    if tls.join("cert.pem").is_file() && tls.join("key.pem").is_file() {
+        features.push(CoreFeature::Tls);
+    }
+    if state.gateway.config().await.enabled {
+        features.push(CoreFeature::Gateway);
+    }
+    Capabilities {
+        api_revision: API_REVISION,
+        build_commit: option_env!("LODESTONE_BUILD_COMMIT").map(|v| v.to_string()),
+        game_types: available_game_types(),
+        features,
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct CoreInfo {
@@ -19,12 +82,28 @@ pub struct CoreInfo {
     uuid: String,
     core_name: String,
     up_since: i64,
+    /// IANA name of the core's timezone, timestamps are UTC regardless
+    timezone: String,
+    /// The current offset of `timezone` from UTC in seconds
+    utc_offset_secs: i32,
+    capabilities: Capabilities,
+    branding: Branding,
+    /// When unavailable, history and event persistence are disabled until it can be reopened
+    database: DbStatus,
 }
 
 pub async fn get_core_info(
     axum::extract::State(state): axum::extract::State<AppState>,
 ) -> Json<CoreInfo> {
     let sys = System::new_all();
+    let settings = state.global_settings.lock().await;
+    let (tz, core_name, branding) = (
+        settings.timezone(),
+        settings.core_name(),
+        settings.branding(),
+    );
+    // a guard held across the struct below would deadlock with a second lock in it
+    drop(settings);
     Json(CoreInfo {
         version: VERSION.with(|v| v.clone()),
         is_setup: state.first_time_setup_key.lock().await.is_none(),
@@ -47,9 +126,14 @@ pub async fn get_core_info(
             .unwrap_or_else(|| "Unknown Hostname".to_string()),
         total_ram: sys.total_memory(),
         total_disk: sys.disks().iter().fold(0, |acc, v| acc + v.total_space()),
-        core_name: state.global_settings.lock().await.core_name(),
+        core_name,
         uuid: state.uuid.clone(),
         up_since: state.up_since,
+        timezone: tz.name().to_string(),
+        utc_offset_secs: utc_offset_secs(tz, chrono::Utc::now().timestamp()),
+        capabilities: capabilities(&state).await,
+        branding,
+        database: state.db_health.status(),
     })
 }
 
@@ -58,3 +142,22 @@ pub fn get_core_info_routes(state: AppState) -> Router {
         .route("/info", get(get_core_info))
         .with_state(state)
 }
+
+#[tokio::test]
+async fn test_get_core_info() {
+    let temp_dir = tempdir::TempDir::new("test_get_core_info").unwrap();
+    crate::prelude::init_paths(temp_dir.path().to_path_buf());
+    let state = AppState::for_tests(temp_dir.path()).await;
+    let (core_name, tz) = {
+        let settings = state.global_settings.lock().await;
+        (settings.core_name(), settings.timezone())
+    };
+    let Json(info) = tokio::time::timeout(
+        std::time::Duration::from_secs(10),
+        get_core_info(axum::extract::State(state)),
+    )
+    .await
+    .expect("get_core_info didn't finish, the settings lock is likely held twice");
+    assert_eq!(info.core_name, core_name);
+    assert_eq!(info.timezone, tz.name());
+}
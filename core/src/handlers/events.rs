@@ -1,33 +1,44 @@
-use std::sync::Arc;
+use std::{convert::Infallible, sync::Arc};
 
 use axum::{
     extract::{ws::WebSocket, Path, Query, WebSocketUpgrade},
-    response::Response,
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        Response,
+    },
     routing::get,
     Json, Router,
 };
 use axum_auth::AuthBearer;
 
 use color_eyre::eyre::eyre;
-use futures::{SinkExt, StreamExt};
+use futures::{SinkExt, Stream, StreamExt};
 use ringbuffer::{AllocRingBuffer, RingBufferExt};
 use tracing::{debug, error};
 
 use crate::output_types::ClientEvent;
-use crate::types::InstanceUuid;
+use crate::types::{InstanceUuid, Snowflake};
 use crate::{
-    auth::{user::UsersManager, user_id::UserId},
-    db::read::search_events,
+    auth::{
+        user::{UserAction, UsersManager},
+        user_id::UserId,
+    },
+    console_filter::CompiledConsoleFilter,
+    db::read::{get_console_output, search_events},
     error::{Error, ErrorKind},
     events::EventQuery,
 };
 
 use crate::{
-    events::{Event, EventInner, UserEventInner},
+    events::{Event, EventInner, EventType, UserEventInner},
+    traits::t_configurable::TConfigurable,
     AppState,
 };
 use serde::Deserialize;
-use tokio::sync::{broadcast::Receiver, RwLock};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    RwLock,
+};
 use ts_rs::TS;
 
 use super::util::parse_bearer_token;
@@ -96,23 +107,43 @@ pub async fn get_event_search(
             kind: ErrorKind::Unauthorized,
             source: eyre!("Token error"),
         })?;
+    state.db_health.ensure_available()?;
     search_events(&state.sqlite_pool, query).await.map(Json)
 }
 
+#[derive(Deserialize)]
+pub struct ConsoleFilterQuery {
+    /// Name of one of the instance's console filters
+    filter: Option<String>,
+}
+
+/// The console filter `name` of the instance, compiled once per request or connection
+async fn console_filter(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    name: Option<&str>,
+) -> Result<Option<CompiledConsoleFilter>, Error> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let instance = state.instances.get(uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let filters = instance.console_filters().await;
+    filters.compile(name).map(Some)
+}
+
 pub async fn get_console_buffer(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
     Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<ConsoleFilterQuery>,
 ) -> Result<Json<Vec<Event>>, Error> {
-    let requester = state
-        .users_manager
-        .read()
-        .await
-        .try_auth(&token)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::Unauthorized,
-            source: eyre!("Token error"),
-        })?;
+    let viewer = ConsoleViewer::authenticate(&state.users_manager, &token, &uuid).await?;
+    let filter = console_filter(&state, &uuid, query.filter.as_deref()).await?;
+    let users_manager = state.users_manager.read().await;
     Ok(Json(
         state
             .console_out_buffer
@@ -124,7 +155,10 @@ pub async fn get_console_buffer(
             .filter(|event| match &event.event_inner {
                 EventInner::InstanceEvent(instance_event) => {
                     (instance_event.instance_uuid == uuid || uuid == "all")
-                        && requester.can_view_event(event)
+                        && viewer.can_view_event(&users_manager, event, &uuid)
+                        && filter
+                            .as_ref()
+                            .map_or(true, |filter| filter.is_event_match(event))
                 }
                 _ => false,
             })
@@ -133,9 +167,94 @@ pub async fn get_console_buffer(
     ))
 }
 
+/// Deepest scrollback served in one request
+const MAX_CONSOLE_SCROLLBACK: u32 = 10000;
+
+#[derive(Deserialize)]
+pub struct ConsoleScrollbackQuery {
+    limit: Option<u32>,
+    /// Only return output older than this event, to page back
+    before: Option<Snowflake>,
+}
+
+/// Console output stored in the database, deeper than what the console buffer holds
+pub async fn get_console_scrollback(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<ConsoleScrollbackQuery>,
+) -> Result<Json<Vec<Event>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessConsole(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    state.db_health.ensure_available()?;
+    let limit = query.limit.unwrap_or(1024).clamp(1, MAX_CONSOLE_SCROLLBACK);
+    Ok(Json(
+        get_console_output(&state.sqlite_pool, &uuid, limit, query.before).await?,
+    ))
+}
+
+/// Who is watching a console, a user or a read-only viewer token scoped to the instance
+enum ConsoleViewer {
+    User(UserId),
+    ViewerToken(String),
+}
+
+impl ConsoleViewer {
+    async fn authenticate(
+        users_manager: &RwLock<UsersManager>,
+        token: &str,
+        uuid: &InstanceUuid,
+    ) -> Result<Self, Error> {
+        let users_manager = users_manager.read().await;
+        if let Some(user) = users_manager.try_auth(token) {
+            return Ok(ConsoleViewer::User(user.uid));
+        }
+        users_manager
+            .try_auth_viewer(token, uuid, chrono::Utc::now().timestamp())
+            .map(|_| ConsoleViewer::ViewerToken(token.to_string()))
+            .ok_or_else(|| Error {
+                kind: ErrorKind::Unauthorized,
+                source: eyre!("Token error"),
+            })
+    }
+
+    /// Whether the viewer still exists and can see `event`, checked again for every event
+    /// so a deleted user or a revoked or expired token stops receiving output
+    fn can_view_event(
+        &self,
+        users_manager: &UsersManager,
+        event: &Event,
+        uuid: &InstanceUuid,
+    ) -> bool {
+        match self {
+            ConsoleViewer::User(uid) => users_manager
+                .get_user(uid)
+                .map(|user| user.can_view_event(event))
+                .unwrap_or(false),
+            ConsoleViewer::ViewerToken(token) => users_manager
+                .try_auth_viewer(token, uuid, chrono::Utc::now().timestamp())
+                .is_some(),
+        }
+    }
+
+    fn is_valid(&self, users_manager: &UsersManager, uuid: &InstanceUuid) -> bool {
+        match self {
+            ConsoleViewer::User(uid) => users_manager.get_user(uid).is_some(),
+            ConsoleViewer::ViewerToken(token) => users_manager
+                .try_auth_viewer(token, uuid, chrono::Utc::now().timestamp())
+                .is_some(),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct WebsocketQuery {
     token: String,
+    /// Name of one of the instance's console filters
+    filter: Option<String>,
 }
 
 pub async fn event_stream(
@@ -208,32 +327,154 @@ async fn event_stream_ws(
     }
 }
 
+#[derive(Deserialize)]
+pub struct EventSseQuery {
+    /// `EventSource` can't set headers, so the token may be passed as `Bearer <token>` here
+    token: Option<String>,
+    instance: Option<InstanceUuid>,
+    /// Comma separated event types, e.g. `InstanceEvent,UserEvent`
+    kinds: Option<String>,
+}
+
+/// Server-sent events alternative to the event websocket
+///
+/// Emits a `lagged` event carrying the number of skipped events if the client fell behind,
+/// the client should refetch whatever it is displaying
+pub async fn event_sse_stream(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    auth: Option<AuthBearer>,
+    Query(query): Query<EventSseQuery>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, Error> {
+    let token = auth
+        .map(|AuthBearer(token)| token)
+        .or_else(|| query.token.as_deref().and_then(parse_bearer_token))
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Missing token"),
+        })?;
+    let user = state
+        .users_manager
+        .read()
+        .await
+        .try_auth(&token)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Token error"),
+        })?;
+    let event_types = query
+        .kinds
+        .map(|kinds| {
+            kinds
+                .split(',')
+                .map(str::trim)
+                .filter(|kind| !kind.is_empty())
+                .map(|kind| {
+                    serde_json::from_value::<EventType>(serde_json::Value::String(kind.to_string()))
+                        .map_err(|_| Error {
+                            kind: ErrorKind::BadRequest,
+                            source: eyre!("Unknown event kind {}", kind),
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+    let filter = EventQuery {
+        event_levels: None,
+        event_types,
+        instance_event_types: None,
+        user_event_types: None,
+        event_user_ids: None,
+        event_instance_ids: query.instance.map(|uuid| vec![uuid]),
+        bearer_token: None,
+        time_range: None,
+    };
+    let event_receiver = state.event_broadcaster.subscribe();
+
+    let stream = futures::stream::unfold(
+        (
+            event_receiver,
+            state.event_broadcaster,
+            state.users_manager,
+            user.uid,
+            filter,
+        ),
+        |(mut event_receiver, event_broadcaster, users_manager, uid, filter)| async move {
+            loop {
+                let sse_event = match event_receiver.recv().await {
+                    Ok(event) => {
+                        if event.is_event_console_message() {
+                            continue;
+                        }
+                        // end the stream once the user is gone
+                        let user = users_manager.read().await.get_user(&uid)?;
+                        if !(filter.filter(ClientEvent::from(&event))
+                            && user.can_view_event(&event))
+                        {
+                            continue;
+                        }
+                        match SseEvent::default().json_data(&event) {
+                            Ok(sse_event) => sse_event,
+                            Err(e) => {
+                                error!("Failed to serialize event: {}", e);
+                                continue;
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        event_broadcaster.record_lag("Event stream", skipped);
+                        SseEvent::default()
+                            .event("lagged")
+                            .data(skipped.to_string())
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((
+                    Ok(sse_event),
+                    (
+                        event_receiver,
+                        event_broadcaster,
+                        users_manager,
+                        uid,
+                        filter,
+                    ),
+                ));
+            }
+        },
+    );
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
 pub async fn console_stream(
     ws: WebSocketUpgrade,
     axum::extract::State(state): axum::extract::State<AppState>,
     query: Query<WebsocketQuery>,
     Path(uuid): Path<InstanceUuid>,
 ) -> Result<Response, Error> {
-    let users_manager = state.users_manager.read().await;
-
-    let user = parse_bearer_token(query.token.as_str())
-        .and_then(|token| users_manager.try_auth(&token))
-        .ok_or_else(|| Error {
-            kind: ErrorKind::Unauthorized,
-            source: eyre!("Token error"),
-        })?;
-    drop(users_manager);
+    let token = parse_bearer_token(query.token.as_str()).ok_or_else(|| Error {
+        kind: ErrorKind::Unauthorized,
+        source: eyre!("Token error"),
+    })?;
+    let viewer = ConsoleViewer::authenticate(&state.users_manager, &token, &uuid).await?;
+    let filter = console_filter(&state, &uuid, query.filter.as_deref()).await?;
     let event_receiver = state.event_broadcaster.subscribe();
 
     Ok(ws.on_upgrade(move |socket| {
-        console_stream_ws(socket, event_receiver, user.uid, uuid, state.users_manager)
+        console_stream_ws(
+            socket,
+            event_receiver,
+            viewer,
+            filter,
+            uuid,
+            state.users_manager,
+        )
     }))
 }
 
 async fn console_stream_ws(
     stream: WebSocket,
     mut event_receiver: Receiver<Event>,
-    uid: UserId,
+    viewer: ConsoleViewer,
+    filter: Option<CompiledConsoleFilter>,
     uuid: InstanceUuid,
     users_manager: Arc<RwLock<UsersManager>>,
 ) {
@@ -243,13 +484,15 @@ async fn console_stream_ws(
             Ok(event) = event_receiver.recv() => {
                 match &event.event_inner {
                     EventInner::InstanceEvent(instance_event) => {
-                        let user = match users_manager.read().await.get_user(&uid) {
-                            Some(user) => user,
-                            None => break,
-                        };
+                        let users_manager = users_manager.read().await;
+                        if !viewer.is_valid(&users_manager, &uuid) {
+                            break;
+                        }
                         if event.is_event_console_message() && (instance_event.instance_uuid == uuid || uuid == "all")
-                            && user.can_view_event(&event)
+                            && viewer.can_view_event(&users_manager, &event, &uuid)
+                            && filter.as_ref().map_or(true, |filter| filter.is_event_match(&event))
                         {
+                            drop(users_manager);
                             if let Err(e) = sender
                                 .send(axum::extract::ws::Message::Text(
                                     serde_json::to_string(&event).unwrap(),
@@ -264,7 +507,7 @@ async fn console_stream_ws(
                     EventInner::UserEvent(user_event) => {
                         match user_event.user_event_inner {
                             UserEventInner::UserLoggedOut | UserEventInner::UserDeleted => {
-                                if user_event.user_id == uid {
+                                if matches!(&viewer, ConsoleViewer::User(uid) if *uid == user_event.user_id) {
                                     break;
                                 }
                             },
@@ -275,6 +518,7 @@ async fn console_stream_ws(
                     EventInner::ProgressionEvent(_) => continue,
                     EventInner::FSEvent(_) => continue,
                     EventInner::PlayitggRunnerEvent(_) => continue,
+                    EventInner::MonitorEvent(_) => continue,
                 }
             }
             Some(Ok(ws_msg)) = receiver.next() => {
@@ -292,7 +536,12 @@ pub fn get_events_routes(state: AppState) -> Router {
         .route("/events/:uuid/stream", get(event_stream))
         .route("/events/:uuid/buffer", get(get_event_buffer))
         .route("/events/search", get(get_event_search))
+        .route("/events/stream", get(event_sse_stream))
         .route("/instance/:uuid/console/stream", get(console_stream))
         .route("/instance/:uuid/console/buffer", get(get_console_buffer))
+        .route(
+            "/instance/:uuid/console/scrollback",
+            get(get_console_scrollback),
+        )
         .with_state(state)
 }
@@ -1,10 +1,23 @@
-use axum::{extract::Path, routing::put, Json, Router};
+use std::collections::HashMap;
+
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
 use axum_auth::AuthBearer;
 
 use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
 use crate::{
+    auth::user::UserAction,
     error::{Error, ErrorKind},
+    gateway::{GatewayConfig, GatewayStatsReport},
+    nat::NatStatus,
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
     AppState,
 };
 
@@ -32,8 +45,148 @@ pub async fn open_port(
     Ok(Json(state.port_manager.lock().await.open_port(port).await?))
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct GatewayRoute {
+    pub hostname: String,
+    pub instance_uuid: InstanceUuid,
+    /// `None` if the instance no longer exists
+    pub instance_name: Option<String>,
+    pub backend_port: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct GatewayConfigReply {
+    pub enabled: bool,
+    pub listen_port: u16,
+    pub routes: Vec<GatewayRoute>,
+}
+
+pub async fn get_gateway_config(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<GatewayConfigReply>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let config = state.gateway.config().await;
+    let mut routes = Vec::new();
+    for (hostname, instance_uuid) in config.routes {
+        if !requester.can_perform_action(&UserAction::ViewInstance(instance_uuid.clone())) {
+            continue;
+        }
+        let instance = state
+            .instances
+            .get(&instance_uuid)
+            .map(|v| v.value().clone());
+        let (instance_name, backend_port) = match instance {
+            Some(instance) => (Some(instance.name().await), Some(instance.port().await)),
+            None => (None, None),
+        };
+        routes.push(GatewayRoute {
+            hostname,
+            instance_uuid,
+            instance_name,
+            backend_port,
+        });
+    }
+    routes.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+    Ok(Json(GatewayConfigReply {
+        enabled: config.enabled,
+        listen_port: config.listen_port,
+        routes,
+    }))
+}
+
+pub async fn set_gateway_config(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(mut config): Json<GatewayConfig>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only owners can configure the gateway"),
+        });
+    }
+    // hostnames are matched case insensitively
+    config.routes = config
+        .routes
+        .into_iter()
+        .map(|(hostname, uuid)| (hostname.trim_end_matches('.').to_lowercase(), uuid))
+        .collect::<HashMap<_, _>>();
+    for uuid in config.routes.values() {
+        if !state.instances.contains_key(uuid) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Instance {} not found", uuid),
+            });
+        }
+    }
+    if config.enabled {
+        for entry in state.instances.iter() {
+            if entry.value().port().await == config.listen_port as u32 {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Port {} is used by instance {}",
+                        config.listen_port,
+                        entry.key()
+                    ),
+                });
+            }
+        }
+    }
+    state.gateway.set_config(config).await?;
+    Ok(Json(()))
+}
+
+pub async fn get_gateway_stats(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<HashMap<InstanceUuid, GatewayStatsReport>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(
+        state
+            .gateway
+            .all_stats()
+            .into_iter()
+            .filter(|(uuid, _)| {
+                requester.can_perform_action(&UserAction::ViewInstance(uuid.clone()))
+            })
+            .collect(),
+    ))
+}
+
+/// The UPnP port forwarding status, admin only since it shows the external address. The
+/// router's answer is cached for a minute
+pub async fn get_nat_status(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<NatStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner && !requester.is_admin {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only admins can view the port forwarding status"),
+        });
+    }
+    let enabled = state.global_settings.lock().await.upnp_port_forwarding();
+    let mut status = state.nat.status(enabled).await;
+    status.mappings.retain(|mapping| {
+        requester.can_perform_action(&UserAction::ViewInstance(mapping.instance_uuid.clone()))
+    });
+    Ok(Json(status))
+}
+
 pub fn get_gateway_routes(state: AppState) -> Router {
     Router::new()
         .route("/gateway/open_port/:port", put(open_port))
+        .route(
+            "/gateway/config",
+            get(get_gateway_config).put(set_gateway_config),
+        )
+        .route("/gateway/stats", get(get_gateway_stats))
+        .route("/gateway/nat", get(get_nat_status))
         .with_state(state)
 }
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 
 use axum::{
     body::{Bytes, StreamBody},
-    extract::{Multipart, Path},
+    extract::{DefaultBodyLimit, Multipart, Path},
     http,
     routing::{delete, get, put},
     Json, Router,
@@ -424,7 +424,7 @@ async fn download_file(
         temp_file_path.push(path.file_name().unwrap());
         temp_file_path.set_extension("zip");
         let files = Vec::from([path.clone()]);
-        zip_files(&files, temp_file_path.clone(), true).context("Failed to zip file")?;
+        zip_files(&files, temp_file_path.clone(), true, None).context("Failed to zip file")?;
         downloadable_file_path = temp_file_path.clone();
         DownloadableFile::ZippedFile((downloadable_file_path.clone(), temp_dir))
     } else {
@@ -651,6 +651,7 @@ pub fn get_global_fs_routes(state: AppState) -> Router {
         .route("/fs/:base64_absolute_path/new", put(new_file))
         .route("/fs/:base64_absolute_path/download", get(download_file))
         .route("/fs/:base64_absolute_path/upload", put(upload_file))
+        .layer(DefaultBodyLimit::disable())
         .route("/file/:key", get(download))
         .with_state(state)
 }
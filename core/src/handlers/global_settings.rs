@@ -1,17 +1,51 @@
 use axum::{
-    routing::{get, put},
+    extract::{Path, Query},
+    http::{HeaderName, HeaderValue},
+    routing::{get, post, put},
     Json, Router,
 };
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use tracing::{error, warn};
 
-use crate::{error::ErrorKind, AppState, Error, GlobalSettingsData};
+use crate::{
+    auth::password_policy::MAX_MIN_LENGTH,
+    db::{read::get_global_settings_history, write::write_global_settings_change},
+    error::ErrorKind,
+    events::CausedBy,
+    global_settings::{GlobalSettingsChange, GlobalSettingsPatch},
+    prelude::GameInstance,
+    timezone::parse_timezone,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    variables::is_valid_name,
+    webhooks::{send_test, WebhookTestResult},
+    AppState, Error, GlobalSettingsData,
+};
+
+/// Each slot holds an event, keep the channel from eating all memory
+const MAX_EVENT_CHANNEL_CAPACITY: usize = 1 << 20;
+
+const MIN_MONITOR_SAMPLE_INTERVAL_SECS: u64 = 10;
+const MAX_MONITOR_SAMPLE_INTERVAL_SECS: u64 = 60 * 60;
+
+async fn record_change(state: &AppState, change: GlobalSettingsChange) {
+    if !state.db_health.is_available() {
+        return;
+    }
+    if let Err(e) = write_global_settings_change(&state.sqlite_pool, &change).await {
+        error!("Failed to record global settings change: {}", e);
+    }
+}
 
 pub async fn get_core_settings(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<GlobalSettingsData>, Error> {
-    state
+    let requester = state
         .users_manager
         .read()
         .await
@@ -21,7 +55,12 @@ pub async fn get_core_settings(
             source: eyre!("Token error"),
         })?;
 
-    Ok(Json(state.global_settings.lock().await.as_ref().clone()))
+    let global_settings = state.global_settings.lock().await;
+    if requester.is_owner || requester.is_admin {
+        Ok(Json(global_settings.as_ref().clone()))
+    } else {
+        Ok(Json(global_settings.as_ref().redacted()))
+    }
 }
 
 pub async fn change_core_name(
@@ -49,12 +88,23 @@ pub async fn change_core_name(
             source: eyre!("Name cannot be empty"),
         });
     }
-    state
-        .global_settings
-        .lock()
-        .await
-        .set_core_name(new_name)
-        .await?;
+    let mut global_settings = state.global_settings.lock().await;
+    let old_name = global_settings.core_name();
+    global_settings.set_core_name(new_name.clone()).await?;
+    drop(global_settings);
+    record_change(
+        &state,
+        GlobalSettingsChange::new(
+            "core_name",
+            old_name,
+            new_name,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        ),
+    )
+    .await;
     Ok(())
 }
 
@@ -71,12 +121,23 @@ pub async fn change_core_safe_mode(
             source: eyre!("Not authorized to change core safe mode"),
         });
     }
-    state
-        .global_settings
-        .lock()
-        .await
-        .set_safe_mode(safe_mode)
-        .await?;
+    let mut global_settings = state.global_settings.lock().await;
+    let old_safe_mode = global_settings.safe_mode();
+    global_settings.set_safe_mode(safe_mode).await?;
+    drop(global_settings);
+    record_change(
+        &state,
+        GlobalSettingsChange::new(
+            "safe_mode",
+            old_safe_mode,
+            safe_mode,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        ),
+    )
+    .await;
     Ok(())
 }
 
@@ -98,16 +159,28 @@ pub async fn change_domain(
             source: eyre!("Domain too long"),
         });
     }
-    state
-        .global_settings
-        .lock()
-        .await
-        .set_domain(if new_domain.is_empty() {
-            None
-        } else {
-            Some(new_domain)
-        })
-        .await?;
+    let new_domain = if new_domain.is_empty() {
+        None
+    } else {
+        Some(new_domain)
+    };
+    let mut global_settings = state.global_settings.lock().await;
+    let old_domain = global_settings.domain();
+    global_settings.set_domain(new_domain.clone()).await?;
+    drop(global_settings);
+    record_change(
+        &state,
+        GlobalSettingsChange::new(
+            "domain",
+            old_domain,
+            new_domain,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        ),
+    )
+    .await;
     Ok(())
 }
 
@@ -125,18 +198,382 @@ pub async fn change_core_playit_enabled(
         });
     }
 
-    state
-        .global_settings
-        .lock()
-        .await
-        .set_playit_enabled(playit_enabled)
+    let mut global_settings = state.global_settings.lock().await;
+    let old_playit_enabled = global_settings.playit_enabled();
+    global_settings.set_playit_enabled(playit_enabled).await?;
+    drop(global_settings);
+    record_change(
+        &state,
+        GlobalSettingsChange::new(
+            "playit_enabled",
+            old_playit_enabled,
+            playit_enabled,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        ),
+    )
+    .await;
+    Ok(())
+}
+
+pub async fn change_min_free_disk_space(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(min_free_disk_space_mb): Json<u64>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change the minimum free disk space."),
+        });
+    }
+
+    let mut global_settings = state.global_settings.lock().await;
+    let old_min_free_disk_space_mb = global_settings.min_free_disk_space_mb();
+    global_settings
+        .set_min_free_disk_space_mb(min_free_disk_space_mb)
+        .await?;
+    drop(global_settings);
+    record_change(
+        &state,
+        GlobalSettingsChange::new(
+            "min_free_disk_space_mb",
+            old_min_free_disk_space_mb,
+            min_free_disk_space_mb,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        ),
+    )
+    .await;
+    Ok(())
+}
+
+pub async fn change_memory_safety_margin(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(memory_safety_margin_mb): Json<u64>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change the memory safety margin."),
+        });
+    }
+
+    let mut global_settings = state.global_settings.lock().await;
+    let old_memory_safety_margin_mb = global_settings.memory_safety_margin_mb();
+    global_settings
+        .set_memory_safety_margin_mb(memory_safety_margin_mb)
         .await?;
+    drop(global_settings);
+    record_change(
+        &state,
+        GlobalSettingsChange::new(
+            "memory_safety_margin_mb",
+            old_memory_safety_margin_mb,
+            memory_safety_margin_mb,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        ),
+    )
+    .await;
     Ok(())
 }
 
+pub async fn patch_core_settings(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(patch): Json<GlobalSettingsPatch>,
+) -> Result<Json<GlobalSettingsData>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change global settings"),
+        });
+    }
+    if let Some(core_name) = &patch.core_name {
+        if core_name.len() > 32 {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Name too long"),
+            });
+        }
+        if core_name.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Name cannot be empty"),
+            });
+        }
+    }
+    if let Some(domain) = &patch.domain {
+        if domain.len() > 253 {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Domain too long"),
+            });
+        }
+    }
+    if let Some(origins) = &patch.cors_allowed_origins {
+        if let Some(origin) = origins
+            .iter()
+            .find(|o| *o != "*" && HeaderValue::from_str(o).is_err())
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid origin {origin}"),
+            });
+        }
+    }
+    if let Some(headers) = &patch.cors_allowed_headers {
+        if let Some(header) = headers
+            .iter()
+            .find(|h| HeaderName::from_bytes(h.as_bytes()).is_err())
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid header name {header}"),
+            });
+        }
+    }
+    if let Some(patterns) = &patch.console_redact_patterns {
+        if let Some((pattern, e)) = patterns
+            .iter()
+            .find_map(|p| fancy_regex::Regex::new(p).err().map(|e| (p, e)))
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid redact pattern {pattern}: {e}"),
+            });
+        }
+    }
+    if let Some(threshold) = &patch.monitor_event_threshold {
+        if !(threshold.cpu_percent >= 0.0 && threshold.memory_percent >= 0.0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Monitor event thresholds must not be negative"),
+            });
+        }
+    }
+    if let Some(capacity) = patch.event_channel_capacity {
+        if !(1..=MAX_EVENT_CHANNEL_CAPACITY).contains(&capacity) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Event channel capacity must be between 1 and {}",
+                    MAX_EVENT_CHANNEL_CAPACITY
+                ),
+            });
+        }
+    }
+    if let Some(interval) = patch.monitor_sample_interval_secs {
+        if !(MIN_MONITOR_SAMPLE_INTERVAL_SECS..=MAX_MONITOR_SAMPLE_INTERVAL_SECS)
+            .contains(&interval)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Monitor sample interval must be between {} and {} seconds",
+                    MIN_MONITOR_SAMPLE_INTERVAL_SECS,
+                    MAX_MONITOR_SAMPLE_INTERVAL_SECS
+                ),
+            });
+        }
+    }
+    if patch.monitor_history_retention_days == Some(0) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Monitor history must be kept for at least one day"),
+        });
+    }
+    if let Some(webhooks) = &patch.webhooks {
+        let mut ids = std::collections::HashSet::new();
+        if let Some(webhook) = webhooks
+            .iter()
+            .find(|webhook| webhook.id.is_empty() || !ids.insert(&webhook.id))
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Webhook ids must be unique and not empty, got \"{}\"",
+                    webhook.id
+                ),
+            });
+        }
+    }
+    if let Some(password_policy) = &patch.password_policy {
+        if password_policy.min_length > MAX_MIN_LENGTH {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "The minimum password length can be at most {}",
+                    MAX_MIN_LENGTH
+                ),
+            });
+        }
+    }
+    if patch.hot_backup_timeout_secs == Some(0) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Saving must stay turned off for at least a second during a backup"),
+        });
+    }
+    if let Some(variables) = &patch.variables {
+        if let Some(name) = variables.keys().find(|name| !is_valid_name(name)) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Invalid variable name {}, only letters, digits and _ are allowed",
+                    name
+                ),
+            });
+        }
+    }
+    if let Some(offsite_backup) = &patch.offsite_backup {
+        offsite_backup.validate()?;
+    }
+    if let Some(event_retention) = &patch.event_retention {
+        event_retention.validate()?;
+    }
+    if let Some(command_filters) = &patch.command_filters {
+        command_filters.validate()?;
+    }
+    if let Some(timezone) = &patch.timezone {
+        parse_timezone(timezone)?;
+    }
+    if let Some(branding) = &patch.branding {
+        branding.validate()?;
+    }
+    let upnp_port_forwarding = patch.upnp_port_forwarding;
+    let mut global_settings = state.global_settings.lock().await;
+    let changes = global_settings
+        .apply_patch(
+            patch,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        )
+        .await?;
+    let global_settings_data = global_settings.as_ref().clone();
+    drop(global_settings);
+    state
+        .macro_executor
+        .variables()
+        .set(global_settings_data.variables.clone());
+    state
+        .macro_executor
+        .set_shell_hooks_allowed(global_settings_data.allow_shell_hooks);
+    state
+        .users_manager
+        .write()
+        .await
+        .set_password_policy(global_settings_data.password_policy.clone());
+    match upnp_port_forwarding {
+        Some(true) => {
+            let running: Vec<GameInstance> = state
+                .instances
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect();
+            let nat = state.nat.clone();
+            tokio::spawn(async move {
+                for instance in running {
+                    if instance.state().await != State::Running {
+                        continue;
+                    }
+                    match u16::try_from(instance.port().await) {
+                        Ok(port) => nat.map(&instance.uuid().await, port).await,
+                        Err(_) => warn!("Not forwarding invalid port of {}", instance.uuid().await),
+                    }
+                }
+            });
+        }
+        Some(false) => {
+            let nat = state.nat.clone();
+            tokio::spawn(async move { nat.unmap_all().await });
+        }
+        None => {}
+    }
+    for change in changes {
+        record_change(&state, change).await;
+    }
+    Ok(Json(global_settings_data))
+}
+
+/// Sends a sample payload to a configured webhook so its setup can be checked
+pub async fn test_webhook(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<WebhookTestResult>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to test webhooks"),
+        });
+    }
+    let (webhook, core_name) = {
+        let global_settings = state.global_settings.lock().await;
+        let webhook = global_settings
+            .webhooks()
+            .into_iter()
+            .find(|webhook| webhook.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Webhook {} not found", id),
+            })?;
+        (webhook, global_settings.core_name())
+    };
+    let url = state
+        .macro_executor
+        .variables()
+        .substitute_with_secrets(&webhook.url)?;
+    send_test(&url, &core_name).await.map(Json)
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    limit: Option<u32>,
+}
+
+pub async fn get_settings_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<GlobalSettingsChange>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner && !requester.is_admin {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to view global settings history"),
+        });
+    }
+    state.db_health.ensure_available()?;
+    get_global_settings_history(&state.sqlite_pool, query.limit.unwrap_or(100))
+        .await
+        .map(Json)
+}
+
 pub fn get_global_settings_routes(state: AppState) -> Router {
     Router::new()
-        .route("/global_settings", get(get_core_settings))
+        .route(
+            "/global_settings",
+            get(get_core_settings).patch(patch_core_settings),
+        )
+        .route("/global_settings/history", get(get_settings_history))
+        .route("/global_settings/webhooks/:id/test", post(test_webhook))
         .route("/global_settings/name", put(change_core_name))
         .route("/global_settings/safe_mode", put(change_core_safe_mode))
         .route("/global_settings/domain", put(change_domain))
@@ -144,5 +581,13 @@ pub fn get_global_settings_routes(state: AppState) -> Router {
             "/global_settings/playit_enabled",
             put(change_core_playit_enabled),
         )
+        .route(
+            "/global_settings/min_free_disk_space",
+            put(change_min_free_disk_space),
+        )
+        .route(
+            "/global_settings/memory_safety_margin",
+            put(change_memory_safety_margin),
+        )
         .with_state(state)
 }
@@ -1,41 +1,71 @@
+use std::future::Future;
+use std::time::Duration;
+
+use axum::http::HeaderMap;
 use axum::routing::{delete, get, post};
 use axum::Router;
-use axum::{extract::Path, Json};
+use axum::{
+    extract::{Path, Query},
+    Json,
+};
 use axum_auth::AuthBearer;
 
 use bollard::container::ListContainersOptions;
 use bollard::Docker;
 use color_eyre::eyre::{eyre, Context};
-use serde::Deserialize;
+use ringbuffer::RingBufferExt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sysinfo::SystemExt;
 use tracing::{error, info};
+use ts_rs::TS;
 
-use crate::auth::user::UserAction;
+use crate::auth::user::{User, UserAction};
+use crate::cgroup::{cgroup_limits, effective_memory};
+use crate::disk_usage::disk_space_of;
 use crate::error::{Error, ErrorKind};
 use crate::events::{CausedBy, Event, ProgressionEndValue, ProgressionStartValue};
+use crate::idempotency::idempotency_key;
+use crate::instance_trash;
 
 use crate::implementations::generic;
 use crate::traits::t_configurable::GameType;
 
+use crate::implementations::minecraft::setup_plan::{plan_setup, SetupPlan};
 use crate::implementations::minecraft::MinecraftInstance;
 use crate::prelude::{path_to_instances, GameInstance};
 use crate::traits::t_configurable::manifest::SetupValue;
 use crate::traits::t_configurable::Game::Generic;
+use crate::traits::t_server::MonitorReport;
 use crate::traits::{t_configurable::TConfigurable, t_server::TServer, InstanceInfo, TInstance};
-use crate::types::{DotLodestoneConfig, InstanceUuid};
+use crate::types::{normalize_tag, DotLodestoneConfig, InstanceUuid};
+use crate::util::format_byte;
 use crate::{implementations::minecraft, traits::t_server::State, AppState};
 
 use super::instance_setup_configs::HandlerGameType;
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct InstanceListQuery {
+    /// only list instances carrying this tag
+    tag: Option<String>,
+    /// also list instances in the trash
+    #[serde(default)]
+    include_deleted: bool,
+}
+
 pub async fn get_instance_list(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
+    Query(query): Query<InstanceListQuery>,
 ) -> Result<Json<Vec<InstanceInfo>>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let tag = query.tag.as_deref().map(normalize_tag).transpose()?;
     let mut list_of_configs: Vec<InstanceInfo> = Vec::new();
 
     for instance in state.instances.iter() {
         if requester.can_perform_action(&UserAction::ViewInstance(instance.uuid().await)) {
-            list_of_configs.push(instance.get_instance_info().await);
+            let mut info = instance.get_instance_info().await;
+            info.restart_required = state.restart_required.contains(&info.uuid);
+            list_of_configs.push(info);
         }
     }
     let docker_bridge = state.docker_bridge.clone();
@@ -43,11 +73,132 @@ pub async fn get_instance_list(
 
     list_of_configs.extend(vec);
 
+    if query.include_deleted {
+        list_of_configs.extend(instance_trash::list_trashed().into_iter().filter(|info| {
+            requester.can_perform_action(&UserAction::ViewInstance(info.uuid.clone()))
+        }));
+    }
+
+    if let Some(tag) = tag {
+        list_of_configs.retain(|info| info.tags.contains(&tag));
+    }
+
     list_of_configs.sort_by(|a, b| a.creation_time.cmp(&b.creation_time));
 
     Ok(Json(list_of_configs))
 }
 
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstanceSortKey {
+    Name,
+    Cpu,
+    Players,
+    CreationTime,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InstanceSearchQuery {
+    sort: Option<InstanceSortKey>,
+    /// defaults to descending for cpu and players, ascending otherwise
+    order: Option<SortOrder>,
+    /// instance state, e.g. `running`
+    filter: Option<String>,
+    /// case insensitive match against name and description
+    q: Option<String>,
+    tag: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct InstanceSearchEntry {
+    pub info: InstanceInfo,
+    pub latest_report: Option<MonitorReport>,
+}
+
+pub async fn search_instances(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<InstanceSearchQuery>,
+) -> Result<Json<Vec<InstanceSearchEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let tag = query.tag.as_deref().map(normalize_tag).transpose()?;
+    let filter = query.filter.map(|v| v.to_lowercase());
+    let q = query.q.map(|v| v.to_lowercase());
+
+    let mut infos: Vec<InstanceInfo> = Vec::new();
+    for instance in state.instances.iter() {
+        if requester.can_perform_action(&UserAction::ViewInstance(instance.key().clone())) {
+            let mut info = instance.get_instance_info().await;
+            info.restart_required = state.restart_required.contains(&info.uuid);
+            infos.push(info);
+        }
+    }
+    infos.extend(
+        state
+            .docker_bridge
+            .list_containers()
+            .await
+            .unwrap_or_default(),
+    );
+
+    infos.retain(|info| {
+        filter.as_ref().map_or(true, |filter| {
+            info.state.to_string().to_lowercase() == *filter
+        }) && q.as_ref().map_or(true, |q| {
+            info.name.to_lowercase().contains(q) || info.description.to_lowercase().contains(q)
+        }) && tag.as_ref().map_or(true, |tag| info.tags.contains(tag))
+    });
+
+    let monitor_buffer = state.monitor_buffer.lock().await;
+    let mut entries = infos
+        .into_iter()
+        .map(|info| InstanceSearchEntry {
+            latest_report: monitor_buffer
+                .get(&info.uuid)
+                .and_then(|buffer| buffer.get(-1))
+                .cloned(),
+            info,
+        })
+        .collect::<Vec<_>>();
+    drop(monitor_buffer);
+
+    let sort = query.sort.unwrap_or(InstanceSortKey::CreationTime);
+    entries.sort_by(|a, b| match sort {
+        InstanceSortKey::Name => a.info.name.to_lowercase().cmp(&b.info.name.to_lowercase()),
+        InstanceSortKey::Cpu => {
+            let cpu = |e: &InstanceSearchEntry| {
+                e.latest_report
+                    .as_ref()
+                    .and_then(|r| r.cpu_usage)
+                    .unwrap_or(0.0)
+            };
+            cpu(a).total_cmp(&cpu(b))
+        }
+        InstanceSortKey::Players => a
+            .info
+            .player_count
+            .unwrap_or(0)
+            .cmp(&b.info.player_count.unwrap_or(0)),
+        InstanceSortKey::CreationTime => a.info.creation_time.cmp(&b.info.creation_time),
+    });
+    let order = query.order.unwrap_or(match sort {
+        InstanceSortKey::Cpu | InstanceSortKey::Players => SortOrder::Desc,
+        InstanceSortKey::Name | InstanceSortKey::CreationTime => SortOrder::Asc,
+    });
+    if let SortOrder::Desc = order {
+        entries.reverse();
+    }
+    Ok(Json(entries))
+}
+
 pub async fn get_instance_info(
     Path(uuid): Path<InstanceUuid>,
     axum::extract::State(state): axum::extract::State<AppState>,
@@ -64,12 +215,53 @@ pub async fn get_instance_info(
         &UserAction::ViewInstance(uuid.clone()),
         state.global_settings.lock().await.safe_mode(),
     )?;
-    Ok(Json(instance.get_instance_info().await))
+    let mut info = instance.get_instance_info().await;
+    info.restart_required = state.restart_required.contains(&uuid);
+    Ok(Json(info))
+}
+
+/// Runs `create` unless a request with the same `Idempotency-Key` already did,
+/// in which case its result is returned again
+async fn with_idempotency_key<T, F>(
+    state: &AppState,
+    requester: &User,
+    headers: &HeaderMap,
+    create: F,
+) -> Result<T, Error>
+where
+    T: Serialize + DeserializeOwned,
+    F: Future<Output = Result<T, Error>>,
+{
+    let key = match idempotency_key(headers)? {
+        Some(key) => key,
+        None => return create.await,
+    };
+    let ttl = Duration::from_secs(
+        state
+            .global_settings
+            .lock()
+            .await
+            .idempotency_key_ttl_secs(),
+    );
+    if let Some(previous) = state.idempotency_keys.begin(&requester.uid, &key, ttl)? {
+        return Ok(serde_json::from_value(previous).context("Failed to replay previous result")?);
+    }
+    let result = create.await;
+    match &result {
+        Ok(value) => state.idempotency_keys.complete(
+            &requester.uid,
+            &key,
+            serde_json::to_value(value).context("Failed to store result")?,
+        ),
+        Err(_) => state.idempotency_keys.abort(&requester.uid, &key),
+    }
+    result
 }
 
 pub async fn create_minecraft_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
+    headers: HeaderMap,
     Path(game_type): Path<HandlerGameType>,
     Json(manifest_value): Json<SetupValue>,
 ) -> Result<Json<InstanceUuid>, Error> {
@@ -78,19 +270,45 @@ pub async fn create_minecraft_instance(
         &UserAction::CreateInstance,
         state.global_settings.lock().await.safe_mode(),
     )?;
-    let mut perm = requester.permissions;
-
-    let mut instance_uuid = InstanceUuid::default();
+    with_idempotency_key(
+        &state,
+        &requester,
+        &headers,
+        setup_minecraft_instance(
+            state.clone(),
+            Some(requester.clone()),
+            None,
+            game_type,
+            manifest_value,
+        ),
+    )
+    .await
+    .map(Json)
+}
 
-    for entry in state.instances.iter() {
-        if let Some(uuid) = entry.key().as_ref().get(0..8) {
-            if uuid == &instance_uuid.no_prefix()[0..8] {
-                instance_uuid = InstanceUuid::default();
+/// Sets up a Minecraft instance in the background, under `instance_uuid` if given. The
+/// requester, if any, is given access to the new instance
+pub(crate) async fn setup_minecraft_instance(
+    state: AppState,
+    requester: Option<User>,
+    instance_uuid: Option<InstanceUuid>,
+    game_type: HandlerGameType,
+    manifest_value: SetupValue,
+) -> Result<InstanceUuid, Error> {
+    let instance_uuid = match instance_uuid {
+        Some(instance_uuid) => instance_uuid,
+        None => {
+            let mut instance_uuid = InstanceUuid::default();
+            for entry in state.instances.iter() {
+                if let Some(uuid) = entry.key().as_ref().get(0..8) {
+                    if uuid == &instance_uuid.no_prefix()[0..8] {
+                        instance_uuid = InstanceUuid::default();
+                    }
+                }
             }
+            instance_uuid
         }
-    }
-
-    let instance_uuid = instance_uuid;
+    };
 
     let flavour = game_type.try_into()?;
 
@@ -121,9 +339,12 @@ pub async fn create_minecraft_instance(
         let uuid = instance_uuid.clone();
         let instance_name = setup_config.name.clone();
         let event_broadcaster = state.event_broadcaster.clone();
-        let caused_by = CausedBy::User {
-            user_id: requester.uid.clone(),
-            user_name: requester.username.clone(),
+        let caused_by = match &requester {
+            Some(requester) => CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+            },
+            None => CausedBy::System,
         };
         async move {
             let (progression_start_event, event_id) = Event::new_progression_event_start(
@@ -134,6 +355,7 @@ pub async fn create_minecraft_instance(
                 }),
                 caused_by,
             );
+            let cancellation_token = state.tasks.cancellation_token(&event_id);
             event_broadcaster.send(progression_start_event);
             let minecraft_instance = match minecraft::MinecraftInstance::new(
                 setup_config.clone(),
@@ -142,6 +364,7 @@ pub async fn create_minecraft_instance(
                 &event_id,
                 state.event_broadcaster.clone(),
                 state.macro_executor.clone(),
+                &cancellation_token,
             )
             .await
             {
@@ -157,10 +380,15 @@ pub async fn create_minecraft_instance(
                     v
                 }
                 Err(e) => {
+                    let message = if cancellation_token.is_cancelled() {
+                        "Instance creation cancelled".to_string()
+                    } else {
+                        format!("Instance creation failed: {e}")
+                    };
                     event_broadcaster.send(Event::new_progression_event_end(
                         event_id,
                         false,
-                        Some(&format!("Instance creation failed: {e}")),
+                        Some(&message),
                         None,
                     ));
                     crate::util::fs::remove_dir_all(setup_path)
@@ -172,28 +400,93 @@ pub async fn create_minecraft_instance(
             };
             let mut port_manager = state.port_manager.lock().await;
             port_manager.add_port(setup_config.port);
-            perm.can_start_instance.insert(uuid.clone());
-            perm.can_stop_instance.insert(uuid.clone());
-            perm.can_view_instance.insert(uuid.clone());
-            perm.can_read_instance_file.insert(uuid.clone());
-            perm.can_write_instance_file.insert(uuid.clone());
-            // ignore errors since we don't care if the permissions update fails
-            let _ = state
-                .users_manager
-                .write()
-                .await
-                .update_permissions(&requester.uid, perm, CausedBy::System)
-                .await
-                .map_err(|e| {
-                    error!("Failed to update permissions: {:?}", e);
-                    e
-                });
+            if let Some(requester) = requester {
+                let mut perm = requester.permissions;
+                perm.can_start_instance.insert(uuid.clone());
+                perm.can_stop_instance.insert(uuid.clone());
+                perm.can_view_instance.insert(uuid.clone());
+                perm.can_read_instance_file.insert(uuid.clone());
+                perm.can_write_instance_file.insert(uuid.clone());
+                // ignore errors since we don't care if the permissions update fails
+                let _ = state
+                    .users_manager
+                    .write()
+                    .await
+                    .update_permissions(&requester.uid, perm, CausedBy::System)
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to update permissions: {:?}", e);
+                        e
+                    });
+            }
             state
                 .instances
                 .insert(uuid.clone(), minecraft_instance.into());
         }
     });
-    Ok(Json(instance_uuid))
+    Ok(instance_uuid)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetupPlanRequest {
+    game_type: HandlerGameType,
+    setup_value: SetupValue,
+}
+
+/// What creating a Minecraft instance with this setup would do, without doing it
+pub async fn plan_instance_setup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<SetupPlanRequest>,
+) -> Result<Json<SetupPlan>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::CreateInstance,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let flavour = request.game_type.try_into()?;
+    let setup_config =
+        MinecraftInstance::construct_setup_config(request.setup_value, flavour).await?;
+    let mut plan = plan_setup(&setup_config).await?;
+
+    let port_status = state.port_manager.lock().await.port_status(plan.port);
+    if port_status.is_allocated {
+        plan.warnings
+            .push(format!("Port {} is used by another instance", plan.port));
+    } else if port_status.is_in_use {
+        plan.warnings
+            .push(format!("Port {} is in use by another program", plan.port));
+    }
+    let max_memory = setup_config.max_ram.unwrap_or(4096) as u64 * 1024 * 1024;
+    let mut sys = state.system.lock().await;
+    sys.refresh_memory();
+    let (total_memory, _) = effective_memory(
+        cgroup_limits().as_ref(),
+        sys.total_memory(),
+        sys.available_memory(),
+    );
+    drop(sys);
+    if max_memory > total_memory {
+        plan.warnings.push(format!(
+            "Maximum memory of {} MB is more than the {} MB this machine has",
+            max_memory / 1024 / 1024,
+            total_memory / 1024 / 1024
+        ));
+    }
+    if let Some(disk) = tokio::task::spawn_blocking(|| disk_space_of(path_to_instances()))
+        .await
+        .ok()
+        .flatten()
+    {
+        if plan.estimated_download_size > disk.free {
+            plan.warnings.push(format!(
+                "The downloads need {} but only {} is free",
+                format_byte(plan.estimated_download_size),
+                format_byte(disk.free)
+            ));
+        }
+    }
+    Ok(Json(plan))
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -205,6 +498,7 @@ pub struct GenericSetupConfig {
 pub async fn create_generic_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
+    headers: HeaderMap,
     Json(setup_config): Json<GenericSetupConfig>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
@@ -212,6 +506,21 @@ pub async fn create_generic_instance(
         &UserAction::CreateInstance,
         state.global_settings.lock().await.safe_mode(),
     )?;
+    with_idempotency_key(
+        &state,
+        &requester,
+        &headers,
+        setup_generic_instance(state.clone(), requester.clone(), setup_config),
+    )
+    .await
+    .map(Json)
+}
+
+async fn setup_generic_instance(
+    state: AppState,
+    requester: User,
+    setup_config: GenericSetupConfig,
+) -> Result<(), Error> {
     let mut instance_uuid = InstanceUuid::default();
     for entry in state.instances.iter() {
         if let Some(uuid) = entry.key().as_ref().get(0..8) {
@@ -303,7 +612,7 @@ pub async fn create_generic_instance(
             .insert(instance_uuid.clone(), instance.into());
     });
 
-    Ok(Json(()))
+    Ok(())
 }
 
 pub async fn delete_instance(
@@ -331,24 +640,24 @@ pub async fn delete_instance(
             let (progression_event_start, event_id) = Event::new_progression_event_start(
                 format!("Deleting instance {}", instance.name().await),
                 Some(10.0),
-                None,
+                Some(ProgressionStartValue::InstanceOperation {
+                    instance_uuid: uuid.clone(),
+                }),
                 caused_by,
             );
             let event_broadcaster = state.event_broadcaster.clone();
             event_broadcaster.send(progression_event_start);
-            if let Err(e) =
-                tokio::fs::remove_file(instance.path().await.join(".lodestone_config")).await
-            {
+            let instance_path = instance.path().await;
+            let info = instance.get_instance_info().await;
+            if let Err(e) = instance_trash::move_to_trash(&instance_path, info).await {
                 event_broadcaster.send(Event::new_progression_event_end(
                     event_id,
                     false,
-                    Some("Failed to delete .lodestone_config. Instance not deleted"),
+                    Some("Failed to move instance to the trash. Instance not deleted"),
                     None,
                 ));
                 state.instances.insert(uuid.clone(), instance);
-                return Err::<Json<()>, std::io::Error>(e)
-                    .context("Failed to delete .lodestone_config file. Instance not deleted")
-                    .map_err(Into::into);
+                return Err(e);
             }
 
             state
@@ -356,33 +665,20 @@ pub async fn delete_instance(
                 .lock()
                 .await
                 .deallocate(instance.port().await);
-            let instance_path = instance.path().await;
+            state.instance_states.forget(&uuid);
             // if instance is generic
             if let GameInstance::GenericInstance(i) = instance {
                 i.destruct().await;
             };
-            let res = crate::util::fs::remove_dir_all(instance_path).await;
-            match &res {
-                Ok(_) => event_broadcaster.send(Event::new_progression_event_end(
-                    event_id,
-                    true,
-                    Some("Instance deleted successfully"),
-                    Some(ProgressionEndValue::InstanceDelete {
-                        instance_uuid: uuid.clone(),
-                    }),
-                )),
-                Err(e) => {
-                    event_broadcaster.send(Event::new_progression_event_end(
-                        event_id,
-                        false,
-                        Some(&format!(
-                            "Failed to delete some of all of instance's files : {e}"
-                        )),
-                        None,
-                    ));
-                }
-            }
-            res.map(|_| Json(()))
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                true,
+                Some("Instance moved to the trash"),
+                Some(ProgressionEndValue::InstanceDelete {
+                    instance_uuid: uuid.clone(),
+                }),
+            ));
+            Ok(Json(()))
         }
     } else {
         Err(Error {
@@ -392,15 +688,53 @@ pub async fn delete_instance(
     }
 }
 
+/// Brings back an instance from the trash
+pub async fn restore_deleted_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::DeleteInstance,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    if state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!("Instance is not deleted"),
+        });
+    }
+    let instance_path = instance_trash::take_from_trash(&uuid).await?;
+    let (uuid, instance) = crate::restore_instance(
+        &instance_path,
+        state.event_broadcaster.clone(),
+        state.macro_executor.clone(),
+    )
+    .await?;
+    state
+        .port_manager
+        .lock()
+        .await
+        .add_port(instance.port().await);
+    let mut info = instance.get_instance_info().await;
+    info.restart_required = state.restart_required.contains(&uuid);
+    state.instances.insert(uuid, instance);
+    Ok(Json(info))
+}
+
 pub fn get_instance_routes(state: AppState) -> Router {
     Router::new()
+        .route("/instance", get(search_instances))
         .route("/instance/list", get(get_instance_list))
         .route(
             "/instance/create/:game_type",
             post(create_minecraft_instance),
         )
         .route("/instance/create_generic", post(create_generic_instance))
+        .route("/setup/plan", post(plan_instance_setup))
         .route("/instance/:uuid", delete(delete_instance))
         .route("/instance/:uuid/info", get(get_instance_info))
+        .route("/instance/:uuid/restore", post(restore_deleted_instance))
         .with_state(state)
 }
@@ -0,0 +1,373 @@
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Path},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::error;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    disk_usage::guard_instance_disk_space,
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, ProgressionEndValue, ProgressionStartValue},
+    implementations::minecraft::{MinecraftInstance, CONFIG_FILE_NAME},
+    prelude::{path_to_instances, path_to_tmp, GameInstance, VERSION},
+    tasks::cancelled_error,
+    traits::{
+        t_configurable::{GameType, TConfigurable},
+        t_server::{State, TServer},
+        TInstance,
+    },
+    types::{validate_instance_notes, DotLodestoneConfig, InstanceUuid},
+    util::{rand_alphanumeric, unzip_file_async, zip_files_async, UnzipOption},
+    AppState,
+};
+
+use super::global_fs::DownloadableFile;
+
+/// Bump this whenever the layout of an exported archive changes in a way
+/// older cores can't import
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_FILE_NAME: &str = "lodestone_export.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct InstanceExportManifest {
+    pub format_version: u32,
+    pub game_type: GameType,
+    pub name: String,
+    pub uuid: InstanceUuid,
+    pub port: u32,
+    pub lodestone_version: String,
+    pub export_time: i64,
+    /// Archives from before notes existed have none
+    #[serde(default)]
+    pub notes: String,
+}
+
+async fn export_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<String, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let exported_config = match instance.value() {
+        GameInstance::MinecraftInstance(minecraft) => minecraft.exported_config().await?,
+        _ => {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("Only Minecraft instances can be exported"),
+            })
+        }
+    };
+    if instance.state().await != State::Stopped {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Instance must be stopped before exporting"),
+        });
+    }
+    guard_instance_disk_space(
+        &instance,
+        state.global_settings.lock().await.min_free_disk_space_mb(),
+        &state.event_broadcaster,
+    )
+    .await?;
+    let manifest = InstanceExportManifest {
+        format_version: EXPORT_FORMAT_VERSION,
+        game_type: GameType::MinecraftJava,
+        name: instance.name().await,
+        uuid: uuid.clone(),
+        port: instance.port().await,
+        lodestone_version: VERSION.with(|v| v.to_string()),
+        export_time: chrono::Utc::now().timestamp(),
+        notes: instance.notes().await,
+    };
+    let root = instance.path().await;
+    drop(instance);
+
+    let (start_event, event_id) = Event::new_progression_event_start(
+        format!("Exporting instance {}", manifest.name),
+        None,
+        Some(ProgressionStartValue::InstanceOperation {
+            instance_uuid: uuid.clone(),
+        }),
+        CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username.clone(),
+        },
+    );
+    let cancellation_token = state.tasks.cancellation_token(&event_id);
+    state.event_broadcaster.send(start_event);
+    let res: Result<DownloadableFile, Error> = async {
+        let temp_dir =
+            tempfile::tempdir_in(path_to_tmp()).context("Failed to create temporary directory")?;
+        let manifest_path = temp_dir.path().join(MANIFEST_FILE_NAME);
+        crate::util::fs::write_all(
+            &manifest_path,
+            serde_json::to_string_pretty(&manifest).context("Failed to serialize manifest")?,
+        )
+        .await?;
+        // the config is replaced by a copy without the sensitive environment variables
+        let config_path = temp_dir.path().join(CONFIG_FILE_NAME);
+        crate::util::fs::write_all(&config_path, exported_config).await?;
+        let mut files = std::fs::read_dir(&root)
+            .context(format!("Failed to read directory {}", root.display()))?
+            .filter_map(|entry| entry.ok().map(|v| v.path()))
+            .filter(|path| path.file_name() != Some(CONFIG_FILE_NAME.as_ref()))
+            .collect::<Vec<_>>();
+        files.push(config_path);
+        files.push(manifest_path);
+        let archive_path = temp_dir.path().join(format!(
+            "{}-{}.zip",
+            sanitize_filename::sanitize(&manifest.name),
+            &uuid.no_prefix()[0..8]
+        ));
+        // the partial archive is removed with temp_dir when cancelled
+        tokio::select! {
+            res = zip_files_async(
+                &files,
+                &archive_path,
+                true,
+                Some(cancellation_token.clone()),
+            ) => res?,
+            _ = cancellation_token.cancelled() => return Err(cancelled_error()),
+        };
+        Ok(DownloadableFile::ZippedFile((archive_path, temp_dir)))
+    }
+    .await;
+    let downloadable_file = match res {
+        Ok(v) => v,
+        Err(e) => {
+            let message = if cancellation_token.is_cancelled() {
+                "Export cancelled".to_string()
+            } else {
+                e.to_string()
+            };
+            state
+                .event_broadcaster
+                .send(Event::new_progression_event_end(
+                    event_id,
+                    false,
+                    Some(&message),
+                    None,
+                ));
+            return Err(e);
+        }
+    };
+    state
+        .event_broadcaster
+        .send(Event::new_progression_event_end(
+            event_id,
+            true,
+            Some("Export complete"),
+            None,
+        ));
+
+    let key = rand_alphanumeric(32);
+    state
+        .download_urls
+        .lock()
+        .await
+        .insert(key.clone(), downloadable_file);
+    Ok(key)
+}
+
+async fn import_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    mut multipart: Multipart,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::CreateInstance,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let mut perm = requester.permissions.clone();
+
+    let temp_dir =
+        tempfile::tempdir_in(path_to_tmp()).context("Failed to create temporary directory")?;
+    let archive_path = temp_dir.path().join("import.zip");
+    let mut field = multipart
+        .next_field()
+        .await
+        .context("Failed to read multipart field")?
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Missing archive"),
+        })?;
+    let mut file = crate::util::fs::create(&archive_path).await?;
+    while let Some(chunk) = field.chunk().await.context("Failed to read chunk")? {
+        file.write_all(&chunk)
+            .await
+            .context("Failed to write chunk")?;
+    }
+    drop(file);
+
+    let extracted = temp_dir.path().join("extracted");
+    unzip_file_async(&archive_path, UnzipOption::ToDir(extracted.clone()))
+        .await
+        .map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: e.source.wrap_err("Failed to extract archive"),
+        })?;
+
+    let manifest_path = extracted.join(MANIFEST_FILE_NAME);
+    let manifest: InstanceExportManifest = serde_json::from_str(
+        &crate::util::fs::read_to_string(&manifest_path)
+            .await
+            .map_err(|_| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Archive is missing {MANIFEST_FILE_NAME}"),
+            })?,
+    )
+    .map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Malformed export manifest: {e}"),
+    })?;
+    if manifest.format_version != EXPORT_FORMAT_VERSION {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Unsupported export format version {}, expected {}",
+                manifest.format_version,
+                EXPORT_FORMAT_VERSION
+            ),
+        });
+    }
+    if manifest.game_type != GameType::MinecraftJava {
+        return Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Only Minecraft instances can be imported"),
+        });
+    }
+    let notes = validate_instance_notes(&manifest.notes)?;
+    crate::util::fs::remove_file(&manifest_path).await?;
+
+    let mut instance_uuid = InstanceUuid::default();
+    for entry in state.instances.iter() {
+        if let Some(uuid) = entry.key().as_ref().get(0..8) {
+            if uuid == &instance_uuid.no_prefix()[0..8] {
+                instance_uuid = InstanceUuid::default();
+            }
+        }
+    }
+    let instance_uuid = instance_uuid;
+
+    let setup_path = path_to_instances().join(format!(
+        "{}-{}",
+        sanitize_filename::sanitize(&manifest.name),
+        &instance_uuid.no_prefix()[0..8]
+    ));
+    crate::util::fs::rename(&extracted, &setup_path).await?;
+
+    let mut dot_lodestone_config =
+        DotLodestoneConfig::new(instance_uuid.clone(), manifest.game_type);
+    dot_lodestone_config.set_notes(notes);
+    let port = state.port_manager.lock().await.allocate(manifest.port);
+
+    let (progression_start_event, event_id) = Event::new_progression_event_start(
+        format!("Importing Minecraft server {}", manifest.name),
+        None,
+        Some(ProgressionStartValue::InstanceCreation {
+            instance_uuid: instance_uuid.clone(),
+        }),
+        CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username.clone(),
+        },
+    );
+    state.event_broadcaster.send(progression_start_event);
+
+    let res: Result<MinecraftInstance, Error> = async {
+        crate::util::fs::write_all(
+            setup_path.join(".lodestone_config"),
+            serde_json::to_string_pretty(&dot_lodestone_config)
+                .context("Failed to serialize .lodestone_config")?,
+        )
+        .await?;
+        let instance = MinecraftInstance::restore(
+            setup_path.clone(),
+            dot_lodestone_config,
+            state.event_broadcaster.clone(),
+            state.macro_executor.clone(),
+        )
+        .await?;
+        // rewrites both the lodestone config and server.properties
+        instance.set_port(port).await?;
+        instance.set_auto_start(false).await?;
+        Ok(instance)
+    }
+    .await;
+
+    let minecraft_instance = match res {
+        Ok(v) => v,
+        Err(e) => {
+            state
+                .event_broadcaster
+                .send(Event::new_progression_event_end(
+                    event_id,
+                    false,
+                    Some(&format!("Instance import failed: {e}")),
+                    None,
+                ));
+            state.port_manager.lock().await.deallocate(port);
+            if let Err(e) = crate::util::fs::remove_dir_all(&setup_path).await {
+                error!("Failed to remove directory after instance import failed: {e}");
+            }
+            return Err(e);
+        }
+    };
+    state
+        .event_broadcaster
+        .send(Event::new_progression_event_end(
+            event_id,
+            true,
+            Some("Instance imported successfully"),
+            Some(ProgressionEndValue::InstanceCreation(
+                minecraft_instance.get_instance_info().await,
+            )),
+        ));
+
+    perm.can_start_instance.insert(instance_uuid.clone());
+    perm.can_stop_instance.insert(instance_uuid.clone());
+    perm.can_view_instance.insert(instance_uuid.clone());
+    perm.can_read_instance_file.insert(instance_uuid.clone());
+    perm.can_write_instance_file.insert(instance_uuid.clone());
+    // ignore errors since we don't care if the permissions update fails
+    let _ = state
+        .users_manager
+        .write()
+        .await
+        .update_permissions(&requester.uid, perm, CausedBy::System)
+        .await
+        .map_err(|e| {
+            error!("Failed to update permissions: {:?}", e);
+            e
+        });
+    state
+        .instances
+        .insert(instance_uuid.clone(), minecraft_instance.into());
+    Ok(Json(instance_uuid))
+}
+
+pub fn get_instance_archive_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/export", get(export_instance))
+        .route("/instance/import", post(import_instance))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state)
+}
@@ -1,22 +1,45 @@
+use std::collections::BTreeSet;
+
 use axum::{
-    extract::Path,
-    routing::{get, put},
+    body::Bytes,
+    extract::{Path, Query},
+    routing::{get, patch, post, put},
     Json, Router,
 };
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
 
 use crate::{
+    ansi::AnsiHandling,
     auth::user::UserAction,
+    auto_start::AutoStartOrder,
+    console_filter::ConsoleFilters,
     error::{Error, ErrorKind},
+    events::{CausedBy, Event},
+    gateway::{is_online_mode, MaintenanceMode},
+    implementations::minecraft::{
+        env_vars::EnvVars,
+        gameplay::{GameplayPatch, GameplaySettings},
+        hooks::LifecycleHooks,
+        jvm_flags::JvmFlagsProfile,
+        launch_command::LaunchCommand,
+        MinecraftInstance,
+    },
+    log_cleanup::{cleanup_logs, LogCleanupReport, LogRetention},
+    prelude::GameInstance,
     traits::t_configurable::{
-        manifest::{ConfigurableManifest, ConfigurableValue},
+        manifest::{ConfigSchema, ConfigurableManifest, ConfigurableValue},
         TConfigurable,
     },
-    types::InstanceUuid,
+    traits::t_server::{State, TServer},
+    types::{normalize_tag, validate_instance_name, validate_instance_notes, InstanceUuid},
     AppState,
 };
 
+use super::instance_server::check_command_filter;
+
 pub async fn get_instance_configurable_manifest(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -34,6 +57,23 @@ pub async fn get_instance_configurable_manifest(
     Ok(Json(instance.configurable_manifest().await))
 }
 
+pub async fn get_instance_config_schema(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ConfigSchema>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.config_schema().await))
+}
+
 pub async fn get_instance_settings(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -70,13 +110,29 @@ pub async fn set_instance_setting(
         source: eyre!("Instance not found"),
     })?;
 
+    let requires_restart = instance
+        .configurable_manifest()
+        .await
+        .get_setting(&section_id, &setting_id)
+        .map(|setting| setting.requires_restart())
+        .unwrap_or(false);
     instance
         .update_configurable(&section_id, &setting_id, value)
         .await?;
+    if requires_restart {
+        mark_restart_required(&state, &uuid, instance.state().await);
+    }
 
     Ok(Json(()))
 }
 
+/// Flags the instance as needing a restart if it is running on the old config
+pub(super) fn mark_restart_required(state: &AppState, uuid: &InstanceUuid, instance_state: State) {
+    if instance_state != State::Stopped {
+        state.restart_required.insert(uuid.clone());
+    }
+}
+
 pub async fn set_instance_name(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -100,6 +156,50 @@ pub async fn set_instance_name(
     Ok(Json(()))
 }
 
+#[derive(Deserialize)]
+pub struct InstanceRename {
+    name: String,
+}
+
+/// Changes the display name only, the instance's directory keeps its name
+pub async fn rename_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(rename): Json<InstanceRename>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let new_name = validate_instance_name(&rename.name)?;
+    let instance = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .value()
+        .clone();
+    let old_name = instance.name().await;
+    if old_name == new_name {
+        return Ok(Json(()));
+    }
+    instance.set_name(new_name.clone()).await?;
+    state.event_broadcaster.send(Event::new_instance_renamed(
+        uuid,
+        old_name,
+        new_name,
+        CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+    ));
+    Ok(Json(()))
+}
+
 pub async fn set_instance_description(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -123,6 +223,47 @@ pub async fn set_instance_description(
     Ok(Json(()))
 }
 
+pub async fn get_instance_notes(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<String>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.notes().await))
+}
+
+pub async fn set_instance_notes(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(notes): Json<String>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let notes = validate_instance_notes(&notes)?;
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_notes(notes)
+        .await?;
+    Ok(Json(()))
+}
+
 pub async fn change_version(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, new_version)): Path<(InstanceUuid, String)>,
@@ -145,19 +286,685 @@ pub async fn change_version(
     Ok(Json(()))
 }
 
+pub async fn get_instance_tags(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<BTreeSet<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.tags().await))
+}
+
+pub async fn set_instance_tags(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(tags): Json<Vec<String>>,
+) -> Result<Json<BTreeSet<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let tags = tags
+        .iter()
+        .map(|tag| normalize_tag(tag))
+        .collect::<Result<BTreeSet<_>, _>>()?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    instance.set_tags(tags.clone()).await?;
+    Ok(Json(tags))
+}
+
+pub async fn add_instance_tag(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, tag)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<BTreeSet<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let tag = normalize_tag(&tag)?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let mut tags = instance.tags().await;
+    if tags.insert(tag) {
+        instance.set_tags(tags.clone()).await?;
+    }
+    Ok(Json(tags))
+}
+
+pub async fn remove_instance_tag(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, tag)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<BTreeSet<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let tag = normalize_tag(&tag)?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let mut tags = instance.tags().await;
+    if tags.remove(&tag) {
+        instance.set_tags(tags.clone()).await?;
+    }
+    Ok(Json(tags))
+}
+
+pub async fn get_auto_start_order(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<AutoStartOrder>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.auto_start_order().await))
+}
+
+pub async fn set_auto_start_order(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(auto_start_order): Json<AutoStartOrder>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    for dependency in &auto_start_order.depends_on {
+        if *dependency == uuid {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("An instance cannot depend on itself"),
+            });
+        }
+        if !state.instances.contains_key(dependency) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Dependency {} not found", dependency),
+            });
+        }
+    }
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_auto_start_order(auto_start_order)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_maintenance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<MaintenanceMode>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.maintenance().await))
+}
+
+pub async fn set_maintenance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(maintenance): Json<MaintenanceMode>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    if maintenance.message.len() > 256 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Maintenance message cannot be longer than 256 characters"),
+        });
+    }
+    let instance = state
+        .instances
+        .get(&uuid)
+        .map(|instance| instance.value().clone())
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?;
+    if !maintenance.allowed_players.is_empty() && !is_online_mode(&instance.path().await).await {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Allowed players can't be verified in offline mode, turn on online-mode to use them"
+            ),
+        });
+    }
+    instance.set_maintenance(maintenance).await?;
+    Ok(Json(()))
+}
+
+pub async fn get_console_filters(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ConsoleFilters>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessConsole(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.console_filters().await))
+}
+
+pub async fn set_console_filters(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(console_filters): Json<ConsoleFilters>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_console_filters(console_filters)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_log_retention(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LogRetention>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.log_retention().await))
+}
+
+pub async fn set_log_retention(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(log_retention): Json<LogRetention>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    if log_retention.max_age_days == Some(0) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Log retention must be at least one day"),
+        });
+    }
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_log_retention(log_retention)
+        .await?;
+    Ok(Json(()))
+}
+
+/// Runs the log cleanup now instead of waiting for the background task
+pub async fn cleanup_instance_logs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LogCleanupReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::WriteInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    let report = cleanup_logs(&instance.path().await, &instance.log_retention().await).await?;
+    state.event_broadcaster.send(Event::new_system_message(
+        uuid,
+        instance.name().await,
+        report.summary(),
+    ));
+    Ok(Json(report))
+}
+
+pub(super) fn minecraft_instance(
+    state: &AppState,
+    uuid: &InstanceUuid,
+) -> Result<MinecraftInstance, Error> {
+    match state.instances.get(uuid).as_deref() {
+        Some(GameInstance::MinecraftInstance(instance)) => Ok(instance.clone()),
+        Some(_) => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This setting is only supported for Minecraft instances"),
+        }),
+        None => Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        }),
+    }
+}
+
+pub async fn get_jvm_flags(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<JvmFlagsProfile>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(minecraft_instance(&state, &uuid)?.jvm_flags().await))
+}
+
+pub async fn set_jvm_flags(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(jvm_flags): Json<JvmFlagsProfile>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = minecraft_instance(&state, &uuid)?;
+    instance.set_jvm_flags(jvm_flags).await?;
+    mark_restart_required(&state, &uuid, instance.state().await);
+    Ok(Json(()))
+}
+
+pub async fn get_env_vars(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<EnvVars>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(minecraft_instance(&state, &uuid)?.env_vars().await))
+}
+
+pub async fn set_env_vars(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(env): Json<EnvVars>,
+) -> Result<Json<EnvVars>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = minecraft_instance(&state, &uuid)?;
+    instance.set_env_vars(env).await?;
+    mark_restart_required(&state, &uuid, instance.state().await);
+    Ok(Json(instance.env_vars().await))
+}
+
+#[derive(Deserialize)]
+pub struct LaunchCommandQuery {
+    /// Substitute `${NAME}` placeholders instead of showing them as written
+    #[serde(default)]
+    resolved: bool,
+}
+
+/// The command line the next start would run, with sensitive environment variables masked
+pub async fn get_launch_command(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<LaunchCommandQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LaunchCommand>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(
+        minecraft_instance(&state, &uuid)?
+            .launch_command(query.resolved)
+            .await?,
+    ))
+}
+
+pub async fn get_run_as(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(minecraft_instance(&state, &uuid)?.run_as().await))
+}
+
+pub async fn get_ansi_handling(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<AnsiHandling>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(
+        minecraft_instance(&state, &uuid)?.ansi_handling().await,
+    ))
+}
+
+/// Whether ANSI escape sequences in the console output are kept, stripped or parsed into
+/// styled spans, from the next start on
+pub async fn set_ansi_handling(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(ansi_handling): Json<AnsiHandling>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = minecraft_instance(&state, &uuid)?;
+    instance.set_ansi_handling(ansi_handling).await?;
+    mark_restart_required(&state, &uuid, instance.state().await);
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct Motd {
+    /// Color codes can be written with `&`, like `&aGreen`
+    pub motd: String,
+}
+
+pub async fn set_motd(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(motd): Json<Motd>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = minecraft_instance(&state, &uuid)?;
+    instance.set_motd(&motd.motd).await?;
+    mark_restart_required(&state, &uuid, instance.state().await);
+    Ok(Json(()))
+}
+
+/// Sets `server-icon.png` from an image in the request body
+pub async fn set_icon(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    body: Bytes,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = minecraft_instance(&state, &uuid)?;
+    instance.set_icon(&body).await?;
+    mark_restart_required(&state, &uuid, instance.state().await);
+    Ok(Json(()))
+}
+
+/// Sets the unprivileged user the server runs as, `null` to run as lodestone's user. Owner only
+pub async fn set_run_as(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(run_as): Json<Option<String>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the owner can change the user a server runs as"),
+        });
+    }
+    let instance = minecraft_instance(&state, &uuid)?;
+    instance.set_run_as(run_as).await?;
+    mark_restart_required(&state, &uuid, instance.state().await);
+    Ok(Json(()))
+}
+
+pub async fn get_hooks(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LifecycleHooks>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(minecraft_instance(&state, &uuid)?.hooks().await))
+}
+
+pub async fn set_hooks(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(hooks): Json<LifecycleHooks>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    // a shell hook runs arbitrary commands as the core
+    if hooks.has_shell_hook() {
+        if !requester.is_owner {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Only the owner can set shell hooks"),
+            });
+        }
+        if !state.global_settings.lock().await.allow_shell_hooks() {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Shell hooks are disabled in the global settings"),
+            });
+        }
+    }
+    minecraft_instance(&state, &uuid)?.set_hooks(hooks).await?;
+    Ok(Json(()))
+}
+
+pub async fn get_gameplay(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<GameplaySettings>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(
+        minecraft_instance(&state, &uuid)?.gameplay_settings().await,
+    ))
+}
+
+/// Applies difficulty, gamemode and gamerules live if the server is running, hardcore only
+/// applies after a restart
+pub async fn set_gameplay(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(patch): Json<GameplayPatch>,
+) -> Result<Json<GameplaySettings>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = minecraft_instance(&state, &uuid)?;
+    patch.validate()?;
+    // the change is made with commands, so it can't get around the command filters
+    if instance.state().await == State::Running {
+        for command in patch.commands() {
+            check_command_filter(&state, &requester, &uuid, &command).await?;
+        }
+    }
+    let (settings, restart_required) = instance
+        .set_gameplay(
+            patch,
+            CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+            },
+        )
+        .await?;
+    if restart_required {
+        mark_restart_required(&state, &uuid, instance.state().await);
+    }
+    Ok(Json(settings))
+}
+
 pub fn get_instance_config_routes(state: AppState) -> Router {
     Router::new()
         .route(
             "/instance/:uuid/configurable_manifest",
             get(get_instance_configurable_manifest),
         )
+        .route(
+            "/instance/:uuid/config_schema",
+            get(get_instance_config_schema),
+        )
         .route("/instance/:uuid/version/:new_version", put(change_version))
         .route("/instance/:uuid/settings", get(get_instance_settings))
         .route(
             "/instance/:uuid/settings/:section_id/:setting_id",
             put(set_instance_setting),
         )
-        .route("/instance/:uuid/name", put(set_instance_name))
+        .route(
+            "/instance/:uuid/name",
+            put(set_instance_name).patch(rename_instance),
+        )
         .route("/instance/:uuid/description", put(set_instance_description))
+        .route(
+            "/instance/:uuid/notes",
+            get(get_instance_notes).put(set_instance_notes),
+        )
+        .route(
+            "/instance/:uuid/tags",
+            get(get_instance_tags).put(set_instance_tags),
+        )
+        .route(
+            "/instance/:uuid/auto_start_order",
+            get(get_auto_start_order).put(set_auto_start_order),
+        )
+        .route(
+            "/instance/:uuid/jvm_flags",
+            get(get_jvm_flags).put(set_jvm_flags),
+        )
+        .route("/instance/:uuid/hooks", get(get_hooks).put(set_hooks))
+        .route("/instance/:uuid/env", get(get_env_vars).put(set_env_vars))
+        .route("/instance/:uuid/run_as", get(get_run_as).put(set_run_as))
+        .route(
+            "/instance/:uuid/console/ansi",
+            get(get_ansi_handling).put(set_ansi_handling),
+        )
+        .route("/instance/:uuid/motd", patch(set_motd))
+        .route(
+            "/instance/:uuid/gameplay",
+            get(get_gameplay).patch(set_gameplay),
+        )
+        .route("/instance/:uuid/icon", post(set_icon))
+        .route("/instance/:uuid/launch-command", get(get_launch_command))
+        .route(
+            "/instance/:uuid/maintenance",
+            get(get_maintenance).put(set_maintenance),
+        )
+        .route(
+            "/instance/:uuid/console/filters",
+            get(get_console_filters).put(set_console_filters),
+        )
+        .route(
+            "/instance/:uuid/logs/retention",
+            get(get_log_retention).put(set_log_retention),
+        )
+        .route("/instance/:uuid/logs/cleanup", post(cleanup_instance_logs))
+        .route(
+            "/instance/:uuid/tags/:tag",
+            post(add_instance_tag).delete(remove_instance_tag),
+        )
         .with_state(state)
 }
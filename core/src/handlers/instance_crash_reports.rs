@@ -0,0 +1,49 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+
+use crate::{
+    auth::user::UserAction,
+    error::Error,
+    implementations::minecraft::crash_report::{
+        list_crash_reports, read_crash_report, CrashReport, CrashReportEntry,
+    },
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::util::instance_root;
+
+pub async fn get_crash_reports(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<CrashReportEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let root = instance_root(&state, &uuid).await?;
+    Ok(Json(list_crash_reports(&root).await?))
+}
+
+pub async fn get_crash_report(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<CrashReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let root = instance_root(&state, &uuid).await?;
+    Ok(Json(read_crash_report(&root, &id).await?))
+}
+
+pub fn get_instance_crash_reports_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/crash-reports", get(get_crash_reports))
+        .route("/instance/:uuid/crash-reports/:id", get(get_crash_report))
+        .with_state(state)
+}
@@ -3,27 +3,39 @@ use std::path::PathBuf;
 
 use axum::{
     body::Bytes,
-    extract::{DefaultBodyLimit, Multipart, Path},
-    routing::{delete, get, put},
+    extract::{DefaultBodyLimit, Multipart, Path, Query},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use axum_auth::AuthBearer;
 use color_eyre::eyre::{eyre, Context};
 use fs_extra::TransitProcess;
 use headers::HeaderMap;
-use reqwest::header::CONTENT_LENGTH;
-use serde::Deserialize;
-use tokio::io::AsyncWriteExt;
+use reqwest::header::{HeaderName, CONTENT_LENGTH, ETAG, IF_MATCH};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tracing::error;
 use ts_rs::TS;
 use walkdir::WalkDir;
 
 use crate::{
-    auth::user::UserAction,
+    auth::user::{User, UserAction},
     error::{Error, ErrorKind},
-    events::{new_fs_event, CausedBy, Event, FSOperation, FSTarget, ProgressionEndValue},
+    events::{
+        new_fs_event, CausedBy, Event, FSOperation, FSTarget, ProgressionEndValue,
+        ProgressionStartValue,
+    },
+    file_diff::{read_text, unified_diff, DiffTarget, FileDiff},
+    file_preview::{preview, FilePreview, PREVIEW_HEAD_BYTES},
+    file_trash::{
+        list_trash, move_to_trash, restore_from_trash, trash_dir, trashed_file, TrashedFile,
+    },
+    implementations::minecraft::CONFIG_FILE_NAME,
     prelude::path_to_tmp,
-    traits::t_configurable::TConfigurable,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
     types::InstanceUuid,
     util::{
         format_byte, format_byte_download, list_dir, rand_alphanumeric, resolve_path_conflict,
@@ -63,11 +75,98 @@ fn is_path_protected(path: impl AsRef<std::path::Path>) -> bool {
     }
 }
 
+/// Whether reading `path` would reveal the instance's config, which holds the values of
+/// sensitive environment variables. It sits in the root, so only the root itself contains it
+fn reveals_instance_config(root: &std::path::Path, path: &std::path::Path) -> bool {
+    path == root || path == root.join(CONFIG_FILE_NAME)
+}
+
+/// Keeps the instance config out of reads and copies, it is only shown masked through the
+/// instance settings. Those who can read global files can already read it anyway
+fn check_instance_config_hidden(
+    requester: &User,
+    root: &std::path::Path,
+    paths: &[&std::path::Path],
+) -> Result<(), Error> {
+    if !requester.can_perform_action(&UserAction::ReadGlobalFile)
+        && paths.iter().any(|path| reveals_instance_config(root, path))
+    {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("The instance config can only be read through the instance settings"),
+        });
+    }
+    Ok(())
+}
+
+/// The trash is only changed by deleting and restoring files, writing to it directly could
+/// forge the `.trash/<id>.json` that decides where a restore lands
+fn check_outside_trash(root: &std::path::Path, path: &std::path::Path) -> Result<(), Error> {
+    if path.starts_with(trash_dir(root)) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("The trash can only be changed by deleting and restoring files"),
+        });
+    }
+    Ok(())
+}
+
 use super::{
     global_fs::{DownloadableFile, FileEntry},
-    util::decode_base64,
+    instance_config::mark_restart_required,
+    util::{decode_base64, instance_root},
 };
 
+/// Rejects a change to a file the requester's role may not change through the console either,
+/// e.g. `ops.json` when `op` is denied, recording the attempt
+async fn check_command_file(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+    root: &std::path::Path,
+    path: &std::path::Path,
+) -> Result<(), Error> {
+    let relative_path = path.strip_prefix(root).unwrap_or(path);
+    let command_filters = state.global_settings.lock().await.command_filters();
+    if let Err(e) = command_filters.check_file(requester, relative_path) {
+        state.event_broadcaster.send(Event::new_command_denied(
+            requester.uid.clone(),
+            uuid.clone(),
+            relative_path.display().to_string(),
+            CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+            },
+        ));
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Hex encoded SHA-256 of a file's content, sent as the `ETag` of a read
+fn content_hash(content: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(content))
+}
+
+/// Fails with `Conflict` if the file no longer has the hash the client started editing from
+///
+/// `current` is `None` if the file doesn't exist anymore
+fn check_unchanged(if_match: Option<&str>, current: Option<&str>) -> Result<(), Error> {
+    let expected = match if_match {
+        Some(expected) => expected.trim().trim_matches('"'),
+        None => return Ok(()),
+    };
+    if expected == "*" || current == Some(expected) {
+        Ok(())
+    } else {
+        Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!("The file was changed since it was read, reload it and try again"),
+        })
+    }
+}
+
 async fn list_instance_files(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
@@ -95,9 +194,11 @@ async fn list_instance_files(
     drop(instance);
     let path = scoped_join_win_safe(&root, relative_path)?;
 
+    let trash_dir = trash_dir(&root);
     let ret: Vec<FileEntry> = list_dir(&path, None)
         .await?
         .iter()
+        .filter(|p| **p != trash_dir)
         .filter_map(move |p| -> Option<FileEntry> {
             // remove the root path from the file path
             let mut r: FileEntry = p.as_path().into();
@@ -125,7 +226,7 @@ async fn read_instance_file(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
     AuthBearer(token): AuthBearer,
-) -> Result<String, Error> {
+) -> Result<([(HeaderName, String); 1], String), Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(
@@ -137,7 +238,7 @@ async fn read_instance_file(
             .docker_bridge
             .read_container_file(&uuid, relative_path.into())
             .await?;
-        return Ok(file);
+        return Ok(([(ETAG, content_hash(file.as_bytes()))], file));
     }
     let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
@@ -145,7 +246,8 @@ async fn read_instance_file(
     })?;
     let root = instance.path().await;
     drop(instance);
-    let path = scoped_join_win_safe(root, relative_path)?;
+    let path = scoped_join_win_safe(&root, relative_path)?;
+    check_instance_config_hidden(&requester, &root, &[&path])?;
 
     let ret = tokio::fs::read_to_string(&path)
         .await
@@ -159,13 +261,107 @@ async fn read_instance_file(
         FSTarget::File(path),
         caused_by,
     ));
-    Ok(ret)
+    Ok(([(ETAG, content_hash(ret.as_bytes()))], ret))
+}
+
+#[derive(Deserialize)]
+struct PreviewQuery {
+    /// relative to the instance root
+    path: String,
+}
+
+async fn preview_instance_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<PreviewQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<FilePreview>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    drop(instance);
+    let path = scoped_join_win_safe(&root, query.path)?;
+    check_instance_config_hidden(&requester, &root, &[&path])?;
+    if !path.is_file() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("File not found"),
+        });
+    }
+
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .context("Failed to open file")?;
+    let size = file
+        .metadata()
+        .await
+        .context("Failed to read file metadata")?
+        .len();
+    let mut head = Vec::with_capacity(PREVIEW_HEAD_BYTES);
+    (&mut file)
+        .take(PREVIEW_HEAD_BYTES as u64)
+        .read_to_end(&mut head)
+        .await
+        .context("Failed to read file")?;
+    let max_edit_size = state.global_settings.lock().await.max_inline_edit_bytes();
+    Ok(Json(preview(&path, &head, size, max_edit_size)))
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    /// relative to the instance root
+    path: String,
+    /// `instance:<uuid>` or `trash:<id>`
+    against: String,
+}
+
+/// Unified diff of a text file against the same file in another instance or a trashed copy
+async fn diff_instance_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<DiffQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<FileDiff>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()), safe_mode)?;
+    let root = instance_root(&state, &uuid).await?;
+    let path = scoped_join_win_safe(&root, &query.path)?;
+    check_instance_config_hidden(&requester, &root, &[&path])?;
+    let other_path = match query.against.parse::<DiffTarget>()? {
+        DiffTarget::Instance(other_uuid) => {
+            requester.try_action(&UserAction::ReadInstanceFile(other_uuid.clone()), safe_mode)?;
+            let other_root = instance_root(&state, &other_uuid).await?;
+            let other_path = scoped_join_win_safe(&other_root, &query.path)?;
+            check_instance_config_hidden(&requester, &other_root, &[&other_path])?;
+            other_path
+        }
+        DiffTarget::Trash(id) => trashed_file(&root, &id).await?.1,
+    };
+
+    let max_size = state.global_settings.lock().await.max_inline_edit_bytes();
+    let current = read_text(&path, max_size).await?;
+    let other = read_text(&other_path, max_size).await?;
+    Ok(Json(unified_diff(
+        &format!("{}/{}", query.against, query.path),
+        &other,
+        &query.path,
+        &current,
+    )))
 }
 
 async fn write_instance_file(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
     AuthBearer(token): AuthBearer,
+    headers: HeaderMap,
     body: Bytes,
 ) -> Result<Json<()>, Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
@@ -174,7 +370,18 @@ async fn write_instance_file(
         &UserAction::WriteInstanceFile(uuid.clone()),
         state.global_settings.lock().await.safe_mode(),
     )?;
+    // hash of the content the client started editing from
+    let if_match = headers.get(IF_MATCH).and_then(|v| v.to_str().ok());
     if uuid.to_string().starts_with("DOCKER-") {
+        if if_match.is_some() {
+            let current = state
+                .docker_bridge
+                .read_container_file(&uuid, relative_path.clone().into())
+                .await
+                .ok()
+                .map(|file| content_hash(file.as_bytes()));
+            check_unchanged(if_match, current.as_deref())?;
+        }
         state
             .docker_bridge
             .write_container_file(&uuid, relative_path.into(), &body)
@@ -187,7 +394,8 @@ async fn write_instance_file(
     })?;
     let root = instance.path().await;
     drop(instance);
-    let path = scoped_join_win_safe(root, relative_path)?;
+    let path = scoped_join_win_safe(&root, relative_path)?;
+    check_outside_trash(&root, &path)?;
     // if target has a protected extension, or no extension, deny
     if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path) {
         return Err(Error {
@@ -195,6 +403,14 @@ async fn write_instance_file(
             source: eyre!("You don't have permission to write to this file"),
         });
     }
+    check_command_file(&state, &requester, &uuid, &root, &path).await?;
+    if if_match.is_some() {
+        let current = match tokio::fs::read(&path).await {
+            Ok(content) => Some(content_hash(&content)),
+            Err(_) => None,
+        };
+        check_unchanged(if_match, current.as_deref())?;
+    }
     let mut file = tokio::fs::File::create(&path)
         .await
         .context("Failed to create file")?;
@@ -231,7 +447,8 @@ async fn make_instance_directory(
     })?;
     let root = instance.path().await;
     drop(instance);
-    let path = scoped_join_win_safe(root, relative_path)?;
+    let path = scoped_join_win_safe(&root, relative_path)?;
+    check_outside_trash(&root, &path)?;
     // create the file if it doesn't exist
     crate::util::fs::create_dir_all(&path).await?;
 
@@ -279,8 +496,14 @@ async fn copy_instance_files(
         .iter()
         .map(|p| scoped_join_win_safe(root.clone(), p))
         .collect::<Result<Vec<_>, _>>()?;
+    check_instance_config_hidden(
+        &requester,
+        &root,
+        &paths_source.iter().map(|p| p.as_path()).collect::<Vec<_>>(),
+    )?;
 
-    let path_dest = scoped_join_win_safe(root, &relative_path_dest)?;
+    let path_dest = scoped_join_win_safe(&root, &relative_path_dest)?;
+    check_outside_trash(&root, &path_dest)?;
 
     if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path_dest)
     {
@@ -289,6 +512,12 @@ async fn copy_instance_files(
             source: eyre!("You don't have permission to write to this file"),
         });
     }
+    for path_source in &paths_source {
+        if let Some(file_name) = path_source.file_name() {
+            check_command_file(&state, &requester, &uuid, &root, &path_dest.join(file_name))
+                .await?;
+        }
+    }
 
     // if the destination path is a subdirectory of any of the source paths, deny
     if paths_source.iter().any(|p| path_dest.starts_with(p)) {
@@ -314,7 +543,9 @@ async fn copy_instance_files(
                     Event::new_progression_event_start(
                         "Copying files(s)",
                         Some(process_info.total_bytes as f64),
-                        None,
+                        Some(ProgressionStartValue::InstanceOperation {
+                            instance_uuid: uuid.clone(),
+                        }),
                         CausedBy::User {
                             user_id: requester.uid.clone(),
                             user_name: requester.username.clone(),
@@ -422,6 +653,9 @@ async fn move_instance_file(
     drop(instance);
     let path_source = scoped_join_win_safe(&root, relative_path_source)?;
     let path_dest = scoped_join_win_safe(&root, relative_path_dest)?;
+    check_instance_config_hidden(&requester, &root, &[&path_source])?;
+    check_outside_trash(&root, &path_source)?;
+    check_outside_trash(&root, &path_dest)?;
 
     let relative_path_source = path_source
         .strip_prefix(&root)
@@ -438,6 +672,8 @@ async fn move_instance_file(
             source: eyre!("You don't have permission to write to this file"),
         });
     }
+    check_command_file(&state, &requester, &uuid, &root, &path_source).await?;
+    check_command_file(&state, &requester, &uuid, &root, &path_dest).await?;
 
     // if the destination is a subdirectory of the source, we reject the request
     if path_dest.starts_with(&path_source) {
@@ -490,7 +726,7 @@ async fn remove_instance_file(
     })?;
     let root = instance.path().await;
     drop(instance);
-    let path = scoped_join_win_safe(root, relative_path)?;
+    let path = scoped_join_win_safe(&root, relative_path)?;
     // if target has a protected extension, or no extension, deny
     if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path) {
         return Err(Error {
@@ -498,8 +734,19 @@ async fn remove_instance_file(
             source: eyre!("File extension is protected"),
         });
     }
+    check_command_file(&state, &requester, &uuid, &root, &path).await?;
+    if !path.is_file() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("File not found"),
+        });
+    }
 
-    crate::util::fs::remove_file(&path).await?;
+    if path.starts_with(trash_dir(&root)) {
+        crate::util::fs::remove_file(&path).await?;
+    } else {
+        move_to_trash(&root, &path).await?;
+    }
 
     let caused_by = CausedBy::User {
         user_id: requester.uid,
@@ -545,11 +792,7 @@ async fn remove_instance_dir(
         });
     }
 
-    if requester.can_perform_action(&UserAction::WriteGlobalFile) {
-        tokio::fs::remove_dir_all(&path)
-            .await
-            .context("Failed to remove directory")?;
-    } else {
+    if !requester.can_perform_action(&UserAction::WriteGlobalFile) {
         // recursively access all files in the directory and check if they are protected
         for entry in WalkDir::new(path.clone()) {
             let entry =
@@ -561,9 +804,13 @@ async fn remove_instance_dir(
                 });
             }
         }
+    }
+    if path.starts_with(trash_dir(&root)) {
         tokio::fs::remove_dir_all(&path)
             .await
             .context("Failed to remove directory")?;
+    } else {
+        move_to_trash(&root, &path).await?;
     }
 
     let caused_by = CausedBy::User {
@@ -595,7 +842,8 @@ async fn new_instance_file(
     })?;
     let root = instance.path().await;
     drop(instance);
-    let path = scoped_join_win_safe(root, relative_path)?;
+    let path = scoped_join_win_safe(&root, relative_path)?;
+    check_outside_trash(&root, &path)?;
     // if target has a protected extension, or no extension, deny
     if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path) {
         return Err(Error {
@@ -603,6 +851,7 @@ async fn new_instance_file(
             source: eyre!("File extension is protected"),
         });
     }
+    check_command_file(&state, &requester, &uuid, &root, &path).await?;
 
     crate::util::fs::create(&path).await?;
 
@@ -636,6 +885,7 @@ async fn get_instance_file_url(
     let root = instance.path().await;
     drop(instance);
     let path = scoped_join_win_safe(&root, &relative_path)?;
+    check_instance_config_hidden(&requester, &root, &[&path])?;
 
     let downloadable_file = if fs::metadata(&path)
         .map_err(|_| Error {
@@ -647,7 +897,9 @@ async fn get_instance_file_url(
         let (start_event, id) = Event::new_progression_event_start(
             format!("Zipping {} for download", relative_path),
             None,
-            None,
+            Some(ProgressionStartValue::InstanceOperation {
+                instance_uuid: uuid.clone(),
+            }),
             CausedBy::User {
                 user_id: requester.uid.clone(),
                 user_name: requester.username.clone(),
@@ -665,7 +917,7 @@ async fn get_instance_file_url(
             })?);
             temp_file_path.set_extension("zip");
             let files = Vec::from([path.clone()]);
-            zip_files(&files, temp_file_path.clone(), true).context("Failed to zip file")?;
+            zip_files(&files, temp_file_path.clone(), true, None).context("Failed to zip file")?;
             Ok(DownloadableFile::ZippedFile((temp_file_path, temp_dir)))
         }
         .await;
@@ -725,14 +977,21 @@ async fn upload_instance_file(
     let root = instance.path().await;
     drop(instance);
     let path_to_dir = scoped_join_win_safe(&root, relative_path)?;
+    check_outside_trash(&root, &path_to_dir)?;
     crate::util::fs::create_dir_all(&path_to_dir).await?;
 
     let total = headers
         .get(CONTENT_LENGTH)
         .and_then(|v| v.to_str().ok())
         .and_then(|v| v.parse::<f64>().ok());
-    let (progression_start_event, event_id) =
-        Event::new_progression_event_start("Uploading files", total, None, caused_by.clone());
+    let (progression_start_event, event_id) = Event::new_progression_event_start(
+        "Uploading files",
+        total,
+        Some(ProgressionStartValue::InstanceOperation {
+            instance_uuid: uuid.clone(),
+        }),
+        caused_by.clone(),
+    );
     state.event_broadcaster.send(progression_start_event);
     while let Ok(Some(mut field)) = multipart.next_field().await {
         let name = field.file_name().ok_or_else(|| Error {
@@ -748,6 +1007,7 @@ async fn upload_instance_file(
                 source: eyre!("File extension is protected"),
             });
         }
+        check_command_file(&state, &requester, &uuid, &root, &path).await?;
         let path = resolve_path_conflict(path, None);
 
         let mut file = crate::util::fs::create(&path).await?;
@@ -859,9 +1119,12 @@ pub async fn unzip_instance_file(
     })?;
     let root = instance.path().await;
     drop(instance);
-    let path_to_zip_file = scoped_join_win_safe(root, &relative_path)?;
+    let path_to_zip_file = scoped_join_win_safe(&root, &relative_path)?;
+    // the other options unzip next to the file
+    check_outside_trash(&root, &path_to_zip_file)?;
 
     if let UnzipOption::ToDir(ref dir) = unzip_option {
+        check_outside_trash(&root, &root.join(dir))?;
         if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(dir) {
             return Err(Error {
                 kind: ErrorKind::PermissionDenied,
@@ -874,7 +1137,9 @@ pub async fn unzip_instance_file(
         let (progression_event_start, event_id) = Event::new_progression_event_start(
             format!("Unzipping {relative_path}"),
             None,
-            None,
+            Some(ProgressionStartValue::InstanceOperation {
+                instance_uuid: uuid.clone(),
+            }),
             CausedBy::User {
                 user_id: requester.uid.clone(),
                 user_name: requester.username.clone(),
@@ -945,6 +1210,15 @@ async fn zip_instance_files(
         *path = scoped_join_win_safe(&root, &*path)?;
     }
     destination_relative_path = scoped_join_win_safe(&root, &destination_relative_path)?;
+    check_outside_trash(&root, &destination_relative_path)?;
+    check_instance_config_hidden(
+        &requester,
+        &root,
+        &target_relative_paths
+            .iter()
+            .map(|p| p.as_path())
+            .collect::<Vec<_>>(),
+    )?;
 
     if !requester.can_perform_action(&UserAction::ReadGlobalFile)
         && is_path_protected(&destination_relative_path)
@@ -973,7 +1247,9 @@ async fn zip_instance_files(
         let (progression_start_event, event_id) = Event::new_progression_event_start(
             format!("Zipping {aggregate_name}"),
             None,
-            None,
+            Some(ProgressionStartValue::InstanceOperation {
+                instance_uuid: uuid.clone(),
+            }),
             CausedBy::User {
                 user_id: requester.uid.clone(),
                 user_name: requester.username.clone(),
@@ -981,8 +1257,13 @@ async fn zip_instance_files(
         );
         event_broadcaster.send(progression_start_event);
 
-        if let Err(e) =
-            zip_files_async(&target_relative_paths, destination_relative_path, false).await
+        if let Err(e) = zip_files_async(
+            &target_relative_paths,
+            destination_relative_path,
+            false,
+            None,
+        )
+        .await
         {
             event_broadcaster.send(Event::new_progression_event_end(
                 event_id,
@@ -1013,6 +1294,233 @@ async fn zip_instance_files(
     Ok(Json(()))
 }
 
+async fn list_instance_trash(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<TrashedFile>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    drop(instance);
+    Ok(Json(list_trash(&root).await?))
+}
+
+async fn restore_instance_trash(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<String>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::WriteInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    drop(instance);
+    let (trashed, content) = trashed_file(&root, &id).await?;
+    let destination = scoped_join_win_safe(&root, &trashed.path)?;
+    check_outside_trash(&root, &destination)?;
+    if !requester.can_perform_action(&UserAction::WriteGlobalFile) {
+        // a directory that doesn't exist yet is judged by its trashed content, of the same name
+        let target = if destination.exists() {
+            &destination
+        } else {
+            &content
+        };
+        if is_path_protected(target) {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("You don't have permission to restore this file"),
+            });
+        }
+        for entry in WalkDir::new(&content) {
+            let entry =
+                entry.context("Failed to walk directory while scanning for protected files")?;
+            if entry.file_type().is_file() && is_path_protected(entry.path()) {
+                return Err(Error {
+                    kind: ErrorKind::PermissionDenied,
+                    source: eyre!("You don't have permission to restore this file"),
+                });
+            }
+        }
+    }
+    check_command_file(&state, &requester, &uuid, &root, &destination).await?;
+    let restored = restore_from_trash(&root, &id).await?;
+
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    let target = if restored.is_dir() {
+        FSTarget::Directory(restored.clone())
+    } else {
+        FSTarget::File(restored.clone())
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Move {
+            source: trash_dir(&root).join(&id),
+        },
+        target,
+        caused_by,
+    ));
+    // where the file ended up, relative to the instance root
+    Ok(Json(
+        restored
+            .strip_prefix(&root)
+            .unwrap_or(&restored)
+            .to_string_lossy()
+            .to_string(),
+    ))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct ConfigSync {
+    pub source: InstanceUuid,
+    /// relative to the instance roots, the same in the source and the targets
+    pub path: String,
+    pub targets: Vec<InstanceUuid>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct ConfigSyncResult {
+    pub instance: InstanceUuid,
+    /// Why the file couldn't be copied to this instance
+    pub error: Option<String>,
+    /// Whether the file was written, it isn't if it already had the same content
+    pub changed: bool,
+    /// The previous version of the file, restorable from the instance's trash
+    pub backup: Option<TrashedFile>,
+    /// The instance is running and only picks up the file after a restart
+    pub restart_required: bool,
+}
+
+/// Copies `content` to `relative_path` in `target`, moving the file it replaces to the trash
+async fn sync_file_to_instance(
+    state: &AppState,
+    requester: &User,
+    target: &InstanceUuid,
+    relative_path: &str,
+    content: &[u8],
+) -> Result<ConfigSyncResult, Error> {
+    requester.try_action(
+        &UserAction::WriteInstanceFile(target.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state
+        .instances
+        .get(target)
+        .map(|instance| instance.value().clone())
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?;
+    let root = instance.path().await;
+    let path = scoped_join_win_safe(&root, relative_path)?;
+    if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You don't have permission to write to this file"),
+        });
+    }
+    check_command_file(state, requester, target, &root, &path).await?;
+    let backup = match tokio::fs::read(&path).await {
+        Ok(current) if current == content => {
+            return Ok(ConfigSyncResult {
+                instance: target.clone(),
+                error: None,
+                changed: false,
+                backup: None,
+                restart_required: false,
+            });
+        }
+        Ok(_) => Some(move_to_trash(&root, &path).await?),
+        Err(_) => None,
+    };
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context("Failed to create parent directory")?;
+    }
+    tokio::fs::write(&path, content)
+        .await
+        .context("Failed to write file")?;
+
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Write,
+        FSTarget::File(path),
+        CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username.clone(),
+        },
+    ));
+    let instance_state = instance.state().await;
+    mark_restart_required(state, target, instance_state);
+    Ok(ConfigSyncResult {
+        instance: target.clone(),
+        error: None,
+        changed: true,
+        backup,
+        restart_required: instance_state != State::Stopped,
+    })
+}
+
+/// Copies a file of one instance to the same path in other instances, for keeping configs
+/// consistent across a network
+///
+/// A target failing doesn't stop the others, each gets its own result
+async fn sync_config_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(sync): Json<ConfigSync>,
+) -> Result<Json<Vec<ConfigSyncResult>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadInstanceFile(sync.source.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&sync.source).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Source instance not found"),
+    })?;
+    let root = instance.path().await;
+    drop(instance);
+    let source_path = scoped_join_win_safe(root, &sync.path)?;
+    let content = tokio::fs::read(&source_path)
+        .await
+        .context("Failed to read source file")?;
+
+    let mut results = Vec::new();
+    for target in sync.targets.iter().filter(|target| **target != sync.source) {
+        results.push(
+            match sync_file_to_instance(&state, &requester, target, &sync.path, &content).await {
+                Ok(result) => result,
+                Err(e) => ConfigSyncResult {
+                    instance: target.clone(),
+                    error: Some(e.source.to_string()),
+                    changed: false,
+                    backup: None,
+                    restart_required: false,
+                },
+            },
+        );
+    }
+    Ok(Json(results))
+}
+
 pub fn get_instance_fs_routes(state: AppState) -> Router {
     Router::new()
         .route(
@@ -1023,6 +1531,8 @@ pub fn get_instance_fs_routes(state: AppState) -> Router {
             "/instance/:uuid/fs/:base64_relative_path/read",
             get(read_instance_file),
         )
+        .route("/instance/:uuid/fs/preview", get(preview_instance_file))
+        .route("/instance/:uuid/fs/diff", get(diff_instance_file))
         .route(
             "/instance/:uuid/fs/:base64_relative_path/write",
             put(write_instance_file),
@@ -1032,6 +1542,11 @@ pub fn get_instance_fs_routes(state: AppState) -> Router {
             put(make_instance_directory),
         )
         .route("/instance/:uuid/fs/cpr", put(copy_instance_files))
+        .route("/instance/:uuid/fs/trash", get(list_instance_trash))
+        .route(
+            "/instance/:uuid/fs/trash/:id/restore",
+            put(restore_instance_trash),
+        )
         .route(
             "/instance/:uuid/fs/:base64_relative_path/move/:base64_relative_path_dest",
             put(move_instance_file),
@@ -1062,5 +1577,34 @@ pub fn get_instance_fs_routes(state: AppState) -> Router {
             put(unzip_instance_file),
         )
         .route("/instance/:uuid/fs/zip", put(zip_instance_files))
+        .route("/instance/config/sync", post(sync_config_file))
         .with_state(state)
 }
+
+#[test]
+fn test_check_unchanged() {
+    let hash = content_hash(b"motd=A Minecraft Server\n");
+    assert!(check_unchanged(None, None).is_ok());
+    assert!(check_unchanged(Some(&hash), Some(&hash)).is_ok());
+    assert!(check_unchanged(Some(&format!("\"{hash}\"")), Some(&hash)).is_ok());
+    assert!(check_unchanged(Some(&hash), Some(&content_hash(b"motd=Changed\n"))).is_err());
+    // the file was deleted since it was read
+    assert!(check_unchanged(Some(&hash), None).is_err());
+}
+
+#[test]
+fn test_reveals_instance_config() {
+    let root = std::path::Path::new("/lodestone/instances/survival");
+    assert!(reveals_instance_config(root, root));
+    assert!(reveals_instance_config(root, &root.join(CONFIG_FILE_NAME)));
+    assert!(!reveals_instance_config(
+        root,
+        &root.join("server.properties")
+    ));
+    assert!(!reveals_instance_config(root, &root.join("world")));
+    // a file of the same name deeper in the instance is just a file
+    assert!(!reveals_instance_config(
+        root,
+        &root.join("backups").join(CONFIG_FILE_NAME)
+    ));
+}
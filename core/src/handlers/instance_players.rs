@@ -1,15 +1,49 @@
 use std::collections::HashSet;
 
-use axum::{extract::Path, routing::get, Json, Router};
+use axum::{extract::Path, http, routing::get, Json, Router};
+use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
 use crate::{
+    auth::user::UserAction,
     error::{Error, ErrorKind},
-    traits::t_player::{Player, TPlayerManagement},
+    implementations::minecraft::player::get_player_head,
+    traits::t_player::{Player, TPlayer, TPlayerManagement},
     types::InstanceUuid,
     AppState,
 };
 
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct OnlinePlayer {
+    pub name: String,
+    pub uuid: Option<String>,
+    /// `None` if the skin is unavailable, e.g. on an offline mode server
+    pub head_url: Option<String>,
+    pub skin_url: Option<String>,
+}
+
+impl From<Player> for OnlinePlayer {
+    fn from(player: Player) -> Self {
+        match player {
+            Player::MinecraftPlayer(player) => OnlinePlayer {
+                head_url: player.head_url(),
+                skin_url: player.skin_url(),
+                name: player.name,
+                uuid: player.uuid,
+            },
+            Player::GenericPlayer(player) => OnlinePlayer {
+                name: player.get_name(),
+                uuid: Some(player.get_id()),
+                head_url: None,
+                skin_url: None,
+            },
+        }
+    }
+}
+
 pub async fn get_player_count(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -75,6 +109,50 @@ pub async fn get_player_list(
         .map(Json)
 }
 
+/// The player list is kept up to date from join/leave messages in the server log,
+/// changes are also pushed to the event stream as `PlayerChange` events
+pub async fn get_online_players(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<OnlinePlayer>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let mut players: Vec<OnlinePlayer> = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .get_player_list()
+        .await?
+        .into_iter()
+        .map(OnlinePlayer::from)
+        .collect();
+    players.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(players))
+}
+
+pub async fn get_head(
+    Path(player_uuid): Path<String>,
+) -> Result<([(http::HeaderName, String); 2], Vec<u8>), Error> {
+    let head = get_player_head(&player_uuid).await?;
+    Ok((
+        [
+            (http::header::CONTENT_TYPE, "image/png".to_string()),
+            (
+                http::header::CACHE_CONTROL,
+                "public, max-age=86400".to_string(),
+            ),
+        ],
+        head,
+    ))
+}
+
 pub fn get_instance_players_routes(state: AppState) -> Router {
     Router::new()
         .route("/instance/:uuid/players/count", get(get_player_count))
@@ -83,5 +161,7 @@ pub fn get_instance_players_routes(state: AppState) -> Router {
             get(get_max_player_count).put(set_max_player_count),
         )
         .route("/instance/:uuid/players", get(get_player_list))
+        .route("/instance/:uuid/players/online", get(get_online_players))
+        .route("/player/:player_uuid/head", get(get_head))
         .with_state(state)
 }
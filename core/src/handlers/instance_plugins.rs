@@ -0,0 +1,282 @@
+use axum::{
+    extract::{Path, Query},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::user::{User, UserAction},
+    error::{Error, ErrorKind},
+    events::{new_fs_event, CausedBy, FSOperation, FSTarget},
+    file_trash::move_to_trash,
+    modrinth::{self, compatibility_warnings, plugin_target, ModrinthSearchHit, ModrinthVersion},
+    plugins::{
+        list_plugins, plugin_path, set_plugin_enabled, validate_file_name, PluginDir, PluginInfo,
+    },
+    prelude::path_to_tmp,
+    traits::t_configurable::{Game, TConfigurable},
+    types::InstanceUuid,
+    util::{download_file, rand_alphanumeric, sha512_file},
+    AppState,
+};
+
+use super::util::instance_root;
+
+/// Jars are protected files, changing them needs the same permissions as in the file manager
+async fn try_write_plugins(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+) -> Result<(), Error> {
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()), safe_mode)?;
+    requester.try_action(&UserAction::WriteGlobalFile, safe_mode)
+}
+
+pub async fn get_plugins(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PluginInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let root = instance_root(&state, &uuid).await?;
+    let plugins = tokio::task::spawn_blocking(move || list_plugins(&root))
+        .await
+        .context("Failed to list plugins")??;
+    Ok(Json(plugins))
+}
+
+pub async fn set_plugin_enabled_state(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, dir, file_name)): Path<(InstanceUuid, PluginDir, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(enabled): Json<bool>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    try_write_plugins(&state, &requester, &uuid).await?;
+    let root = instance_root(&state, &uuid).await?;
+    set_plugin_enabled(&root, dir, &file_name, enabled).await?;
+    Ok(Json(()))
+}
+
+/// Moves the jar to the instance trash, like deleting it from the file manager
+pub async fn delete_plugin(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, dir, file_name)): Path<(InstanceUuid, PluginDir, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    try_write_plugins(&state, &requester, &uuid).await?;
+    let root = instance_root(&state, &uuid).await?;
+    let path = plugin_path(&root, dir, &file_name)?;
+    move_to_trash(&root, &path).await?;
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Delete,
+        FSTarget::File(path),
+        CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+    ));
+    Ok(Json(()))
+}
+
+/// The game version of the instance, where its plugins go and the Modrinth loaders it runs
+async fn plugin_target_of(
+    state: &AppState,
+    uuid: &InstanceUuid,
+) -> Result<
+    (
+        String,
+        PluginDir,
+        modrinth::ModrinthProjectType,
+        &'static [&'static str],
+    ),
+    Error,
+> {
+    let instance = state.instances.get(uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let target = match instance.game_type().await {
+        Game::MinecraftJava { variant } => plugin_target(&variant),
+        _ => None,
+    };
+    let (dir, project_type, loaders) = target.ok_or_else(|| Error {
+        kind: ErrorKind::UnsupportedOperation,
+        source: eyre!("This instance cannot load plugins or mods"),
+    })?;
+    Ok((instance.version().await, dir, project_type, loaders))
+}
+
+#[derive(Deserialize)]
+pub struct ModrinthSearchQuery {
+    query: String,
+    limit: Option<u32>,
+}
+
+/// Searches Modrinth for plugins or mods, depending on what the instance can load
+pub async fn search_modrinth(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<ModrinthSearchQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ModrinthSearchHit>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let (_, _, project_type, _) = plugin_target_of(&state, &uuid).await?;
+    Ok(Json(
+        modrinth::search(&query.query, project_type, query.limit.unwrap_or(20)).await?,
+    ))
+}
+
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ModrinthVersionInfo {
+    #[serde(flatten)]
+    pub version: ModrinthVersion,
+    /// Why the version might not work on the instance, empty if it should
+    pub warnings: Vec<String>,
+}
+
+pub async fn get_modrinth_versions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, project_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ModrinthVersionInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let (game_version, _, _, loaders) = plugin_target_of(&state, &uuid).await?;
+    Ok(Json(
+        modrinth::project_versions(&project_id)
+            .await?
+            .into_iter()
+            .map(|version| ModrinthVersionInfo {
+                warnings: compatibility_warnings(&version, &game_version, loaders),
+                version,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct PluginInstallRequest {
+    pub project_id: String,
+    pub version_id: String,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct PluginInstallResult {
+    pub dir: PluginDir,
+    pub file_name: String,
+    /// Why the plugin might not work on the instance, it is installed anyway
+    pub warnings: Vec<String>,
+}
+
+/// Downloads a Modrinth version into `plugins/` or `mods/` after checking its SHA-512
+pub async fn install_plugin(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<PluginInstallRequest>,
+) -> Result<Json<PluginInstallResult>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    try_write_plugins(&state, &requester, &uuid).await?;
+    let (game_version, dir, _, loaders) = plugin_target_of(&state, &uuid).await?;
+    let root = instance_root(&state, &uuid).await?;
+
+    let version = modrinth::version(&request.version_id).await?;
+    if version.project_id != request.project_id {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Version {} is not a version of {}",
+                version.id,
+                request.project_id
+            ),
+        });
+    }
+    let file = version.primary_file().ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Version {} has no files", version.id),
+    })?;
+    validate_file_name(&file.filename)?;
+    if plugin_path(&root, dir, &file.filename).is_ok() {
+        return Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!("{} is already installed", file.filename),
+        });
+    }
+
+    // downloaded outside the instance, so the server never sees an unverified jar
+    let tmp_dir = path_to_tmp().join(rand_alphanumeric(12));
+    let downloaded = async {
+        let path = download_file(
+            &file.url,
+            &tmp_dir,
+            Some(&file.filename),
+            &|_| {},
+            true,
+            None,
+        )
+        .await?;
+        let sha512 = sha512_file(&path).await?;
+        if !sha512.eq_ignore_ascii_case(&file.hashes.sha512) {
+            return Err(Error {
+                kind: ErrorKind::External,
+                source: eyre!("Checksum of {} does not match Modrinth's", file.filename),
+            });
+        }
+        let plugins_dir = root.join(dir.dir_name());
+        crate::util::fs::create_dir_all(&plugins_dir).await?;
+        crate::util::fs::rename(&path, plugins_dir.join(&file.filename)).await
+    }
+    .await;
+    let _ = tokio::fs::remove_dir_all(&tmp_dir).await;
+    downloaded?;
+
+    Ok(Json(PluginInstallResult {
+        dir,
+        file_name: file.filename.clone(),
+        warnings: compatibility_warnings(&version, &game_version, loaders),
+    }))
+}
+
+pub fn get_instance_plugins_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/plugins", get(get_plugins))
+        .route("/instance/:uuid/plugins/install", post(install_plugin))
+        .route(
+            "/instance/:uuid/plugins/modrinth/search",
+            get(search_modrinth),
+        )
+        .route(
+            "/instance/:uuid/plugins/modrinth/:project_id/versions",
+            get(get_modrinth_versions),
+        )
+        .route(
+            "/instance/:uuid/plugins/:dir/:file_name",
+            delete(delete_plugin),
+        )
+        .route(
+            "/instance/:uuid/plugins/:dir/:file_name/enabled",
+            put(set_plugin_enabled_state),
+        )
+        .with_state(state)
+}
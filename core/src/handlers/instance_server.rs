@@ -1,5 +1,5 @@
 use axum::{
-    extract::Path,
+    extract::{Path, Query},
     routing::{get, post, put},
     Router,
 };
@@ -7,26 +7,273 @@ use axum::{
 use axum::Json;
 use axum_auth::AuthBearer;
 
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
 use color_eyre::eyre::eyre;
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use sysinfo::SystemExt;
+use tokio::sync::{broadcast::error::RecvError, Notify};
+use tracing::error;
+use ts_rs::TS;
 
 use crate::{
-    auth::user::UserAction,
+    auth::user::{User, UserAction},
+    auto_start::auto_start_tiers,
+    cgroup::{cgroup_limits, effective_memory},
+    disk_usage::{disk_space_of, guard_instance_disk_space},
     error::{Error, ErrorKind},
-    events::CausedBy,
-    types::InstanceUuid,
+    events::{CausedBy, Event, EventInner, InstanceEventInner},
+    implementations::minecraft::{
+        line_parser::parse_startup_duration,
+        ping::{server_list_ping, ServerListPing},
+        signal::ProcessSignal,
+    },
+    instance_state::InstanceStateReport,
+    startup_diagnostics::{DiagnosticCheck, StartupCheck, StartupDiagnostics},
+    tasks::cancelled_error,
+    types::{normalize_tag, InstanceUuid, Snowflake},
 };
 
 use crate::{
-    traits::{t_configurable::TConfigurable, t_server::TServer},
+    prelude::GameInstance,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
     AppState,
 };
 
+use super::console_history::record_console_command;
+
+#[derive(Deserialize)]
+pub struct RestartQuery {
+    /// Warn players in-game with the configured countdown before restarting
+    #[serde(default)]
+    countdown: bool,
+}
+
+#[derive(Deserialize)]
+pub struct StartQuery {
+    /// Skip the memory headroom check
+    #[serde(default)]
+    force: bool,
+    /// Hold the response until the server is ready for players, or `timeout_secs` runs out
+    #[serde(default)]
+    wait: bool,
+    timeout_secs: Option<u64>,
+}
+
+const DEFAULT_START_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_START_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct StartResponse {
+    #[serde(flatten)]
+    pub state: InstanceStateReport,
+    /// Only set when waiting, `false` if the server was still starting when the timeout ran out
+    pub ready: Option<bool>,
+    /// Seconds the server took to start, as printed by the server if it does
+    pub startup_duration: Option<f64>,
+}
+
+/// Waits for `uuid` to become ready, returning whether it did before `timeout` and how
+/// long it took to start
+///
+/// Fails if the instance stops before it is ready
+async fn wait_until_ready(
+    event_receiver: &mut tokio::sync::broadcast::Receiver<Event>,
+    uuid: &InstanceUuid,
+    started_at: Instant,
+    timeout: Duration,
+) -> Result<(bool, Option<f64>), Error> {
+    let mut reported_duration = None;
+    let wait = async {
+        loop {
+            let event = match event_receiver.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => {
+                    return Err(Error {
+                        kind: ErrorKind::Internal,
+                        source: eyre!("Event channel closed"),
+                    })
+                }
+            };
+            let instance_event = match event.event_inner {
+                EventInner::InstanceEvent(instance_event)
+                    if &instance_event.instance_uuid == uuid =>
+                {
+                    instance_event
+                }
+                _ => continue,
+            };
+            match instance_event.instance_event_inner {
+                InstanceEventInner::InstanceOutput { message, .. } => {
+                    if let Some(duration) = parse_startup_duration(&message) {
+                        reported_duration = Some(duration);
+                    }
+                }
+                InstanceEventInner::StateTransition { to: State::Running } => return Ok(()),
+                InstanceEventInner::StateTransition {
+                    to: State::Stopped | State::Error,
+                } => {
+                    return Err(Error {
+                        kind: ErrorKind::Internal,
+                        source: eyre!("Instance exited unexpectedly before starting"),
+                    })
+                }
+                _ => {}
+            }
+        }
+    };
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(Ok(())) => Ok((
+            true,
+            reported_duration.or_else(|| Some(started_at.elapsed().as_secs_f64())),
+        )),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Ok((false, None)),
+    }
+}
+
+/// Refuse to start an instance if its maximum memory on top of what the host
+/// already uses would leave less than the configured safety margin free
+async fn check_memory_headroom(state: &AppState, instance: &GameInstance) -> Result<(), Error> {
+    let max_memory = match instance.max_memory().await {
+        Some(v) => v as u64 * 1024 * 1024,
+        None => return Ok(()),
+    };
+    let margin = state
+        .global_settings
+        .lock()
+        .await
+        .memory_safety_margin_mb()
+        .saturating_mul(1024 * 1024);
+    let mut sys = state.system.lock().await;
+    sys.refresh_memory();
+    // inside a container, its memory limit is the real ceiling
+    let (total, available) = effective_memory(
+        cgroup_limits().as_ref(),
+        sys.total_memory(),
+        sys.available_memory(),
+    );
+    let used = total.saturating_sub(available);
+    drop(sys);
+    if used + max_memory + margin > total {
+        return Err(Error {
+            kind: ErrorKind::InsufficientMemory,
+            source: eyre!(
+                "Starting this instance with {} MB of memory would leave less than {} MB free ({} MB of {} MB in use). Start with force to override",
+                max_memory / 1024 / 1024,
+                margin / 1024 / 1024,
+                used / 1024 / 1024,
+                total / 1024 / 1024
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// The checks before starting, then the start itself
+pub(super) async fn start_checked(
+    state: &AppState,
+    instance: &GameInstance,
+    caused_by: CausedBy,
+    force: bool,
+) -> Result<(), Error> {
+    let port = instance.port().await;
+
+    // check if port is already in use
+    if state.port_manager.lock().await.port_status(port).is_in_use {
+        return Err(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!("Port {} is in use", port),
+        });
+    }
+
+    guard_instance_disk_space(
+        instance,
+        state.global_settings.lock().await.min_free_disk_space_mb(),
+        &state.event_broadcaster,
+    )
+    .await?;
+    if !force {
+        check_memory_headroom(state, instance).await?;
+    }
+    instance.start(caused_by, false).await
+}
+
+/// Every startup check, run after a start failed to tell the user what to fix
+async fn startup_diagnostics(state: &AppState, instance: &GameInstance) -> StartupDiagnostics {
+    let mut checks = match instance {
+        GameInstance::MinecraftInstance(minecraft) => minecraft.startup_checks().await,
+        _ => Vec::new(),
+    };
+
+    let port = instance.port().await;
+    checks.push(
+        if state.port_manager.lock().await.port_status(port).is_in_use {
+            DiagnosticCheck::failed(
+                StartupCheck::Port,
+                format!(
+                    "Port {} is in use, stop whatever is using it or change the port",
+                    port
+                ),
+            )
+        } else {
+            DiagnosticCheck::passed(StartupCheck::Port, format!("Port {} is free", port))
+        },
+    );
+
+    checks.push(match check_memory_headroom(state, instance).await {
+        Ok(()) => DiagnosticCheck::passed(StartupCheck::Memory, "Enough memory is free"),
+        Err(e) => DiagnosticCheck::failed(StartupCheck::Memory, e.source.to_string()),
+    });
+
+    let min_free_mb = state.global_settings.lock().await.min_free_disk_space_mb();
+    let path = instance.path().await;
+    checks.push(
+        match tokio::task::spawn_blocking(move || disk_space_of(path))
+            .await
+            .ok()
+            .flatten()
+        {
+            Some(disk_space) if disk_space.free < min_free_mb.saturating_mul(1024 * 1024) => {
+                DiagnosticCheck::failed(
+                    StartupCheck::DiskSpace,
+                    format!(
+                        "Only {} MB of disk space left, at least {} MB is required",
+                        disk_space.free / 1024 / 1024,
+                        min_free_mb
+                    ),
+                )
+            }
+            Some(disk_space) => DiagnosticCheck::passed(
+                StartupCheck::DiskSpace,
+                format!("{} MB of disk space left", disk_space.free / 1024 / 1024),
+            ),
+            None => DiagnosticCheck::passed(
+                StartupCheck::DiskSpace,
+                "Free disk space could not be determined",
+            ),
+        },
+    );
+
+    StartupDiagnostics { checks }
+}
+
 pub async fn start_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<StartQuery>,
     AuthBearer(token): AuthBearer,
-) -> Result<Json<()>, Error> {
+) -> Result<Json<StartResponse>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(
         &UserAction::StartInstance(uuid.clone()),
@@ -35,7 +282,11 @@ pub async fn start_instance(
     if uuid.to_string().starts_with("DOCKER-") {
         let docker_bridge = state.docker_bridge.clone();
         docker_bridge.start_container(&uuid).await?;
-        return Ok(Json(()));
+        return Ok(Json(StartResponse {
+            state: instance_state_report(&state, &uuid).await?,
+            ready: None,
+            startup_duration: None,
+        }));
     }
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
@@ -45,25 +296,41 @@ pub async fn start_instance(
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
-    let port = instance.port().await;
-
-    // check if port is already in use
-    if state.port_manager.lock().await.port_status(port).is_in_use {
-        return Err(Error {
-            kind: ErrorKind::Internal,
-            source: eyre!("Port {} is in use", port),
-        });
+    // subscribe before starting so the ready transition can't be missed
+    let mut event_receiver = state.event_broadcaster.subscribe();
+    let started_at = Instant::now();
+    if let Err(e) = start_checked(&state, &instance, caused_by, query.force).await {
+        // refused because of the state it is in, nothing to diagnose
+        if e.kind == ErrorKind::Conflict {
+            return Err(e);
+        }
+        return Err(startup_diagnostics(&state, &instance).await.attach(e));
     }
-
-    instance.start(caused_by, false).await?;
-    Ok(Json(()))
+    drop(instance);
+    let (ready, startup_duration) = if query.wait {
+        let timeout = query
+            .timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_START_TIMEOUT)
+            .min(MAX_START_TIMEOUT);
+        let (ready, startup_duration) =
+            wait_until_ready(&mut event_receiver, &uuid, started_at, timeout).await?;
+        (Some(ready), startup_duration)
+    } else {
+        (None, None)
+    };
+    Ok(Json(StartResponse {
+        state: instance_state_report(&state, &uuid).await?,
+        ready,
+        startup_duration,
+    }))
 }
 
 pub async fn stop_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
     AuthBearer(token): AuthBearer,
-) -> Result<Json<()>, Error> {
+) -> Result<Json<InstanceStateReport>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(
         &UserAction::StopInstance(uuid.clone()),
@@ -72,7 +339,7 @@ pub async fn stop_instance(
     if uuid.to_string().starts_with("DOCKER-") {
         let docker_bridge = state.docker_bridge.clone();
         docker_bridge.stop_container(&uuid).await?;
-        return Ok(Json(()));
+        return Ok(Json(instance_state_report(&state, &uuid).await?));
     }
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
@@ -87,12 +354,13 @@ pub async fn stop_instance(
         })?
         .stop(caused_by, false)
         .await?;
-    Ok(Json(()))
+    Ok(Json(instance_state_report(&state, &uuid).await?))
 }
 
 pub async fn restart_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<RestartQuery>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
@@ -115,10 +383,64 @@ pub async fn restart_instance(
         source: eyre!("Instance not found"),
     })?;
 
+    if query.countdown {
+        if let GameInstance::MinecraftInstance(minecraft) = instance.value() {
+            if minecraft.state().await != State::Running {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Instance is not running"),
+                });
+            }
+            let early_trigger = Arc::new(Notify::new());
+            match state.pending_restarts.entry(uuid.clone()) {
+                Entry::Occupied(_) => {
+                    return Err(Error {
+                        kind: ErrorKind::Conflict,
+                        source: eyre!("A restart is already counting down"),
+                    })
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(early_trigger.clone());
+                }
+            }
+            let warnings = state.global_settings.lock().await.restart_warnings();
+            let minecraft = minecraft.clone();
+            let pending_restarts = state.pending_restarts.clone();
+            tokio::spawn(async move {
+                if let Err(e) = minecraft
+                    .restart_with_warnings(&warnings, early_trigger, caused_by)
+                    .await
+                {
+                    error!("Failed to restart instance {}: {}", uuid, e);
+                }
+                pending_restarts.remove(&uuid);
+            });
+            return Ok(Json(()));
+        }
+    }
     instance.restart(caused_by, false).await?;
     Ok(Json(()))
 }
 
+/// Skips the rest of a running restart countdown and restarts right away
+pub async fn restart_instance_now(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    requester
+        .try_action(&UserAction::StopInstance(uuid.clone()), safe_mode)
+        .and_then(|_x| requester.try_action(&UserAction::StartInstance(uuid.clone()), safe_mode))?;
+    let early_trigger = state.pending_restarts.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("No restart is counting down for this instance"),
+    })?;
+    early_trigger.notify_one();
+    Ok(Json(()))
+}
+
 pub async fn kill_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -138,18 +460,42 @@ pub async fn kill_instance(
         docker_bridge.kill_container(&uuid).await?;
         return Ok(Json(json!("ok")));
     }
-    state
+    let instance = state
         .instances
         .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
         })?
-        .kill(caused_by)
-        .await?;
+        .clone();
+    state.instance_states.request_stop(&uuid);
+    instance.kill(caused_by).await?;
     Ok(Json(json!("ok")))
 }
 
+/// Rejects a command the requester's role may not send, recording the attempt
+pub(crate) async fn check_command_filter(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+    command: &str,
+) -> Result<(), Error> {
+    let command_filters = state.global_settings.lock().await.command_filters();
+    if let Err(e) = command_filters.check_command(requester, command) {
+        state.event_broadcaster.send(Event::new_command_denied(
+            requester.uid.clone(),
+            uuid.clone(),
+            command.to_string(),
+            CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+            },
+        ));
+        return Err(e);
+    }
+    Ok(())
+}
+
 pub async fn send_command(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -161,6 +507,7 @@ pub async fn send_command(
         &UserAction::AccessConsole(uuid.clone()),
         state.global_settings.lock().await.safe_mode(),
     )?;
+    check_command_filter(&state, &requester, &uuid, &command).await?;
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
@@ -172,48 +519,543 @@ pub async fn send_command(
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
         })?
-        .send_command(&command, caused_by)
-        .await
-        .map(|_| Json(()))
+        .send_command(&command, caused_by.clone())
+        .await?;
+    record_console_command(&state, &uuid, &command, &caused_by).await;
+    Ok(Json(()))
 }
 
-pub async fn get_instance_state(
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct CommandResponse {
+    /// Output of the command, `None` if RCON is unavailable and the command
+    /// was written to the console instead
+    pub response: Option<String>,
+}
+
+/// Sends a command over RCON to get its output back, falling back to the console
+pub async fn send_command_with_response(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
     AuthBearer(token): AuthBearer,
-) -> Result<Json<Value>, Error> {
+    Json(command): Json<String>,
+) -> Result<Json<CommandResponse>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    if !requester.can_perform_action(&UserAction::ViewInstance(uuid.clone())) {
+    requester.try_action(
+        &UserAction::AccessConsole(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    check_command_filter(&state, &requester, &uuid, &command).await?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let instance = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .value()
+        .clone();
+    // stop goes through the console so the state transition is tracked
+    if command.trim() != "stop" {
+        if let GameInstance::MinecraftInstance(minecraft) = &instance {
+            if let Ok(response) = minecraft.send_rcon(&command).await {
+                record_console_command(&state, &uuid, &command, &caused_by).await;
+                return Ok(Json(CommandResponse {
+                    response: Some(response),
+                }));
+            }
+        }
+    }
+    instance.send_command(&command, caused_by.clone()).await?;
+    record_console_command(&state, &uuid, &command, &caused_by).await;
+    Ok(Json(CommandResponse { response: None }))
+}
+
+/// How long console output is collected after sending a signal, a JVM thread dump is printed
+/// well within this
+const SIGNAL_OUTPUT_WINDOW: Duration = Duration::from_secs(2);
+/// At most this many lines of output are returned, the rest is still in the console
+const SIGNAL_OUTPUT_MAX_LINES: usize = 2000;
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct SendSignal {
+    pub signal: ProcessSignal,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct SignalResult {
+    pub signal: ProcessSignal,
+    pub pid: u32,
+    pub sent_at: i64,
+    /// Console output printed shortly after the signal was sent
+    pub output: Vec<String>,
+    /// Whether more output was printed than is returned
+    pub output_truncated: bool,
+}
+
+/// Sends a signal from an allowlist to the server process, admin only and Unix only
+pub async fn send_signal(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(body): Json<SendSignal>,
+) -> Result<Json<SignalResult>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner && !requester.is_admin {
         return Err(Error {
             kind: ErrorKind::PermissionDenied,
-            source: eyre!("You don't have permission to view this instance"),
+            source: eyre!("Not authorized to send signals to the server process"),
         });
     }
-    if uuid.to_string().starts_with("DOCKER-") {
-        let docker_bridge = state.docker_bridge.clone();
-        let state = docker_bridge.get_container_state(&uuid).await?;
-        return Ok(Json(json!(state)));
+    let instance = match state.instances.get(&uuid).as_deref() {
+        Some(GameInstance::MinecraftInstance(instance)) => instance.clone(),
+        Some(_) => {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("Signals are only supported for Minecraft instances"),
+            })
+        }
+        None => {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })
+        }
+    };
+    // subscribe before sending so no output is missed
+    let mut event_receiver = state.event_broadcaster.subscribe();
+    let sent_at = chrono::Utc::now().timestamp();
+    let pid = instance.send_signal(body.signal).await?;
+    state.event_broadcaster.send(Event::new_system_message(
+        uuid.clone(),
+        instance.name().await,
+        format!(
+            "{} sent {} to the server process (pid {pid})",
+            requester.username, body.signal
+        ),
+    ));
+    let mut output = Vec::new();
+    let mut output_truncated = false;
+    let _ = tokio::time::timeout(SIGNAL_OUTPUT_WINDOW, async {
+        loop {
+            let event = match event_receiver.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => {
+                    output_truncated = true;
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            match event.event_inner {
+                EventInner::InstanceEvent(instance_event)
+                    if instance_event.instance_uuid == uuid =>
+                {
+                    if let InstanceEventInner::InstanceOutput { message, .. } =
+                        instance_event.instance_event_inner
+                    {
+                        if output.len() < SIGNAL_OUTPUT_MAX_LINES {
+                            output.push(message);
+                        } else {
+                            output_truncated = true;
+                        }
+                    }
+                }
+                _ => continue,
+            }
+        }
+    })
+    .await;
+    Ok(Json(SignalResult {
+        signal: body.signal,
+        pid,
+        sent_at,
+        output,
+        output_truncated,
+    }))
+}
+
+/// How long to wait for a server list ping before considering the server unreachable
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks that the server actually accepts connections, regardless of what state the process is in
+pub async fn ping_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ServerListPing>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .value()
+        .clone();
+    if !matches!(instance, GameInstance::MinecraftInstance(_)) {
+        return Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Only Minecraft instances can be pinged"),
+        });
     }
-    Ok(Json(json!(
-        state
+    let port = instance.port().await;
+    server_list_ping(port as u16, PING_TIMEOUT).await.map(Json)
+}
+
+#[derive(Serialize, TS)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[ts(export)]
+pub enum BroadcastStatus {
+    Sent,
+    NotRunning,
+    /// Not a Minecraft instance, so there is no `say` command
+    Unsupported,
+    Failed {
+        message: String,
+    },
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct BroadcastResult {
+    pub uuid: InstanceUuid,
+    pub name: String,
+    pub status: BroadcastStatus,
+}
+
+/// Sends `say <message>` to every running Minecraft instance the requester can use the console of
+pub async fn broadcast_message(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(message): Json<String>,
+) -> Result<Json<Vec<BroadcastResult>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let message = message.trim();
+    if message.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Message cannot be empty"),
+        });
+    }
+    // a line break would let the message run a second command
+    if message.contains(['\n', '\r']) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Message must be a single line"),
+        });
+    }
+    let command = format!("say {message}");
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    let instances: Vec<(InstanceUuid, GameInstance)> = state
+        .instances
+        .iter()
+        .filter(|entry| {
+            requester
+                .try_action(&UserAction::AccessConsole(entry.key().clone()), safe_mode)
+                .is_ok()
+        })
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect();
+
+    let mut results = Vec::new();
+    for (uuid, instance) in instances {
+        let status = if !matches!(instance, GameInstance::MinecraftInstance(_)) {
+            BroadcastStatus::Unsupported
+        } else if instance.state().await != State::Running {
+            BroadcastStatus::NotRunning
+        } else if let Err(e) = check_command_filter(&state, &requester, &uuid, &command).await {
+            BroadcastStatus::Failed {
+                message: e.source.to_string(),
+            }
+        } else {
+            match instance.send_command(&command, caused_by.clone()).await {
+                Ok(_) => {
+                    record_console_command(&state, &uuid, &command, &caused_by).await;
+                    BroadcastStatus::Sent
+                }
+                Err(e) => BroadcastStatus::Failed {
+                    message: e.source.to_string(),
+                },
+            }
+        };
+        results.push(BroadcastResult {
+            name: instance.name().await,
+            uuid,
+            status,
+        });
+    }
+    Ok(Json(results))
+}
+
+const DEFAULT_ROLLING_RESTART_DELAY: Duration = Duration::from_secs(10);
+const MAX_ROLLING_RESTART_DELAY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct RollingRestartRequest {
+    /// Instances to restart, in order
+    #[serde(default)]
+    pub instances: Vec<InstanceUuid>,
+    /// Restart every instance with this tag instead, in auto start order
+    pub tag: Option<String>,
+    /// Seconds to wait after an instance is ready before restarting the next one
+    pub delay_secs: Option<u64>,
+    /// Seconds each instance has to become ready, the sequence is aborted otherwise
+    pub ready_timeout_secs: Option<u64>,
+}
+
+/// The instances a rolling restart goes through, in order
+async fn rolling_restart_targets(
+    state: &AppState,
+    request: &RollingRestartRequest,
+) -> Result<Vec<(InstanceUuid, GameInstance)>, Error> {
+    match (&request.tag, request.instances.is_empty()) {
+        (Some(tag), true) => {
+            let tag = normalize_tag(tag)?;
+            let instances: Vec<(InstanceUuid, GameInstance)> = state
+                .instances
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .collect();
+            let mut tagged = Vec::new();
+            for (uuid, instance) in &instances {
+                if instance.tags().await.contains(&tag) {
+                    tagged.push((uuid.clone(), instance.auto_start_order().await));
+                }
+            }
+            // the same order the instances are started in when lodestone starts
+            let (tiers, cyclic) = auto_start_tiers(&tagged);
+            Ok(tiers
+                .into_iter()
+                .flatten()
+                .chain(cyclic)
+                .filter_map(|uuid| {
+                    instances
+                        .iter()
+                        .find(|(other, _)| *other == uuid)
+                        .map(|(_, instance)| (uuid, instance.clone()))
+                })
+                .collect())
+        }
+        (None, false) => request
+            .instances
+            .iter()
+            .map(|uuid| {
+                state
+                    .instances
+                    .get(uuid)
+                    .map(|instance| (uuid.clone(), instance.value().clone()))
+                    .ok_or_else(|| Error {
+                        kind: ErrorKind::NotFound,
+                        source: eyre!("Instance {} not found", uuid),
+                    })
+            })
+            .collect(),
+        _ => Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Specify either a list of instances or a tag"),
+        }),
+    }
+}
+
+/// Restarts instances one at a time, each after the previous one is ready again
+///
+/// Runs as a task whose id is returned. Instances that aren't running are skipped, and the
+/// sequence stops at the first instance that fails to come back
+pub async fn rolling_restart(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<RollingRestartRequest>,
+) -> Result<Json<Snowflake>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    let targets = rolling_restart_targets(&state, &request).await?;
+    if targets.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("No instances to restart"),
+        });
+    }
+    for (uuid, _) in &targets {
+        requester.try_action(&UserAction::StopInstance(uuid.clone()), safe_mode)?;
+        requester.try_action(&UserAction::StartInstance(uuid.clone()), safe_mode)?;
+    }
+    let delay = request
+        .delay_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_ROLLING_RESTART_DELAY)
+        .min(MAX_ROLLING_RESTART_DELAY);
+    let ready_timeout = request
+        .ready_timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_START_TIMEOUT)
+        .min(MAX_START_TIMEOUT);
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+
+    let (start_event, event_id) = Event::new_progression_event_start(
+        format!("Rolling restart of {} instance(s)", targets.len()),
+        Some(targets.len() as f64),
+        None,
+        caused_by.clone(),
+    );
+    let task_id = event_id.inner();
+    let cancellation_token = state.tasks.cancellation_token(&event_id);
+    state.event_broadcaster.send(start_event);
+    tokio::spawn(async move {
+        let res: Result<(), Error> = async {
+            for (i, (uuid, instance)) in targets.iter().enumerate() {
+                if cancellation_token.is_cancelled() {
+                    return Err(cancelled_error());
+                }
+                let name = instance.name().await;
+                if instance.state().await != State::Running {
+                    state
+                        .event_broadcaster
+                        .send(Event::new_progression_event_update(
+                            &event_id,
+                            format!("Skipped {name}, it is not running"),
+                            1.0,
+                        ));
+                    continue;
+                }
+                state
+                    .event_broadcaster
+                    .send(Event::new_progression_event_update(
+                        &event_id,
+                        format!("Restarting {name}"),
+                        0.0,
+                    ));
+                instance.stop(caused_by.clone(), true).await?;
+                // subscribe before starting so the ready transition can't be missed
+                let mut event_receiver = state.event_broadcaster.subscribe();
+                let started_at = Instant::now();
+                instance.start(caused_by.clone(), false).await?;
+                let (ready, _) =
+                    wait_until_ready(&mut event_receiver, uuid, started_at, ready_timeout)
+                        .await
+                        .map_err(|e| Error {
+                            kind: e.kind,
+                            source: e.source.wrap_err(format!("{name} failed to restart")),
+                        })?;
+                if !ready {
+                    return Err(Error {
+                        kind: ErrorKind::Internal,
+                        source: eyre!(
+                            "{} was not ready within {} seconds",
+                            name,
+                            ready_timeout.as_secs()
+                        ),
+                    });
+                }
+                state
+                    .event_broadcaster
+                    .send(Event::new_progression_event_update(
+                        &event_id,
+                        format!("{name} is ready"),
+                        1.0,
+                    ));
+                if i + 1 < targets.len() {
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = cancellation_token.cancelled() => return Err(cancelled_error()),
+                    }
+                }
+            }
+            Ok(())
+        }
+        .await;
+        let end_event = match res {
+            Ok(()) => Event::new_progression_event_end(
+                event_id,
+                true,
+                Some("Rolling restart complete"),
+                None,
+            ),
+            Err(e) => {
+                let message = if cancellation_token.is_cancelled() {
+                    "Rolling restart cancelled".to_string()
+                } else {
+                    format!("Rolling restart aborted: {}", e.source)
+                };
+                error!("{}", message);
+                Event::new_progression_event_end(event_id, false, Some(&message), None)
+            }
+        };
+        state.event_broadcaster.send(end_event);
+    });
+    Ok(Json(task_id))
+}
+
+/// Where `uuid` is in its lifecycle, and since when
+async fn instance_state_report(
+    state: &AppState,
+    uuid: &InstanceUuid,
+) -> Result<InstanceStateReport, Error> {
+    let current = if uuid.to_string().starts_with("DOCKER-") {
+        state.docker_bridge.get_container_state(uuid).await?
+    } else {
+        let instance = state
             .instances
-            .get(&uuid)
+            .get(uuid)
             .ok_or_else(|| Error {
                 kind: ErrorKind::NotFound,
                 source: eyre!("Instance not found"),
             })?
-            .state()
-            .await
-    )))
+            .clone();
+        instance.state().await
+    };
+    Ok(state.instance_states.report(uuid, current))
+}
+
+pub async fn get_instance_state(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceStateReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.can_perform_action(&UserAction::ViewInstance(uuid.clone())) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You don't have permission to view this instance"),
+        });
+    }
+    Ok(Json(instance_state_report(&state, &uuid).await?))
 }
 
 pub fn get_instance_server_routes(state: AppState) -> Router {
     Router::new()
+        .route("/instance/broadcast", post(broadcast_message))
+        .route("/instance/group/rolling-restart", post(rolling_restart))
         .route("/instance/:uuid/start", put(start_instance))
         .route("/instance/:uuid/stop", put(stop_instance))
         .route("/instance/:uuid/restart", put(restart_instance))
+        .route("/instance/:uuid/restart/now", put(restart_instance_now))
         .route("/instance/:uuid/kill", put(kill_instance))
+        .route("/instance/:uuid/signal", post(send_signal))
         .route("/instance/:uuid/console", post(send_command))
+        .route(
+            "/instance/:uuid/console/response",
+            post(send_command_with_response),
+        )
         .route("/instance/:uuid/state", get(get_instance_state))
+        .route("/instance/:uuid/ping", get(ping_instance))
         .with_state(state)
 }
@@ -2,6 +2,7 @@ use crate::error::Error;
 use crate::error::ErrorKind;
 use crate::implementations::generic;
 use crate::implementations::minecraft;
+use crate::implementations::registry::{game_type_manifests, GameTypeManifest, SetupMethod};
 use crate::minecraft::FlavourKind;
 use crate::traits::t_configurable::manifest::SetupManifest;
 use crate::traits::t_configurable::GameType;
@@ -17,7 +18,7 @@ use serde::Serialize;
 use ts_rs::TS;
 
 #[allow(clippy::enum_variant_names)]
-#[derive(Serialize, Deserialize, TS, Clone, Copy)]
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Debug)]
 #[ts(export)]
 pub enum HandlerGameType {
     MinecraftJavaVanilla,
@@ -58,13 +59,25 @@ impl TryFrom<HandlerGameType> for FlavourKind {
     }
 }
 
+/// Game types that have a working implementation and can be set up from a setup manifest
+pub fn available_game_types() -> Vec<HandlerGameType> {
+    game_type_manifests()
+        .into_iter()
+        .flat_map(|manifest| match manifest.setup {
+            SetupMethod::Manifest { variants } => variants,
+            SetupMethod::Url => Vec::new(),
+        })
+        .map(|variant| variant.id)
+        .collect()
+}
+
 pub async fn get_available_games() -> Json<Vec<HandlerGameType>> {
-    Json(vec![
-        HandlerGameType::MinecraftJavaVanilla,
-        HandlerGameType::MinecraftFabric,
-        HandlerGameType::MinecraftForge,
-        HandlerGameType::MinecraftPaper,
-    ])
+    Json(available_game_types())
+}
+
+/// The registered game type implementations, with how to set them up and what they depend on
+pub async fn get_game_type_manifests() -> Json<Vec<GameTypeManifest>> {
+    Json(game_type_manifests())
 }
 
 pub async fn get_setup_manifest(
@@ -96,6 +109,7 @@ pub async fn get_generic_setup_manifest(
 pub fn get_instance_setup_config_routes(appstate: AppState) -> Router {
     Router::new()
         .route("/games", get(get_available_games))
+        .route("/setup/game-types", get(get_game_type_manifests))
         .route("/setup_manifest/:game_type", get(get_setup_manifest))
         .route("/generic_setup_manifest", put(get_generic_setup_manifest))
         .with_state(appstate)
@@ -0,0 +1,376 @@
+use std::{path::Path as FsPath, time::Duration};
+
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Path},
+    routing::{get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::{User, UserAction},
+    backup_retention::{BackupPolicy, BackupPruneReport},
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event},
+    implementations::minecraft::MinecraftInstance,
+    offsite_backup::spawn_upload,
+    prelude::{path_to_tmp, GameInstance},
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+    util::{unzip_file_async, UnzipOption},
+    AppState,
+};
+
+use super::{instance_config::minecraft_instance, instance_server::start_checked};
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct WorldResetPreview {
+    pub level_name: String,
+    /// The directories that would be deleted, relative to the instance root
+    pub directories: Vec<String>,
+    /// To confirm the reset with, valid for a few minutes
+    pub confirmation_token: String,
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct WorldReset {
+    pub confirmation_token: String,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct WorldResetResult {
+    /// The archive the old world was backed up to, relative to the instance root.
+    /// `None` if there was no world yet
+    pub backup: Option<String>,
+    pub deleted: Vec<String>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct WorldImportResult {
+    /// The archive the replaced world was backed up to, relative to the instance root
+    pub backup: Option<String>,
+    /// `level-name` is changed to this if the imported world has another name
+    pub level_name: String,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct WorldBackupResult {
+    /// The archive, relative to the instance root
+    pub backup: String,
+    /// Why the backup may be inconsistent, if it was taken while the server was running
+    /// and saving couldn't be turned off
+    pub warning: Option<String>,
+    /// What the backup policy pruned after the backup
+    pub pruned: BackupPruneReport,
+}
+
+fn reset_action(uuid: &InstanceUuid) -> String {
+    format!("world_reset:{uuid}")
+}
+
+fn relative_to_root(root: &FsPath, path: &FsPath) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+/// Backs up the current world directories, `None` if there are none
+async fn backup_current_world(
+    state: &AppState,
+    instance: &MinecraftInstance,
+) -> Result<Option<String>, Error> {
+    let dirs = instance.world_dirs().await?;
+    if dirs.is_empty() {
+        return Ok(None);
+    }
+    let archive = instance
+        .backup_worlds(&dirs, state.global_settings.lock().await.timezone(), None)
+        .await?;
+    Ok(Some(relative_to_root(&instance.path().await, &archive)))
+}
+
+async fn check_world_permissions(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+) -> Result<(), Error> {
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()), safe_mode)?;
+    requester.try_action(&UserAction::StopInstance(uuid.clone()), safe_mode)?;
+    requester.try_action(&UserAction::StartInstance(uuid.clone()), safe_mode)
+}
+
+/// Backs up the world while the server keeps running, see
+/// [`MinecraftInstance::hot_backup_worlds`]
+pub async fn backup_world(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<WorldBackupResult>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::WriteInstanceFile(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    let instance = minecraft_instance(&state, &uuid)?;
+    let dirs = instance.world_dirs().await?;
+    if dirs.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("The instance has no world yet"),
+        });
+    }
+    let timeout = Duration::from_secs(state.global_settings.lock().await.hot_backup_timeout_secs());
+    let tz = state.global_settings.lock().await.timezone();
+    let backup = instance.hot_backup_worlds(&dirs, timeout, tz).await?;
+    spawn_upload(
+        state.global_settings.lock().await.offsite_backup(),
+        state.macro_executor.variables().clone(),
+        state.event_broadcaster.clone(),
+        uuid.clone(),
+        instance.name().await,
+        backup.archive.clone(),
+        tz,
+        caused_by,
+    );
+    let pruned = instance
+        .prune_world_backups(&instance.backup_policy().await, tz)
+        .await?;
+    if let Some(warning) = &backup.warning {
+        state.event_broadcaster.send(Event::new_system_message(
+            uuid.clone(),
+            instance.name().await,
+            warning.clone(),
+        ));
+    }
+    Ok(Json(WorldBackupResult {
+        backup: relative_to_root(&instance.path().await, &backup.archive),
+        warning: backup.warning,
+        pruned,
+    }))
+}
+
+pub async fn get_backup_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<BackupPolicy>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = minecraft_instance(&state, &uuid)?;
+    Ok(Json(instance.backup_policy().await))
+}
+
+/// Sets when the world is backed up and which backups are kept, the backups are pruned
+/// right away so the new policy shows what it keeps
+pub async fn set_backup_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(backup_policy): Json<BackupPolicy>,
+) -> Result<Json<BackupPruneReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()), safe_mode)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()), safe_mode)?;
+    let instance = minecraft_instance(&state, &uuid)?;
+    instance.set_backup_policy(backup_policy.clone()).await?;
+    Ok(Json(
+        instance
+            .prune_world_backups(
+                &backup_policy,
+                state.global_settings.lock().await.timezone(),
+            )
+            .await?,
+    ))
+}
+
+/// What a reset would delete, with the token to confirm it. A POST so the token is only
+/// issued on a deliberate request, never by a prefetch or a link
+pub async fn prepare_world_reset(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<WorldResetPreview>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_world_permissions(&state, &requester, &uuid).await?;
+    let instance = minecraft_instance(&state, &uuid)?;
+    let root = instance.path().await;
+    Ok(Json(WorldResetPreview {
+        level_name: instance.level_name().await,
+        directories: instance
+            .world_dirs()
+            .await?
+            .iter()
+            .map(|dir| relative_to_root(&root, dir))
+            .collect(),
+        confirmation_token: state
+            .confirmations
+            .issue(&requester.uid, &reset_action(&uuid)),
+    }))
+}
+
+/// Stops the instance, backs up and deletes its world, then starts it so a new world is
+/// generated. Plugins and config are kept
+pub async fn reset_world(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(reset): Json<WorldReset>,
+) -> Result<Json<WorldResetResult>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_world_permissions(&state, &requester, &uuid).await?;
+    let instance = minecraft_instance(&state, &uuid)?;
+    state.confirmations.redeem(
+        &requester.uid,
+        &reset_action(&uuid),
+        &reset.confirmation_token,
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+
+    if instance.state().await != State::Stopped {
+        instance.stop(caused_by.clone(), true).await?;
+    }
+    let root = instance.path().await;
+    // nothing is deleted unless the backup succeeded
+    let backup = backup_current_world(&state, &instance).await?;
+    let dirs = instance.world_dirs().await?;
+    instance.delete_worlds(&dirs).await?;
+
+    let game_instance: GameInstance = instance.clone().into();
+    if let Err(e) = start_checked(&state, &game_instance, caused_by, false).await {
+        return Err(Error {
+            kind: e.kind,
+            source: e
+                .source
+                .wrap_err("The world was reset, but the instance failed to start"),
+        });
+    }
+    Ok(Json(WorldResetResult {
+        backup,
+        deleted: dirs
+            .iter()
+            .map(|dir| relative_to_root(&root, dir))
+            .collect(),
+    }))
+}
+
+/// Swaps the world for one uploaded as a zip archive, the current world is backed up first
+/// and put back if the import fails. The instance is started again if it was running
+pub async fn import_world(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    mut multipart: Multipart,
+) -> Result<Json<WorldImportResult>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_world_permissions(&state, &requester, &uuid).await?;
+    let instance = minecraft_instance(&state, &uuid)?;
+
+    let temp_dir =
+        tempfile::tempdir_in(path_to_tmp()).context("Failed to create temporary directory")?;
+    let archive_path = temp_dir.path().join("world.zip");
+    let mut field = multipart
+        .next_field()
+        .await
+        .context("Failed to read multipart field")?
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Missing archive"),
+        })?;
+    let mut file = crate::util::fs::create(&archive_path).await?;
+    while let Some(chunk) = field.chunk().await.context("Failed to read chunk")? {
+        file.write_all(&chunk)
+            .await
+            .context("Failed to write chunk")?;
+    }
+    drop(file);
+    let extracted = temp_dir.path().join("extracted");
+    unzip_file_async(&archive_path, UnzipOption::ToDir(extracted.clone()))
+        .await
+        .map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: e.source.wrap_err("Failed to extract archive"),
+        })?;
+
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    let was_running = instance.state().await != State::Stopped;
+    if was_running {
+        instance.stop(caused_by.clone(), true).await?;
+    }
+    let imported = async {
+        let backup = backup_current_world(&state, &instance).await?;
+        let level_name = instance.import_world(&extracted).await?;
+        Ok::<_, Error>((backup, level_name))
+    }
+    .await;
+
+    // started again whether or not the import worked, a failed import put the old world back
+    if was_running {
+        let game_instance: GameInstance = instance.clone().into();
+        if let Err(e) = start_checked(&state, &game_instance, caused_by, false).await {
+            return Err(match imported {
+                Ok(_) => Error {
+                    kind: e.kind,
+                    source: e
+                        .source
+                        .wrap_err("The world was imported, but the instance failed to start"),
+                },
+                Err(import_error) => Error {
+                    kind: import_error.kind,
+                    source: import_error.source.wrap_err(format!(
+                        "The import failed, and the instance failed to start again: {}",
+                        e.source
+                    )),
+                },
+            });
+        }
+    }
+    let (backup, level_name) = imported?;
+    Ok(Json(WorldImportResult { backup, level_name }))
+}
+
+pub fn get_instance_world_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/world/reset/prepare",
+            post(prepare_world_reset),
+        )
+        .route("/instance/:uuid/world/reset", post(reset_world))
+        .route("/instance/:uuid/world/backup", post(backup_world))
+        .route(
+            "/instance/:uuid/world/backup/policy",
+            get(get_backup_policy).put(set_backup_policy),
+        )
+        .route(
+            "/instance/:uuid/world/import",
+            post(import_world).layer(DefaultBodyLimit::disable()),
+        )
+        .with_state(state)
+}
@@ -2,22 +2,30 @@
 // pub mod instance;
 // pub mod users;
 pub mod checks;
+pub mod console_history;
 pub mod core_info;
 pub mod events;
 pub mod gateway;
 pub mod global_fs;
 pub mod global_settings;
 pub mod instance;
+pub mod instance_archive;
 pub mod instance_config;
+pub mod instance_crash_reports;
 pub mod instance_fs;
 pub mod instance_macro;
 pub mod instance_players;
+pub mod instance_plugins;
 pub mod instance_server;
 pub mod instance_setup_configs;
+pub mod instance_world;
 pub mod monitor;
+pub mod peers;
 pub mod playitgg;
+pub mod secrets;
 pub mod setup;
 pub mod system;
+pub mod tasks;
 pub mod users;
 mod util;
 pub mod extension;
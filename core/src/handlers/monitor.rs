@@ -1,30 +1,125 @@
 use std::{collections::HashMap, sync::Arc};
 
 use axum::{
-    extract::{ws::WebSocket, Path, WebSocketUpgrade},
+    body::StreamBody,
+    extract::{ws::WebSocket, Path, Query, WebSocketUpgrade},
+    http::{self, HeaderName},
     response::Response,
     routing::get,
-    Router,
+    Json, Router,
 };
+use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
 use futures::{SinkExt, StreamExt};
 use ringbuffer::{AllocRingBuffer, RingBufferExt};
-use tokio::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::error;
+use ts_rs::TS;
 
 use crate::{
-    error::Error,
+    auth::user::UserAction,
+    db::{read::stream_monitor_samples, types::MonitorSample},
+    error::{Error, ErrorKind},
+    gateway::Gateway,
     prelude::GameInstance,
     traits::{t_server::MonitorReport, t_server::TServer},
     types::InstanceUuid,
     AppState,
 };
 
+use super::util::parse_bearer_token;
+
+/// Longest time range a single export covers
+const MAX_EXPORT_RANGE_SECS: i64 = 31 * 24 * 60 * 60;
+/// Time range exported when `from` is not given
+const DEFAULT_EXPORT_RANGE_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Deserialize)]
+pub struct MonitorQuery {
+    /// Include the live buffer, the last minute or so of reports
+    #[serde(default)]
+    history: bool,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct MonitorSnapshot {
+    pub latest: Option<MonitorReport>,
+    /// Oldest first, at most as many reports as the live buffer holds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<MonitorReport>>,
+}
+
+/// The latest report from the live buffer, and the whole buffer with `?history=true`
+pub async fn get_monitor_snapshot(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<MonitorQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<MonitorSnapshot>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    let monitor_buffer = state.monitor_buffer.lock().await;
+    let buffer = monitor_buffer.get(&uuid);
+    Ok(Json(MonitorSnapshot {
+        latest: buffer.and_then(|buffer| buffer.get(-1)).cloned(),
+        history: query.history.then(|| {
+            buffer
+                .map(|buffer| buffer.iter().cloned().collect())
+                .unwrap_or_default()
+        }),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct MonitorStreamQuery {
+    token: String,
+}
+
+/// Users who can view the instance and viewer tokens covering it can follow its reports
+async fn authorize_monitor_stream(
+    state: &AppState,
+    token: &str,
+    uuid: &InstanceUuid,
+) -> Result<(), Error> {
+    let token = parse_bearer_token(token).ok_or_else(|| Error {
+        kind: ErrorKind::Unauthorized,
+        source: eyre!("Token error"),
+    })?;
+    let users_manager = state.users_manager.read().await;
+    match users_manager.try_auth(&token) {
+        Some(requester) => requester.try_action(
+            &UserAction::ViewInstance(uuid.clone()),
+            state.global_settings.lock().await.safe_mode(),
+        ),
+        None => users_manager
+            .try_auth_viewer(&token, uuid, chrono::Utc::now().timestamp())
+            .map(|_| ())
+            .ok_or_else(|| Error {
+                kind: ErrorKind::Unauthorized,
+                source: eyre!("Token error"),
+            }),
+    }
+}
+
 pub async fn monitor(
     ws: WebSocketUpgrade,
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<MonitorStreamQuery>,
 ) -> Result<Response, Error> {
+    authorize_monitor_stream(&state, &query.token, &uuid).await?;
     let instance = state
         .instances
         .get(&uuid)
@@ -33,15 +128,27 @@ pub async fn monitor(
             source: eyre!("Instance not found"),
         })?
         .to_owned();
-    Ok(ws
-        .on_upgrade(move |stream| monitor_ws(stream, state.monitor_buffer.clone(), instance, uuid)))
+    Ok(ws.on_upgrade(move |stream| {
+        monitor_ws(
+            stream,
+            state.monitor_buffer.clone(),
+            state.gateway.clone(),
+            instance,
+            uuid,
+            state,
+            query.token,
+        )
+    }))
 }
 
 async fn monitor_ws(
     stream: WebSocket,
     monitor_buffer: Arc<Mutex<HashMap<InstanceUuid, AllocRingBuffer<MonitorReport>>>>,
+    gateway: Gateway,
     instance: GameInstance,
     uuid: InstanceUuid,
+    state: AppState,
+    token: String,
 ) {
     let (mut tx, mut rx) = stream.split();
     if let Some(buffer) = monitor_buffer.lock().await.get(&uuid) {
@@ -61,7 +168,12 @@ async fn monitor_ws(
     loop {
         tokio::select! {
             _ = interval.tick() => {
-                let monitor = instance.monitor().await;
+                // a deleted user or a revoked token stops receiving reports
+                if authorize_monitor_stream(&state, &token, &uuid).await.is_err() {
+                    break;
+                }
+                let mut monitor = instance.monitor().await;
+                monitor.gateway_stats = gateway.stats(&uuid);
                 if let Err(e) = tx
                     .send(axum::extract::ws::Message::Text(
                         serde_json::to_string(&monitor).unwrap(),
@@ -81,8 +193,136 @@ async fn monitor_ws(
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MonitorExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Deserialize)]
+pub struct MonitorExportQuery {
+    /// Unix time in seconds, defaults to a day before `to`
+    from: Option<i64>,
+    /// Unix time in seconds, defaults to now
+    to: Option<i64>,
+    #[serde(default)]
+    format: MonitorExportFormat,
+}
+
+fn csv_row(sample: &MonitorSample) -> String {
+    fn field<T: ToString>(value: Option<T>) -> String {
+        value.map(|v| v.to_string()).unwrap_or_default()
+    }
+    format!(
+        "{},{},{},{}\n",
+        sample.timestamp,
+        field(sample.cpu_usage),
+        field(sample.memory_usage),
+        field(sample.player_count)
+    )
+}
+
+/// Streams the stored monitor history of an instance as CSV or a JSON array
+pub async fn export_monitor_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<MonitorExportQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<
+    (
+        [(HeaderName, String); 2],
+        StreamBody<ReceiverStream<Result<String, std::io::Error>>>,
+    ),
+    Error,
+> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    let to = query
+        .to
+        .unwrap_or_else(|| chrono::Utc::now().timestamp())
+        .min(chrono::Utc::now().timestamp());
+    let from = query
+        .from
+        .unwrap_or(to - DEFAULT_EXPORT_RANGE_SECS)
+        .max(to - MAX_EXPORT_RANGE_SECS);
+    if from > to {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("from must be before to"),
+        });
+    }
+    state.db_health.ensure_available()?;
+
+    let (tx, rx) = mpsc::channel(64);
+    let pool = state.sqlite_pool.clone();
+    let format = query.format;
+    tokio::spawn(async move {
+        let (header, footer) = match format {
+            MonitorExportFormat::Csv => ("timestamp,cpu_usage,memory_usage,player_count\n", ""),
+            MonitorExportFormat::Json => ("[", "]"),
+        };
+        if tx.send(Ok(header.to_string())).await.is_err() {
+            return;
+        }
+        let mut samples = stream_monitor_samples(&pool, &uuid, from, to).enumerate();
+        while let Some((i, sample)) = samples.next().await {
+            let chunk = match sample {
+                Ok(sample) => match format {
+                    MonitorExportFormat::Csv => Ok(csv_row(&sample)),
+                    MonitorExportFormat::Json => Ok(format!(
+                        "{}{}",
+                        if i == 0 { "" } else { "," },
+                        serde_json::to_string(&sample).unwrap()
+                    )),
+                },
+                Err(e) => Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    e.to_string(),
+                )),
+            };
+            let failed = chunk.is_err();
+            // the client went away
+            if tx.send(chunk).await.is_err() || failed {
+                return;
+            }
+        }
+        let _ = tx.send(Ok(footer.to_string())).await;
+    });
+
+    let (content_type, extension) = match format {
+        MonitorExportFormat::Csv => ("text/csv", "csv"),
+        MonitorExportFormat::Json => ("application/json", "json"),
+    };
+    Ok((
+        [
+            (http::header::CONTENT_TYPE, content_type.to_string()),
+            (
+                http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"monitor-{uuid}-{from}-{to}.{extension}\""),
+            ),
+        ],
+        StreamBody::new(ReceiverStream::new(rx)),
+    ))
+}
+
 pub fn get_monitor_routes(state: AppState) -> Router {
     Router::new()
         .route("/monitor/:uuid", get(monitor))
+        .route("/instance/:uuid/monitor", get(get_monitor_snapshot))
+        .route(
+            "/instance/:uuid/monitor/export",
+            get(export_monitor_history),
+        )
         .with_state(state)
 }
@@ -0,0 +1,66 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    error::{Error, ErrorKind},
+    peers::{Peer, PeerRegistration},
+    AppState,
+};
+
+pub async fn get_peers(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<Peer>>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(state.peers.list()))
+}
+
+/// Called by another core with a token of this core's owner
+pub async fn register_peer(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(registration): Json<PeerRegistration>,
+) -> Result<Json<Peer>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to register peers"),
+        });
+    }
+    let peer = state
+        .peers
+        .register(registration, &state.uuid, chrono::Utc::now().timestamp())?;
+    Ok(Json(peer))
+}
+
+pub async fn remove_peer(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to remove peers"),
+        });
+    }
+    state.peers.remove(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Peer not found"),
+    })?;
+    Ok(Json(()))
+}
+
+pub fn get_peers_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/core/peers", get(get_peers).post(register_peer))
+        .route("/core/peers/:uuid", delete(remove_peer))
+        .with_state(state)
+}
@@ -0,0 +1,98 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, ErrorKind},
+    secrets::SecretInfo,
+    variables::is_valid_name,
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct SetSecretBody {
+    value: String,
+}
+
+fn owner_only(is_owner: bool) -> Result<(), Error> {
+    if !is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the owner can manage secrets"),
+        });
+    }
+    Ok(())
+}
+
+/// Makes the new secrets available to `${secret.NAME}` references
+async fn reload_secrets(state: &AppState) -> Result<(), Error> {
+    state
+        .macro_executor
+        .variables()
+        .set_secrets(state.secrets.decrypt_all().await?);
+    Ok(())
+}
+
+pub async fn get_secrets(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<SecretInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    owner_only(requester.is_owner)?;
+    Ok(Json(state.secrets.list().await))
+}
+
+pub async fn set_secret(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(name): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Json(body): Json<SetSecretBody>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    owner_only(requester.is_owner)?;
+    if !is_valid_name(&name) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Invalid secret name {}, only letters, digits and _ are allowed",
+                name
+            ),
+        });
+    }
+    if body.value.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Secret value cannot be empty"),
+        });
+    }
+    state
+        .secrets
+        .set(&name, &body.value, chrono::Utc::now().timestamp())
+        .await?;
+    reload_secrets(&state).await?;
+    Ok(Json(()))
+}
+
+pub async fn delete_secret(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(name): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    owner_only(requester.is_owner)?;
+    state.secrets.delete(&name).await?;
+    reload_secrets(&state).await?;
+    Ok(Json(()))
+}
+
+pub fn get_secrets_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/secrets", get(get_secrets))
+        .route("/secrets/:name", put(set_secret).delete(delete_secret))
+        .with_state(state)
+}
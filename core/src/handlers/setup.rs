@@ -1,14 +1,20 @@
-use axum::{extract::Path, Json, Router};
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::HeaderMap,
+    Json, Router,
+};
 use color_eyre::eyre::eyre;
 
 use crate::{
     auth::{permission::UserPermission, user::User},
     error::{Error, ErrorKind},
-    events::CausedBy,
-    AppState,
+    events::{CausedBy, Event},
+    generate_first_time_setup_key, AppState,
 };
 
-use super::users::LoginReply;
+use super::users::{client_info, login_reply, LoginReply};
 
 #[derive(serde::Deserialize)]
 pub struct OwnerSetup {
@@ -19,12 +25,19 @@ pub struct OwnerSetup {
 pub async fn setup_owner(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(key): Path<String>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(owner_setup): Json<OwnerSetup>,
 ) -> Result<Json<LoginReply>, Error> {
+    // held until the owner is created so the key can't be used twice
     let mut setup_key_lock = state.first_time_setup_key.lock().await;
     match setup_key_lock.clone() {
         Some(k) if k == key => {
-            setup_key_lock.take();
+            state
+                .users_manager
+                .read()
+                .await
+                .check_password(&owner_setup.password)?;
             let owner = User::new(
                 owner_setup.username,
                 &owner_setup.password,
@@ -32,16 +45,25 @@ pub async fn setup_owner(
                 false,
                 UserPermission::default(),
             );
-            state
-                .users_manager
-                .write()
-                .await
+            let mut users_manager = state.users_manager.write().await;
+            users_manager
                 .add_user(owner.clone(), CausedBy::System)
                 .await?;
-            Ok(Json(LoginReply {
-                token: owner.create_jwt()?,
-                user: owner.into(),
-            }))
+            setup_key_lock.take();
+            drop(setup_key_lock);
+            state
+                .event_broadcaster
+                .send(Event::new_setup_completed(owner.uid.clone()));
+            Ok(Json(
+                login_reply(
+                    &state,
+                    &users_manager,
+                    owner,
+                    client_info(connect_info, &headers),
+                    false,
+                )
+                .await?,
+            ))
         }
         None => Err(Error {
             kind: ErrorKind::PermissionDenied,
@@ -54,8 +76,73 @@ pub async fn setup_owner(
     }
 }
 
+/// Replaces the setup key with a new one printed to the log, for when the
+/// original key was lost before the owner account was created.
+///
+/// The new key is never returned, only someone with access to the log can use it. Only
+/// requests from the machine itself are accepted, so no one else can keep rotating the key
+/// before the operator finishes setup
+pub async fn regenerate_setup_key(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+) -> Result<Json<()>, Error> {
+    if !is_loopback(connect_info.as_ref()) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!(
+                "The setup key can only be regenerated from the machine running Lodestone"
+            ),
+        });
+    }
+    if state
+        .users_manager
+        .read()
+        .await
+        .as_ref()
+        .iter()
+        .any(|(_, user)| user.is_owner)
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Owner account already present"),
+        });
+    }
+    state
+        .first_time_setup_key
+        .lock()
+        .await
+        .replace(generate_first_time_setup_key());
+    Ok(Json(()))
+}
+
+fn is_loopback(connect_info: Option<&ConnectInfo<SocketAddr>>) -> bool {
+    match connect_info {
+        Some(ConnectInfo(addr)) => match addr.ip() {
+            std::net::IpAddr::V4(ip) => ip.is_loopback(),
+            std::net::IpAddr::V6(ip) => {
+                ip.is_loopback() || ip.to_ipv4_mapped().map_or(false, |ip| ip.is_loopback())
+            }
+        },
+        None => false,
+    }
+}
+
 pub fn get_setup_route(state: AppState) -> Router {
     Router::new()
+        .route(
+            "/setup/regenerate_key",
+            axum::routing::post(regenerate_setup_key),
+        )
         .route("/setup/:key", axum::routing::post(setup_owner))
         .with_state(state)
 }
+
+#[test]
+fn test_is_loopback() {
+    let addr = |s: &str| ConnectInfo(s.parse::<SocketAddr>().unwrap());
+    assert!(is_loopback(Some(&addr("127.0.0.1:50000"))));
+    assert!(is_loopback(Some(&addr("[::1]:50000"))));
+    assert!(is_loopback(Some(&addr("[::ffff:127.0.0.1]:50000"))));
+    assert!(!is_loopback(Some(&addr("192.168.1.20:50000"))));
+    assert!(!is_loopback(None));
+}
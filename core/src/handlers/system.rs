@@ -1,9 +1,25 @@
 use axum::{routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use ringbuffer::RingBufferExt;
 use serde::{Deserialize, Serialize};
 use sysinfo::{CpuExt, CpuRefreshKind, DiskExt, SystemExt};
 
 use tokio::time::sleep;
+use ts_rs::TS;
 
+use crate::auth::user::UserAction;
+use crate::cgroup::{cgroup_limits, effective_memory, CgroupLimits};
+use crate::disk_usage::{disk_space_of, DiskSpace, InstanceSize};
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::EventLagReport;
+use crate::java_runtimes::{detect_java_runtimes, JavaRuntime};
+use crate::port_manager::ALLOCATABLE_PORTS;
+use crate::prelude::{lodestone_path, GameInstance};
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_player::TPlayerManagement;
+use crate::traits::t_server::{MonitorReport, State, TServer};
+use crate::types::InstanceUuid;
 use crate::AppState;
 
 // Since MemInfo is not serializable, we need to create a new struct that is serializable.
@@ -41,6 +57,45 @@ pub async fn get_disk(
     })
 }
 
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct InstanceDiskUsage {
+    pub uuid: InstanceUuid,
+    pub name: String,
+    /// None until the first background size scan has finished
+    pub size: Option<InstanceSize>,
+}
+
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct LodestoneDiskUsage {
+    /// Space of the filesystem holding the lodestone directory
+    pub disk: Option<DiskSpace>,
+    pub instances: Vec<InstanceDiskUsage>,
+}
+
+pub async fn get_lodestone_disk_usage(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LodestoneDiskUsage>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let mut instances = Vec::new();
+    for entry in state.instances.iter() {
+        if requester.can_perform_action(&UserAction::ViewInstance(entry.key().clone())) {
+            instances.push(InstanceDiskUsage {
+                uuid: entry.key().clone(),
+                name: entry.value().name().await,
+                size: state.instance_sizes.get(entry.key()),
+            });
+        }
+    }
+    let disk = tokio::task::spawn_blocking(|| disk_space_of(lodestone_path()))
+        .await
+        .ok()
+        .flatten();
+    Ok(Json(LodestoneDiskUsage { disk, instances }))
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CPUInfo {
     pub cpu_speed: u64,
@@ -63,10 +118,335 @@ pub async fn get_cpu_info(
     })
 }
 
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ResourceInfo {
+    pub total_memory: u64,
+    pub available_memory: u64,
+    /// Fractional when the cgroup's CPU quota isn't a whole number of CPUs
+    pub cpu_count: f64,
+}
+
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct SystemInfo {
+    pub host: ResourceInfo,
+    /// What lodestone can actually use, lower than `host` inside a limited container
+    pub effective: ResourceInfo,
+    pub cgroup: Option<CgroupLimits>,
+}
+
+pub async fn get_system_info(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<SystemInfo>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    let mut sys = state.system.lock().await;
+    sys.refresh_memory();
+    let host = ResourceInfo {
+        total_memory: sys.total_memory(),
+        available_memory: sys.available_memory(),
+        cpu_count: sys.cpus().len() as f64,
+    };
+    drop(sys);
+    let cgroup = cgroup_limits();
+    let (total_memory, available_memory) =
+        effective_memory(cgroup.as_ref(), host.total_memory, host.available_memory);
+    let effective = ResourceInfo {
+        total_memory,
+        available_memory,
+        cpu_count: cgroup
+            .and_then(|limits| limits.cpu_limit)
+            .map_or(host.cpu_count, |limit| limit.min(host.cpu_count)),
+    };
+    Ok(Json(SystemInfo {
+        host,
+        effective,
+        cgroup,
+    }))
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct InstanceStateCounts {
+    pub starting: u32,
+    pub running: u32,
+    pub stopping: u32,
+    pub stopped: u32,
+    pub error: u32,
+}
+
+/// Resource usage of all instances together, from their latest monitor reports
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct UsageSummary {
+    pub instance_count: u32,
+    pub states: InstanceStateCounts,
+    /// Sum of the instances' CPU usage, each is a percentage of the whole machine
+    pub cpu_usage: f32,
+    /// Bytes
+    pub memory_usage: u64,
+    pub player_count: u32,
+}
+
+impl UsageSummary {
+    fn add(&mut self, state: State, report: Option<&MonitorReport>, player_count: Option<u32>) {
+        self.instance_count += 1;
+        match state {
+            State::Starting => self.states.starting += 1,
+            State::Running => self.states.running += 1,
+            State::Stopping => self.states.stopping += 1,
+            State::Stopped => self.states.stopped += 1,
+            State::Error => self.states.error += 1,
+        }
+        // a stopped instance's last report is from when it was running
+        if state != State::Stopped {
+            if let Some(report) = report {
+                self.cpu_usage += report.cpu_usage.unwrap_or(0.0);
+                self.memory_usage += report.memory_usage.unwrap_or(0);
+            }
+            self.player_count += player_count.unwrap_or(0);
+        }
+    }
+}
+
+/// Totals over the instances the requester can see, computed from the live monitor buffer and
+/// the cached player lists, so no instance is queried
+pub async fn get_usage_summary(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<UsageSummary>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let instances: Vec<(InstanceUuid, GameInstance)> = state
+        .instances
+        .iter()
+        .filter(|entry| {
+            requester.can_perform_action(&UserAction::ViewInstance(entry.key().clone()))
+        })
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect();
+    let mut states = Vec::new();
+    for (uuid, instance) in instances {
+        let instance_state = instance.state().await;
+        // counted from the players tracked off the console, a stopped instance has none
+        let player_count = if instance_state == State::Stopped {
+            None
+        } else {
+            instance.get_player_count().await.ok()
+        };
+        states.push((uuid, instance_state, player_count));
+    }
+    let monitor_buffer = state.monitor_buffer.lock().await;
+    let mut summary = UsageSummary::default();
+    for (uuid, instance_state, player_count) in states {
+        summary.add(
+            instance_state,
+            monitor_buffer.get(&uuid).and_then(|buffer| buffer.get(-1)),
+            player_count,
+        );
+    }
+    Ok(Json(summary))
+}
+
+/// Java runtimes installed on this machine, newest first
+pub async fn get_java_runtimes(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<JavaRuntime>>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(detect_java_runtimes().await))
+}
+
+/// How often event subscribers fell behind, to tune `event_channel_capacity`
+pub async fn get_event_lag(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<EventLagReport>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(state.event_broadcaster.lag_report()))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[ts(export)]
+pub struct PortOwner {
+    pub uuid: InstanceUuid,
+    pub name: String,
+    pub state: State,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[ts(export)]
+pub struct PortAllocation {
+    pub port: u32,
+    /// Instances configured to use the port, more than one is a conflict
+    pub owners: Vec<PortOwner>,
+    /// Whether the port manager has handed out the port
+    pub is_allocated: bool,
+    /// Whether something on this machine is listening on the port
+    pub is_bound: bool,
+    /// Allocated but nothing is listening on it, which is expected only while the owner is
+    /// stopped
+    pub is_unbound: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[ts(export)]
+pub struct PortReport {
+    pub ports: Vec<PortAllocation>,
+    /// First port of the range instances are allocated from
+    pub range_start: u32,
+    /// Last port of the range, inclusive
+    pub range_end: u32,
+    /// Ports in the range that aren't allocated
+    pub free_count: u32,
+}
+
+/// One entry per port that is allocated or used by an instance, in ascending order
+fn port_allocations(
+    allocated: &[u32],
+    owners: Vec<(u32, PortOwner)>,
+    is_bound: impl Fn(u32) -> bool,
+) -> Vec<PortAllocation> {
+    let mut ports: Vec<u32> = allocated
+        .iter()
+        .copied()
+        .chain(owners.iter().map(|(port, _)| *port))
+        .collect();
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+        .into_iter()
+        .map(|port| {
+            let port_owners = owners
+                .iter()
+                .filter(|(owned, _)| *owned == port)
+                .map(|(_, owner)| owner.clone())
+                .collect();
+            let is_allocated = allocated.contains(&port);
+            let is_bound = is_bound(port);
+            PortAllocation {
+                port,
+                owners: port_owners,
+                is_allocated,
+                is_bound,
+                is_unbound: is_allocated && !is_bound,
+            }
+        })
+        .collect()
+}
+
+/// Allocated ports and who owns them, to diagnose port conflicts
+pub async fn get_ports(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<PortReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner && !requester.is_admin {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to view allocated ports"),
+        });
+    }
+    let instances: Vec<(InstanceUuid, GameInstance)> = state
+        .instances
+        .iter()
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect();
+    let mut owners = Vec::new();
+    for (uuid, instance) in instances {
+        owners.push((
+            instance.port().await,
+            PortOwner {
+                uuid,
+                name: instance.name().await,
+                state: instance.state().await,
+            },
+        ));
+    }
+    let port_manager = state.port_manager.lock().await;
+    let allocated = port_manager.allocated_ports();
+    let free_count = port_manager.free_port_count();
+    drop(port_manager);
+    let ports = tokio::task::spawn_blocking(move || {
+        port_allocations(&allocated, owners, |port| {
+            port > u16::MAX as u32 || !port_scanner::local_port_available(port as u16)
+        })
+    })
+    .await
+    .context("Failed to check which ports are bound")?;
+    Ok(Json(PortReport {
+        ports,
+        range_start: *ALLOCATABLE_PORTS.start(),
+        range_end: *ALLOCATABLE_PORTS.end(),
+        free_count,
+    }))
+}
+
 pub fn get_system_routes(state: AppState) -> Router {
     Router::new()
+        .route("/system/info", get(get_system_info))
         .route("/system/ram", get(get_ram))
         .route("/system/disk", get(get_disk))
+        .route("/system/disk/lodestone", get(get_lodestone_disk_usage))
         .route("/system/cpu", get(get_cpu_info))
+        .route("/system/java", get(get_java_runtimes))
+        .route("/system/usage", get(get_usage_summary))
+        .route("/system/events/lag", get(get_event_lag))
+        .route("/system/ports", get(get_ports))
         .with_state(state)
 }
+
+#[test]
+fn test_usage_summary() {
+    let report = MonitorReport {
+        cpu_usage: Some(12.5),
+        memory_usage: Some(1024),
+        ..Default::default()
+    };
+    let mut summary = UsageSummary::default();
+    summary.add(State::Running, Some(&report), Some(3));
+    summary.add(State::Running, Some(&report), None);
+    summary.add(State::Stopped, Some(&report), Some(0));
+    summary.add(State::Starting, None, None);
+    assert_eq!(
+        summary,
+        UsageSummary {
+            instance_count: 4,
+            states: InstanceStateCounts {
+                starting: 1,
+                running: 2,
+                stopped: 1,
+                ..Default::default()
+            },
+            cpu_usage: 25.0,
+            memory_usage: 2048,
+            player_count: 3,
+        }
+    );
+}
+
+#[test]
+fn test_port_allocations() {
+    let owner = |name: &str, state: State| PortOwner {
+        uuid: InstanceUuid::default(),
+        name: name.to_string(),
+        state,
+    };
+    let allocations = port_allocations(
+        &[25566, 25565, 25570],
+        vec![
+            (25565, owner("lobby", State::Running)),
+            (25566, owner("survival", State::Stopped)),
+            (25566, owner("creative", State::Stopped)),
+            (25567, owner("imported", State::Stopped)),
+        ],
+        |port| port == 25565,
+    );
+    let ports: Vec<u32> = allocations.iter().map(|a| a.port).collect();
+    assert_eq!(ports, vec![25565, 25566, 25567, 25570]);
+    assert!(allocations[0].is_bound && !allocations[0].is_unbound);
+    assert_eq!(allocations[1].owners.len(), 2);
+    assert!(allocations[1].is_unbound);
+    assert!(!allocations[2].is_allocated && !allocations[2].is_unbound);
+    assert!(allocations[3].owners.is_empty() && allocations[3].is_unbound);
+}
@@ -0,0 +1,83 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::{User, UserAction},
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    tasks::TaskInfo,
+    types::Snowflake,
+    AppState,
+};
+
+/// Tasks on an instance are only visible to those who can view the instance
+fn can_view_task(requester: &User, task: &TaskInfo) -> bool {
+    match &task.instance_uuid {
+        Some(instance_uuid) => {
+            requester.can_perform_action(&UserAction::ViewInstance(instance_uuid.clone()))
+        }
+        None => true,
+    }
+}
+
+pub async fn get_tasks(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<TaskInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(
+        state
+            .tasks
+            .list(chrono::Utc::now().timestamp())
+            .into_iter()
+            .filter(|task| can_view_task(&requester, task))
+            .collect(),
+    ))
+}
+
+pub async fn get_task(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<TaskInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .tasks
+        .get(&id, chrono::Utc::now().timestamp())
+        .filter(|task| can_view_task(&requester, task))
+        .map(Json)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Task not found"),
+        })
+}
+
+/// Cancels a running task, only its starter or the owner can cancel it
+pub async fn cancel_task(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let now = chrono::Utc::now().timestamp();
+    let started_by_requester = match state.tasks.get(&id, now).map(|task| task.caused_by) {
+        Some(CausedBy::User { user_id, .. }) => user_id == requester.uid,
+        _ => false,
+    };
+    if !requester.is_owner && !started_by_requester {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the user who started the task or the owner can cancel it"),
+        });
+    }
+    state.tasks.cancel(&id, now)?;
+    Ok(Json(()))
+}
+
+pub fn get_tasks_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/tasks", get(get_tasks))
+        .route("/tasks/:id", get(get_task).delete(cancel_task))
+        .with_state(state)
+}
@@ -2,24 +2,33 @@ use crate::{
     auth::{
         jwt_token::JwtToken,
         permission::UserPermission,
-        user::{PublicUser, User, UserAction},
+        session::{truncate_user_agent, SessionInfo},
+        totp::TotpEnrollmentInfo,
+        user::{PublicUser, User, UserAction, UsersManager},
         user_id::UserId,
+        viewer_token::{MintedViewerToken, ViewerToken},
     },
+    db::write::{sync_sessions, write_session},
     error::{Error, ErrorKind},
     events::CausedBy,
+    types::InstanceUuid,
     AppState,
 };
 
 use axum::{
-    extract::Path,
+    extract::{ConnectInfo, Path},
+    http::{header::USER_AGENT, HeaderMap},
     routing::{delete, get, post, put},
     Json, Router,
 };
 use axum_auth::{AuthBasic, AuthBearer};
 
+use std::{collections::HashSet, net::SocketAddr};
+
 use color_eyre::eyre::eyre;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use tracing::error;
 use ts_rs::TS;
 
 #[derive(Deserialize, Serialize)]
@@ -32,13 +41,14 @@ pub async fn new_user(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
     Json(config): Json<NewUser>,
-) -> Result<Json<LoginReply>, Error> {
+) -> Result<Json<PublicUser>, Error> {
     let mut users_manager = state.users_manager.write().await;
     let requester = users_manager.try_auth_or_err(&token)?;
     requester.try_action(
         &UserAction::ManageUser,
         state.global_settings.lock().await.safe_mode(),
     )?;
+    users_manager.check_password(&config.password)?;
     let user = User::new(
         config.username,
         config.password,
@@ -53,10 +63,8 @@ pub async fn new_user(
     users_manager
         .add_user(user.clone(), caused_by.clone())
         .await?;
-    Ok(Json(LoginReply {
-        token: user.create_jwt()?,
-        user: user.into(),
-    }))
+    // no token, the new user starts their own session by logging in
+    Ok(Json(user.into()))
 }
 
 pub async fn delete_user(
@@ -110,6 +118,7 @@ pub async fn logout(
     users_manager
         .logout_user(uid.clone(), caused_by.clone())
         .await?;
+    save_sessions(&state, &users_manager).await?;
     Ok(Json(()))
 }
 
@@ -200,6 +209,77 @@ pub async fn rename_user(
     Ok(Json(()))
 }
 
+fn owner_only(requester: &User, message: &str) -> Result<(), Error> {
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("{}", message),
+        });
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct UserRole {
+    pub is_owner: bool,
+    pub is_admin: bool,
+}
+
+pub async fn set_user_role(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(role): Json<UserRole>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManageUser,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    owner_only(&requester, "Only an owner can grant or revoke roles")?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .set_role(uid, role.is_owner, role.is_admin, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct TransferOwnership {
+    /// Stay an owner alongside the new one instead of becoming an admin
+    #[serde(default)]
+    pub keep_owner: bool,
+}
+
+pub async fn transfer_ownership(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(transfer): Json<TransferOwnership>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManageUser,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    owner_only(&requester, "Only an owner can transfer ownership")?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .transfer_ownership(&requester.uid, uid, transfer.keep_owner, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
 #[derive(Deserialize)]
 pub struct ChangePasswordConfig {
     uid: UserId,
@@ -251,25 +331,91 @@ pub async fn change_password(
 pub struct LoginReply {
     pub token: JwtToken,
     pub user: PublicUser,
+    /// The password doesn't meet the current password policy and should be changed
+    pub password_update_required: bool,
+}
+
+/// The reply to a password login, a second step is needed when two-factor is enabled
+#[derive(Serialize, TS)]
+#[serde(untagged)]
+#[ts(export)]
+pub enum LoginResponse {
+    LoggedIn(LoginReply),
+    /// Answer with a code at `/user/login/totp`
+    TwoFactorRequired {
+        two_factor_challenge: String,
+    },
+}
+
+/// Address and user agent of the client logging in, recorded with the session
+pub(super) fn client_info(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: &HeaderMap,
+) -> (Option<String>, Option<String>) {
+    let ip = connect_info.map(|ConnectInfo(addr)| addr.ip().to_string());
+    let user_agent = headers
+        .get(USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(truncate_user_agent);
+    (ip, user_agent)
+}
+
+/// Starts a session for a successful login and saves it
+pub(super) async fn login_reply(
+    state: &AppState,
+    users_manager: &UsersManager,
+    user: User,
+    (ip, user_agent): (Option<String>, Option<String>),
+    password_update_required: bool,
+) -> Result<LoginReply, Error> {
+    let (token, session) =
+        users_manager.create_session(&user, ip, user_agent, chrono::Utc::now().timestamp())?;
+    // an unsaved session still works, until the next restart
+    if state.db_health.is_available() {
+        if let Err(e) = write_session(&state.sqlite_pool, &session).await {
+            error!("Failed to save session: {}", e);
+        }
+    }
+    Ok(LoginReply {
+        token,
+        user: user.into(),
+        password_update_required,
+    })
 }
 
 pub async fn login(
     axum::extract::State(state): axum::extract::State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     AuthBasic((username, password)): AuthBasic,
-) -> Result<Json<LoginReply>, Error> {
+) -> Result<Json<LoginResponse>, Error> {
     if let Some(password) = password {
+        // hashing is slow, only a read lock is held for it so other requests aren't blocked
         let users_manager = state.users_manager.read().await;
-
-        Ok(Json(LoginReply {
-            token: users_manager.login(&username, &password)?,
-            user: users_manager
-                .get_user_by_username(&username)
-                .ok_or_else(|| Error {
-                    kind: ErrorKind::NotFound,
-                    source: eyre!("User not found"),
-                })?
-                .into(),
-        }))
+        let user = users_manager.verify_credentials(&username, &password)?;
+        // checked now since only the hash is stored, existing users aren't locked out
+        let password_update_required = users_manager.check_password(&password).is_err();
+        if user.two_factor_enabled() {
+            drop(users_manager);
+            let now = chrono::Utc::now().timestamp();
+            return Ok(Json(LoginResponse::TwoFactorRequired {
+                two_factor_challenge: state.users_manager.write().await.start_totp_challenge(
+                    &user.uid,
+                    password_update_required,
+                    now,
+                ),
+            }));
+        }
+        Ok(Json(LoginResponse::LoggedIn(
+            login_reply(
+                &state,
+                &users_manager,
+                user,
+                client_info(connect_info, &headers),
+                password_update_required,
+            )
+            .await?,
+        )))
     } else {
         Err(Error {
             kind: ErrorKind::BadRequest,
@@ -278,6 +424,195 @@ pub async fn login(
     }
 }
 
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct TotpLogin {
+    pub two_factor_challenge: String,
+    /// A code from the authenticator, or a recovery code
+    pub code: String,
+}
+
+pub async fn login_totp(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(login): Json<TotpLogin>,
+) -> Result<Json<LoginReply>, Error> {
+    let now = chrono::Utc::now().timestamp();
+    let mut users_manager = state.users_manager.write().await;
+    let (user, password_update_required) = users_manager
+        .complete_totp_challenge(
+            &login.two_factor_challenge,
+            &login.code,
+            now,
+            &state.secrets,
+        )
+        .await?;
+    Ok(Json(
+        login_reply(
+            &state,
+            &users_manager,
+            user,
+            client_info(connect_info, &headers),
+            password_update_required,
+        )
+        .await?,
+    ))
+}
+
+pub async fn get_sessions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<SessionInfo>>, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    let current = UsersManager::session_id(&token);
+    Ok(Json(
+        users_manager
+            .sessions_of(&requester.uid, chrono::Utc::now().timestamp())
+            .into_iter()
+            .map(|session| SessionInfo {
+                current: current.as_ref() == Some(&session.id),
+                session,
+            })
+            .collect(),
+    ))
+}
+
+/// Saves the sessions right away so a revoked one doesn't come back after a restart
+async fn save_sessions(state: &AppState, users_manager: &UsersManager) -> Result<(), Error> {
+    // the session sync task writes them all once the database is back
+    if !state.db_health.is_available() {
+        return Ok(());
+    }
+    sync_sessions(
+        &state.sqlite_pool,
+        &users_manager.sessions(chrono::Utc::now().timestamp()),
+    )
+    .await
+}
+
+pub async fn revoke_session(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    users_manager.revoke_session(&requester.uid, &id)?;
+    save_sessions(&state, &users_manager).await?;
+    Ok(Json(()))
+}
+
+/// Logs the requester out of every session, including the current one
+pub async fn revoke_all_sessions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager.logout_user(requester.uid, caused_by).await?;
+    save_sessions(&state, &users_manager).await?;
+    Ok(Json(()))
+}
+
+fn self_only(requester: &User, uid: &UserId) -> Result<(), Error> {
+    if requester.uid != *uid {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You can only set up two-factor authentication for yourself"),
+        });
+    }
+    Ok(())
+}
+
+pub async fn enroll_totp(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<TotpEnrollmentInfo>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    self_only(&requester, &uid)?;
+    Ok(Json(users_manager.enroll_totp(uid, &state.secrets).await?))
+}
+
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct TotpCode {
+    pub code: Option<String>,
+}
+
+pub async fn verify_totp(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(TotpCode { code }): Json<TotpCode>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    self_only(&requester, &uid)?;
+    let code = code.ok_or_else(|| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("A code from the authenticator is required"),
+    })?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .confirm_totp(
+            uid,
+            &code,
+            chrono::Utc::now().timestamp(),
+            &state.secrets,
+            caused_by,
+        )
+        .await?;
+    Ok(Json(()))
+}
+
+/// Users turn off their own two-factor with a code, an owner can reset anyone's without one
+pub async fn disable_totp(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(TotpCode { code }): Json<TotpCode>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    let code = if requester.uid == uid {
+        Some(code.ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("A code or recovery code is required"),
+        })?)
+    } else {
+        requester.try_action(
+            &UserAction::ManageUser,
+            state.global_settings.lock().await.safe_mode(),
+        )?;
+        None
+    };
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .disable_totp(
+            uid,
+            code.as_deref(),
+            chrono::Utc::now().timestamp(),
+            &state.secrets,
+            caused_by,
+        )
+        .await?;
+    Ok(Json(()))
+}
+
 pub async fn get_all_users(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
@@ -300,6 +635,88 @@ pub async fn get_all_users(
     ))
 }
 
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct NewViewerToken {
+    pub label: String,
+    pub instances: HashSet<InstanceUuid>,
+    /// Seconds until the token expires, `None` for a token that never expires
+    pub expires_in_secs: Option<u32>,
+}
+
+pub async fn mint_viewer_token(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<NewViewerToken>,
+) -> Result<Json<MintedViewerToken>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    if config.instances.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("A viewer token needs at least one instance"),
+        });
+    }
+    // a token can't see more than the user who minted it
+    for uuid in &config.instances {
+        if !requester.can_perform_action(&UserAction::ViewInstance(uuid.clone())) {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("You don't have permission to view instance {}", uuid),
+            });
+        }
+    }
+    let now = chrono::Utc::now().timestamp();
+    let minted = users_manager
+        .mint_viewer_token(
+            config.label,
+            config.instances,
+            requester.uid,
+            now,
+            config.expires_in_secs.map(|secs| now + secs as i64),
+        )
+        .await?;
+    Ok(Json(minted))
+}
+
+pub async fn get_viewer_tokens(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ViewerToken>>, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    Ok(Json(
+        users_manager
+            .viewer_tokens(chrono::Utc::now().timestamp())
+            .into_iter()
+            .filter(|viewer_token| requester.is_owner || viewer_token.created_by == requester.uid)
+            .collect(),
+    ))
+}
+
+pub async fn revoke_viewer_token(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    let viewer_token = users_manager.get_viewer_token(&id).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Viewer token not found"),
+    })?;
+    if !requester.is_owner && viewer_token.created_by != requester.uid {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the owner or the creator of a viewer token can revoke it"),
+        });
+    }
+    users_manager
+        .revoke_viewer_token(&id, chrono::Utc::now().timestamp())
+        .await?;
+    Ok(Json(()))
+}
+
 // return the thing created by Router::new() so we can nest it in main
 pub fn get_user_routes(state: AppState) -> Router {
     Router::new()
@@ -311,7 +728,23 @@ pub fn get_user_routes(state: AppState) -> Router {
         .route("/user/info", get(get_self_info))
         .route("/user/:uid/rename", put(rename_user))
         .route("/user/:uid/password", put(change_password))
+        .route("/user/:uid/role", put(set_user_role))
+        .route("/user/:uid/transfer-ownership", post(transfer_ownership))
         .route("/user/login", post(login))
+        .route("/user/login/totp", post(login_totp))
+        .route(
+            "/user/sessions",
+            get(get_sessions).delete(revoke_all_sessions),
+        )
+        .route("/user/sessions/:id", delete(revoke_session))
+        .route("/user/:uid/totp", delete(disable_totp))
+        .route("/user/:uid/totp/enroll", post(enroll_totp))
+        .route("/user/:uid/totp/verify", post(verify_totp))
         .route("/user/logout/:uid", post(logout))
+        .route(
+            "/user/viewer_tokens",
+            get(get_viewer_tokens).post(mint_viewer_token),
+        )
+        .route("/user/viewer_tokens/:id", delete(revoke_viewer_token))
         .with_state(state)
 }
@@ -1,6 +1,13 @@
-use color_eyre::eyre::Context;
+use std::path::PathBuf;
 
-use crate::error::Error;
+use color_eyre::eyre::{eyre, Context};
+
+use crate::{
+    error::{Error, ErrorKind},
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    AppState,
+};
 
 pub fn parse_bearer_token(token: &str) -> Option<String> {
     let mut split = token.split_ascii_whitespace();
@@ -23,3 +30,17 @@ pub fn decode_base64(input: &str) -> Result<String, Error> {
     )
     .context("Invalid UTF-8")?)
 }
+
+/// The directory of the instance, `NotFound` if there is no such instance
+pub async fn instance_root(state: &AppState, uuid: &InstanceUuid) -> Result<PathBuf, Error> {
+    // cloned so the map isn't borrowed across the await
+    let instance = state
+        .instances
+        .get(uuid)
+        .map(|instance| instance.clone())
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?;
+    Ok(instance.path().await)
+}
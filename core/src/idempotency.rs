@@ -0,0 +1,111 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::http::HeaderMap;
+use color_eyre::eyre::eyre;
+use dashmap::{mapref::entry::Entry, DashMap};
+use serde_json::Value;
+
+use crate::{
+    auth::user_id::UserId,
+    error::{Error, ErrorKind},
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// The client supplied `Idempotency-Key` of a request, if any
+pub fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, Error> {
+    let key = match headers.get(IDEMPOTENCY_KEY_HEADER) {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= 255 => Ok(Some(key.to_owned())),
+        _ => Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Idempotency-Key must be between 1 and 255 visible ASCII characters"),
+        }),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Outcome {
+    InProgress,
+    Done(Value),
+}
+
+/// Results of requests made with an `Idempotency-Key`, so a retried request
+/// gets the original result instead of being performed twice
+///
+/// Keys are scoped to the user that sent them
+#[derive(Debug, Clone, Default)]
+pub struct IdempotencyCache {
+    entries: Arc<DashMap<(UserId, String), (Instant, Outcome)>>,
+}
+
+impl IdempotencyCache {
+    /// Claims `key` for a new request, or returns the result of the request that already used it
+    ///
+    /// Fails with `Conflict` while the first request with the key is still being handled
+    pub fn begin(&self, user: &UserId, key: &str, ttl: Duration) -> Result<Option<Value>, Error> {
+        self.entries
+            .retain(|_, (created_at, _)| created_at.elapsed() < ttl);
+        match self.entries.entry((user.clone(), key.to_owned())) {
+            Entry::Occupied(entry) => match &entry.get().1 {
+                Outcome::Done(value) => Ok(Some(value.clone())),
+                Outcome::InProgress => Err(Error {
+                    kind: ErrorKind::Conflict,
+                    source: eyre!("A request with this Idempotency-Key is still in progress"),
+                }),
+            },
+            Entry::Vacant(entry) => {
+                entry.insert((Instant::now(), Outcome::InProgress));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Stores the result to replay for `key`
+    pub fn complete(&self, user: &UserId, key: &str, value: Value) {
+        if let Some(mut entry) = self.entries.get_mut(&(user.clone(), key.to_owned())) {
+            entry.1 = Outcome::Done(value);
+        }
+    }
+
+    /// Frees `key` after a failed request so it can be retried
+    pub fn abort(&self, user: &UserId, key: &str) {
+        self.entries.remove(&(user.clone(), key.to_owned()));
+    }
+}
+
+#[test]
+fn test_idempotency_cache() {
+    let cache = IdempotencyCache::default();
+    let user = UserId::from("user".to_string());
+    let ttl = Duration::from_secs(60);
+
+    assert!(cache.begin(&user, "key", ttl).unwrap().is_none());
+    assert!(matches!(
+        cache.begin(&user, "key", ttl),
+        Err(Error {
+            kind: ErrorKind::Conflict,
+            ..
+        })
+    ));
+    cache.complete(&user, "key", Value::from("INSTANCE_1"));
+    assert_eq!(
+        cache.begin(&user, "key", ttl).unwrap(),
+        Some(Value::from("INSTANCE_1"))
+    );
+    // other users don't share keys
+    let other = UserId::from("other".to_string());
+    assert!(cache.begin(&other, "key", ttl).unwrap().is_none());
+
+    cache.abort(&other, "key");
+    assert!(cache.begin(&other, "key", ttl).unwrap().is_none());
+
+    // expired keys are forgotten
+    assert!(cache.begin(&user, "key", Duration::ZERO).unwrap().is_none());
+}
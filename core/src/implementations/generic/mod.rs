@@ -292,6 +292,10 @@ impl TInstance for GenericInstance {
             player_count: self.get_player_count().await.ok(),
             max_player_count: self.get_max_player_count().await.ok(),
             player_list: self.get_player_list().await.ok(),
+            tags: self.tags().await,
+            notes: self.notes().await,
+            restart_required: false,
+            deleted_at: None,
         }
     }
 }
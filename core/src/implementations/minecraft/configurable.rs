@@ -3,6 +3,8 @@ use std::sync::atomic;
 
 use async_trait::async_trait;
 use color_eyre::eyre::{eyre, Context, ContextCompat};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
 use crate::error::{Error, ErrorKind};
 use crate::prelude::path_to_tmp;
@@ -60,6 +62,10 @@ impl TConfigurable for MinecraftInstance {
         self.config.lock().await.restart_on_crash
     }
 
+    async fn max_memory(&self) -> Option<u32> {
+        Some(self.config.lock().await.max_ram)
+    }
+
     async fn set_name(&self, name: String) -> Result<(), Error> {
         if name.is_empty() {
             return Err(Error {
@@ -73,8 +79,11 @@ impl TConfigurable for MinecraftInstance {
                 source: eyre!("Name cannot be longer than 100 characters"),
             });
         }
-        self.config.lock().await.name = name;
-        self.write_config_to_file().await?;
+        let old_name = std::mem::replace(&mut self.config.lock().await.name, name);
+        if let Err(e) = self.write_config_to_file().await {
+            self.config.lock().await.name = old_name;
+            return Err(e);
+        }
         Ok(())
     }
 
@@ -164,6 +173,7 @@ impl TConfigurable for MinecraftInstance {
             Some("server.jar"),
             &Box::new(|_| {}),
             true,
+            None,
         )
         .await?;
         let jar_path = temp_dir.path().join("server.jar");
@@ -336,7 +346,8 @@ impl CmdArgSetting {
 
 impl From<CmdArgSetting> for SettingManifest {
     fn from(value: CmdArgSetting) -> Self {
-        match value {
+        // command line arguments are only read when the server is launched
+        let setting = match value {
             CmdArgSetting::MinRam(min_ram) => SettingManifest::new_optional_value(
                 value.get_identifier().to_owned(),
                 value.get_name().to_owned(),
@@ -383,7 +394,8 @@ impl From<CmdArgSetting> for SettingManifest {
                 false,
                 true,
             ),
-        }
+        };
+        setting.with_requires_restart(true)
     }
 }
 
@@ -428,8 +440,10 @@ impl TryFrom<SettingManifest> for CmdArgSetting {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
-pub(super) enum Gamemode {
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum Gamemode {
     #[default]
     Survival,
     Creative,
@@ -466,8 +480,10 @@ impl FromStr for Gamemode {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
-pub(super) enum Difficulty {
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum Difficulty {
     #[default]
     Peaceful,
     Easy,
@@ -568,7 +584,8 @@ pub(super) enum ServerPropertySetting {
 
 impl From<ServerPropertySetting> for SettingManifest {
     fn from(value: ServerPropertySetting) -> Self {
-        match value {
+        // server.properties is only read when the server starts
+        let setting = match value {
             ServerPropertySetting::EnableJmxMonitoring(inner_val) => Self::new_required_value(
                 value.get_identifier(),
                 value.get_name(),
@@ -1130,7 +1147,8 @@ impl From<ServerPropertySetting> for SettingManifest {
                 false,
                 true,
             ),
-        }
+        };
+        setting.with_requires_restart(true)
     }
 }
 
@@ -0,0 +1,272 @@
+//! Reads the crash reports Minecraft writes to `crash-reports/` when the server crashes
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+pub const CRASH_REPORTS_DIR: &str = "crash-reports";
+
+/// Stack frames kept from the top of the stack trace
+const STACK_HEAD_LINES: usize = 8;
+
+/// Crash reports larger than this are not parsed
+const MAX_CRASH_REPORT_BYTES: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct CrashReportEntry {
+    /// File name of the report in `crash-reports/`
+    pub id: String,
+    pub size: u64,
+    /// Unix time the report was written
+    pub modified: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct CrashReport {
+    pub id: String,
+    /// As written in the report, in the server's local time
+    pub time: Option<String>,
+    pub description: Option<String>,
+    /// First line of the stack trace, e.g. `java.lang.NullPointerException: ...`
+    pub exception: Option<String>,
+    /// The top frames of the stack trace
+    pub stack_head: Vec<String>,
+    /// Mods Forge suspects caused the crash, or whose section reports a failure
+    pub suspected_mods: Vec<String>,
+    pub minecraft_version: Option<String>,
+}
+
+fn field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    line.trim()
+        .strip_prefix(name)?
+        .strip_prefix(':')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+pub fn parse_crash_report(id: &str, content: &str) -> CrashReport {
+    let mut report = CrashReport {
+        id: id.to_string(),
+        ..Default::default()
+    };
+    let lines: Vec<&str> = content.lines().collect();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if report.time.is_none() {
+            if let Some(time) = field(line, "Time") {
+                report.time = Some(time.to_string());
+            }
+        }
+        if let Some(description) = field(line, "Description") {
+            if report.description.is_none() {
+                report.description = Some(description.to_string());
+                // the stack trace follows the description after a blank line
+                let mut j = i + 1;
+                while j < lines.len() && lines[j].trim().is_empty() {
+                    j += 1;
+                }
+                if j < lines.len() {
+                    report.exception = Some(lines[j].trim().to_string());
+                    report.stack_head = lines[j + 1..]
+                        .iter()
+                        .take_while(|l| l.trim_start().starts_with("at "))
+                        .take(STACK_HEAD_LINES)
+                        .map(|l| l.trim().to_string())
+                        .collect();
+                }
+            }
+        }
+        if let Some(version) = field(line, "Minecraft Version") {
+            report
+                .minecraft_version
+                .get_or_insert_with(|| version.to_string());
+        }
+        // Forge: `Suspected Mod: Example Mod (examplemod), Version: 1.0`, or the mods on
+        // the following indented lines, or `Suspected Mods: NONE`
+        let trimmed = line.trim();
+        if let Some(rest) = trimmed
+            .strip_prefix("Suspected Mods:")
+            .or_else(|| trimmed.strip_prefix("Suspected Mod:"))
+        {
+            let rest = rest.trim();
+            if !rest.is_empty() && rest != "NONE" {
+                report.suspected_mods.push(rest.to_string());
+            }
+            // each mod is indented once, its details twice
+            while i + 1 < lines.len() && lines[i + 1].starts_with('\t') {
+                i += 1;
+                if !lines[i].starts_with("\t\t") {
+                    report.suspected_mods.push(lines[i].trim().to_string());
+                }
+            }
+        }
+        // older Forge: a `-- MOD <id> --` section with a failure message
+        if let Some(mod_id) = trimmed
+            .strip_prefix("-- MOD ")
+            .and_then(|rest| rest.strip_suffix(" --"))
+        {
+            let failed = lines[i + 1..]
+                .iter()
+                .take_while(|l| !l.starts_with("-- "))
+                .any(|l| field(l, "Failure message").is_some());
+            if failed && !report.suspected_mods.iter().any(|m| m == mod_id) {
+                report.suspected_mods.push(mod_id.to_string());
+            }
+        }
+        i += 1;
+    }
+    report
+}
+
+/// Path of the report `id`, rejecting anything but a plain report file name
+fn report_path(root: &Path, id: &str) -> Result<PathBuf, Error> {
+    if !id.ends_with(".txt") || id.contains(['/', '\\']) || id.starts_with('.') {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid crash report id"),
+        });
+    }
+    Ok(root.join(CRASH_REPORTS_DIR).join(id))
+}
+
+/// Crash reports of the instance at `root`, most recent first
+pub async fn list_crash_reports(root: &Path) -> Result<Vec<CrashReportEntry>, Error> {
+    let mut read_dir = match tokio::fs::read_dir(root.join(CRASH_REPORTS_DIR)).await {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(eyre!("Failed to read crash reports: {}", e).into()),
+    };
+    let mut reports = Vec::new();
+    while let Some(entry) = read_dir
+        .next_entry()
+        .await
+        .context("Failed to read crash reports")?
+    {
+        let id = entry.file_name().to_string_lossy().to_string();
+        let metadata = match entry.metadata().await {
+            Ok(metadata) if metadata.is_file() && id.ends_with(".txt") => metadata,
+            _ => continue,
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_secs() as i64);
+        reports.push(CrashReportEntry {
+            id,
+            size: metadata.len(),
+            modified,
+        });
+    }
+    reports.sort_by(|a, b| b.modified.cmp(&a.modified).then(b.id.cmp(&a.id)));
+    Ok(reports)
+}
+
+pub async fn read_crash_report(root: &Path, id: &str) -> Result<CrashReport, Error> {
+    let path = report_path(root, id)?;
+    let metadata = tokio::fs::metadata(&path).await.map_err(|_| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Crash report not found"),
+    })?;
+    if metadata.len() > MAX_CRASH_REPORT_BYTES {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Crash report is too large to parse"),
+        });
+    }
+    let content = tokio::fs::read(&path)
+        .await
+        .context("Failed to read crash report")?;
+    Ok(parse_crash_report(id, &String::from_utf8_lossy(&content)))
+}
+
+#[cfg(test)]
+const VANILLA_CRASH_REPORT: &str = "---- Minecraft Crash Report ----
+// Surprise! Haha. Well, this is awkward.
+
+Time: 2023-06-01 12:34:56
+Description: Exception in server tick loop
+
+java.lang.NullPointerException: Cannot invoke \"net.minecraft.world.entity.Entity.getX()\" because \"entity\" is null
+\tat net.minecraft.server.level.ServerLevel.tickNonPassenger(ServerLevel.java:693)
+\tat net.minecraft.world.level.Level.guardEntityTick(Level.java:479)
+\tat net.minecraft.server.MinecraftServer.tickServer(MinecraftServer.java:866)
+
+A detailed walkthrough of the error, its code path and all known details is as follows:
+---------------------------------------------------------------------------------------
+
+-- System Details --
+Details:
+\tMinecraft Version: 1.20.1
+\tMinecraft Version ID: 1.20.1
+\tOperating System: Linux (amd64) version 5.15.0
+";
+
+#[cfg(test)]
+const FORGE_CRASH_REPORT: &str = "---- Minecraft Crash Report ----
+// Who set us up the TNT?
+
+Time: 2023-06-02 08:00:01
+Description: Ticking block entity
+
+java.lang.IllegalStateException: Invalid energy buffer
+\tat com.example.machines.block.GeneratorBlockEntity.tick(GeneratorBlockEntity.java:88) ~[examplemachines-1.4.2.jar%23183!/:1.4.2] {re:classloading}
+\tat net.minecraft.world.level.chunk.LevelChunk$BoundTickingBlockEntity.m_142224_(LevelChunk.java:695) ~[forge-1.20.1-47.1.0.jar%23190!/:?] {re:mixin}
+
+A detailed walkthrough of the error, its code path and all known details is as follows:
+---------------------------------------------------------------------------------------
+
+-- Head --
+Thread: Server thread
+Suspected Mod:
+\tExample Machines (examplemachines), Version: 1.4.2
+\t\tIssue tracker URL: https://github.com/example/machines/issues
+\t\tat TRANSFORMER/examplemachines@1.4.2/com.example.machines.block.GeneratorBlockEntity.tick(GeneratorBlockEntity.java:88)
+Stacktrace:
+\tat com.example.machines.block.GeneratorBlockEntity.tick(GeneratorBlockEntity.java:88)
+
+-- System Details --
+Details:
+\tMinecraft Version: 1.20.1
+";
+
+#[test]
+fn test_parse_crash_report() {
+    let report = parse_crash_report("crash-vanilla-server.txt", VANILLA_CRASH_REPORT);
+    assert_eq!(report.time.as_deref(), Some("2023-06-01 12:34:56"));
+    assert_eq!(
+        report.description.as_deref(),
+        Some("Exception in server tick loop")
+    );
+    assert!(report
+        .exception
+        .unwrap()
+        .starts_with("java.lang.NullPointerException"));
+    assert_eq!(report.stack_head.len(), 3);
+    assert!(report.stack_head[0].starts_with("at net.minecraft.server.level.ServerLevel"));
+    assert!(report.suspected_mods.is_empty());
+    assert_eq!(report.minecraft_version.as_deref(), Some("1.20.1"));
+
+    let report = parse_crash_report("crash-forge-server.txt", FORGE_CRASH_REPORT);
+    assert_eq!(report.description.as_deref(), Some("Ticking block entity"));
+    assert_eq!(
+        report.exception.as_deref(),
+        Some("java.lang.IllegalStateException: Invalid energy buffer")
+    );
+    assert_eq!(report.stack_head.len(), 2);
+    assert_eq!(
+        report.suspected_mods,
+        vec!["Example Machines (examplemachines), Version: 1.4.2".to_string()]
+    );
+
+    let root = Path::new("/srv/instance");
+    assert!(report_path(root, "../server.properties").is_err());
+    assert!(report_path(root, "crash-2023-06-01_12.34.56-server.txt").is_ok());
+}
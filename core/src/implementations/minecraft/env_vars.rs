@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    variables::{is_valid_name, Variables},
+};
+
+const MAX_ENV_VARS: usize = 64;
+const MAX_ENV_NAME_LEN: usize = 128;
+const MAX_ENV_VALUE_LEN: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct EnvVar {
+    /// `None` in responses when the variable is sensitive, and in an update to keep the
+    /// current value of a sensitive variable
+    pub value: Option<String>,
+    #[serde(default)]
+    pub sensitive: bool,
+}
+
+/// Environment variables set on the server process, on top of the environment of the core
+///
+/// Values may reference `${VAR}`, secrets can't be referenced since non-owners can set these
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(transparent)]
+#[ts(export)]
+pub struct EnvVars {
+    pub vars: BTreeMap<String, EnvVar>,
+}
+
+impl EnvVars {
+    /// The variables with the values of sensitive ones left out, for API responses
+    pub fn masked(&self) -> EnvVars {
+        EnvVars {
+            vars: self
+                .vars
+                .iter()
+                .map(|(name, var)| {
+                    let value = if var.sensitive {
+                        None
+                    } else {
+                        var.value.clone()
+                    };
+                    (
+                        name.clone(),
+                        EnvVar {
+                            value,
+                            sensitive: var.sensitive,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Validates `update` and fills in the values it left out from `self`
+    pub fn updated(&self, update: EnvVars) -> Result<EnvVars, Error> {
+        if update.vars.len() > MAX_ENV_VARS {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "An instance can have at most {} environment variables",
+                    MAX_ENV_VARS
+                ),
+            });
+        }
+        let mut vars = BTreeMap::new();
+        for (name, var) in update.vars {
+            if !is_valid_name(&name) || name.len() > MAX_ENV_NAME_LEN {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Invalid environment variable name {}, only letters, digits and _ are allowed",
+                        name
+                    ),
+                });
+            }
+            let value = match var.value {
+                Some(value) => value,
+                None => self
+                    .vars
+                    .get(&name)
+                    .and_then(|current| current.value.clone())
+                    .ok_or_else(|| Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Environment variable {} has no value", name),
+                    })?,
+            };
+            if value.len() > MAX_ENV_VALUE_LEN || value.contains('\0') {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Environment variable {} must be at most {} bytes and not contain NUL",
+                        name,
+                        MAX_ENV_VALUE_LEN
+                    ),
+                });
+            }
+            vars.insert(
+                name,
+                EnvVar {
+                    value: Some(value),
+                    sensitive: var.sensitive,
+                },
+            );
+        }
+        Ok(EnvVars { vars })
+    }
+
+    /// Name and value pairs to pass to the server `Command`
+    pub fn resolve(&self, variables: &Variables) -> Result<Vec<(String, String)>, Error> {
+        self.vars
+            .iter()
+            .filter_map(|(name, var)| var.value.as_ref().map(|value| (name, value)))
+            .map(|(name, value)| Ok((name.clone(), variables.substitute(value)?)))
+            .collect()
+    }
+}
+
+#[test]
+fn test_env_vars() {
+    let var = |value: Option<&str>, sensitive| EnvVar {
+        value: value.map(str::to_string),
+        sensitive,
+    };
+    let current = EnvVars::default()
+        .updated(EnvVars {
+            vars: BTreeMap::from([
+                ("MALLOC_ARENA_MAX".to_string(), var(Some("2"), false)),
+                ("API_KEY".to_string(), var(Some("hunter2"), true)),
+            ]),
+        })
+        .unwrap();
+
+    let masked = current.masked();
+    assert_eq!(masked.vars["API_KEY"].value, None);
+    assert_eq!(masked.vars["MALLOC_ARENA_MAX"].value.as_deref(), Some("2"));
+
+    // sending back a masked variable keeps its value
+    let updated = current.updated(masked).unwrap();
+    assert_eq!(updated, current);
+    assert_eq!(
+        updated.resolve(&Variables::default()).unwrap(),
+        vec![
+            ("API_KEY".to_string(), "hunter2".to_string()),
+            ("MALLOC_ARENA_MAX".to_string(), "2".to_string())
+        ]
+    );
+
+    assert!(current
+        .updated(EnvVars {
+            vars: BTreeMap::from([("NEW".to_string(), var(None, true))]),
+        })
+        .is_err());
+    assert!(current
+        .updated(EnvVars {
+            vars: BTreeMap::from([("BAD-NAME".to_string(), var(Some("1"), false))]),
+        })
+        .is_err());
+}
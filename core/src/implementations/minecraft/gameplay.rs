@@ -0,0 +1,412 @@
+//! Difficulty, default gamemode, hardcore and gamerules, changed with commands while the server
+//! runs so they apply without a restart
+//!
+//! Gamerules are stored in the world rather than server.properties, so they can only be read
+//! and changed while the server is running
+
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::events::CausedBy;
+use crate::traits::t_configurable::{manifest::ConfigurableValue, TConfigurable};
+use crate::traits::t_server::{State, TServer};
+
+use super::configurable::{Difficulty, Gamemode, ServerPropertySetting};
+use super::MinecraftInstance;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GameruleKind {
+    Bool,
+    Int { min: i32, max: i32 },
+}
+
+/// The gamerules of recent vanilla versions, ones a version doesn't have are left out when
+/// reading them from the server
+const GAMERULES: &[(&str, GameruleKind)] = &[
+    ("announceAdvancements", GameruleKind::Bool),
+    ("blockExplosionDropDecay", GameruleKind::Bool),
+    ("commandBlockOutput", GameruleKind::Bool),
+    (
+        "commandModificationBlockLimit",
+        GameruleKind::Int {
+            min: 1,
+            max: i32::MAX,
+        },
+    ),
+    ("disableElytraMovementCheck", GameruleKind::Bool),
+    ("disableRaids", GameruleKind::Bool),
+    ("doDaylightCycle", GameruleKind::Bool),
+    ("doEntityDrops", GameruleKind::Bool),
+    ("doFireTick", GameruleKind::Bool),
+    ("doImmediateRespawn", GameruleKind::Bool),
+    ("doInsomnia", GameruleKind::Bool),
+    ("doLimitedCrafting", GameruleKind::Bool),
+    ("doMobLoot", GameruleKind::Bool),
+    ("doMobSpawning", GameruleKind::Bool),
+    ("doPatrolSpawning", GameruleKind::Bool),
+    ("doTileDrops", GameruleKind::Bool),
+    ("doTraderSpawning", GameruleKind::Bool),
+    ("doVinesSpread", GameruleKind::Bool),
+    ("doWardenSpawning", GameruleKind::Bool),
+    ("doWeatherCycle", GameruleKind::Bool),
+    ("drowningDamage", GameruleKind::Bool),
+    ("fallDamage", GameruleKind::Bool),
+    ("fireDamage", GameruleKind::Bool),
+    ("forgiveDeadPlayers", GameruleKind::Bool),
+    ("freezeDamage", GameruleKind::Bool),
+    ("globalSoundEvents", GameruleKind::Bool),
+    ("keepInventory", GameruleKind::Bool),
+    ("lavaSourceConversion", GameruleKind::Bool),
+    ("logAdminCommands", GameruleKind::Bool),
+    (
+        "maxCommandChainLength",
+        GameruleKind::Int {
+            min: 0,
+            max: i32::MAX,
+        },
+    ),
+    (
+        "maxCommandForkCount",
+        GameruleKind::Int {
+            min: 0,
+            max: i32::MAX,
+        },
+    ),
+    (
+        "maxEntityCramming",
+        GameruleKind::Int {
+            min: 0,
+            max: i32::MAX,
+        },
+    ),
+    ("mobExplosionDropDecay", GameruleKind::Bool),
+    ("mobGriefing", GameruleKind::Bool),
+    ("naturalRegeneration", GameruleKind::Bool),
+    (
+        "playersSleepingPercentage",
+        GameruleKind::Int { min: 0, max: 100 },
+    ),
+    ("randomTickSpeed", GameruleKind::Int { min: 0, max: 4096 }),
+    ("reducedDebugInfo", GameruleKind::Bool),
+    ("sendCommandFeedback", GameruleKind::Bool),
+    ("showDeathMessages", GameruleKind::Bool),
+    (
+        "snowAccumulationHeight",
+        GameruleKind::Int { min: 0, max: 8 },
+    ),
+    (
+        "spawnRadius",
+        GameruleKind::Int {
+            min: 0,
+            max: i32::MAX,
+        },
+    ),
+    ("spectatorsGenerateChunks", GameruleKind::Bool),
+    ("tntExplosionDropDecay", GameruleKind::Bool),
+    ("universalAnger", GameruleKind::Bool),
+    ("waterSourceConversion", GameruleKind::Bool),
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[serde(untagged)]
+#[ts(export)]
+pub enum GameruleValue {
+    Bool(bool),
+    Int(i32),
+}
+
+impl std::fmt::Display for GameruleValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameruleValue::Bool(value) => write!(f, "{value}"),
+            GameruleValue::Int(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+fn gamerule_kind(name: &str) -> Result<GameruleKind, Error> {
+    GAMERULES
+        .iter()
+        .find(|(rule, _)| *rule == name)
+        .map(|(_, kind)| *kind)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Unknown gamerule {name}"),
+        })
+}
+
+fn validate_gamerule(name: &str, value: GameruleValue) -> Result<(), Error> {
+    match (gamerule_kind(name)?, value) {
+        (GameruleKind::Bool, GameruleValue::Bool(_)) => Ok(()),
+        (GameruleKind::Int { min, max }, GameruleValue::Int(value))
+            if (min..=max).contains(&value) =>
+        {
+            Ok(())
+        }
+        (GameruleKind::Int { min, max }, _) => Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Gamerule {name} must be a number from {min} to {max}"),
+        }),
+        (GameruleKind::Bool, _) => Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Gamerule {name} must be true or false"),
+        }),
+    }
+}
+
+/// Parses the response to `difficulty`, like `The difficulty is Normal`
+fn parse_difficulty_response(response: &str) -> Option<Difficulty> {
+    response
+        .split_whitespace()
+        .last()?
+        .to_lowercase()
+        .parse()
+        .ok()
+}
+
+/// Parses the response to `gamerule <name>`, like `Gamerule keepInventory is currently set to: true`
+fn parse_gamerule_response(name: &str, response: &str) -> Option<GameruleValue> {
+    let (_, value) = response.trim().rsplit_once(": ")?;
+    match gamerule_kind(name).ok()? {
+        GameruleKind::Bool => value.parse().ok().map(GameruleValue::Bool),
+        GameruleKind::Int { .. } => value.parse().ok().map(GameruleValue::Int),
+    }
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct GameplaySettings {
+    pub difficulty: Difficulty,
+    /// The gamemode players join with for the first time
+    pub gamemode: Gamemode,
+    /// Only applies after a restart
+    pub hardcore: bool,
+    /// Empty unless the server is running with RCON enabled
+    pub gamerules: BTreeMap<String, GameruleValue>,
+    /// Whether the values were read from the running server rather than server.properties
+    pub live: bool,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct GameplayPatch {
+    pub difficulty: Option<Difficulty>,
+    pub gamemode: Option<Gamemode>,
+    pub hardcore: Option<bool>,
+    /// Can only be changed while the server is running
+    #[serde(default)]
+    pub gamerules: BTreeMap<String, GameruleValue>,
+}
+
+impl GameplayPatch {
+    pub fn validate(&self) -> Result<(), Error> {
+        for (name, value) in &self.gamerules {
+            validate_gamerule(name, *value)?;
+        }
+        Ok(())
+    }
+
+    /// The commands that apply the patch to a running server
+    pub fn commands(&self) -> Vec<String> {
+        let mut commands = Vec::new();
+        if let Some(difficulty) = &self.difficulty {
+            commands.push(format!("difficulty {}", difficulty.to_string()));
+        }
+        if let Some(gamemode) = &self.gamemode {
+            commands.push(format!("defaultgamemode {}", gamemode.to_string()));
+        }
+        for (name, value) in &self.gamerules {
+            commands.push(format!("gamerule {name} {value}"));
+        }
+        commands
+    }
+}
+
+impl MinecraftInstance {
+    async fn property(&self, setting: ServerPropertySetting) -> Option<ConfigurableValue> {
+        self.configurable_manifest()
+            .await
+            .get_unique_setting_key(&setting.get_identifier())
+            .and_then(|setting| setting.get_value().cloned())
+    }
+
+    /// The values in server.properties, overridden by the running server's where RCON can
+    /// read them
+    pub async fn gameplay_settings(&self) -> GameplaySettings {
+        let mut settings = GameplaySettings {
+            difficulty: self
+                .property(ServerPropertySetting::Difficulty(Default::default()))
+                .await
+                .and_then(|v| v.try_as_enum().ok().and_then(|v| v.parse().ok()))
+                .unwrap_or_default(),
+            gamemode: self
+                .property(ServerPropertySetting::Gamemode(Default::default()))
+                .await
+                .and_then(|v| v.try_as_enum().ok().and_then(|v| v.parse().ok()))
+                .unwrap_or_default(),
+            hardcore: self
+                .property(ServerPropertySetting::Hardcore(false))
+                .await
+                .and_then(|v| v.try_as_boolean().ok())
+                .unwrap_or(false),
+            gamerules: BTreeMap::new(),
+            live: false,
+        };
+        if self.state().await != State::Running {
+            return settings;
+        }
+        let difficulty = match self.send_rcon("difficulty").await {
+            Ok(response) => parse_difficulty_response(&response),
+            Err(_) => return settings,
+        };
+        if let Some(difficulty) = difficulty {
+            settings.difficulty = difficulty;
+        }
+        for (name, _) in GAMERULES {
+            if let Some(value) = self
+                .send_rcon(&format!("gamerule {name}"))
+                .await
+                .ok()
+                .and_then(|response| parse_gamerule_response(name, &response))
+            {
+                settings.gamerules.insert(name.to_string(), value);
+            }
+        }
+        settings.live = true;
+        settings
+    }
+
+    /// Prefers RCON so the command has run by the time this returns
+    async fn run_gameplay_command(&self, command: &str, caused_by: &CausedBy) -> Result<(), Error> {
+        match self.send_rcon(command).await {
+            Ok(_) => Ok(()),
+            Err(_) => self.send_command(command, caused_by.clone()).await,
+        }
+    }
+
+    /// Writes the change to server.properties, and runs the commands applying it if the server
+    /// is running. Returns the values now in effect and whether a restart is needed for all of
+    /// them to apply
+    pub async fn set_gameplay(
+        &self,
+        patch: GameplayPatch,
+        caused_by: CausedBy,
+    ) -> Result<(GameplaySettings, bool), Error> {
+        patch.validate()?;
+        let running = self.state().await == State::Running;
+        if !running && !patch.gamerules.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Gamerules are stored in the world and can only be changed while the server is running"
+                ),
+            });
+        }
+        let section_id = ServerPropertySetting::get_section_id();
+        if let Some(difficulty) = &patch.difficulty {
+            self.update_configurable(
+                section_id,
+                &ServerPropertySetting::Difficulty(Default::default()).get_identifier(),
+                ConfigurableValue::Enum(difficulty.to_string()),
+            )
+            .await?;
+        }
+        if let Some(gamemode) = &patch.gamemode {
+            self.update_configurable(
+                section_id,
+                &ServerPropertySetting::Gamemode(Default::default()).get_identifier(),
+                ConfigurableValue::Enum(gamemode.to_string()),
+            )
+            .await?;
+        }
+        if let Some(hardcore) = patch.hardcore {
+            self.update_configurable(
+                section_id,
+                &ServerPropertySetting::Hardcore(false).get_identifier(),
+                ConfigurableValue::Boolean(hardcore),
+            )
+            .await?;
+        }
+        if running {
+            for command in patch.commands() {
+                self.run_gameplay_command(&command, &caused_by).await?;
+            }
+        }
+
+        let mut settings = self.gameplay_settings().await;
+        // commands sent to the console may not have run yet
+        if let Some(difficulty) = patch.difficulty {
+            settings.difficulty = difficulty;
+        }
+        for (name, value) in patch.gamerules {
+            settings.gamerules.insert(name, value);
+        }
+        Ok((settings, running && patch.hardcore.is_some()))
+    }
+}
+
+#[test]
+fn test_validate_gamerule() {
+    assert!(validate_gamerule("keepInventory", GameruleValue::Bool(true)).is_ok());
+    assert!(validate_gamerule("keepInventory", GameruleValue::Int(1)).is_err());
+    assert!(validate_gamerule("randomTickSpeed", GameruleValue::Int(3)).is_ok());
+    assert!(validate_gamerule("randomTickSpeed", GameruleValue::Int(-1)).is_err());
+    assert!(validate_gamerule("playersSleepingPercentage", GameruleValue::Int(101)).is_err());
+    assert!(validate_gamerule("keepinventory", GameruleValue::Bool(true)).is_err());
+    assert!(validate_gamerule("doEverything", GameruleValue::Bool(true)).is_err());
+}
+
+#[test]
+fn test_gameplay_patch_commands() {
+    let patch = GameplayPatch {
+        difficulty: Some(Difficulty::Hard),
+        gamemode: None,
+        hardcore: Some(true),
+        gamerules: BTreeMap::from([
+            ("keepInventory".to_string(), GameruleValue::Bool(true)),
+            ("randomTickSpeed".to_string(), GameruleValue::Int(6)),
+        ]),
+    };
+    assert_eq!(
+        patch.commands(),
+        vec![
+            "difficulty hard",
+            "gamerule keepInventory true",
+            "gamerule randomTickSpeed 6"
+        ]
+    );
+}
+
+#[test]
+fn test_parse_gameplay_responses() {
+    assert_eq!(
+        parse_difficulty_response("The difficulty is Peaceful"),
+        Some(Difficulty::Peaceful)
+    );
+    assert_eq!(
+        parse_gamerule_response(
+            "keepInventory",
+            "Gamerule keepInventory is currently set to: true"
+        ),
+        Some(GameruleValue::Bool(true))
+    );
+    assert_eq!(
+        parse_gamerule_response(
+            "randomTickSpeed",
+            "Gamerule randomTickSpeed is currently set to: 3"
+        ),
+        Some(GameruleValue::Int(3))
+    );
+    // a version without the gamerule
+    assert_eq!(
+        parse_gamerule_response(
+            "doVinesSpread",
+            "Incorrect argument for command\ngamerule doVinesSpread<--[HERE]"
+        ),
+        None
+    );
+}
@@ -0,0 +1,318 @@
+use std::{fmt, process::Stdio, time::Duration};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event},
+    macro_executor::DefaultWorkerOptionGenerator,
+    traits::t_macro::ExitStatus,
+    types::InstanceUuid,
+    util::dont_spawn_terminal,
+};
+
+use super::{r#macro::resolve_macro_invocation, run_as::apply_run_as, MinecraftInstance};
+
+const MAX_HOOK_TIMEOUT_SECS: u64 = 60 * 60;
+
+fn default_hook_timeout_secs() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookStage {
+    PreStart,
+    PostStop,
+}
+
+impl fmt::Display for HookStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookStage::PreStart => write!(f, "pre-start"),
+            HookStage::PostStop => write!(f, "post-stop"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[ts(export)]
+pub enum HookAction {
+    /// Run with `sh -c` (`cmd /C` on Windows) in the instance directory, only allowed
+    /// when shell hooks are enabled in the global settings
+    Shell { command: String },
+    /// A macro of the instance
+    Macro {
+        name: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct Hook {
+    pub action: HookAction,
+    /// Only for pre-start hooks, don't start the server if the hook fails
+    #[serde(default)]
+    pub abort_on_failure: bool,
+    #[serde(default = "default_hook_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// Commands run around the server process, none run unless `enabled` is set
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct LifecycleHooks {
+    #[serde(default)]
+    pub enabled: bool,
+    pub pre_start: Option<Hook>,
+    pub post_stop: Option<Hook>,
+}
+
+impl LifecycleHooks {
+    pub fn get(&self, stage: HookStage) -> Option<&Hook> {
+        match stage {
+            HookStage::PreStart => self.pre_start.as_ref(),
+            HookStage::PostStop => self.post_stop.as_ref(),
+        }
+    }
+
+    pub fn has_shell_hook(&self) -> bool {
+        [&self.pre_start, &self.post_stop]
+            .into_iter()
+            .flatten()
+            .any(|hook| matches!(hook.action, HookAction::Shell { .. }))
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        for hook in [&self.pre_start, &self.post_stop].into_iter().flatten() {
+            let reason = match &hook.action {
+                HookAction::Shell { command } if command.trim().is_empty() => {
+                    Some("shell command cannot be empty")
+                }
+                HookAction::Macro { name, .. }
+                    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") =>
+                {
+                    Some("invalid macro name")
+                }
+                _ if !(1..=MAX_HOOK_TIMEOUT_SECS).contains(&hook.timeout_secs) => {
+                    Some("timeout must be between 1 second and an hour")
+                }
+                _ => None,
+            };
+            if let Some(reason) = reason {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid hook: {}", reason),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Forwards each line of a hook's output as a system message of the instance
+async fn forward_output(
+    output: impl AsyncRead + Unpin,
+    stage: HookStage,
+    event_broadcaster: EventBroadcaster,
+    uuid: InstanceUuid,
+    name: String,
+) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        event_broadcaster.send(Event::new_system_message(
+            uuid.clone(),
+            name.clone(),
+            format!("[{stage} hook] {line}"),
+        ));
+    }
+}
+
+impl MinecraftInstance {
+    async fn run_shell_hook(
+        &self,
+        stage: HookStage,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        if !self.macro_executor.shell_hooks_allowed() {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Shell hooks are disabled in the global settings"),
+            });
+        }
+        let (name, env, run_as) = {
+            let config = self.config.lock().await;
+            (
+                config.name.clone(),
+                config.env.resolve(self.macro_executor.variables())?,
+                config.run_as.clone(),
+            )
+        };
+        let command = self.macro_executor.variables().substitute(command)?;
+        let mut shell = if cfg!(windows) {
+            let mut shell = tokio::process::Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = tokio::process::Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        // hooks get no more access than the server itself
+        if let Some(user) = &run_as {
+            apply_run_as(&mut shell, user, &self.path_to_instance)?;
+        }
+        let mut child = dont_spawn_terminal(
+            shell
+                .arg(&command)
+                .current_dir(&self.path_to_instance)
+                .envs(env)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true),
+        )
+        .spawn()
+        .context("Failed to spawn hook")?;
+        let mut forwarders = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            forwarders.push(tokio::spawn(forward_output(
+                stdout,
+                stage,
+                self.event_broadcaster.clone(),
+                self.uuid.clone(),
+                name.clone(),
+            )));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forwarders.push(tokio::spawn(forward_output(
+                stderr,
+                stage,
+                self.event_broadcaster.clone(),
+                self.uuid.clone(),
+                name,
+            )));
+        }
+        let status = match tokio::time::timeout(timeout, child.wait()).await {
+            Ok(status) => status.context("Failed to wait for hook")?,
+            Err(_) => {
+                let _ = child.kill().await;
+                return Err(eyre!("Hook timed out after {} seconds", timeout.as_secs()).into());
+            }
+        };
+        for forwarder in forwarders {
+            let _ = forwarder.await;
+        }
+        if !status.success() {
+            return Err(eyre!("Hook exited with {}", status).into());
+        }
+        Ok(())
+    }
+
+    async fn run_macro_hook(
+        &self,
+        name: &str,
+        args: &[String],
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        let path = resolve_macro_invocation(&self.path_to_macros, name).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Macro {} not found", name),
+        })?;
+        let spawn_result = self
+            .macro_executor
+            .spawn(
+                path,
+                args.to_vec(),
+                CausedBy::System,
+                Box::new(DefaultWorkerOptionGenerator),
+                None,
+                None,
+                Some(self.uuid.clone()),
+            )
+            .await?;
+        match tokio::time::timeout(timeout, spawn_result.exit_future).await {
+            Ok(Ok(ExitStatus::Success { .. })) => Ok(()),
+            Ok(Ok(ExitStatus::Killed { .. })) => Err(eyre!("Hook macro was killed").into()),
+            Ok(Ok(ExitStatus::Error { error_msg, .. })) => {
+                Err(eyre!("Hook macro failed: {}", error_msg).into())
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                let _ = self.macro_executor.abort_macro(spawn_result.macro_pid);
+                Err(eyre!("Hook timed out after {} seconds", timeout.as_secs()).into())
+            }
+        }
+    }
+
+    /// Runs the hook configured for `stage`, if hooks are enabled for the instance
+    ///
+    /// Failures are reported as a system message, and only returned when the hook
+    /// is set to abort the start
+    pub(super) async fn run_hook(&self, stage: HookStage) -> Result<(), Error> {
+        let (hooks, name) = {
+            let config = self.config.lock().await;
+            (config.hooks.clone(), config.name.clone())
+        };
+        let hook = match hooks.get(stage) {
+            Some(hook) if hooks.enabled => hook,
+            _ => return Ok(()),
+        };
+        let timeout = Duration::from_secs(hook.timeout_secs);
+        let result = match &hook.action {
+            HookAction::Shell { command } => self.run_shell_hook(stage, command, timeout).await,
+            HookAction::Macro { name, args } => self.run_macro_hook(name, args, timeout).await,
+        };
+        if let Err(e) = &result {
+            warn!("[{}] {} hook failed: {}", name, stage, e);
+            self.event_broadcaster.send(Event::new_system_message(
+                self.uuid.clone(),
+                name,
+                format!("{stage} hook failed: {e}"),
+            ));
+        }
+        match result {
+            Err(e) if stage == HookStage::PreStart && hook.abort_on_failure => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_validate_hooks() {
+    let hook = |action| Hook {
+        action,
+        abort_on_failure: true,
+        timeout_secs: default_hook_timeout_secs(),
+    };
+    let hooks = LifecycleHooks {
+        enabled: true,
+        pre_start: Some(hook(HookAction::Shell {
+            command: "git pull".to_string(),
+        })),
+        post_stop: Some(hook(HookAction::Macro {
+            name: "upload_backup".to_string(),
+            args: vec![],
+        })),
+    };
+    assert!(hooks.validate().is_ok());
+    assert!(hooks.has_shell_hook());
+
+    let hooks = LifecycleHooks {
+        pre_start: Some(hook(HookAction::Macro {
+            name: "../escape".to_string(),
+            args: vec![],
+        })),
+        ..Default::default()
+    };
+    assert!(hooks.validate().is_err());
+    assert!(!hooks.has_shell_hook());
+}
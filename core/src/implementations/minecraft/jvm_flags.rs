@@ -0,0 +1,133 @@
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+/// Aikar's G1GC tuning, see https://docs.papermc.io/paper/aikars-flags
+const AIKAR_FLAGS: &[&str] = &[
+    "-XX:+UseG1GC",
+    "-XX:+ParallelRefProcEnabled",
+    "-XX:MaxGCPauseMillis=200",
+    "-XX:+UnlockExperimentalVMOptions",
+    "-XX:+DisableExplicitGC",
+    "-XX:+AlwaysPreTouch",
+    "-XX:G1NewSizePercent=30",
+    "-XX:G1MaxNewSizePercent=40",
+    "-XX:G1HeapRegionSize=8M",
+    "-XX:G1ReservePercent=20",
+    "-XX:G1HeapWastePercent=5",
+    "-XX:G1MixedGCCountTarget=4",
+    "-XX:InitiatingHeapOccupancyPercent=15",
+    "-XX:G1MixedGCLiveThresholdPercent=90",
+    "-XX:G1RSetUpdatingPauseTimePercent=5",
+    "-XX:SurvivorRatio=32",
+    "-XX:+PerfDisableSharedMem",
+    "-XX:MaxTenuringThreshold=1",
+    "-Dusing.aikars.flags=https://mcflags.emc.gs",
+    "-Daikars.new.flags=true",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum JvmFlagsPreset {
+    /// Only the memory flags, leave everything else to the JVM
+    #[default]
+    Default,
+    Aikar,
+}
+
+impl JvmFlagsPreset {
+    pub fn flags(&self) -> &'static [&'static str] {
+        match self {
+            JvmFlagsPreset::Default => &[],
+            JvmFlagsPreset::Aikar => AIKAR_FLAGS,
+        }
+    }
+}
+
+/// JVM arguments passed between `java` and `-jar`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct JvmFlagsProfile {
+    #[serde(default)]
+    pub preset: JvmFlagsPreset,
+    /// Applied after the preset, so they can override it
+    #[serde(default)]
+    pub extra_flags: Vec<String>,
+}
+
+impl JvmFlagsProfile {
+    /// Rejects flags that would change what gets launched or fight with the memory settings
+    pub fn validate(&self) -> Result<(), Error> {
+        for flag in &self.extra_flags {
+            let reason = if !flag.starts_with('-') {
+                Some("JVM flags must start with '-'")
+            } else if flag == "-jar" {
+                Some("the server jar is chosen by lodestone")
+            } else if matches!(flag.as_str(), "-cp" | "-classpath" | "--class-path")
+                || flag.starts_with("--class-path=")
+            {
+                Some("the classpath cannot be overridden")
+            } else if flag.starts_with("-Xmx") || flag.starts_with("-Xms") {
+                Some("use the minimum and maximum RAM settings instead")
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid JVM flag \"{}\": {}", flag, reason),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flags(&self) -> Vec<String> {
+        self.preset
+            .flags()
+            .iter()
+            .map(|flag| flag.to_string())
+            .chain(
+                self.extra_flags
+                    .iter()
+                    .filter(|flag| !flag.is_empty())
+                    .cloned(),
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(extra_flags: &[&str]) -> JvmFlagsProfile {
+        JvmFlagsProfile {
+            preset: JvmFlagsPreset::Aikar,
+            extra_flags: extra_flags.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate_jvm_flags() {
+        assert!(profile(&["-XX:+UseZGC", "-Dfile.encoding=UTF-8"])
+            .validate()
+            .is_ok());
+        for flag in ["-jar", "-cp", "--class-path=evil.jar", "-Xmx16G", "nogui"] {
+            assert!(profile(&[flag]).validate().is_err(), "{flag}");
+        }
+    }
+
+    #[test]
+    fn test_jvm_flags_order() {
+        let flags = profile(&["-XX:MaxGCPauseMillis=100"]).flags();
+        assert_eq!(flags.first().map(String::as_str), Some("-XX:+UseG1GC"));
+        assert_eq!(
+            flags.last().map(String::as_str),
+            Some("-XX:MaxGCPauseMillis=100")
+        );
+    }
+}
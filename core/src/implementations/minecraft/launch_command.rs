@@ -0,0 +1,258 @@
+//! Assembles the command line the server is launched with, shared by the launch itself and
+//! the API showing it
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::error::Error;
+use crate::java_runtimes::java_binary;
+use crate::util::list_dir;
+
+use super::{Flavour, ForgeBuildVersion, MinecraftInstance, RestoreConfig};
+
+/// Shown in place of sensitive environment variables
+pub const MASKED_VALUE: &str = "********";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct LaunchCommand {
+    /// The java binary
+    pub program: String,
+    /// JVM flags, then the jar and its arguments
+    pub args: Vec<String>,
+    /// Environment variables set on top of the environment of the core
+    pub env: BTreeMap<String, String>,
+    pub working_dir: String,
+    /// The user the server runs as, if not the user running lodestone
+    pub run_as: Option<String>,
+}
+
+/// What the server is started from
+pub(super) enum ServerJar {
+    /// Passed as `-jar <path>`
+    Jar(PathBuf),
+    /// The file Forge 1.17+ keeps its launch arguments in, passed as `@<path>`
+    ArgsFile(PathBuf),
+}
+
+impl ServerJar {
+    pub fn path(&self) -> &Path {
+        match self {
+            ServerJar::Jar(path) | ServerJar::ArgsFile(path) => path,
+        }
+    }
+
+    /// Built as `OsString`s so paths that aren't valid UTF-8 reach java unchanged
+    pub fn args(&self) -> Vec<OsString> {
+        match self {
+            ServerJar::Jar(path) => vec![OsString::from("-jar"), path.clone().into_os_string()],
+            ServerJar::ArgsFile(path) => {
+                let mut arg = OsString::from("@");
+                arg.push(path);
+                vec![arg]
+            }
+        }
+    }
+}
+
+impl MinecraftInstance {
+    /// The java binary, the pinned one unless it no longer exists
+    pub(super) fn java_path(&self, config: &RestoreConfig) -> PathBuf {
+        let default_jre = java_binary(
+            &self
+                .path_to_runtimes
+                .join("java")
+                .join(format!("jre{}", config.jre_major_version)),
+        );
+        match &config.java_cmd {
+            // a bare command like `java` is looked up in PATH, only check actual paths
+            Some(jre)
+                if jre.contains(std::path::MAIN_SEPARATOR) && !PathBuf::from(jre).exists() =>
+            {
+                warn!(
+                    "[{}] Pinned java {} does not exist, falling back to {}",
+                    config.name,
+                    jre,
+                    default_jre.display()
+                );
+                default_jre
+            }
+            Some(jre) => PathBuf::from(jre),
+            None => default_jre,
+        }
+    }
+
+    /// The server jar of the flavour
+    pub(super) async fn server_jar(&self, config: &RestoreConfig) -> Result<ServerJar, Error> {
+        let jar = match &config.flavour {
+            Flavour::Forge { build_version } => {
+                let ForgeBuildVersion(build_version) = build_version
+                    .as_ref()
+                    .ok_or_else(|| eyre!("Forge version not found"))?;
+                let version_parts: Vec<&str> = config.version.split('.').collect();
+                let major_version: i32 = version_parts[1]
+                    .parse()
+                    .context("Unable to parse major Minecraft version for Forge")?;
+
+                if 17 <= major_version {
+                    let forge_args = match std::env::consts::OS {
+                        "windows" => "win_args.txt",
+                        _ => "unix_args.txt",
+                    };
+                    let forge_args = self
+                        .path_to_instance
+                        .join("libraries")
+                        .join("net")
+                        .join("minecraftforge")
+                        .join("forge")
+                        .join(build_version.as_str())
+                        .join(forge_args);
+                    return Ok(ServerJar::ArgsFile(forge_args));
+                } else if (7..=16).contains(&major_version) {
+                    let files = list_dir(&self.path_to_instance, Some(false))
+                        .await
+                        .context("Failed to find forge.jar")?;
+                    let forge_jar_name = files
+                        .iter()
+                        .find(|p| {
+                            p.extension().unwrap_or_default() == "jar"
+                                && p.file_name()
+                                    .unwrap_or_default()
+                                    .to_str()
+                                    .unwrap_or_default()
+                                    .starts_with(format!("forge-{}-", config.version,).as_str())
+                        })
+                        .ok_or_else(|| eyre!("Failed to find forge.jar"))?;
+                    self.path_to_instance.join(forge_jar_name)
+                } else {
+                    // 1.5 doesn't work due to JRE issues
+                    // 1.4 doesn't work since forge doesn't provide an installer
+                    let files = list_dir(&self.path_to_instance, Some(false))
+                        .await
+                        .context("Failed to find minecraftforge.jar")?;
+                    let server_jar_name = files
+                        .iter()
+                        .find(|p| {
+                            p.extension().unwrap_or_default() == "jar"
+                                && p.file_name()
+                                    .unwrap_or_default()
+                                    .to_str()
+                                    .unwrap_or_default()
+                                    .starts_with("minecraftforge")
+                        })
+                        .ok_or_else(|| eyre!("Failed to find minecraftforge.jar"))?;
+                    self.path_to_instance.join(server_jar_name)
+                }
+            }
+            _ => self.path_to_instance.join("server.jar"),
+        };
+        Ok(ServerJar::Jar(jar))
+    }
+
+    /// The arguments java is started with for `config`: JVM flags, then the jar and its
+    /// arguments. Placeholders are substituted if `resolve`, otherwise returned as written
+    pub(super) async fn launch_args(
+        &self,
+        config: &RestoreConfig,
+        resolve: bool,
+    ) -> Result<Vec<OsString>, Error> {
+        let variables = self.macro_executor.variables();
+        let mut args: Vec<OsString> = vec![
+            format!("-Xmx{}M", config.max_ram).into(),
+            format!("-Xms{}M", config.min_ram).into(),
+        ];
+        args.extend(config.jvm_flags.flags().into_iter().map(OsString::from));
+        for arg in config.cmd_args.iter().filter(|s| !s.is_empty()) {
+            args.push(if resolve {
+                variables.substitute(arg)?.into()
+            } else {
+                arg.into()
+            });
+        }
+        args.extend(self.server_jar(config).await?.args());
+        args.push("nogui".into());
+        Ok(args)
+    }
+
+    /// The environment variables set for the server, substituted if `resolve` and with
+    /// sensitive ones replaced by [`MASKED_VALUE`] if `mask`
+    pub(super) fn launch_env(
+        &self,
+        config: &RestoreConfig,
+        resolve: bool,
+        mask: bool,
+    ) -> Result<BTreeMap<String, String>, Error> {
+        let variables = self.macro_executor.variables();
+        let mut env = BTreeMap::new();
+        for (name, var) in &config.env.vars {
+            let value = match &var.value {
+                Some(value) => value,
+                None => continue,
+            };
+            let value = if mask && var.sensitive {
+                MASKED_VALUE.to_string()
+            } else if resolve {
+                variables.substitute(value)?
+            } else {
+                value.clone()
+            };
+            env.insert(name.clone(), value);
+        }
+        Ok(env)
+    }
+
+    /// The command the server is started with for `config`, for display
+    ///
+    /// Placeholders in arguments and environment variables are substituted if `resolve`,
+    /// otherwise they are returned as written. With `mask`, sensitive environment variables
+    /// are replaced by [`MASKED_VALUE`]
+    pub(super) async fn assemble_launch_command(
+        &self,
+        config: &RestoreConfig,
+        resolve: bool,
+        mask: bool,
+    ) -> Result<LaunchCommand, Error> {
+        Ok(LaunchCommand {
+            program: self.java_path(config).display().to_string(),
+            args: self
+                .launch_args(config, resolve)
+                .await?
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            env: self.launch_env(config, resolve, mask)?,
+            working_dir: self.path_to_instance.display().to_string(),
+            run_as: config.run_as.clone(),
+        })
+    }
+
+    /// The command the next start would use, with sensitive environment variables masked
+    pub async fn launch_command(&self, resolve: bool) -> Result<LaunchCommand, Error> {
+        let config = self.config.lock().await.clone();
+        self.assemble_launch_command(&config, resolve, true).await
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_server_jar_args_keep_non_utf8_paths() {
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    let path = PathBuf::from(std::ffi::OsStr::from_bytes(b"/srv/mc/\xffargs.txt"));
+    let args = ServerJar::ArgsFile(path.clone()).args();
+    assert_eq!(args.len(), 1);
+    assert_eq!(
+        args[0].clone().into_vec(),
+        b"@/srv/mc/\xffargs.txt".to_vec()
+    );
+    assert_eq!(
+        ServerJar::Jar(path.clone()).args(),
+        vec![OsString::from("-jar"), path.into_os_string()]
+    );
+}
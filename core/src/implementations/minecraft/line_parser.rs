@@ -39,7 +39,8 @@ pub fn parse_player_msg(msg: &str) -> Option<PlayerMessage> {
 
 pub fn parse_player_joined(system_msg: &str) -> Option<String> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"(.+) joined the game").unwrap();
+        static ref RE: Regex =
+            Regex::new(r"^(\w+)(?: \(formerly known as \w+\))? joined the game$").unwrap();
     }
     if RE.is_match(system_msg).unwrap() {
         if let Some(cap) = RE.captures(system_msg).ok()? {
@@ -54,7 +55,7 @@ pub fn parse_player_joined(system_msg: &str) -> Option<String> {
 
 pub fn parse_player_left(system_msg: &str) -> Option<String> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"(.+) left the game").unwrap();
+        static ref RE: Regex = Regex::new(r"^(\w+) left the game$").unwrap();
     }
     if RE.is_match(system_msg).unwrap() {
         if let Some(cap) = RE.captures(system_msg).ok()? {
@@ -67,9 +68,147 @@ pub fn parse_player_left(system_msg: &str) -> Option<String> {
     }
 }
 
+/// Parses a vanilla death message, returning the name of the player who died.
+///
+/// Death messages have no common marker, so this only recognizes the message if it starts
+/// with one of the known death message phrases.
+/// Callers should check the name against the online players to avoid false positives.
+pub fn parse_player_death(system_msg: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"^(\w+) (?:was (?:slain|shot|killed|blown up|fireballed|pummeled|squashed|impaled|skewered|struck by lightning|squished|poked to death|stung to death|obliterated|frozen to death|pricked to death|burnt to a crisp|doomed to fall|roasted)|fell |drowned|died|blew up|burned to death|hit the ground too hard|went up in flames|went off with a bang|walked into|tried to swim in lava|experienced kinetic energy|froze to death|starved to death|suffocated in a wall|withered away|discovered the floor was lava|didn't want to live|left the confines of this world)"
+        )
+        .unwrap();
+    }
+    RE.captures(system_msg)
+        .ok()?
+        .and_then(|cap| Some(cap.get(1)?.as_str().to_string()))
+}
+
 pub fn parse_server_started(system_msg: &str) -> bool {
     lazy_static! {
         static ref RE: Regex = Regex::new(r#"Done \(.+\)!"#).unwrap();
     }
     RE.is_match(system_msg).unwrap()
 }
+
+/// Seconds the server says it took to start, from `Done (12.345s)! For help, type "help"`
+pub fn parse_startup_duration(system_msg: &str) -> Option<f64> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r#"Done \((\d+(?:\.\d+)?)s\)!"#).unwrap();
+    }
+    RE.captures(system_msg).ok()??.get(1)?.as_str().parse().ok()
+}
+
+/// Parses the command names out of the response of `/help`
+///
+/// Over RCON vanilla joins the usage lines without a separator, e.g. `/advancement ...>/attribute ...`,
+/// so every `/` followed by a name is taken as the start of a command
+pub fn parse_help_commands(response: &str) -> Vec<String> {
+    let mut commands: Vec<String> = response
+        .split('/')
+        .skip(1)
+        .map(|part| {
+            part.chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | ':'))
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty())
+        .collect();
+    commands.sort();
+    commands.dedup();
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VANILLA_JOIN: &str = "[18:42:03] [Server thread/INFO]: Steve joined the game";
+    const VANILLA_LEFT: &str = "[18:45:11] [Server thread/INFO]: Steve left the game";
+    const VANILLA_CHAT: &str = "[18:43:27] [Server thread/INFO]: <Steve> hello world";
+    const VANILLA_DEATH: &str = "[18:44:02] [Server thread/INFO]: Steve was slain by Zombie";
+    const VANILLA_RENAMED_JOIN: &str =
+        "[18:42:03] [Server thread/INFO]: Steve (formerly known as Alex) joined the game";
+    const PAPER_JOIN: &str = "[18:42:03 INFO]: Alex_2 joined the game";
+    const PAPER_LEFT: &str = "[18:45:11 INFO]: Alex_2 left the game";
+    const PAPER_CHAT: &str = "[18:43:27 INFO]: <Alex_2> gg";
+    const PAPER_DEATH: &str = "[18:44:02 INFO]: Alex_2 fell from a high place";
+    const PAPER_LOGIN: &str =
+        "[18:42:03 INFO]: Alex_2[/127.0.0.1:51234] logged in with entity id 123 at ([world]0.5, 64.0, 0.5)";
+
+    #[test]
+    fn test_parse_join_left() {
+        for (line, expected) in [
+            (VANILLA_JOIN, "Steve"),
+            (VANILLA_RENAMED_JOIN, "Steve"),
+            (PAPER_JOIN, "Alex_2"),
+        ] {
+            let system_msg = parse_system_msg(line).unwrap();
+            assert_eq!(parse_player_joined(&system_msg), Some(expected.to_string()));
+            assert_eq!(parse_player_left(&system_msg), None);
+        }
+        for (line, expected) in [(VANILLA_LEFT, "Steve"), (PAPER_LEFT, "Alex_2")] {
+            let system_msg = parse_system_msg(line).unwrap();
+            assert_eq!(parse_player_left(&system_msg), Some(expected.to_string()));
+            assert_eq!(parse_player_joined(&system_msg), None);
+        }
+        let system_msg = parse_system_msg(PAPER_LOGIN).unwrap();
+        assert_eq!(parse_player_joined(&system_msg), None);
+    }
+
+    #[test]
+    fn test_parse_chat() {
+        assert!(parse_system_msg(VANILLA_CHAT).is_none());
+        let msg = parse_player_msg(VANILLA_CHAT).unwrap();
+        assert_eq!(msg.player, "Steve");
+        assert_eq!(msg.message, "hello world");
+
+        assert!(parse_system_msg(PAPER_CHAT).is_none());
+        let msg = parse_player_msg(PAPER_CHAT).unwrap();
+        assert_eq!(msg.player, "Alex_2");
+        assert_eq!(msg.message, "gg");
+    }
+
+    #[test]
+    fn test_parse_death() {
+        let system_msg = parse_system_msg(VANILLA_DEATH).unwrap();
+        assert_eq!(parse_player_death(&system_msg), Some("Steve".to_string()));
+        let system_msg = parse_system_msg(PAPER_DEATH).unwrap();
+        assert_eq!(parse_player_death(&system_msg), Some("Alex_2".to_string()));
+
+        for line in [VANILLA_JOIN, PAPER_LEFT, PAPER_LOGIN] {
+            let system_msg = parse_system_msg(line).unwrap();
+            assert_eq!(parse_player_death(&system_msg), None);
+        }
+    }
+
+    #[test]
+    fn test_parse_help_commands() {
+        assert_eq!(
+            parse_help_commands(
+                "/advancement (grant|revoke)/attribute <target> <attribute>/ban <targets> [<reason>]/say <message>/ban-ip <target>"
+            ),
+            vec!["advancement", "attribute", "ban", "ban-ip", "say"]
+        );
+        assert!(parse_help_commands("Unknown command").is_empty());
+    }
+
+    #[test]
+    fn test_parse_startup_duration() {
+        assert_eq!(
+            parse_startup_duration(
+                r#"[18:41:57] [Server thread/INFO]: Done (12.345s)! For help, type "help""#
+            ),
+            Some(12.345)
+        );
+        assert_eq!(
+            parse_startup_duration("[18:41:57 INFO]: Done (3s)! For help, type \"help\""),
+            Some(3.0)
+        );
+        assert_eq!(
+            parse_startup_duration("[18:41:57 INFO]: Done preparing level"),
+            None
+        );
+    }
+}
@@ -1,41 +1,59 @@
 pub mod configurable;
+pub mod crash_report;
+pub mod env_vars;
 pub mod fabric;
 mod forge;
-mod line_parser;
+pub mod gameplay;
+pub mod hooks;
+pub mod jvm_flags;
+pub mod launch_command;
+pub mod line_parser;
 pub mod r#macro;
+pub mod motd;
 mod paper;
+pub mod ping;
 pub mod player;
 mod players_manager;
+pub mod run_as;
 pub mod server;
+pub mod setup_plan;
+pub mod signal;
+mod startup_checks;
 pub mod util;
 mod vanilla;
 pub mod versions;
+pub mod world;
 
 use color_eyre::eyre::{eyre, Context, ContextCompat};
 use enum_kinds::EnumKind;
 use indexmap::IndexMap;
 
 use std::collections::HashMap;
+use std::path::Path;
 use std::process::Stdio;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use sysinfo::SystemExt;
 use tokio::io::AsyncWriteExt;
 use tokio::process::{Child, Command};
 
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 
 use ::serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
 
-use tracing::error;
+use tracing::{error, warn};
 
 use tokio;
 use ts_rs::TS;
 
-use crate::error::Error;
+use crate::ansi::AnsiHandling;
+use crate::error::{Error, ErrorKind};
 use crate::event_broadcaster::EventBroadcaster;
 use crate::events::{Event, ProgressionEventID};
+use crate::java_runtimes::{detect_java_runtimes, java_binary, JavaRuntime};
 use crate::macro_executor::{MacroExecutor, MacroPID};
 use crate::prelude::path_to_binaries;
 use crate::traits::t_configurable::PathBuf;
@@ -49,19 +67,32 @@ use crate::traits::t_macro::TaskEntry;
 use crate::traits::t_server::State;
 use crate::traits::TInstance;
 use crate::types::{DotLodestoneConfig, InstanceUuid};
-use crate::util::{
-    dont_spawn_terminal, download_file, format_byte, format_byte_download, unzip_file_async,
-    UnzipOption,
-};
+use crate::util::{dont_spawn_terminal, download_file, format_byte, format_byte_download};
 
 use self::configurable::{CmdArgSetting, ServerPropertySetting};
+use self::env_vars::EnvVars;
 use self::fabric::get_fabric_minecraft_versions;
 use self::forge::get_forge_minecraft_versions;
+use self::hooks::LifecycleHooks;
+use self::jvm_flags::JvmFlagsProfile;
+use self::line_parser::parse_help_commands;
 use self::paper::get_paper_minecraft_versions;
 use self::players_manager::PlayersManager;
-use self::util::{get_jre_url, get_server_jar_url, read_properties_from_path};
+use self::run_as::validate_run_as;
+use self::util::{get_jre_url, get_server_jar_url, install_jre, read_properties_from_path};
 use self::vanilla::get_vanilla_minecraft_versions;
 
+/// Lodestone's config of the instance, in the root of the instance directory. It holds the
+/// values of sensitive environment variables, so it is never served as a file
+pub const CONFIG_FILE_NAME: &str = ".lodestone_minecraft_config.json";
+
+/// How long connecting to RCON or running a single RCON command may take
+/// before the connection is considered dead
+const RCON_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait after a failed RCON connection before trying again
+const RCON_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
 #[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
 #[ts(export)]
 pub struct FabricLoaderVersion(String);
@@ -137,6 +168,28 @@ impl ToString for FlavourKind {
     }
 }
 
+/// Where the JRE an instance runs on comes from
+pub(crate) enum JreSource {
+    /// Already downloaded by lodestone
+    Managed(PathBuf),
+    Installed(JavaRuntime),
+    Download,
+}
+
+/// Picks the JRE for a new instance, preferring one lodestone manages
+pub(crate) async fn choose_jre(path_to_java: &Path, jre_major_version: u64) -> JreSource {
+    let managed_jre = java_binary(&path_to_java.join(format!("jre{}", jre_major_version)));
+    if managed_jre.exists() {
+        return JreSource::Managed(managed_jre);
+    }
+    detect_java_runtimes()
+        .await
+        .into_iter()
+        // newer java versions tend to break old minecraft versions, only an exact match is safe
+        .find(|runtime| runtime.major_version == jre_major_version)
+        .map_or(JreSource::Download, JreSource::Installed)
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SetupConfig {
     pub name: String,
@@ -167,6 +220,17 @@ pub struct RestoreConfig {
     pub backup_period: Option<u32>,
     pub jre_major_version: u64,
     pub has_started: bool,
+    #[serde(default)]
+    pub jvm_flags: JvmFlagsProfile,
+    #[serde(default)]
+    pub env: EnvVars,
+    #[serde(default)]
+    pub hooks: LifecycleHooks,
+    /// Unprivileged user the server process runs as, on Unix
+    #[serde(default)]
+    pub run_as: Option<String>,
+    #[serde(default)]
+    pub ansi_handling: AnsiHandling,
 }
 #[allow(dead_code)]
 #[derive(Clone)]
@@ -196,6 +260,7 @@ pub struct MinecraftInstance {
     configurable_manifest: Arc<Mutex<ConfigurableManifest>>,
     macro_executor: MacroExecutor,
     rcon_conn: Arc<Mutex<Option<rcon::Connection<tokio::net::TcpStream>>>>,
+    rcon_retry_after: Arc<Mutex<Option<Instant>>>,
     macro_name_to_last_run: Arc<Mutex<HashMap<String, i64>>>,
     pid_to_task_entry: Arc<Mutex<IndexMap<MacroPID, TaskEntry>>>,
 }
@@ -433,8 +498,9 @@ impl MinecraftInstance {
         progression_event_id: &ProgressionEventID,
         event_broadcaster: EventBroadcaster,
         macro_executor: MacroExecutor,
+        cancel: &CancellationToken,
     ) -> Result<MinecraftInstance, Error> {
-        let path_to_config = path_to_instance.join(".lodestone_minecraft_config.json");
+        let path_to_config = path_to_instance.join(CONFIG_FILE_NAME);
         let path_to_eula = path_to_instance.join("eula.txt");
         let path_to_macros = path_to_instance.join("macros");
         let path_to_resources = path_to_instance.join("resources");
@@ -463,74 +529,53 @@ impl MinecraftInstance {
                 e
             })?;
 
-        // Step 2: Download JRE
-        let (url, jre_major_version) = get_jre_url(config.version.as_str())
+        // Step 2: Find or download a JRE
+        let (_, jre_major_version) = get_jre_url(config.version.as_str())
             .await
             .context("Could not get JRE URL")?;
-        if !path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", jre_major_version))
-            .exists()
-        {
-            let downloaded = download_file(
-                &url,
-                &path_to_runtimes.join("java"),
-                None,
-                {
-                    let event_broadcaster = event_broadcaster.clone();
-                    &move |dl| {
-                        if let Some(total) = dl.total {
-                            event_broadcaster.send(Event::new_progression_event_update(
-                                progression_event_id,
-                                format!(
-                                    "2/4: Downloading JRE {}",
-                                    format_byte_download(dl.downloaded, total)
-                                ),
-                                (dl.step as f64 / total as f64) * 4.0,
-                            ));
+        let path_to_java = path_to_runtimes.join("java");
+        let jre = match choose_jre(&path_to_java, jre_major_version).await {
+            JreSource::Managed(managed_jre) => {
+                event_broadcaster.send(Event::new_progression_event_update(
+                    progression_event_id,
+                    "2/4: JRE already downloaded",
+                    4.0,
+                ));
+                managed_jre
+            }
+            JreSource::Installed(runtime) => {
+                event_broadcaster.send(Event::new_progression_event_update(
+                    progression_event_id,
+                    format!("2/4: Using installed Java {}", runtime.version),
+                    4.0,
+                ));
+                runtime.path
+            }
+            JreSource::Download => {
+                let jre_dir = install_jre(
+                    &path_to_java,
+                    jre_major_version,
+                    {
+                        let event_broadcaster = event_broadcaster.clone();
+                        &move |dl| {
+                            if let Some(total) = dl.total {
+                                event_broadcaster.send(Event::new_progression_event_update(
+                                    progression_event_id,
+                                    format!(
+                                        "2/4: Downloading JRE {}",
+                                        format_byte_download(dl.downloaded, total)
+                                    ),
+                                    (dl.step as f64 / total as f64) * 4.0,
+                                ));
+                            }
                         }
-                    }
-                },
-                true,
-            )
-            .await?;
-
-            let unzipped_content = unzip_file_async(
-                &downloaded,
-                UnzipOption::ToDir(path_to_runtimes.join("java")),
-            )
-            .await?;
-            if unzipped_content.len() != 1 {
-                return Err(eyre!(
-                    "Expected only one file in the JRE archive, got {}",
-                    unzipped_content.len()
+                    },
+                    Some(cancel),
                 )
-                .into());
+                .await?;
+                java_binary(&jre_dir)
             }
-
-            tokio::fs::remove_file(&downloaded).await.context(format!(
-                "Could not remove downloaded JRE file {}",
-                downloaded.display()
-            ))?;
-
-            tokio::fs::rename(
-                unzipped_content.iter().last().unwrap(),
-                path_to_runtimes
-                    .join("java")
-                    .join(format!("jre{}", jre_major_version)),
-            )
-            .await
-            .context(format!(
-                "Could not rename JRE directory {}",
-                unzipped_content.iter().last().unwrap().display()
-            ))?;
-        } else {
-            event_broadcaster.send(Event::new_progression_event_update(
-                progression_event_id,
-                "2/4: JRE already downloaded",
-                4.0,
-            ));
-        }
+        };
 
         // Step 3: Download server.jar
         let flavour_name = config.flavour.to_string();
@@ -583,17 +628,9 @@ impl MinecraftInstance {
                 }
             },
             true,
+            Some(cancel),
         )
         .await?;
-        let jre = path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", jre_major_version))
-            .join(if std::env::consts::OS == "macos" {
-                "Contents/Home/bin"
-            } else {
-                "bin"
-            })
-            .join("java");
         // Step 3 (part 2): Forge Setup
         if let Flavour::Forge { .. } = flavour.clone() {
             event_broadcaster.send(Event::new_progression_event_update(
@@ -653,6 +690,11 @@ impl MinecraftInstance {
             jre_major_version,
             has_started: false,
             java_cmd: Some(jre.to_string_lossy().to_string()),
+            jvm_flags: JvmFlagsProfile::default(),
+            env: EnvVars::default(),
+            hooks: LifecycleHooks::default(),
+            run_as: None,
+            ansi_handling: AnsiHandling::default(),
         };
         // create config file
         tokio::fs::write(
@@ -681,7 +723,7 @@ impl MinecraftInstance {
         event_broadcaster: EventBroadcaster,
         macro_executor: MacroExecutor,
     ) -> Result<MinecraftInstance, Error> {
-        let path_to_config = path_to_instance.join(".lodestone_minecraft_config.json");
+        let path_to_config = path_to_instance.join(CONFIG_FILE_NAME);
         let restore_config: RestoreConfig =
             serde_json::from_reader(std::fs::File::open(&path_to_config).context(format!(
                 "Failed to open config file at {}",
@@ -740,6 +782,7 @@ impl MinecraftInstance {
             system: Arc::new(Mutex::new(sysinfo::System::new_all())),
             stdin: Arc::new(Mutex::new(None)),
             rcon_conn: Arc::new(Mutex::new(None)),
+            rcon_retry_after: Arc::new(Mutex::new(None)),
             configurable_manifest,
             macro_name_to_last_run: Arc::new(Mutex::new(HashMap::new())),
             pid_to_task_entry: Arc::new(Mutex::new(IndexMap::new())),
@@ -751,17 +794,34 @@ impl MinecraftInstance {
         Ok(instance)
     }
 
+    /// The config as it goes into an export, without the values of sensitive environment
+    /// variables
+    pub async fn exported_config(&self) -> Result<String, Error> {
+        let mut config = self.config.lock().await.clone();
+        config.env = config.env.masked();
+        Ok(to_string_pretty(&config)
+            .context("Failed to serialize config to string, this is a bug, please report it")?)
+    }
+
+    /// Writes to a temporary file first, so a failed write never leaves a truncated config behind
     async fn write_config_to_file(&self) -> Result<(), Error> {
+        let path_to_tmp = self.path_to_config.with_extension("json.tmp");
         tokio::fs::write(
-            &self.path_to_config,
+            &path_to_tmp,
             to_string_pretty(&*self.config.lock().await)
                 .context("Failed to serialize config to string, this is a bug, please report it")?,
         )
         .await
         .context(format!(
             "Failed to write config to file at {}",
-            &self.path_to_config.display()
+            &path_to_tmp.display()
         ))?;
+        tokio::fs::rename(&path_to_tmp, &self.path_to_config)
+            .await
+            .context(format!(
+                "Failed to write config to file at {}",
+                &self.path_to_config.display()
+            ))?;
         Ok(())
     }
 
@@ -886,23 +946,155 @@ impl MinecraftInstance {
         );
     }
 
+    /// Names of the commands the server knows, from `/help` over RCON
+    pub async fn known_commands(&self) -> Result<Vec<String>, Error> {
+        Ok(parse_help_commands(&self.send_rcon("help").await?))
+    }
+
+    pub async fn jvm_flags(&self) -> JvmFlagsProfile {
+        self.config.lock().await.jvm_flags.clone()
+    }
+
+    /// Takes effect on the next start
+    pub async fn set_jvm_flags(&self, jvm_flags: JvmFlagsProfile) -> Result<(), Error> {
+        jvm_flags.validate()?;
+        self.config.lock().await.jvm_flags = jvm_flags;
+        self.write_config_to_file().await
+    }
+
+    /// Environment variables with the values of sensitive ones masked
+    pub async fn env_vars(&self) -> EnvVars {
+        self.config.lock().await.env.masked()
+    }
+
+    /// Takes effect on the next start, masked values keep the current value
+    pub async fn set_env_vars(&self, env: EnvVars) -> Result<(), Error> {
+        let mut config = self.config.lock().await;
+        config.env = config.env.updated(env)?;
+        drop(config);
+        self.write_config_to_file().await
+    }
+
+    pub async fn hooks(&self) -> LifecycleHooks {
+        self.config.lock().await.hooks.clone()
+    }
+
+    pub async fn set_hooks(&self, hooks: LifecycleHooks) -> Result<(), Error> {
+        hooks.validate()?;
+        self.config.lock().await.hooks = hooks;
+        self.write_config_to_file().await
+    }
+
+    pub async fn run_as(&self) -> Option<String> {
+        self.config.lock().await.run_as.clone()
+    }
+
+    /// Takes effect on the next start
+    pub async fn set_run_as(&self, run_as: Option<String>) -> Result<(), Error> {
+        if let Some(user) = &run_as {
+            validate_run_as(user, &self.path_to_instance)?;
+        }
+        self.config.lock().await.run_as = run_as;
+        self.write_config_to_file().await
+    }
+
+    pub async fn ansi_handling(&self) -> AnsiHandling {
+        self.config.lock().await.ansi_handling
+    }
+
+    /// Takes effect on the next start
+    pub async fn set_ansi_handling(&self, ansi_handling: AnsiHandling) -> Result<(), Error> {
+        self.config.lock().await.ansi_handling = ansi_handling;
+        self.write_config_to_file().await
+    }
+
     pub fn get_rcon(&self) -> Arc<Mutex<Option<rcon::Connection<tokio::net::TcpStream>>>> {
         self.rcon_conn.clone()
     }
 
+    /// RCON password and port from server.properties, if RCON is enabled
+    pub async fn rcon_settings(&self) -> Option<(String, u32)> {
+        let lock = self.configurable_manifest.lock().await;
+        let enabled = lock
+            .get_unique_setting_key("enable-rcon")
+            .and_then(|v| v.get_value().map(|v| v.try_as_boolean().ok()))
+            .flatten()?;
+        let password = lock
+            .get_unique_setting_key("rcon.password")
+            .and_then(|v| v.get_value().map(|v| v.try_as_string().ok()))
+            .flatten()
+            .cloned()?;
+        let port = lock
+            .get_unique_setting_key("rcon.port")
+            .and_then(|v| v.get_value().map(|v| v.try_as_unsigned_integer().ok()))
+            .flatten()?;
+        (enabled && !password.is_empty()).then_some((password, port))
+    }
+
+    /// Opens a new RCON connection, giving up after `RCON_TIMEOUT`
+    pub async fn open_rcon(&self) -> Result<rcon::Connection<tokio::net::TcpStream>, Error> {
+        let (password, port) = self.rcon_settings().await.ok_or_else(|| Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("RCON is not enabled or misconfigured"),
+        })?;
+        let connect = <rcon::Connection<tokio::net::TcpStream>>::builder()
+            .enable_minecraft_quirks(true)
+            .connect(format!("localhost:{}", port), &password);
+        match tokio::time::timeout(RCON_TIMEOUT, connect).await {
+            Ok(Ok(conn)) => Ok(conn),
+            Ok(Err(rcon::Error::Auth)) => Err(eyre!(
+                "RCON rejected the password, check rcon.password in server.properties"
+            )
+            .into()),
+            Ok(Err(e)) => Err(eyre!("Failed to connect to RCON: {}", e).into()),
+            Err(_) => Err(eyre!("Timed out connecting to RCON").into()),
+        }
+    }
+
+    /// Sends a command over RCON and returns its response.
+    ///
+    /// Reconnects if the connection was lost, but at most once every `RCON_RECONNECT_BACKOFF`
+    /// so a dead RCON socket doesn't hold up every command
     pub async fn send_rcon(&self, cmd: &str) -> Result<String, Error> {
-        let a = self
-            .rcon_conn
-            .lock()
-            .await
-            .as_mut()
-            .ok_or_else(|| {
-                eyre!("Failed to send rcon command, rcon connection is not initialized")
-            })?
-            .cmd(cmd)
-            .await
-            .context("Failed to send rcon command")?;
-        Ok(a)
+        let mut rcon_conn = self.rcon_conn.lock().await;
+        if rcon_conn.is_none() {
+            if *self.state.lock().await != State::Running {
+                return Err(eyre!("Failed to send rcon command, instance is not running").into());
+            }
+            let mut retry_after = self.rcon_retry_after.lock().await;
+            if retry_after.map_or(false, |t| Instant::now() < t) {
+                return Err(eyre!(
+                    "Failed to send rcon command, rcon connection is not initialized"
+                )
+                .into());
+            }
+            match self.open_rcon().await {
+                Ok(conn) => {
+                    retry_after.take();
+                    rcon_conn.replace(conn);
+                }
+                Err(e) => {
+                    retry_after.replace(Instant::now() + RCON_RECONNECT_BACKOFF);
+                    return Err(e);
+                }
+            }
+        }
+        let conn = rcon_conn.as_mut().ok_or_else(|| {
+            eyre!("Failed to send rcon command, rcon connection is not initialized")
+        })?;
+        match tokio::time::timeout(RCON_TIMEOUT, conn.cmd(cmd)).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => {
+                warn!("RCON command failed, dropping connection: {}", e);
+                rcon_conn.take();
+                Err(eyre!("Failed to send rcon command: {}", e).into())
+            }
+            Err(_) => {
+                warn!("RCON command timed out, dropping connection");
+                rcon_conn.take();
+                Err(eyre!("Failed to send rcon command, RCON did not respond in time").into())
+            }
+        }
     }
 }
 
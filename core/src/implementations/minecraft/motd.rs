@@ -0,0 +1,146 @@
+//! The server list entry: the MOTD in server.properties and `server-icon.png`
+
+use std::io::Cursor;
+
+use color_eyre::eyre::{eyre, Context};
+use image::{imageops::FilterType, io::Reader as ImageReader, ImageOutputFormat};
+
+use crate::error::{Error, ErrorKind};
+use crate::traits::t_configurable::{manifest::ConfigurableValue, TConfigurable};
+
+use super::configurable::ServerPropertySetting;
+use super::MinecraftInstance;
+
+/// Minecraft only shows icons of exactly this size
+pub const ICON_SIZE: u32 = 64;
+
+/// Larger images are rejected before decoding them
+const MAX_ICON_SOURCE_SIZE: u32 = 4096;
+
+const MAX_MOTD_LINES: usize = 2;
+
+/// Color and formatting codes that can be written with `&` instead of `§`
+const FORMATTING_CODES: &str = "0123456789abcdefklmnor";
+
+/// Turns a MOTD as typed by a user into its server.properties value
+///
+/// `&` followed by a formatting code becomes `§`, a line break becomes `\n`, and anything
+/// outside ASCII is `\u` escaped since the server reads the file as Latin-1
+fn encode_motd(motd: &str) -> Result<String, Error> {
+    let motd = motd.replace("\r\n", "\n");
+    if motd.lines().count() > MAX_MOTD_LINES {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The MOTD can be at most {} lines", MAX_MOTD_LINES),
+        });
+    }
+    let mut encoded = String::new();
+    let mut chars = motd.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' if chars.peek().map_or(false, |next| {
+                FORMATTING_CODES.contains(next.to_ascii_lowercase())
+            }) =>
+            {
+                encoded.push_str("\\u00A7")
+            }
+            '\n' => encoded.push_str("\\n"),
+            '\\' => encoded.push_str("\\\\"),
+            c if c.is_ascii() && !c.is_ascii_control() => encoded.push(c),
+            c => {
+                let mut units = [0u16; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    encoded.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+        }
+    }
+    Ok(encoded)
+}
+
+/// Any PNG, JPEG, GIF or WebP as a `server-icon.png`, square images are scaled to fit
+fn server_icon_png(image: &[u8]) -> Result<Vec<u8>, Error> {
+    let invalid = |message: String| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!(message),
+    };
+    let reader = ImageReader::new(Cursor::new(image))
+        .with_guessed_format()
+        .context("Failed to read image")?;
+    let (width, height) = reader
+        .into_dimensions()
+        .map_err(|e| invalid(format!("Unsupported image: {e}")))?;
+    if width != height {
+        return Err(invalid(format!(
+            "The icon must be square, got {width}x{height}"
+        )));
+    }
+    if width > MAX_ICON_SOURCE_SIZE {
+        return Err(invalid(format!(
+            "The icon can be at most {MAX_ICON_SOURCE_SIZE}x{MAX_ICON_SOURCE_SIZE}"
+        )));
+    }
+    let mut icon = ImageReader::new(Cursor::new(image))
+        .with_guessed_format()
+        .context("Failed to read image")?
+        .decode()
+        .map_err(|e| invalid(format!("Unsupported image: {e}")))?;
+    if width != ICON_SIZE {
+        icon = icon.resize_exact(ICON_SIZE, ICON_SIZE, FilterType::Lanczos3);
+    }
+    let mut png = Vec::new();
+    icon.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .context("Failed to encode icon")?;
+    Ok(png)
+}
+
+impl MinecraftInstance {
+    pub async fn set_motd(&self, motd: &str) -> Result<(), Error> {
+        self.update_configurable(
+            ServerPropertySetting::get_section_id(),
+            &ServerPropertySetting::Motd(String::new()).get_identifier(),
+            ConfigurableValue::String(encode_motd(motd)?),
+        )
+        .await
+    }
+
+    /// Writes `server-icon.png`, the server only loads it on start
+    pub async fn set_icon(&self, image: &[u8]) -> Result<(), Error> {
+        let png = server_icon_png(image)?;
+        tokio::fs::write(self.path_to_instance.join("server-icon.png"), png)
+            .await
+            .context("Failed to write server-icon.png")?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_encode_motd() {
+    assert_eq!(
+        encode_motd("A Minecraft Server").unwrap(),
+        "A Minecraft Server"
+    );
+    assert_eq!(
+        encode_motd("&aWelcome &lhome\nTom & Jerry").unwrap(),
+        "\\u00A7aWelcome \\u00A7lhome\\nTom & Jerry"
+    );
+    assert_eq!(encode_motd("§6Gold ✦").unwrap(), "\\u00A76Gold \\u2726");
+    assert!(encode_motd("one\ntwo\nthree").is_err());
+}
+
+#[test]
+fn test_server_icon_png() {
+    let mut source = Vec::new();
+    image::DynamicImage::new_rgba8(128, 128)
+        .write_to(&mut Cursor::new(&mut source), ImageOutputFormat::Png)
+        .unwrap();
+    let icon = image::load_from_memory(&server_icon_png(&source).unwrap()).unwrap();
+    assert_eq!((icon.width(), icon.height()), (ICON_SIZE, ICON_SIZE));
+
+    let mut wide = Vec::new();
+    image::DynamicImage::new_rgba8(128, 64)
+        .write_to(&mut Cursor::new(&mut wide), ImageOutputFormat::Png)
+        .unwrap();
+    assert!(server_icon_png(&wide).is_err());
+    assert!(server_icon_png(b"not an image").is_err());
+}
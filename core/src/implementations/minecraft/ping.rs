@@ -0,0 +1,152 @@
+//! Client side of the Minecraft Server List Ping protocol, used to check that a
+//! server is actually accepting connections
+
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    gateway::{read_packet, read_string, read_var_int, write_var_int},
+};
+
+/// The status response carries the MOTD and player sample, it can get large with a fancy MOTD
+const MAX_STATUS_LENGTH: i32 = 256 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ServerListPing {
+    pub motd: String,
+    pub version: String,
+    pub protocol: i32,
+    pub online_players: u32,
+    pub max_players: u32,
+    pub latency_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct StatusResponse {
+    version: StatusVersion,
+    players: StatusPlayers,
+    #[serde(default)]
+    description: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct StatusVersion {
+    name: String,
+    protocol: i32,
+}
+
+#[derive(Deserialize)]
+struct StatusPlayers {
+    max: u32,
+    online: u32,
+}
+
+/// Flattens a chat component (or a plain string) into its text
+fn chat_to_text(component: &serde_json::Value) -> String {
+    match component {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(components) => components.iter().map(chat_to_text).collect(),
+        serde_json::Value::Object(object) => {
+            let mut text = object
+                .get("text")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string();
+            if let Some(extra) = object.get("extra") {
+                text.push_str(&chat_to_text(extra));
+            }
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+fn packet(body: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::new();
+    write_var_int(&mut packet, body.len() as i32);
+    packet.extend_from_slice(body);
+    packet
+}
+
+async fn ping(port: u16) -> Result<ServerListPing, Error> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port))
+        .await
+        .context(format!("Failed to connect to port {}", port))?;
+
+    let mut handshake = Vec::new();
+    write_var_int(&mut handshake, 0x00);
+    // -1 asks the server to report its own protocol version
+    write_var_int(&mut handshake, -1);
+    write_var_int(&mut handshake, "localhost".len() as i32);
+    handshake.extend_from_slice(b"localhost");
+    handshake.extend_from_slice(&port.to_be_bytes());
+    write_var_int(&mut handshake, 1);
+    let mut request = packet(&handshake);
+    // status request, has no fields
+    request.extend_from_slice(&packet(&[0x00]));
+    stream
+        .write_all(&request)
+        .await
+        .context("Failed to send status request")?;
+
+    let (_, response) = read_packet(&mut stream, MAX_STATUS_LENGTH).await?;
+    let mut cursor = 0;
+    let status = match read_var_int(&response, &mut cursor) {
+        Some(0x00) => read_string(&response, &mut cursor),
+        _ => None,
+    }
+    .ok_or_else(|| eyre!("Malformed status response"))?;
+    let status: StatusResponse =
+        serde_json::from_str(status).context("Failed to parse status response")?;
+
+    let mut ping = Vec::new();
+    write_var_int(&mut ping, 0x01);
+    ping.extend_from_slice(&0_i64.to_be_bytes());
+    let sent_at = Instant::now();
+    stream
+        .write_all(&packet(&ping))
+        .await
+        .context("Failed to send ping")?;
+    read_packet(&mut stream, 16).await?;
+    let latency_ms = sent_at.elapsed().as_millis() as u64;
+
+    Ok(ServerListPing {
+        motd: chat_to_text(&status.description),
+        version: status.version.name,
+        protocol: status.version.protocol,
+        online_players: status.players.online,
+        max_players: status.players.max,
+        latency_ms,
+    })
+}
+
+/// Pings the server listening on `port` on this machine, giving up after `timeout`
+pub async fn server_list_ping(port: u16, timeout: Duration) -> Result<ServerListPing, Error> {
+    tokio::time::timeout(timeout, ping(port))
+        .await
+        .map_err(|_| Error {
+            kind: ErrorKind::External,
+            source: eyre!("Server did not answer the ping within {:?}", timeout),
+        })?
+}
+
+#[test]
+fn test_chat_to_text() {
+    assert_eq!(
+        chat_to_text(&serde_json::json!("A Minecraft Server")),
+        "A Minecraft Server"
+    );
+    assert_eq!(
+        chat_to_text(&serde_json::json!({
+            "text": "Hello ",
+            "extra": [{ "text": "world", "color": "gold" }, "!"]
+        })),
+        "Hello world!"
+    );
+}
@@ -1,8 +1,11 @@
 use async_trait::async_trait;
 
+use color_eyre::eyre::{eyre, Context};
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
+use crate::error::ErrorKind;
+use crate::prelude::path_to_stores;
 use crate::traits::t_player::Player;
 use crate::traits::t_player::{TPlayer, TPlayerManagement};
 use crate::Error;
@@ -21,6 +24,65 @@ impl MinecraftPlayer {
     pub fn new(name: String, uuid: Option<String>) -> Self {
         Self { name, uuid }
     }
+
+    /// Url to the player's head, served from the core's head cache.
+    ///
+    /// Returns `None` if the player has no uuid, e.g. on an offline mode server
+    pub fn head_url(&self) -> Option<String> {
+        self.uuid
+            .as_ref()
+            .map(|uuid| format!("/api/v1/player/{}/head", uuid))
+    }
+
+    pub fn skin_url(&self) -> Option<String> {
+        self.uuid
+            .as_ref()
+            .map(|uuid| format!("https://crafatar.com/skins/{}", uuid))
+    }
+}
+
+fn is_valid_player_uuid(uuid: &str) -> bool {
+    let stripped = uuid.replace('-', "");
+    stripped.len() == 32 && stripped.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Get the head texture of a player, downloading it if it's not already cached.
+///
+/// Heads are cached in the stores directory so we don't hit the skin server
+/// every time a dashboard is opened.
+pub async fn get_player_head(uuid: &str) -> Result<Vec<u8>, Error> {
+    if !is_valid_player_uuid(uuid) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid player uuid: {}", uuid),
+        });
+    }
+    let uuid = uuid.replace('-', "").to_lowercase();
+    let path_to_heads = path_to_stores().join("player_heads");
+    let path_to_head = path_to_heads.join(format!("{}.png", uuid));
+    if let Ok(head) = tokio::fs::read(&path_to_head).await {
+        return Ok(head);
+    }
+    let response = reqwest::get(format!("https://crafatar.com/avatars/{}?overlay", uuid))
+        .await
+        .context("Failed to fetch player head")?;
+    if !response.status().is_success() {
+        return Err(Error {
+            kind: ErrorKind::External,
+            source: eyre!(
+                "Failed to fetch player head, skin server returned {}",
+                response.status()
+            ),
+        });
+    }
+    let head = response
+        .bytes()
+        .await
+        .context("Failed to read player head")?
+        .to_vec();
+    crate::util::fs::create_dir_all(&path_to_heads).await?;
+    crate::util::fs::write_all(&path_to_head, &head).await?;
+    Ok(head)
 }
 
 impl PartialEq for MinecraftPlayer {
@@ -77,6 +77,10 @@ impl PlayersManager {
         }
     }
 
+    pub fn contains_name(&self, player_name: impl AsRef<str>) -> bool {
+        self.players.iter().any(|p| p.name == player_name.as_ref())
+    }
+
     pub fn count(&self) -> u32 {
         self.players.len() as u32
     }
@@ -0,0 +1,133 @@
+//! Running the server process as another, unprivileged, user on Unix
+
+use std::path::Path;
+
+use color_eyre::eyre::eyre;
+use tokio::process::Command;
+
+use crate::error::{Error, ErrorKind};
+
+const MAX_USER_NAME_LEN: usize = 32;
+
+/// POSIX portable user names, which also rules out anything that isn't a plain name
+#[cfg_attr(not(unix), allow(dead_code))]
+fn is_valid_user_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_USER_NAME_LEN
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// The uid and gid of `user`, which must not be root, after checking that lodestone can switch
+/// to it and that it can read and write the instance directory
+#[cfg(unix)]
+fn resolve(user: &str, instance_dir: &Path) -> Result<(u32, u32), Error> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    if !is_valid_user_name(user) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid user name {}", user),
+        });
+    }
+    let target = nix::unistd::User::from_name(user)
+        .map_err(|e| eyre!("Failed to look up user {}: {}", user, e))?
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("User {} does not exist", user),
+        })?;
+    if target.uid.is_root() {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Refusing to run servers as {}, which is root", user),
+        });
+    }
+    let euid = nix::unistd::geteuid();
+    if euid != target.uid && !euid.is_root() {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!(
+                "Lodestone must run as root to start servers as {}, it runs as uid {}",
+                user,
+                euid
+            ),
+        });
+    }
+    let metadata = std::fs::metadata(instance_dir)
+        .map_err(|e| eyre!("Failed to read {}: {}", instance_dir.display(), e))?;
+    let mode = metadata.permissions().mode();
+    let bits = if metadata.uid() == target.uid.as_raw() {
+        mode >> 6
+    } else if metadata.gid() == target.gid.as_raw() {
+        mode >> 3
+    } else {
+        mode
+    };
+    if bits & 0o7 != 0o7 {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!(
+                "{} cannot read and write {}, give it ownership of the instance directory",
+                user,
+                instance_dir.display()
+            ),
+        });
+    }
+    Ok((target.uid.as_raw(), target.gid.as_raw()))
+}
+
+pub fn validate_run_as(user: &str, instance_dir: &Path) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        resolve(user, instance_dir).map(|_| ())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (user, instance_dir);
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Running servers as another user is only supported on Unix"),
+        })
+    }
+}
+
+/// Makes `command` switch to `user` before running, failing instead of running as lodestone's user
+pub fn apply_run_as(command: &mut Command, user: &str, instance_dir: &Path) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        let (uid, gid) = resolve(user, instance_dir).map_err(|e| Error {
+            kind: e.kind,
+            source: e.source.wrap_err(format!(
+                "Cannot switch to {}, refusing to run as lodestone's user",
+                user
+            )),
+        })?;
+        // supplementary groups of lodestone's user are dropped along with the uid
+        command.uid(uid).gid(gid);
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = command;
+        validate_run_as(user, instance_dir)
+    }
+}
+
+#[test]
+fn test_is_valid_user_name() {
+    assert!(is_valid_user_name("minecraft"));
+    assert!(is_valid_user_name("mc-server_1"));
+    assert!(!is_valid_user_name(""));
+    assert!(!is_valid_user_name("-root"));
+    assert!(!is_valid_user_name("mc server"));
+    assert!(!is_valid_user_name("../etc"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_rejects_root() {
+    let err = validate_run_as("root", &std::env::temp_dir()).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::PermissionDenied);
+}
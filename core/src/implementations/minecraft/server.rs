@@ -1,36 +1,45 @@
-use std::path::PathBuf;
 use std::process::Stdio;
+use std::sync::Arc;
 use std::time::Duration;
 
 use color_eyre::eyre::{eyre, Context};
 use sysinfo::{Pid, PidExt, ProcessExt, SystemExt};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
+use tokio::sync::Notify;
 
+use crate::ansi::{AnsiDecoder, AnsiHandling};
 use crate::error::{Error, ErrorKind};
 use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::global_settings::RestartWarnings;
 use crate::implementations::minecraft::line_parser::{
-    parse_player_joined, parse_player_left, parse_player_msg, parse_server_started,
-    parse_system_msg, PlayerMessage,
+    parse_player_death, parse_player_joined, parse_player_left, parse_player_msg,
+    parse_server_started, parse_system_msg, PlayerMessage,
 };
 use crate::implementations::minecraft::player::MinecraftPlayer;
 use crate::implementations::minecraft::util::name_to_uuid;
+use crate::java_runtimes::probe_java;
 use crate::macro_executor::{DefaultWorkerOptionGenerator, SpawnResult};
 use crate::traits::t_configurable::TConfigurable;
 use crate::traits::t_macro::TaskEntry;
+use crate::traits::t_player::TPlayerManagement;
 use crate::traits::t_server::{MonitorReport, State, StateAction, TServer};
 
 use crate::types::Snowflake;
-use crate::util::{dont_spawn_terminal, list_dir};
+use crate::util::dont_spawn_terminal;
 
+use super::hooks::HookStage;
 use super::r#macro::resolve_macro_invocation;
-use super::{Flavour, ForgeBuildVersion, MinecraftInstance};
-use tracing::{error, info, warn};
+use super::run_as::apply_run_as;
+use super::MinecraftInstance;
+use tracing::{debug, error, info, warn};
 
 #[async_trait::async_trait]
 impl TServer for MinecraftInstance {
     async fn start(&self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
         let config = self.config.lock().await.clone();
+        // checked and updated under one lock, so of concurrent starts (or a start
+        // racing a stop) only one gets past this and the others get a Conflict
         self.state.lock().await.try_transition(
             StateAction::UserStart,
             Some(&|state| {
@@ -47,353 +56,208 @@ impl TServer for MinecraftInstance {
             }),
         )?;
 
-        if !port_scanner::local_port_available(config.port as u16) {
-            return Err(Error {
-                kind: ErrorKind::Internal,
-                source: eyre!("Port {} is already in use", config.port),
-            });
-        }
+        let abort_cause_by = cause_by.clone();
+        let res: Result<(), Error> = async {
+            if !port_scanner::local_port_available(config.port as u16) {
+                return Err(Error {
+                    kind: ErrorKind::Internal,
+                    source: eyre!("Port {} is already in use", config.port),
+                });
+            }
 
-        let prelaunch = resolve_macro_invocation(&self.path_to_instance, "prelaunch");
-        if let Some(prelaunch) = prelaunch {
-            let res: Result<SpawnResult, Error> = self
-                .macro_executor
-                .spawn(
-                    prelaunch,
-                    Vec::new(),
-                    CausedBy::System,
-                    Box::new(DefaultWorkerOptionGenerator),
-                    None,
-                    None,
-                    Some(self.uuid.clone()),
-                )
-                .await;
+            self.run_hook(HookStage::PreStart).await?;
+
+            let prelaunch = resolve_macro_invocation(&self.path_to_instance, "prelaunch");
+            if let Some(prelaunch) = prelaunch {
+                let res: Result<SpawnResult, Error> = self
+                    .macro_executor
+                    .spawn(
+                        prelaunch,
+                        Vec::new(),
+                        CausedBy::System,
+                        Box::new(DefaultWorkerOptionGenerator),
+                        None,
+                        None,
+                        Some(self.uuid.clone()),
+                    )
+                    .await;
 
-            if let Ok(SpawnResult {
-                macro_pid: pid,
-                exit_future,
-                detach_future,
-            }) = res
-            {
-                self.pid_to_task_entry.lock().await.insert(
-                    pid,
-                    TaskEntry {
+                if let Ok(SpawnResult {
+                    macro_pid: pid,
+                    exit_future,
+                    detach_future,
+                }) = res
+                {
+                    self.pid_to_task_entry.lock().await.insert(
                         pid,
-                        name: "prelaunch".to_string(),
-                        creation_time: chrono::Utc::now().timestamp(),
-                    },
-                );
-                tokio::select! {
-                    _ = exit_future => {
-                        info!("Prelaunch script exited");
-                    }
-                    _ = detach_future => {
-                        info!("Prelaunch script requested detach");
+                        TaskEntry {
+                            pid,
+                            name: "prelaunch".to_string(),
+                            creation_time: chrono::Utc::now().timestamp(),
+                        },
+                    );
+                    tokio::select! {
+                        _ = exit_future => {
+                            info!("Prelaunch script exited");
+                        }
+                        _ = detach_future => {
+                            info!("Prelaunch script requested detach");
+                        }
                     }
                 }
+            } else {
+                info!(
+                    "[{}] No prelaunch script found, skipping",
+                    config.name.clone()
+                );
             }
-        } else {
-            info!(
-                "[{}] No prelaunch script found, skipping",
-                config.name.clone()
-            );
-        }
 
-        let jre = if let Some(jre) = &config.java_cmd {
-            PathBuf::from(jre)
-        } else {
-            self.path_to_runtimes
-                .join("java")
-                .join(format!("jre{}", config.jre_major_version))
-                .join(if std::env::consts::OS == "macos" {
-                    "Contents/Home/bin"
-                } else {
-                    "bin"
-                })
-                .join("java")
-        };
-
-        let mut server_start_command = Command::new(&jre);
-        let server_start_command = server_start_command
-            .arg(format!("-Xmx{}M", config.max_ram))
-            .arg(format!("-Xms{}M", config.min_ram))
-            .args(
-                &config
-                    .cmd_args
-                    .iter()
-                    .filter(|s| !s.is_empty())
-                    .collect::<Vec<&String>>(),
-            );
-
-        let server_start_command = match &config.flavour {
-            Flavour::Forge { build_version } => {
-                let ForgeBuildVersion(build_version) = build_version
-                    .as_ref()
-                    .ok_or_else(|| eyre!("Forge version not found"))?;
-                let version_parts: Vec<&str> = config.version.split('.').collect();
-                let major_version: i32 = version_parts[1]
-                    .parse()
-                    .context("Unable to parse major Minecraft version for Forge")?;
-
-                if 17 <= major_version {
-                    let forge_args = match std::env::consts::OS {
-                        "windows" => "win_args.txt",
-                        _ => "unix_args.txt",
-                    };
-
-                    let mut full_forge_args = std::ffi::OsString::from("@");
-                    full_forge_args.push(
-                        self.path_to_instance
-                            .join("libraries")
-                            .join("net")
-                            .join("minecraftforge")
-                            .join("forge")
-                            .join(build_version.as_str())
-                            .join(forge_args)
-                            .into_os_string()
-                            .as_os_str(),
+            let jre = self.java_path(&config);
+            match probe_java(&jre).await {
+                Some((version, major_version)) if major_version < config.jre_major_version => {
+                    let message = format!(
+                        "Java {} is too old for Minecraft {}, Java {} or newer is required",
+                        version, config.version, config.jre_major_version
                     );
-
-                    server_start_command.arg(full_forge_args)
-                } else if (7..=16).contains(&major_version) {
-                    let files = list_dir(&self.path_to_instance, Some(false))
-                        .await
-                        .context("Failed to find forge.jar")?;
-                    let forge_jar_name = files
-                        .iter()
-                        .find(|p| {
-                            p.extension().unwrap_or_default() == "jar"
-                                && p.file_name()
-                                    .unwrap_or_default()
-                                    .to_str()
-                                    .unwrap_or_default()
-                                    .starts_with(format!("forge-{}-", config.version,).as_str())
-                        })
-                        .ok_or_else(|| eyre!("Failed to find forge.jar"))?;
-                    server_start_command
-                        .arg("-jar")
-                        .arg(&self.path_to_instance.join(forge_jar_name))
-                } else {
-                    // 1.5 doesn't work due to JRE issues
-                    // 1.4 doesn't work since forge doesn't provide an installer
-                    let files = list_dir(&self.path_to_instance, Some(false))
-                        .await
-                        .context("Failed to find minecraftforge.jar")?;
-                    let server_jar_name = files
-                        .iter()
-                        .find(|p| {
-                            p.extension().unwrap_or_default() == "jar"
-                                && p.file_name()
-                                    .unwrap_or_default()
-                                    .to_str()
-                                    .unwrap_or_default()
-                                    .starts_with("minecraftforge")
-                        })
-                        .ok_or_else(|| eyre!("Failed to find minecraftforge.jar"))?;
-                    server_start_command
-                        .arg("-jar")
-                        .arg(&self.path_to_instance.join(server_jar_name))
+                    warn!("[{}] {}", config.name, message);
+                    self.event_broadcaster.send(Event::new_instance_warning(
+                        self.uuid.clone(),
+                        config.name.clone(),
+                        message,
+                    ));
                 }
+                Some(_) => {}
+                None => warn!(
+                    "[{}] Could not determine the version of {}",
+                    config.name,
+                    jre.display()
+                ),
             }
-            _ => server_start_command
-                .arg("-jar")
-                .arg(&self.path_to_instance.join("server.jar")),
-        };
 
-        let server_start_command = server_start_command
-            .arg("nogui")
-            .current_dir(&self.path_to_instance);
+            let args = self.launch_args(&config, true).await?;
+            let env = self.launch_env(&config, true, false)?;
+            let mut server_start_command = Command::new(&jre);
+            let server_start_command = server_start_command
+                .args(&args)
+                .envs(&env)
+                .current_dir(&self.path_to_instance);
+            if let Some(user) = &config.run_as {
+                apply_run_as(server_start_command, user, &self.path_to_instance)?;
+            }
+            // logged as configured, substituted values may be sensitive
+            if let Ok(launch_command) = self.assemble_launch_command(&config, false, true).await {
+                debug!("[{}] Launch command: {:?}", config.name, launch_command);
+            }
 
-        match dont_spawn_terminal(server_start_command)
-            .stdout(Stdio::piped())
-            .stdin(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-        {
-            Ok(mut proc) => {
-                let stdin = proc.stdin.take().ok_or_else(|| {
-                    error!(
-                        "[{}] Failed to take stdin during startup",
-                        config.name.clone()
-                    );
-                    eyre!("Failed to take stdin during startup")
-                })?;
-                self.stdin.lock().await.replace(stdin);
-                let stdout = proc.stdout.take().ok_or_else(|| {
-                    error!(
-                        "[{}] Failed to take stdout during startup",
-                        config.name.clone()
-                    );
-                    eyre!("Failed to take stdout during startup")
-                })?;
-                let stderr = proc.stderr.take().ok_or_else(|| {
-                    error!(
-                        "[{}] Failed to take stderr during startup",
-                        config.name.clone()
-                    );
-                    eyre!("Failed to take stderr during startup")
-                })?;
-                *self.process.lock().await = Some(proc);
-                tokio::task::spawn({
-                    let mut __self = self.clone();
-                    let event_broadcaster = __self.event_broadcaster.clone();
-                    let uuid = __self.uuid.clone();
-                    let name = config.name.clone();
-                    let players_manager = __self.players_manager.clone();
-                    async move {
-                        let mut did_start = false;
-
-                        let mut stdout_reader = BufReader::new(stdout);
-                        let mut stderr_reader = BufReader::new(stderr);
-
-                        loop {
-                            let (line_res, is_stdout) = tokio::select!(
-                                line_res = async {
-                                    let mut line = Vec::new();
-                                    match stdout_reader.read_until(b'\n', &mut line).await {
-                                        Ok(0) => return Ok(None),
-                                        Err(e) => return Err(e),
-                                        Ok(_) => {}
-
-                                    };
-                                    Ok(Some(line))
-                                } => {
-                                    (line_res, true)
-                                },
-                                line_res = async {
-                                    let mut line = Vec::new();
-                                    match stderr_reader.read_until(b'\n', &mut line).await {
-                                        Ok(0) => return Ok(None),
-                                        Err(e) => return Err(e),
-                                        Ok(_) => {}
-                                    };
-                                    Ok(Some(line))
-                                } => {
-                                    (line_res, false)
-                                }
-                            );
-                            let _ = line_res.as_ref().map_err(|e| {
-                                error!("[{}] Failed to read from stdout/stderr: {}", name, e);
-                            });
-
-                            if let Ok(line) = line_res {
-                                if let Some(line) = line {
-                                    let line = String::from_utf8_lossy(&line).to_string();
-                                    if !is_stdout {
-                                        // info!("[{}] {}", name, line);
-                                        warn!("[{}] {}", name, line);
+            match dont_spawn_terminal(server_start_command)
+                .stdout(Stdio::piped())
+                .stdin(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+            {
+                Ok(mut proc) => {
+                    let stdin = proc.stdin.take().ok_or_else(|| {
+                        error!(
+                            "[{}] Failed to take stdin during startup",
+                            config.name.clone()
+                        );
+                        eyre!("Failed to take stdin during startup")
+                    })?;
+                    self.stdin.lock().await.replace(stdin);
+                    let stdout = proc.stdout.take().ok_or_else(|| {
+                        error!(
+                            "[{}] Failed to take stdout during startup",
+                            config.name.clone()
+                        );
+                        eyre!("Failed to take stdout during startup")
+                    })?;
+                    let stderr = proc.stderr.take().ok_or_else(|| {
+                        error!(
+                            "[{}] Failed to take stderr during startup",
+                            config.name.clone()
+                        );
+                        eyre!("Failed to take stderr during startup")
+                    })?;
+                    *self.process.lock().await = Some(proc);
+                    tokio::task::spawn({
+                        let mut __self = self.clone();
+                        let event_broadcaster = __self.event_broadcaster.clone();
+                        let uuid = __self.uuid.clone();
+                        let name = config.name.clone();
+                        let players_manager = __self.players_manager.clone();
+                        let ansi_handling = config.ansi_handling;
+                        async move {
+                            let mut did_start = false;
+                            // each stream has its own pending escape sequence and style
+                            let mut stdout_ansi = AnsiDecoder::default();
+                            let mut stderr_ansi = AnsiDecoder::default();
+
+                            let mut stdout_reader = BufReader::new(stdout);
+                            let mut stderr_reader = BufReader::new(stderr);
+
+                            loop {
+                                let (line_res, is_stdout) = tokio::select!(
+                                    line_res = async {
+                                        let mut line = Vec::new();
+                                        match stdout_reader.read_until(b'\n', &mut line).await {
+                                            Ok(0) => return Ok(None),
+                                            Err(e) => return Err(e),
+                                            Ok(_) => {}
+
+                                        };
+                                        Ok(Some(line))
+                                    } => {
+                                        (line_res, true)
+                                    },
+                                    line_res = async {
+                                        let mut line = Vec::new();
+                                        match stderr_reader.read_until(b'\n', &mut line).await {
+                                            Ok(0) => return Ok(None),
+                                            Err(e) => return Err(e),
+                                            Ok(_) => {}
+                                        };
+                                        Ok(Some(line))
+                                    } => {
+                                        (line_res, false)
                                     }
-                                    event_broadcaster.send(Event {
-                                        event_inner: EventInner::InstanceEvent(InstanceEvent {
-                                            instance_uuid: uuid.clone(),
-                                            instance_event_inner:
-                                                InstanceEventInner::InstanceOutput {
-                                                    message: line.clone(),
-                                                },
-                                            instance_name: name.clone(),
-                                        }),
-                                        details: "".to_string(),
-                                        snowflake: Snowflake::default(),
-                                        caused_by: CausedBy::System,
-                                    });
-
-                                    if parse_server_started(&line) && !did_start {
-                                        did_start = true;
-                                        __self
-                                            .state
-                                            .lock()
-                                            .await
-                                            .try_transition(
-                                                StateAction::InstanceStart,
-                                                Some(&|state| {
-                                                    event_broadcaster.send(Event {
-                                                event_inner: EventInner::InstanceEvent(
-                                                    InstanceEvent {
-                                                        instance_name: config.name.clone(),
-                                                        instance_uuid: __self.uuid.clone(),
-                                                        instance_event_inner:
-                                                            InstanceEventInner::StateTransition {
-                                                                to: state,
-                                                            },
-                                                    },
-                                                ),
-                                                snowflake: Snowflake::default(),
-                                                details: "Starting server".to_string(),
-                                                caused_by: cause_by.clone(),
-                                            });
-                                                }),
-                                            )
-                                            .unwrap();
-                                        info!("[{}] Instance started", name);
-
-                                        if let (Some(true), Some(rcon_psw), Some(rcon_port)) = {
-                                            let lock = __self.configurable_manifest.lock().await;
-
-                                            let a = lock
-                                                .get_unique_setting_key("enable-rcon")
-                                                .and_then(|v| {
-                                                    v.get_value().map(|v| v.try_as_boolean().ok())
-                                                })
-                                                .flatten();
-
-                                            let b = lock
-                                                .get_unique_setting_key("rcon.password")
-                                                .and_then(|v| {
-                                                    v.get_value().map(|v| v.try_as_string().ok())
-                                                })
-                                                .flatten()
-                                                .cloned();
-
-                                            let c = lock
-                                                .get_unique_setting_key("rcon.port")
-                                                .and_then(|v| {
-                                                    v.get_value()
-                                                        .map(|v| v.try_as_unsigned_integer().ok())
-                                                })
-                                                .flatten();
-                                            (a, b, c)
-                                        } {
-                                            let max_retry = 3;
-                                            for i in 0..max_retry {
-                                                let rcon =
-                                                <rcon::Connection<tokio::net::TcpStream>>::builder(
-                                                )
-                                                .enable_minecraft_quirks(true)
-                                                .connect(
-                                                    &format!("localhost:{}", rcon_port),
-                                                    &rcon_psw,
+                                );
+                                let _ = line_res.as_ref().map_err(|e| {
+                                    error!("[{}] Failed to read from stdout/stderr: {}", name, e);
+                                });
+
+                                if let Ok(line) = line_res {
+                                    if let Some(line) = line {
+                                        let line = String::from_utf8_lossy(&line).to_string();
+                                        let ansi = if is_stdout {
+                                            &mut stdout_ansi
+                                        } else {
+                                            &mut stderr_ansi
+                                        };
+                                        let (line, spans) = match ansi_handling {
+                                            AnsiHandling::Raw => (line, None),
+                                            AnsiHandling::Strip => (ansi.strip(&line), None),
+                                            AnsiHandling::Parse => {
+                                                let spans = ansi.decode(&line);
+                                                (
+                                                    spans
+                                                        .iter()
+                                                        .map(|span| span.text.as_str())
+                                                        .collect::<String>(),
+                                                    Some(spans),
                                                 )
-                                                .await
-                                                .map_err(|e| {
-                                                    warn!(
-                                                    "[{}] Failed to connect to RCON: {}, retry {}/{}",
-                                                    config.name,
-                                                    e, i, max_retry
-                                                );
-                                                    e
-                                                });
-                                                if let Ok(rcon) = rcon {
-                                                    info!("[{}] Connected to RCON", config.name);
-                                                    __self.rcon_conn.lock().await.replace(rcon);
-                                                    break;
-                                                }
-                                                tokio::time::sleep(Duration::from_secs(
-                                                    2_u64.pow(i),
-                                                ))
-                                                .await;
                                             }
-                                        } else {
-                                            warn!("RCON is not enabled or misconfigured, skipping");
-                                            __self.rcon_conn.lock().await.take();
+                                        };
+                                        if !is_stdout {
+                                            // info!("[{}] {}", name, line);
+                                            warn!("[{}] {}", name, line);
                                         }
-                                    }
-                                    if let Some(system_msg) = parse_system_msg(&line) {
-                                        let _ = event_broadcaster.send(Event {
+                                        event_broadcaster.send(Event {
                                             event_inner: EventInner::InstanceEvent(InstanceEvent {
                                                 instance_uuid: uuid.clone(),
                                                 instance_event_inner:
-                                                    InstanceEventInner::SystemMessage {
-                                                        message: line,
+                                                    InstanceEventInner::InstanceOutput {
+                                                        message: line.clone(),
+                                                        spans,
                                                     },
                                                 instance_name: name.clone(),
                                             }),
@@ -401,130 +265,251 @@ impl TServer for MinecraftInstance {
                                             snowflake: Snowflake::default(),
                                             caused_by: CausedBy::System,
                                         });
-                                        if let Some(player_name) = parse_player_joined(&system_msg)
-                                        {
-                                            players_manager.lock().await.add_player(
-                                                MinecraftPlayer {
-                                                    name: player_name.clone(),
-                                                    uuid: name_to_uuid(&player_name).await,
-                                                },
-                                                __self.name().await,
-                                            );
-                                        } else if let Some(player_name) =
-                                            parse_player_left(&system_msg)
-                                        {
-                                            players_manager
+
+                                        if parse_server_started(&line) && !did_start {
+                                            did_start = true;
+                                            __self
+                                                .state
                                                 .lock()
                                                 .await
-                                                .remove_by_name(&player_name, __self.name().await);
+                                                .try_transition(
+                                                    StateAction::InstanceStart,
+                                                    Some(&|state| {
+                                                        event_broadcaster.send(Event {
+                                                    event_inner: EventInner::InstanceEvent(
+                                                        InstanceEvent {
+                                                            instance_name: config.name.clone(),
+                                                            instance_uuid: __self.uuid.clone(),
+                                                            instance_event_inner:
+                                                                InstanceEventInner::StateTransition {
+                                                                    to: state,
+                                                                },
+                                                        },
+                                                    ),
+                                                    snowflake: Snowflake::default(),
+                                                    details: "Starting server".to_string(),
+                                                    caused_by: cause_by.clone(),
+                                                });
+                                                    }),
+                                                )
+                                                .unwrap();
+                                            info!("[{}] Instance started", name);
+
+                                            if __self.rcon_settings().await.is_some() {
+                                                let max_retry = 3;
+                                                for i in 0..max_retry {
+                                                    match __self.open_rcon().await {
+                                                        Ok(rcon) => {
+                                                            info!(
+                                                                "[{}] Connected to RCON",
+                                                                config.name
+                                                            );
+                                                            __self.rcon_conn.lock().await.replace(rcon);
+                                                            __self.rcon_retry_after.lock().await.take();
+                                                            break;
+                                                        }
+                                                        Err(e) => warn!(
+                                                            "[{}] {}, retry {}/{}",
+                                                            config.name, e, i, max_retry
+                                                        ),
+                                                    }
+                                                    tokio::time::sleep(Duration::from_secs(
+                                                        2_u64.pow(i),
+                                                    ))
+                                                    .await;
+                                                }
+                                            } else {
+                                                warn!("RCON is not enabled or misconfigured, skipping");
+                                                __self.rcon_conn.lock().await.take();
+                                            }
+                                        }
+                                        if let Some(system_msg) = parse_system_msg(&line) {
+                                            let _ = event_broadcaster.send(Event {
+                                                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                                                    instance_uuid: uuid.clone(),
+                                                    instance_event_inner:
+                                                        InstanceEventInner::SystemMessage {
+                                                            message: line,
+                                                        },
+                                                    instance_name: name.clone(),
+                                                }),
+                                                details: "".to_string(),
+                                                snowflake: Snowflake::default(),
+                                                caused_by: CausedBy::System,
+                                            });
+                                            if let Some(player_name) = parse_player_joined(&system_msg)
+                                            {
+                                                // offline mode players don't have a Mojang account,
+                                                // so looking them up would give us someone else's skin
+                                                let online_mode = __self
+                                                    .configurable_manifest
+                                                    .lock()
+                                                    .await
+                                                    .get_unique_setting_key("online-mode")
+                                                    .and_then(|v| {
+                                                        v.get_value().map(|v| v.try_as_boolean().ok())
+                                                    })
+                                                    .flatten()
+                                                    .unwrap_or(true);
+                                                let player_uuid = if online_mode {
+                                                    name_to_uuid(&player_name).await
+                                                } else {
+                                                    None
+                                                };
+                                                players_manager.lock().await.add_player(
+                                                    MinecraftPlayer {
+                                                        name: player_name.clone(),
+                                                        uuid: player_uuid,
+                                                    },
+                                                    __self.name().await,
+                                                );
+                                                event_broadcaster.send(Event::new_player_joined(
+                                                    uuid.clone(),
+                                                    name.clone(),
+                                                    player_name,
+                                                ));
+                                            } else if let Some(player_name) =
+                                                parse_player_left(&system_msg)
+                                            {
+                                                players_manager
+                                                    .lock()
+                                                    .await
+                                                    .remove_by_name(&player_name, __self.name().await);
+                                                event_broadcaster.send(Event::new_player_left(
+                                                    uuid.clone(),
+                                                    name.clone(),
+                                                    player_name,
+                                                ));
+                                            } else if let Some(player_name) =
+                                                parse_player_death(&system_msg)
+                                            {
+                                                if players_manager
+                                                    .lock()
+                                                    .await
+                                                    .contains_name(&player_name)
+                                                {
+                                                    event_broadcaster.send(Event::new_player_death(
+                                                        uuid.clone(),
+                                                        name.clone(),
+                                                        player_name,
+                                                        system_msg,
+                                                    ));
+                                                }
+                                            }
+                                        } else if let Some(PlayerMessage { player, message }) =
+                                            parse_player_msg(&line)
+                                        {
+                                            event_broadcaster.send(Event {
+                                                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                                                    instance_uuid: uuid.clone(),
+                                                    instance_event_inner:
+                                                        InstanceEventInner::PlayerMessage {
+                                                            player,
+                                                            player_message: message,
+                                                        },
+                                                    instance_name: name.clone(),
+                                                }),
+                                                details: "".to_string(),
+                                                snowflake: Snowflake::default(),
+                                                caused_by: CausedBy::System,
+                                            });
                                         }
-                                    } else if let Some(PlayerMessage { player, message }) =
-                                        parse_player_msg(&line)
-                                    {
+                                    } else {
+                                        break;
+                                    }
+                                }
+                            }
+                            info!("Instance {} process shutdown", name);
+                            __self
+                                .state
+                                .lock()
+                                .await
+                                .try_transition(
+                                    StateAction::InstanceStop,
+                                    Some(&|state| {
                                         event_broadcaster.send(Event {
                                             event_inner: EventInner::InstanceEvent(InstanceEvent {
-                                                instance_uuid: uuid.clone(),
+                                                instance_name: config.name.clone(),
+                                                instance_uuid: __self.uuid.clone(),
                                                 instance_event_inner:
-                                                    InstanceEventInner::PlayerMessage {
-                                                        player,
-                                                        player_message: message,
-                                                    },
-                                                instance_name: name.clone(),
+                                                    InstanceEventInner::StateTransition { to: state },
                                             }),
-                                            details: "".to_string(),
                                             snowflake: Snowflake::default(),
-                                            caused_by: CausedBy::System,
+                                            details: "Instance stopping as server process exited"
+                                                .to_string(),
+                                            caused_by: cause_by.clone(),
                                         });
-                                    }
-                                } else {
-                                    break;
-                                }
-                            }
+                                    }),
+                                )
+                                .unwrap();
+                            __self.players_manager.lock().await.clear(name);
+                            __self.rcon_conn.lock().await.take();
+                            let _ = __self.run_hook(HookStage::PostStop).await;
                         }
-                        info!("Instance {} process shutdown", name);
-                        __self
-                            .state
-                            .lock()
-                            .await
-                            .try_transition(
-                                StateAction::InstanceStop,
-                                Some(&|state| {
-                                    event_broadcaster.send(Event {
-                                        event_inner: EventInner::InstanceEvent(InstanceEvent {
-                                            instance_name: config.name.clone(),
-                                            instance_uuid: __self.uuid.clone(),
-                                            instance_event_inner:
-                                                InstanceEventInner::StateTransition { to: state },
-                                        }),
-                                        snowflake: Snowflake::default(),
-                                        details: "Instance stopping as server process exited"
-                                            .to_string(),
-                                        caused_by: cause_by.clone(),
-                                    });
-                                }),
-                            )
-                            .unwrap();
-                        __self.players_manager.lock().await.clear(name);
-                        __self.rcon_conn.lock().await.take();
-                    }
-                });
-                self.config.lock().await.has_started = true;
-                self.write_config_to_file().await?;
-                let instance_uuid = self.uuid.clone();
-                let mut rx = self.event_broadcaster.subscribe();
-
-                if block {
-                    while let Ok(event) = rx.recv().await {
-                        if let EventInner::InstanceEvent(InstanceEvent {
-                            instance_uuid: event_instance_uuid,
-                            instance_event_inner: InstanceEventInner::StateTransition { to },
-                            ..
-                        }) = event.event_inner
-                        {
-                            if instance_uuid == event_instance_uuid {
-                                if to == State::Running {
-                                    return Ok(()); // Instance started successfully
-                                } else if to == State::Stopped {
-                                    return Err(eyre!(
-                                        "Instance exited unexpectedly before starting"
-                                    )
-                                    .into());
+                    });
+                    self.config.lock().await.has_started = true;
+                    self.write_config_to_file().await?;
+                    let instance_uuid = self.uuid.clone();
+                    let mut rx = self.event_broadcaster.subscribe();
+
+                    if block {
+                        while let Ok(event) = rx.recv().await {
+                            if let EventInner::InstanceEvent(InstanceEvent {
+                                instance_uuid: event_instance_uuid,
+                                instance_event_inner: InstanceEventInner::StateTransition { to },
+                                ..
+                            }) = event.event_inner
+                            {
+                                if instance_uuid == event_instance_uuid {
+                                    if to == State::Running {
+                                        return Ok(()); // Instance started successfully
+                                    } else if to == State::Stopped {
+                                        return Err(eyre!(
+                                            "Instance exited unexpectedly before starting"
+                                        )
+                                        .into());
+                                    }
                                 }
                             }
                         }
+                        Err(eyre!("Sender shutdown").into())
+                    } else {
+                        Ok(())
                     }
-                    Err(eyre!("Sender shutdown").into())
-                } else {
-                    Ok(())
+                }
+                Err(e) => {
+                    error!("Failed to start server, {}", e);
+                    self.state
+                        .lock()
+                        .await
+                        .try_transition(
+                            StateAction::InstanceStop,
+                            Some(&|state| {
+                                self.event_broadcaster.send(Event {
+                                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                                        instance_name: config.name.clone(),
+                                        instance_uuid: self.uuid.clone(),
+                                        instance_event_inner: InstanceEventInner::StateTransition {
+                                            to: state,
+                                        },
+                                    }),
+                                    snowflake: Snowflake::default(),
+                                    details: "Starting server".to_string(),
+                                    caused_by: cause_by.clone(),
+                                });
+                            }),
+                        )
+                        .unwrap();
+                    Err(e).context("Failed to start server")?;
+                    unreachable!();
                 }
             }
-            Err(e) => {
-                error!("Failed to start server, {}", e);
-                self.state
-                    .lock()
-                    .await
-                    .try_transition(
-                        StateAction::InstanceStop,
-                        Some(&|state| {
-                            self.event_broadcaster.send(Event {
-                                event_inner: EventInner::InstanceEvent(InstanceEvent {
-                                    instance_name: config.name.clone(),
-                                    instance_uuid: self.uuid.clone(),
-                                    instance_event_inner: InstanceEventInner::StateTransition {
-                                        to: state,
-                                    },
-                                }),
-                                snowflake: Snowflake::default(),
-                                details: "Starting server".to_string(),
-                                caused_by: cause_by.clone(),
-                            });
-                        }),
-                    )
-                    .unwrap();
-                Err(e).context("Failed to start server")?;
-                unreachable!();
-            }
         }
+        .await;
+        if res.is_err() {
+            self.abort_start(abort_cause_by).await;
+        }
+        res
     }
     async fn stop(&self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
         let config = self.config.lock().await.clone();
@@ -596,8 +581,14 @@ impl TServer for MinecraftInstance {
 
             let mut __self = self.clone();
             tokio::task::spawn(async move {
-                __self.stop(caused_by.clone(), true).await.unwrap();
-                __self.start(caused_by, block).await.unwrap()
+                // another lifecycle request may have got in first, which is not a bug
+                if let Err(e) = __self.stop(caused_by.clone(), true).await {
+                    warn!("Failed to stop instance for restart: {e}");
+                    return;
+                }
+                if let Err(e) = __self.start(caused_by, block).await {
+                    warn!("Failed to start instance for restart: {e}");
+                }
             });
             Ok(())
         }
@@ -707,6 +698,8 @@ impl TServer for MinecraftInstance {
                     disk_usage: Some(disk_usage.into()),
                     cpu_usage: Some(cpu_usage),
                     start_time: Some(start_time),
+                    gateway_stats: None,
+                    timestamp: None,
                 }
             } else {
                 MonitorReport::default()
@@ -716,3 +709,112 @@ impl TServer for MinecraftInstance {
         }
     }
 }
+
+/// How often the player count is checked while a restart countdown is running
+const RESTART_EMPTY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+impl MinecraftInstance {
+    /// Moves an instance whose launch failed back to Stopped, unless its server
+    /// process is running and will report the state itself
+    async fn abort_start(&self, cause_by: CausedBy) {
+        let process_running = match self.process.lock().await.as_mut() {
+            Some(process) => matches!(process.try_wait(), Ok(None)),
+            None => false,
+        };
+        let name = self.config.lock().await.name.clone();
+        let mut state = self.state.lock().await;
+        if *state != State::Starting || process_running {
+            return;
+        }
+        *state = State::Stopped;
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_name: name,
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner: InstanceEventInner::StateTransition { to: State::Stopped },
+            }),
+            snowflake: Snowflake::default(),
+            details: "Failed to start server".to_string(),
+            caused_by: cause_by,
+        });
+    }
+
+    /// Waits for `duration`, returning early (with `true`) if `early_trigger` is
+    /// notified or, when `restart_when_empty` is set, once no players are online
+    async fn wait_restart_countdown(
+        &self,
+        duration: Duration,
+        early_trigger: &Notify,
+        restart_when_empty: bool,
+    ) -> bool {
+        let deadline = tokio::time::Instant::now() + duration;
+        loop {
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            let tick = (deadline - now).min(RESTART_EMPTY_CHECK_INTERVAL);
+            tokio::select! {
+                _ = early_trigger.notified() => return true,
+                _ = tokio::time::sleep(tick) => {}
+            }
+            if restart_when_empty && self.get_player_count().await.unwrap_or(1) == 0 {
+                return true;
+            }
+        }
+    }
+
+    /// Warns players with `/say` at each offset of `warnings` before restarting.
+    ///
+    /// Notifying `early_trigger` skips the rest of the countdown
+    pub async fn restart_with_warnings(
+        &self,
+        warnings: &RestartWarnings,
+        early_trigger: Arc<Notify>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let mut offsets: Vec<u64> = warnings
+            .offsets_secs
+            .iter()
+            .copied()
+            .filter(|offset| *offset > 0)
+            .collect();
+        offsets.sort_unstable_by(|a, b| b.cmp(a));
+        offsets.dedup();
+        let mut remaining = offsets.first().copied().unwrap_or(0);
+        for offset in offsets {
+            if self
+                .wait_restart_countdown(
+                    Duration::from_secs(remaining - offset),
+                    &early_trigger,
+                    warnings.restart_when_empty,
+                )
+                .await
+            {
+                remaining = 0;
+                break;
+            }
+            remaining = offset;
+            if let Err(e) = self
+                .send_command(
+                    &format!("say {}", warnings.render(offset)),
+                    caused_by.clone(),
+                )
+                .await
+            {
+                warn!(
+                    "[{}] Failed to send restart warning: {}",
+                    self.name().await,
+                    e
+                );
+            }
+        }
+        self.wait_restart_countdown(
+            Duration::from_secs(remaining),
+            &early_trigger,
+            warnings.restart_when_empty,
+        )
+        .await;
+        self.restart(caused_by, true).await
+    }
+}
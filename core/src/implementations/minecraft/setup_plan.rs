@@ -0,0 +1,128 @@
+//! What setting up a Minecraft instance would do, worked out without doing any of it
+
+use std::path::PathBuf;
+
+use color_eyre::eyre::eyre;
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::java_runtimes::java_binary;
+use crate::prelude::path_to_binaries;
+use crate::util::content_length;
+
+use super::util::{get_jre_package, get_jre_url, get_server_jar_url};
+use super::{choose_jre, Flavour, JreSource, SetupConfig};
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct PlannedDownload {
+    pub name: String,
+    pub url: String,
+    /// Bytes, `None` if the server didn't say
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct SetupPlan {
+    pub name: String,
+    pub version: String,
+    pub flavour: String,
+    pub port: u32,
+    pub jre_major_version: u64,
+    /// The java executable the instance will run on, after it is downloaded if needed
+    pub java: PathBuf,
+    pub downloads: Vec<PlannedDownload>,
+    /// Sum of the known download sizes
+    pub estimated_download_size: u64,
+    /// Command the server will be started with, relative to the instance directory
+    pub command: Vec<String>,
+    /// Things that would make the setup or the first start fail
+    pub warnings: Vec<String>,
+}
+
+/// Resolves the same JRE and server jar the real setup would download
+pub async fn plan_setup(config: &SetupConfig) -> Result<SetupPlan, Error> {
+    let (_, jre_major_version) =
+        get_jre_url(config.version.as_str())
+            .await
+            .ok_or_else(|| Error {
+                kind: ErrorKind::External,
+                source: eyre!("Could not find the JRE for version {}", config.version),
+            })?;
+    let mut downloads = Vec::new();
+    let path_to_java = path_to_binaries().join("java");
+    let java = match choose_jre(&path_to_java, jre_major_version).await {
+        JreSource::Managed(path) => path,
+        JreSource::Installed(runtime) => runtime.path,
+        JreSource::Download => {
+            let package = get_jre_package(jre_major_version).await;
+            let size = match package.size {
+                Some(size) => Some(size),
+                None => content_length(&package.url).await,
+            };
+            downloads.push(PlannedDownload {
+                name: format!("Java {jre_major_version} runtime"),
+                url: package.url,
+                size,
+            });
+            java_binary(&path_to_java.join(format!("jre{jre_major_version}")))
+        }
+    };
+
+    let (jar_url, flavour) = get_server_jar_url(config.version.as_str(), &config.flavour)
+        .await
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Could not find a {} server.jar for version {}",
+                config.flavour.to_string(),
+                config.version
+            ),
+        })?;
+    let jar_name = match flavour {
+        Flavour::Forge { .. } => "forge-installer.jar",
+        _ => "server.jar",
+    };
+    downloads.push(PlannedDownload {
+        name: jar_name.to_string(),
+        size: content_length(&jar_url).await,
+        url: jar_url,
+    });
+
+    let min_ram = config.min_ram.unwrap_or(2048);
+    let max_ram = config.max_ram.unwrap_or(4096);
+    let mut warnings = Vec::new();
+    if min_ram > max_ram {
+        warnings.push(format!(
+            "Minimum memory ({min_ram} MB) is more than the maximum memory ({max_ram} MB)"
+        ));
+    }
+    let mut command = vec![
+        java.display().to_string(),
+        format!("-Xmx{max_ram}M"),
+        format!("-Xms{min_ram}M"),
+    ];
+    command.extend(config.cmd_args.iter().filter(|s| !s.is_empty()).cloned());
+    command.push("-jar".to_string());
+    command.push(match flavour {
+        // the jar to run is only known once the forge installer has run
+        Flavour::Forge { .. } => "<forge server jar>".to_string(),
+        _ => "server.jar".to_string(),
+    });
+    command.push("nogui".to_string());
+
+    Ok(SetupPlan {
+        name: config.name.clone(),
+        version: config.version.clone(),
+        flavour: flavour.to_string(),
+        port: config.port,
+        jre_major_version,
+        java,
+        estimated_download_size: downloads.iter().filter_map(|d| d.size).sum(),
+        downloads,
+        command,
+        warnings,
+    })
+}
@@ -0,0 +1,80 @@
+//! Diagnostic signals sent to the server process, e.g. SIGQUIT for a JVM thread dump
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+use super::MinecraftInstance;
+
+/// The signals that can be sent, ones that would stop or suspend the server are left out.
+/// That includes SIGUSR1, which the JVM doesn't handle and so terminates it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum ProcessSignal {
+    /// Makes the JVM print a thread dump to the console
+    #[serde(rename = "SIGQUIT", alias = "QUIT")]
+    Quit,
+}
+
+impl std::fmt::Display for ProcessSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessSignal::Quit => write!(f, "SIGQUIT"),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn send(pid: u32, signal: ProcessSignal) -> Result<(), Error> {
+    use color_eyre::eyre::Context;
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    let signal = match signal {
+        ProcessSignal::Quit => Signal::SIGQUIT,
+    };
+    kill(Pid::from_raw(pid as i32), signal).context(format!("Failed to send {signal}"))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_pid: u32, _signal: ProcessSignal) -> Result<(), Error> {
+    Err(Error {
+        kind: ErrorKind::UnsupportedOperation,
+        source: eyre!("Signals are only supported on Unix"),
+    })
+}
+
+impl MinecraftInstance {
+    /// Sends `signal` to the server process, returns its pid
+    pub async fn send_signal(&self, signal: ProcessSignal) -> Result<u32, Error> {
+        let pid = self
+            .process
+            .lock()
+            .await
+            .as_ref()
+            .and_then(|process| process.id())
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The server process is not running"),
+            })?;
+        send(pid, signal)?;
+        Ok(pid)
+    }
+}
+
+#[test]
+fn test_signal_names() {
+    assert_eq!(
+        serde_json::from_str::<ProcessSignal>("\"SIGQUIT\"").unwrap(),
+        ProcessSignal::Quit
+    );
+    assert_eq!(
+        serde_json::from_str::<ProcessSignal>("\"QUIT\"").unwrap(),
+        ProcessSignal::Quit
+    );
+    assert!(serde_json::from_str::<ProcessSignal>("\"SIGUSR1\"").is_err());
+    assert!(serde_json::from_str::<ProcessSignal>("\"SIGKILL\"").is_err());
+}
@@ -0,0 +1,85 @@
+//! The Minecraft specific startup diagnostics: java, the EULA and the server jar
+
+use crate::java_runtimes::probe_java;
+use crate::startup_diagnostics::{DiagnosticCheck, StartupCheck};
+
+use super::MinecraftInstance;
+
+/// Whether `eula.txt` has `eula=true`
+fn eula_accepted(content: &str) -> bool {
+    content.lines().any(|line| {
+        line.split_once('=').map_or(false, |(key, value)| {
+            key.trim() == "eula" && value.trim().eq_ignore_ascii_case("true")
+        })
+    })
+}
+
+impl MinecraftInstance {
+    pub async fn startup_checks(&self) -> Vec<DiagnosticCheck> {
+        let config = self.config.lock().await.clone();
+        let mut checks = Vec::new();
+
+        let jre = self.java_path(&config);
+        checks.push(match probe_java(&jre).await {
+            Some((version, major_version)) if major_version < config.jre_major_version => {
+                DiagnosticCheck::failed(
+                    StartupCheck::Java,
+                    format!(
+                        "Java {} is too old for Minecraft {}, Java {} or newer is required",
+                        version, config.version, config.jre_major_version
+                    ),
+                )
+            }
+            Some((version, _)) => {
+                DiagnosticCheck::passed(StartupCheck::Java, format!("Java {} found", version))
+            }
+            None => DiagnosticCheck::failed(
+                StartupCheck::Java,
+                format!(
+                    "Java could not be run at {}, reinstall it or pick another java",
+                    jre.display()
+                ),
+            ),
+        });
+
+        checks.push(
+            match tokio::fs::read_to_string(self.path_to_instance.join("eula.txt")).await {
+                Ok(content) if eula_accepted(&content) => {
+                    DiagnosticCheck::passed(StartupCheck::Eula, "The EULA is accepted")
+                }
+                _ => DiagnosticCheck::failed(
+                    StartupCheck::Eula,
+                    "The EULA has not been accepted, set eula=true in eula.txt",
+                ),
+            },
+        );
+
+        checks.push(match self.server_jar(&config).await {
+            Ok(jar) => {
+                let path = jar.path();
+                if tokio::fs::metadata(path).await.is_ok() {
+                    DiagnosticCheck::passed(StartupCheck::ServerJar, "The server jar exists")
+                } else {
+                    DiagnosticCheck::failed(
+                        StartupCheck::ServerJar,
+                        format!("{} is missing, reinstall the server", path.display()),
+                    )
+                }
+            }
+            Err(e) => DiagnosticCheck::failed(
+                StartupCheck::ServerJar,
+                format!("{}, reinstall the server", e.source),
+            ),
+        });
+
+        checks
+    }
+}
+
+#[test]
+fn test_eula_accepted() {
+    assert!(eula_accepted("#generated by Lodestone\neula=true"));
+    assert!(eula_accepted("eula = TRUE\n"));
+    assert!(!eula_accepted("eula=false"));
+    assert!(!eula_accepted("#eula=true"));
+}
@@ -1,13 +1,20 @@
 use color_eyre::eyre::{eyre, Context, ContextCompat};
 use indexmap::IndexMap;
 use serde_json::{self, Value};
-use std::{collections::BTreeMap, path::Path, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use tokio::io::AsyncBufReadExt;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
 
 use super::{
     FabricInstallerVersion, FabricLoaderVersion, Flavour, ForgeBuildVersion, PaperBuildVersion,
 };
 use crate::error::Error;
+use crate::util::{download_file, sha256_file, unzip_file_async, DownloadProgress, UnzipOption};
 
 pub async fn read_properties_from_path(
     path_to_properties: &Path,
@@ -355,8 +362,8 @@ pub async fn get_forge_jar_url(
     ))
 }
 
-pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
-    let client = reqwest::Client::new();
+/// Adoptium's names for the current os and architecture
+fn adoptium_os_arch() -> (&'static str, &'static str) {
     let os = if std::env::consts::OS == "macos" {
         "mac"
     } else {
@@ -367,6 +374,107 @@ pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
     } else {
         std::env::consts::ARCH
     };
+    (os, arch)
+}
+
+/// Adoptium download url of the latest JRE for a major java version on this platform
+pub fn get_jre_url_for_major_version(major_java_version: u64) -> String {
+    let (os, arch) = adoptium_os_arch();
+    format!(
+        "https://api.adoptium.net/v3/binary/latest/{}/ga/{}/{}/jre/hotspot/normal/eclipse",
+        major_java_version, os, arch
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JrePackage {
+    pub url: String,
+    pub sha256: Option<String>,
+    /// Bytes, if known
+    pub size: Option<u64>,
+}
+
+/// The latest JRE package for a major java version on this platform, with its checksum
+///
+/// Falls back to the unverified download url if the Adoptium asset API is unavailable
+pub async fn get_jre_package(major_java_version: u64) -> JrePackage {
+    let (os, arch) = adoptium_os_arch();
+    let package = async {
+        let assets: Value = reqwest::Client::new()
+            .get(format!(
+                "https://api.adoptium.net/v3/assets/latest/{}/hotspot?architecture={}&image_type=jre&os={}&vendor=eclipse",
+                major_java_version, arch, os
+            ))
+            .send()
+            .await
+            .ok()?
+            .json()
+            .await
+            .ok()?;
+        let package = assets.as_array()?.first()?.get("binary")?.get("package")?;
+        Some(JrePackage {
+            url: package.get("link")?.as_str()?.to_string(),
+            sha256: Some(package.get("checksum")?.as_str()?.to_lowercase()),
+            size: package.get("size").and_then(Value::as_u64),
+        })
+    }
+    .await;
+    package.unwrap_or_else(|| {
+        warn!(
+            "Failed to get the checksum of JRE {}, the download won't be verified",
+            major_java_version
+        );
+        JrePackage {
+            url: get_jre_url_for_major_version(major_java_version),
+            sha256: None,
+            size: None,
+        }
+    })
+}
+
+/// Downloads and extracts a JRE into `path_to_java/jre{major_java_version}`, verifying its checksum
+pub async fn install_jre(
+    path_to_java: &Path,
+    major_java_version: u64,
+    on_download: &(dyn Fn(DownloadProgress) + Send + Sync),
+    cancel: Option<&CancellationToken>,
+) -> Result<PathBuf, Error> {
+    let jre_dir = path_to_java.join(format!("jre{major_java_version}"));
+    let package = get_jre_package(major_java_version).await;
+    let downloaded =
+        download_file(&package.url, path_to_java, None, on_download, true, cancel).await?;
+    if let Some(expected) = &package.sha256 {
+        let actual = sha256_file(&downloaded).await?;
+        if &actual != expected {
+            crate::util::fs::remove_file(&downloaded).await?;
+            return Err(eyre!(
+                "Checksum mismatch for JRE {}, expected {} but got {}",
+                major_java_version,
+                expected,
+                actual
+            )
+            .into());
+        }
+    }
+    let unzipped_content =
+        unzip_file_async(&downloaded, UnzipOption::ToDir(path_to_java.to_owned())).await?;
+    crate::util::fs::remove_file(&downloaded).await?;
+    if unzipped_content.len() != 1 {
+        return Err(eyre!(
+            "Expected only one file in the JRE archive, got {}",
+            unzipped_content.len()
+        )
+        .into());
+    }
+    if jre_dir.exists() {
+        crate::util::fs::remove_dir_all(&jre_dir).await?;
+    }
+    crate::util::fs::rename(unzipped_content.iter().last().unwrap(), &jre_dir).await?;
+    Ok(jre_dir)
+}
+
+pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
+    let client = reqwest::Client::new();
 
     let major_java_version = {
         let val = match serde_json::Value::from_str(
@@ -415,10 +523,7 @@ pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
     };
 
     Some((
-        format!(
-            "https://api.adoptium.net/v3/binary/latest/{}/ga/{}/{}/jre/hotspot/normal/eclipse",
-            major_java_version, os, arch
-        ),
+        get_jre_url_for_major_version(major_java_version),
         major_java_version,
     ))
 }
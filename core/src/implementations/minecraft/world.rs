@@ -0,0 +1,410 @@
+//! The world directories of a server, for backing them up, wiping them so the server
+//! generates a new world on its next start, and swapping in a world from an archive
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono_tz::Tz;
+use color_eyre::eyre::{eyre, Context};
+use tokio::sync::broadcast::Receiver;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+use walkdir::WalkDir;
+
+use crate::backup_retention::{
+    newest_backup_time, prune_backups, BackupPolicy, BackupPruneReport, BACKUP_TIMESTAMP_FORMAT,
+};
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::traits::t_configurable::{manifest::ConfigurableValue, TConfigurable};
+use crate::traits::t_server::{State, TServer};
+use crate::types::InstanceUuid;
+use crate::util::{fs, scoped_join_win_safe, zip_files_async};
+
+use super::configurable::ServerPropertySetting;
+use super::util::read_properties_from_path;
+use super::MinecraftInstance;
+
+const DEFAULT_LEVEL_NAME: &str = "world";
+
+/// Bukkit based servers keep the other dimensions next to the overworld instead of inside it
+const DIMENSION_SUFFIXES: [&str; 3] = ["", "_nether", "_the_end"];
+
+/// Where world backups go, relative to the instance root
+pub const WORLD_BACKUP_DIR: &str = "backups";
+
+/// How deep in an archive `level.dat` is looked for, maps are often zipped inside a folder
+const MAX_LEVEL_DAT_DEPTH: usize = 3;
+
+/// Suffix the replaced world directories get during an import, until it succeeded
+const ROLLBACK_SUFFIX: &str = ".import-rollback";
+
+/// The console line `save-all flush` finishes with
+const SAVED_LINE: &str = "Saved the game";
+
+/// How long to wait for `save-all flush` to finish before copying anyway
+const SAVE_ALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A world backup, with why it may be inconsistent if it was taken while the server was
+/// writing to the world
+pub struct WorldBackup {
+    pub archive: PathBuf,
+    pub warning: Option<String>,
+}
+
+/// Waits until the instance's console says the world was saved
+async fn wait_for_save(events: &mut Receiver<Event>, uuid: &InstanceUuid) {
+    while let Ok(event) = events.recv().await {
+        if let EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid,
+            instance_event_inner: InstanceEventInner::InstanceOutput { message, .. },
+            ..
+        }) = event.event_inner
+        {
+            if instance_uuid == *uuid && message.contains(SAVED_LINE) {
+                return;
+            }
+        }
+    }
+}
+
+/// The world directory in an extracted archive, the shallowest one holding a `level.dat`
+fn find_world_dir(extracted: &Path) -> Option<PathBuf> {
+    WalkDir::new(extracted)
+        .max_depth(MAX_LEVEL_DAT_DEPTH)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && entry.file_name() == "level.dat")
+        .min_by_key(|entry| entry.depth())
+        .and_then(|entry| entry.path().parent().map(Path::to_path_buf))
+}
+
+fn level_name_or_default(level_name: Option<&str>) -> String {
+    match level_name.map(str::trim) {
+        Some(level_name) if !level_name.is_empty() => level_name.to_owned(),
+        _ => DEFAULT_LEVEL_NAME.to_owned(),
+    }
+}
+
+/// Refuses level names that would point the world directories anywhere but a directory
+/// directly inside the instance root, like `../other` or `.`, which is the root itself
+fn check_level_name(level_name: &str) -> Result<(), Error> {
+    if level_name == "." || level_name == ".." || level_name.contains(['/', '\\']) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "level-name {} is not a plain directory name, fix it in server.properties",
+                level_name
+            ),
+        });
+    }
+    Ok(())
+}
+
+impl MinecraftInstance {
+    /// `level-name` from server.properties, `world` if it isn't set
+    pub async fn level_name(&self) -> String {
+        let properties = read_properties_from_path(&self.path_to_properties)
+            .await
+            .unwrap_or_default();
+        level_name_or_default(properties.get("level-name").map(String::as_str))
+    }
+
+    /// The level name, failing if it isn't safe to build world paths from
+    async fn checked_level_name(&self) -> Result<String, Error> {
+        let level_name = self.level_name().await;
+        check_level_name(&level_name)?;
+        Ok(level_name)
+    }
+
+    /// The world directories that currently exist, the overworld first
+    pub async fn world_dirs(&self) -> Result<Vec<PathBuf>, Error> {
+        let level_name = self.checked_level_name().await?;
+        let mut dirs = Vec::new();
+        for suffix in DIMENSION_SUFFIXES {
+            let dir =
+                scoped_join_win_safe(&self.path_to_instance, format!("{level_name}{suffix}"))?;
+            if dir == self.path_to_instance {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("level-name {} resolves to the instance root", level_name),
+                });
+            }
+            if dir.is_dir() {
+                dirs.push(dir);
+            }
+        }
+        Ok(dirs)
+    }
+
+    /// Zips the world directories into the instance's backup directory, returning the archive.
+    /// Its name ends with the time in `tz`. Nothing is left behind if `cancel` stops it
+    pub async fn backup_worlds(
+        &self,
+        dirs: &[PathBuf],
+        tz: Tz,
+        cancel: Option<CancellationToken>,
+    ) -> Result<PathBuf, Error> {
+        let archive = self.path_to_instance.join(WORLD_BACKUP_DIR).join(format!(
+            "{}-{}.zip",
+            self.checked_level_name().await?,
+            chrono::Utc::now()
+                .with_timezone(&tz)
+                .format(BACKUP_TIMESTAMP_FORMAT)
+        ));
+        zip_files_async(dirs, &archive, false, cancel).await
+    }
+
+    /// Deletes the world backups `policy` no longer keeps
+    pub async fn prune_world_backups(
+        &self,
+        policy: &BackupPolicy,
+        tz: Tz,
+    ) -> Result<BackupPruneReport, Error> {
+        prune_backups(&self.path_to_instance.join(WORLD_BACKUP_DIR), policy, tz).await
+    }
+
+    /// Whether the newest world backup is older than the interval of `policy`
+    pub async fn world_backup_due(&self, policy: &BackupPolicy, tz: Tz) -> bool {
+        let interval_hours = match policy.interval_hours {
+            Some(interval_hours) => interval_hours as i64,
+            None => return false,
+        };
+        let dir = self.path_to_instance.join(WORLD_BACKUP_DIR);
+        match tokio::task::spawn_blocking(move || newest_backup_time(&dir, tz)).await {
+            Ok(Some(newest)) => chrono::Utc::now().timestamp() - newest >= interval_hours * 60 * 60,
+            Ok(None) => true,
+            Err(_) => false,
+        }
+    }
+
+    /// Backs up the world directories, turning saving off around the copy if the server is
+    /// running so it doesn't write to the world mid-copy
+    ///
+    /// Saving is turned back on when the copy finishes, fails, or takes longer than
+    /// `save_off_timeout`. If saving can't be turned off, the backup is taken anyway with a
+    /// warning that it may be inconsistent
+    pub async fn hot_backup_worlds(
+        &self,
+        dirs: &[PathBuf],
+        save_off_timeout: Duration,
+        tz: Tz,
+    ) -> Result<WorldBackup, Error> {
+        if self.state().await != State::Running {
+            return Ok(WorldBackup {
+                archive: self.backup_worlds(dirs, tz, None).await?,
+                warning: None,
+            });
+        }
+        let mut events = self.event_broadcaster.subscribe();
+        if let Err(e) = self.send_command("save-off", CausedBy::System).await {
+            let warning = format!(
+                "Could not turn off saving ({}), the backup may be inconsistent",
+                e.source
+            );
+            warn!("[{}] {}", self.uuid, warning);
+            return Ok(WorldBackup {
+                archive: self.backup_worlds(dirs, tz, None).await?,
+                warning: Some(warning),
+            });
+        }
+
+        let mut warning = None;
+        let res = async {
+            self.send_command("save-all flush", CausedBy::System)
+                .await?;
+            if tokio::time::timeout(SAVE_ALL_TIMEOUT, wait_for_save(&mut events, &self.uuid))
+                .await
+                .is_err()
+            {
+                warning = Some(
+                    "The server didn't confirm saving the world, the backup may be inconsistent"
+                        .to_string(),
+                );
+            }
+            let cancel = CancellationToken::new();
+            let backup = self.backup_worlds(dirs, tz, Some(cancel.clone()));
+            tokio::pin!(backup);
+            match tokio::time::timeout(save_off_timeout, &mut backup).await {
+                Ok(res) => res,
+                Err(_) => {
+                    // the zip has to stop before saving is turned back on, an archive it
+                    // finished in the meantime is removed as well
+                    cancel.cancel();
+                    if let Ok(archive) = backup.await {
+                        if let Err(e) = fs::remove_file(&archive).await {
+                            error!("[{}] Failed to remove timed out backup: {}", self.uuid, e);
+                        }
+                    }
+                    Err(eyre!(
+                        "The backup took longer than {} seconds",
+                        save_off_timeout.as_secs()
+                    )
+                    .into())
+                }
+            }
+        }
+        .await;
+        if let Err(e) = self.send_command("save-on", CausedBy::System).await {
+            error!("[{}] Failed to turn saving back on: {}", self.uuid, e);
+        }
+        Ok(WorldBackup {
+            archive: res?,
+            warning,
+        })
+    }
+
+    async fn set_level_name(&self, level_name: &str) -> Result<(), Error> {
+        self.update_configurable(
+            ServerPropertySetting::get_section_id(),
+            &ServerPropertySetting::LevelName(String::new()).get_identifier(),
+            ConfigurableValue::String(level_name.to_owned()),
+        )
+        .await
+    }
+
+    /// Replaces the world directories with the world in `extracted`, an extracted archive,
+    /// returning the level name it was installed as
+    ///
+    /// The world keeps the name of its directory in the archive and `level-name` is changed
+    /// to match, or it takes the current level name if `level.dat` is at the archive root.
+    /// Everything is put back as it was if any step fails. The server must be stopped and
+    /// the caller backs up the current world first
+    pub async fn import_world(&self, extracted: &Path) -> Result<String, Error> {
+        let world_dir = find_world_dir(extracted).ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The archive doesn't contain a world, no level.dat found"),
+        })?;
+        let old_level_name = self.level_name().await;
+        let level_name = match world_dir.strip_prefix(extracted) {
+            Ok(relative) if relative.as_os_str().is_empty() => old_level_name.clone(),
+            _ => world_dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| old_level_name.clone()),
+        };
+        // the other dimensions of a Bukkit world are next to it in the archive
+        let mut incoming = Vec::new();
+        for suffix in DIMENSION_SUFFIXES {
+            let source = if suffix.is_empty() {
+                world_dir.clone()
+            } else {
+                match world_dir.parent() {
+                    Some(parent) if world_dir != extracted => {
+                        parent.join(format!("{level_name}{suffix}"))
+                    }
+                    _ => continue,
+                }
+            };
+            if source.is_dir() {
+                let dest =
+                    scoped_join_win_safe(&self.path_to_instance, format!("{level_name}{suffix}"))?;
+                incoming.push((source, dest));
+            }
+        }
+
+        let old_dirs = self.world_dirs().await?;
+        for (_, dest) in &incoming {
+            if dest.exists() && !old_dirs.contains(dest) {
+                return Err(Error {
+                    kind: ErrorKind::Conflict,
+                    source: eyre!(
+                        "{} already exists and isn't the current world",
+                        dest.display()
+                    ),
+                });
+            }
+        }
+
+        let mut set_aside = Vec::new();
+        let mut moved_in = Vec::new();
+        let res: Result<(), Error> = async {
+            for dir in &old_dirs {
+                let aside = PathBuf::from(format!("{}{ROLLBACK_SUFFIX}", dir.display()));
+                fs::rename(dir, &aside).await?;
+                set_aside.push((dir.clone(), aside));
+            }
+            for (source, dest) in &incoming {
+                fs::rename(source, dest).await?;
+                moved_in.push(dest.clone());
+            }
+            if level_name != old_level_name {
+                self.set_level_name(&level_name).await?;
+            }
+            Ok(())
+        }
+        .await;
+
+        match res {
+            Ok(()) => {
+                for (_, aside) in set_aside {
+                    if let Err(e) = fs::remove_dir_all(&aside).await {
+                        error!("Failed to remove replaced world: {}", e);
+                    }
+                }
+                Ok(level_name)
+            }
+            Err(e) => {
+                for dir in moved_in {
+                    if let Err(e) = fs::remove_dir_all(&dir).await {
+                        error!("Failed to remove partially imported world: {}", e);
+                    }
+                }
+                for (dir, aside) in set_aside {
+                    if let Err(e) = fs::rename(&aside, &dir).await {
+                        error!("Failed to put back the previous world: {}", e);
+                    }
+                }
+                if self.level_name().await != old_level_name {
+                    if let Err(e) = self.set_level_name(&old_level_name).await {
+                        error!("Failed to restore level-name: {}", e);
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Deletes the world directories, the caller backs them up first
+    pub async fn delete_worlds(&self, dirs: &[PathBuf]) -> Result<(), Error> {
+        for dir in dirs {
+            tokio::fs::remove_dir_all(dir)
+                .await
+                .context(format!("Failed to delete {}", dir.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_find_world_dir() {
+    let temp_dir = tempdir::TempDir::new("test_find_world_dir").unwrap();
+    let root = temp_dir.path();
+    assert_eq!(find_world_dir(root), None);
+
+    std::fs::create_dir_all(root.join("Skyblock/region")).unwrap();
+    std::fs::write(root.join("Skyblock/region/level.dat"), b"").unwrap();
+    std::fs::write(root.join("Skyblock/level.dat"), b"").unwrap();
+    assert_eq!(find_world_dir(root), Some(root.join("Skyblock")));
+
+    std::fs::write(root.join("level.dat"), b"").unwrap();
+    assert_eq!(find_world_dir(root), Some(root.to_path_buf()));
+}
+
+#[test]
+fn test_level_name_or_default() {
+    assert_eq!(level_name_or_default(Some("survival")), "survival");
+    assert_eq!(level_name_or_default(Some(" ")), "world");
+    assert_eq!(level_name_or_default(None), "world");
+}
+
+#[test]
+fn test_check_level_name() {
+    assert!(check_level_name("world").is_ok());
+    assert!(check_level_name("My World.v2").is_ok());
+    assert!(check_level_name(".").is_err());
+    assert!(check_level_name("..").is_err());
+    assert!(check_level_name("../other").is_err());
+    assert!(check_level_name("worlds/survival").is_err());
+    assert!(check_level_name("worlds\\survival").is_err());
+}
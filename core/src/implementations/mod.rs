@@ -1,2 +1,3 @@
 pub mod generic;
 pub mod minecraft;
+pub mod registry;
@@ -0,0 +1,189 @@
+//! The game types with an implementation, used both to restore an instance from the game type in
+//! its `.lodestone_config` and to tell the frontend what can be set up
+//!
+//! A new implementation only has to be registered in [`registered_game_types`]
+
+use std::path::PathBuf;
+
+use color_eyre::eyre::eyre;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::EventBroadcaster;
+use crate::handlers::instance_setup_configs::HandlerGameType;
+use crate::macro_executor::MacroExecutor;
+use crate::prelude::GameInstance;
+use crate::traits::t_configurable::GameType;
+use crate::types::DotLodestoneConfig;
+
+use super::{generic, minecraft};
+
+type RestoreFn = fn(
+    PathBuf,
+    DotLodestoneConfig,
+    EventBroadcaster,
+    MacroExecutor,
+) -> BoxFuture<'static, Result<GameInstance, Error>>;
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct SetupVariant {
+    pub id: HandlerGameType,
+    pub display_name: String,
+}
+
+/// How an instance of a game type is set up
+#[derive(Debug, Clone, Serialize, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum SetupMethod {
+    /// Each variant has its setup manifest at `GET /setup_manifest/:id` and is created with
+    /// `POST /instance/create/:id`
+    Manifest { variants: Vec<SetupVariant> },
+    /// Set up from the URL of its implementation with `PUT /generic_setup_manifest`, created
+    /// with `POST /instance/create_generic`
+    Url,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct GameTypeDependency {
+    pub name: String,
+    pub description: String,
+    /// Whether lodestone installs it by itself
+    pub managed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct GameTypeManifest {
+    pub game_type: GameType,
+    pub display_name: String,
+    pub setup: SetupMethod,
+    pub dependencies: Vec<GameTypeDependency>,
+}
+
+pub struct GameTypeRegistration {
+    pub manifest: GameTypeManifest,
+    restore: RestoreFn,
+}
+
+fn variant(id: HandlerGameType, display_name: &str) -> SetupVariant {
+    SetupVariant {
+        id,
+        display_name: display_name.to_string(),
+    }
+}
+
+fn restore_minecraft(
+    path: PathBuf,
+    config: DotLodestoneConfig,
+    event_broadcaster: EventBroadcaster,
+    macro_executor: MacroExecutor,
+) -> BoxFuture<'static, Result<GameInstance, Error>> {
+    async move {
+        minecraft::MinecraftInstance::restore(path, config, event_broadcaster, macro_executor)
+            .await
+            .map(GameInstance::from)
+    }
+    .boxed()
+}
+
+fn restore_generic(
+    path: PathBuf,
+    config: DotLodestoneConfig,
+    event_broadcaster: EventBroadcaster,
+    macro_executor: MacroExecutor,
+) -> BoxFuture<'static, Result<GameInstance, Error>> {
+    async move {
+        generic::GenericInstance::restore(path, config, event_broadcaster, macro_executor)
+            .await
+            .map(GameInstance::from)
+    }
+    .boxed()
+}
+
+pub fn registered_game_types() -> Vec<GameTypeRegistration> {
+    vec![
+        GameTypeRegistration {
+            manifest: GameTypeManifest {
+                game_type: GameType::MinecraftJava,
+                display_name: "Minecraft: Java Edition".to_string(),
+                setup: SetupMethod::Manifest {
+                    variants: vec![
+                        variant(HandlerGameType::MinecraftJavaVanilla, "Vanilla"),
+                        variant(HandlerGameType::MinecraftFabric, "Fabric"),
+                        variant(HandlerGameType::MinecraftForge, "Forge"),
+                        variant(HandlerGameType::MinecraftPaper, "Paper"),
+                    ],
+                },
+                dependencies: vec![GameTypeDependency {
+                    name: "Java".to_string(),
+                    description: "The Java runtime the selected Minecraft version needs"
+                        .to_string(),
+                    managed: true,
+                }],
+            },
+            restore: restore_minecraft,
+        },
+        GameTypeRegistration {
+            manifest: GameTypeManifest {
+                game_type: GameType::Generic,
+                display_name: "Generic".to_string(),
+                setup: SetupMethod::Url,
+                dependencies: vec![GameTypeDependency {
+                    name: "Instance implementation".to_string(),
+                    description:
+                        "A TypeScript implementation of the instance, fetched from the URL given at setup"
+                            .to_string(),
+                    managed: false,
+                }],
+            },
+            restore: restore_generic,
+        },
+    ]
+}
+
+pub fn game_type_manifests() -> Vec<GameTypeManifest> {
+    registered_game_types()
+        .into_iter()
+        .map(|registration| registration.manifest)
+        .collect()
+}
+
+/// Constructs the instance stored in `path` with the implementation of its game type
+pub async fn restore_by_game_type(
+    path: PathBuf,
+    config: DotLodestoneConfig,
+    event_broadcaster: EventBroadcaster,
+    macro_executor: MacroExecutor,
+) -> Result<GameInstance, Error> {
+    let game_type = config.game_type().clone();
+    let registration = registered_game_types()
+        .into_iter()
+        .find(|registration| registration.manifest.game_type == game_type)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!(
+                "No implementation is registered for game type {:?}",
+                game_type
+            ),
+        })?;
+    (registration.restore)(path, config, event_broadcaster, macro_executor).await
+}
+
+#[test]
+fn test_registered_game_types_are_unique() {
+    let manifests = game_type_manifests();
+    for (index, manifest) in manifests.iter().enumerate() {
+        assert!(!manifests[index + 1..]
+            .iter()
+            .any(|other| other.game_type == manifest.game_type));
+    }
+    assert!(manifests
+        .iter()
+        .all(|manifest| manifest.game_type != GameType::MinecraftBedrock));
+}
@@ -0,0 +1,179 @@
+//! When each instance entered its current state, and whether it stopped on its own
+
+use std::sync::Arc;
+
+use dashmap::{DashMap, DashSet};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{traits::t_server::State, types::InstanceUuid};
+
+/// Stopped → Starting → Running → Stopping → Stopped, with `Crashed` when the
+/// server went from Starting or Running to Stopped without being asked to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum LifecycleState {
+    Stopped,
+    Starting,
+    Running,
+    Stopping,
+    Crashed,
+    Error,
+}
+
+impl From<State> for LifecycleState {
+    fn from(state: State) -> Self {
+        match state {
+            State::Starting => LifecycleState::Starting,
+            State::Running => LifecycleState::Running,
+            State::Stopping => LifecycleState::Stopping,
+            State::Stopped => LifecycleState::Stopped,
+            State::Error => LifecycleState::Error,
+        }
+    }
+}
+
+impl LifecycleState {
+    fn matches(self, state: State) -> bool {
+        match self {
+            LifecycleState::Crashed => state == State::Stopped,
+            _ => self == state.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct InstanceStateReport {
+    pub state: LifecycleState,
+    /// Unix time the state was entered, `None` if it was entered before Lodestone Core started
+    pub since: Option<i64>,
+}
+
+fn next_state(previous: Option<LifecycleState>, to: State, stop_requested: bool) -> LifecycleState {
+    match (previous, to) {
+        (Some(LifecycleState::Starting | LifecycleState::Running), State::Stopped)
+            if !stop_requested =>
+        {
+            LifecycleState::Crashed
+        }
+        (_, to) => to.into(),
+    }
+}
+
+/// Fed with every state transition event
+#[derive(Debug, Clone, Default)]
+pub struct StateTracker {
+    entered: Arc<DashMap<InstanceUuid, (LifecycleState, i64)>>,
+    stop_requested: Arc<DashSet<InstanceUuid>>,
+}
+
+impl StateTracker {
+    /// Marks the next stop of the instance as asked for, so a kill that skips
+    /// `Stopping` isn't reported as a crash
+    pub fn request_stop(&self, uuid: &InstanceUuid) {
+        self.stop_requested.insert(uuid.clone());
+    }
+
+    pub fn record(&self, uuid: &InstanceUuid, to: State, at: i64) {
+        let stop_requested = match to {
+            State::Stopped | State::Starting => self.stop_requested.remove(uuid).is_some(),
+            _ => self.stop_requested.contains(uuid),
+        };
+        let previous = self.entered.get(uuid).map(|entry| entry.0);
+        let next = next_state(previous, to, stop_requested);
+        if previous != Some(next) {
+            self.entered.insert(uuid.clone(), (next, at));
+        }
+    }
+
+    pub fn forget(&self, uuid: &InstanceUuid) {
+        self.entered.remove(uuid);
+        self.stop_requested.remove(uuid);
+    }
+
+    /// `current` is what the instance itself reports, and wins if a transition was missed
+    pub fn report(&self, uuid: &InstanceUuid, current: State) -> InstanceStateReport {
+        match self.entered.get(uuid).map(|entry| *entry) {
+            Some((state, since)) if state.matches(current) => InstanceStateReport {
+                state,
+                since: Some(since),
+            },
+            _ => InstanceStateReport {
+                state: current.into(),
+                since: None,
+            },
+        }
+    }
+}
+
+#[test]
+fn test_state_tracker() {
+    let tracker = StateTracker::default();
+    let uuid = InstanceUuid::from("INSTANCE_1".to_string());
+    assert_eq!(
+        tracker.report(&uuid, State::Stopped),
+        InstanceStateReport {
+            state: LifecycleState::Stopped,
+            since: None
+        }
+    );
+
+    tracker.record(&uuid, State::Starting, 10);
+    tracker.record(&uuid, State::Running, 20);
+    tracker.record(&uuid, State::Stopping, 30);
+    tracker.record(&uuid, State::Stopped, 40);
+    assert_eq!(
+        tracker.report(&uuid, State::Stopped),
+        InstanceStateReport {
+            state: LifecycleState::Stopped,
+            since: Some(40)
+        }
+    );
+
+    tracker.record(&uuid, State::Starting, 50);
+    tracker.record(&uuid, State::Running, 60);
+    // the process exited without a stop being requested
+    tracker.record(&uuid, State::Stopped, 70);
+    assert_eq!(
+        tracker.report(&uuid, State::Stopped),
+        InstanceStateReport {
+            state: LifecycleState::Crashed,
+            since: Some(70)
+        }
+    );
+
+    tracker.record(&uuid, State::Starting, 80);
+    tracker.record(&uuid, State::Running, 90);
+    // killed by the user, which skips Stopping
+    tracker.request_stop(&uuid);
+    tracker.record(&uuid, State::Stopped, 100);
+    assert_eq!(
+        tracker.report(&uuid, State::Stopped),
+        InstanceStateReport {
+            state: LifecycleState::Stopped,
+            since: Some(100)
+        }
+    );
+
+    // a request that never led to a stop doesn't carry over to the next run
+    tracker.request_stop(&uuid);
+    tracker.record(&uuid, State::Starting, 110);
+    tracker.record(&uuid, State::Running, 120);
+    tracker.record(&uuid, State::Stopped, 130);
+    assert_eq!(
+        tracker.report(&uuid, State::Stopped),
+        InstanceStateReport {
+            state: LifecycleState::Crashed,
+            since: Some(130)
+        }
+    );
+    // a missed transition falls back to the instance's own state
+    assert_eq!(
+        tracker.report(&uuid, State::Running),
+        InstanceStateReport {
+            state: LifecycleState::Running,
+            since: None
+        }
+    );
+}
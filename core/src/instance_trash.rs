@@ -0,0 +1,122 @@
+//! Deleted instances are moved to the trash first, so they can be restored until
+//! the retention period runs out
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::error::{Error, ErrorKind};
+use crate::prelude::{path_to_instances, path_to_trash};
+use crate::traits::InstanceInfo;
+use crate::types::InstanceUuid;
+use crate::util::fs;
+
+/// Written into a trashed instance's directory, next to its `.lodestone_config`
+const TRASH_MARKER: &str = ".lodestone_deleted";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TrashMarker {
+    /// Name of the directory under `instances/` to restore into
+    dir_name: String,
+    info: InstanceInfo,
+}
+
+fn trashed_path(uuid: &InstanceUuid) -> PathBuf {
+    path_to_trash().join(uuid.no_prefix())
+}
+
+fn read_marker(trashed_path: &Path) -> Result<TrashMarker, Error> {
+    let marker = std::fs::read_to_string(trashed_path.join(TRASH_MARKER))
+        .context("Failed to read trash marker")?;
+    Ok(serde_json::from_str(&marker).context("Failed to parse trash marker")?)
+}
+
+fn is_expired(deleted_at: i64, now: i64, retention_days: u32) -> bool {
+    now - deleted_at >= retention_days as i64 * 24 * 60 * 60
+}
+
+/// Moves an instance's directory into the trash
+///
+/// The instance must already be removed from the app state
+pub async fn move_to_trash(instance_path: &Path, mut info: InstanceInfo) -> Result<(), Error> {
+    let dir_name = instance_path
+        .file_name()
+        .ok_or_else(|| eyre!("Instance path has no directory name"))?
+        .to_string_lossy()
+        .to_string();
+    let trashed_path = trashed_path(&info.uuid);
+    if trashed_path.exists() {
+        fs::remove_dir_all(&trashed_path).await?;
+    }
+    fs::rename(instance_path, &trashed_path).await?;
+    info.deleted_at = Some(chrono::Utc::now().timestamp());
+    info.path = trashed_path.display().to_string();
+    fs::write_all(
+        trashed_path.join(TRASH_MARKER),
+        serde_json::to_string_pretty(&TrashMarker { dir_name, info }).unwrap(),
+    )
+    .await
+}
+
+/// Info of every instance in the trash, as it was when deleted
+pub fn list_trashed() -> Vec<InstanceInfo> {
+    let entries = match path_to_trash().read_dir() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to read trash directory: {e}");
+            return Vec::new();
+        }
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| read_marker(&entry.path()).ok())
+        .map(|marker| marker.info)
+        .collect()
+}
+
+/// Moves a trashed instance back into the instances directory, returning its new path
+pub async fn take_from_trash(uuid: &InstanceUuid) -> Result<PathBuf, Error> {
+    let trashed_path = trashed_path(uuid);
+    let marker = read_marker(&trashed_path).map_err(|_| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance is not in the trash"),
+    })?;
+    let instance_path = path_to_instances().join(&marker.dir_name);
+    if instance_path.exists() {
+        return Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!(
+                "Cannot restore instance, {} already exists",
+                instance_path.display()
+            ),
+        });
+    }
+    fs::remove_file(trashed_path.join(TRASH_MARKER)).await?;
+    fs::rename(&trashed_path, &instance_path).await?;
+    Ok(instance_path)
+}
+
+/// Permanently deletes instances that have been in the trash longer than `retention_days`
+pub async fn sweep_trash(retention_days: u32) {
+    let now = chrono::Utc::now().timestamp();
+    for info in list_trashed() {
+        match info.deleted_at {
+            Some(deleted_at) if is_expired(deleted_at, now, retention_days) => {}
+            _ => continue,
+        }
+        match fs::remove_dir_all(trashed_path(&info.uuid)).await {
+            Ok(_) => info!("Permanently deleted instance {} from the trash", info.name),
+            Err(e) => error!("Failed to empty {} from the trash: {e}", info.name),
+        }
+    }
+}
+
+#[test]
+fn test_is_expired() {
+    let day = 24 * 60 * 60;
+    assert!(!is_expired(0, 6 * day, 7));
+    assert!(is_expired(0, 7 * day, 7));
+    assert!(is_expired(100, 100, 0));
+}
@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::prelude::path_to_binaries;
+use crate::util::dont_spawn_terminal;
+
+/// `java -version` should answer almost instantly, a broken install may hang instead
+const JAVA_VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct JavaRuntime {
+    pub path: PathBuf,
+    /// Full version string, e.g. `17.0.8` or `1.8.0_382`
+    pub version: String,
+    pub major_version: u64,
+    /// Downloaded and managed by lodestone rather than installed on the system
+    pub managed: bool,
+}
+
+fn java_executable_name() -> &'static str {
+    if cfg!(windows) {
+        "java.exe"
+    } else {
+        "java"
+    }
+}
+
+/// Path to the `java` executable inside a JRE or JDK home directory
+pub fn java_binary(java_home: &Path) -> PathBuf {
+    java_home
+        .join(if std::env::consts::OS == "macos" {
+            "Contents/Home/bin"
+        } else {
+            "bin"
+        })
+        .join(java_executable_name())
+}
+
+/// Extracts the version from the output of `java -version`, which goes to stderr
+///
+/// Java 8 and older report `1.8.0_382` style versions, whose major version is the second component
+pub fn parse_java_version_output(output: &str) -> Option<(String, u64)> {
+    let version = output
+        .lines()
+        .find(|line| line.contains("version \""))?
+        .split('"')
+        .nth(1)?
+        .to_string();
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    let major = match parts.next()?.parse::<u64>().ok()? {
+        1 => parts.next()?.parse().ok()?,
+        major => major,
+    };
+    Some((version, major))
+}
+
+/// Runs `java -version`, returns `None` if it isn't a working java executable
+pub async fn probe_java(path: &Path) -> Option<(String, u64)> {
+    let output = tokio::time::timeout(
+        JAVA_VERSION_TIMEOUT,
+        dont_spawn_terminal(tokio::process::Command::new(path).arg("-version"))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output(),
+    )
+    .await
+    .ok()?
+    .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_java_version_output(&String::from_utf8_lossy(&output.stderr))
+}
+
+/// Java homes in the usual install locations of each platform
+fn common_java_homes() -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+    if cfg!(target_os = "macos") {
+        roots.push("/Library/Java/JavaVirtualMachines".into());
+    } else if cfg!(windows) {
+        for program_files in ["ProgramFiles", "ProgramW6432"] {
+            if let Some(dir) = std::env::var_os(program_files) {
+                let dir = PathBuf::from(dir);
+                for vendor in [
+                    "Java",
+                    "Eclipse Adoptium",
+                    "Microsoft",
+                    "Zulu",
+                    "Amazon Corretto",
+                ] {
+                    roots.push(dir.join(vendor));
+                }
+            }
+        }
+    } else {
+        roots.push("/usr/lib/jvm".into());
+        roots.push("/usr/java".into());
+        roots.push("/opt/java".into());
+    }
+    roots.push(path_to_binaries().join("java"));
+
+    let mut homes = Vec::new();
+    for root in roots {
+        if let Ok(entries) = std::fs::read_dir(&root) {
+            homes.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()));
+        }
+    }
+    homes
+}
+
+fn candidate_java_paths() -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Some(java_home) = std::env::var_os("JAVA_HOME") {
+        candidates.push(
+            PathBuf::from(java_home)
+                .join("bin")
+                .join(java_executable_name()),
+        );
+    }
+    if let Some(path) = std::env::var_os("PATH") {
+        candidates.extend(std::env::split_paths(&path).map(|dir| dir.join(java_executable_name())));
+    }
+    candidates.extend(common_java_homes().iter().map(|home| java_binary(home)));
+    candidates
+}
+
+/// Finds every working java executable on this machine
+pub async fn detect_java_runtimes() -> Vec<JavaRuntime> {
+    let managed_root = path_to_binaries().join("java");
+    let mut seen = HashSet::new();
+    let mut runtimes = Vec::new();
+    for candidate in candidate_java_paths() {
+        // PATH entries are usually symlinks to a runtime found elsewhere
+        let path = match tokio::fs::canonicalize(&candidate).await {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        if let Some((version, major_version)) = probe_java(&path).await {
+            runtimes.push(JavaRuntime {
+                managed: path.starts_with(&managed_root),
+                path,
+                version,
+                major_version,
+            });
+        }
+    }
+    runtimes.sort_by(|a, b| b.major_version.cmp(&a.major_version));
+    runtimes
+}
+
+#[test]
+fn test_parse_java_version_output() {
+    let openjdk_17 = "openjdk version \"17.0.8\" 2023-07-18\nOpenJDK Runtime Environment Temurin-17.0.8+7 (build 17.0.8+7)\n";
+    assert_eq!(
+        parse_java_version_output(openjdk_17),
+        Some(("17.0.8".to_string(), 17))
+    );
+    let java_8 =
+        "java version \"1.8.0_382\"\nJava(TM) SE Runtime Environment (build 1.8.0_382-b05)\n";
+    assert_eq!(
+        parse_java_version_output(java_8),
+        Some(("1.8.0_382".to_string(), 8))
+    );
+    let java_21_ea = "openjdk version \"21-ea\" 2023-09-19\n";
+    assert_eq!(
+        parse_java_version_output(java_21_ea),
+        Some(("21-ea".to_string(), 21))
+    );
+    assert_eq!(parse_java_version_output("command not found"), None);
+}
@@ -1,45 +1,50 @@
 #![allow(clippy::comparison_chain, clippy::type_complexity)]
 
-use crate::error::ErrorKind;
 use crate::event_broadcaster::EventBroadcaster;
 use crate::handlers::extension::get_extension_routes;
 use crate::migration::migrate;
 use crate::prelude::{
-    init_app_state, init_paths, lodestone_path, path_to_global_settings, path_to_stores,
+    init_app_state, init_paths_with, lodestone_path, path_to_global_settings, path_to_stores,
     path_to_tmp, path_to_users, VERSION,
 };
-use crate::traits::t_configurable::GameType;
 use crate::traits::t_server::State;
 use crate::{
-    db::write::write_event_to_db_task,
+    db::{
+        health::DbHealth,
+        read::{get_console_output, get_sessions},
+        write::{sync_sessions, write_event_to_db_task},
+    },
     global_settings::GlobalSettingsData,
     handlers::{
-        checks::get_checks_routes, core_info::get_core_info_routes, events::get_events_routes,
-        gateway::get_gateway_routes, global_fs::get_global_fs_routes,
-        global_settings::get_global_settings_routes, instance::*,
-        instance_config::get_instance_config_routes, instance_fs::get_instance_fs_routes,
-        instance_macro::get_instance_macro_routes, instance_players::get_instance_players_routes,
-        instance_server::get_instance_server_routes,
-        instance_setup_configs::get_instance_setup_config_routes, monitor::get_monitor_routes,
-        playitgg::get_playitgg_routes, setup::get_setup_route, system::get_system_routes,
+        checks::get_checks_routes, console_history::get_console_history_routes,
+        core_info::get_core_info_routes, events::get_events_routes, gateway::get_gateway_routes,
+        global_fs::get_global_fs_routes, global_settings::get_global_settings_routes, instance::*,
+        instance_archive::get_instance_archive_routes, instance_config::get_instance_config_routes,
+        instance_crash_reports::get_instance_crash_reports_routes,
+        instance_fs::get_instance_fs_routes, instance_macro::get_instance_macro_routes,
+        instance_players::get_instance_players_routes,
+        instance_plugins::get_instance_plugins_routes, instance_server::get_instance_server_routes,
+        instance_setup_configs::get_instance_setup_config_routes,
+        instance_world::get_instance_world_routes, monitor::get_monitor_routes,
+        peers::get_peers_routes, playitgg::get_playitgg_routes, secrets::get_secrets_routes,
+        setup::get_setup_route, system::get_system_routes, tasks::get_tasks_routes,
         users::get_user_routes,
     },
     util::rand_alphanumeric,
 };
 
 use auth::user::UsersManager;
-use axum::Router;
+use axum::{extract::DefaultBodyLimit, Router};
 
 use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
 use color_eyre::eyre::Context;
 use color_eyre::Report;
-use dashmap::DashMap;
-use error::Error;
-use events::{CausedBy, Event};
+use dashmap::{DashMap, DashSet};
+use events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
 use futures::Future;
 use global_settings::GlobalSettings;
-use implementations::{generic, minecraft};
+use implementations::minecraft;
 use macro_executor::MacroExecutor;
 use playitgg::utils::is_valid_secret_key;
 use port_manager::PortManager;
@@ -47,15 +52,13 @@ use prelude::GameInstance;
 use reqwest::{header, Method};
 use ringbuffer::{AllocRingBuffer, RingBufferWrite};
 
-use fs3::FileExt;
+use data_dir_lock::DataDirLock;
 use semver::Version;
-use sqlx::{sqlite::SqliteConnectOptions, Pool};
 use std::sync::atomic::AtomicBool;
 use std::{
     collections::{HashMap, HashSet},
     net::SocketAddr,
     path::{Path, PathBuf},
-    str::FromStr,
     sync::Arc,
     time::Duration,
 };
@@ -65,7 +68,11 @@ use tokio::{
     sync::{broadcast::error::RecvError, Mutex, RwLock},
 };
 use tower_http::{
-    cors::{Any, CorsLayer},
+    compression::{
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+        CompressionLayer,
+    },
+    cors::{AllowHeaders, AllowOrigin, CorsLayer},
     trace::TraceLayer,
 };
 use tracing::{debug, error, info, warn};
@@ -75,30 +82,67 @@ use traits::{t_configurable::TConfigurable, t_server::MonitorReport, t_server::T
 use types::{DotLodestoneConfig, InstanceUuid};
 use uuid::Uuid;
 
+mod ansi;
 pub mod auth;
+mod auto_start;
+mod backup_retention;
+mod body_limit;
+mod branding;
+mod cgroup;
 mod command_console;
+mod command_filter;
+mod confirmation;
+mod console_filter;
+mod correlation;
+mod data_dir_lock;
 pub mod db;
 mod deno_ops;
+mod disk_usage;
 mod docker_bridge;
 pub mod error;
 mod event_broadcaster;
+mod event_retention;
 mod events;
 mod extension;
+mod file_diff;
+mod file_preview;
+mod file_trash;
+mod gateway;
 pub mod global_settings;
 mod handlers;
+mod idempotency;
 pub mod implementations;
+mod instance_state;
+mod instance_trash;
+mod java_runtimes;
+mod log_cleanup;
 pub mod macro_executor;
 mod migration;
+mod modrinth;
+mod monitor_history;
+mod nat;
+mod offsite_backup;
 mod output_types;
+mod peers;
 pub mod playitgg;
+mod plugins;
 mod port_manager;
 pub mod prelude;
+mod provision;
+mod secrets;
+mod startup_diagnostics;
+mod tasks;
 pub mod tauri_export;
+mod timezone;
 mod traits;
 pub mod types;
 pub mod util;
+mod variables;
+mod webhooks;
 use handlers::global_fs::DownloadableFile;
 
+pub use error::{Error, ErrorKind};
+
 #[derive(Clone)]
 pub struct AppState {
     instances: Arc<DashMap<InstanceUuid, GameInstance>>,
@@ -110,6 +154,8 @@ pub struct AppState {
     uuid: String,
     up_since: i64,
     global_settings: Arc<Mutex<GlobalSettings>>,
+    /// The timezone of the global settings, read on every request
+    timezone: tokio::sync::watch::Receiver<chrono_tz::Tz>,
     system: Arc<Mutex<sysinfo::System>>,
     port_manager: Arc<Mutex<PortManager>>,
     first_time_setup_key: Arc<Mutex<Option<String>>>,
@@ -117,14 +163,30 @@ pub struct AppState {
     download_urls: Arc<Mutex<HashMap<String, DownloadableFile>>>,
     macro_executor: MacroExecutor,
     sqlite_pool: sqlx::SqlitePool,
+    /// Whether `sqlite_pool` can be used, lodestone runs without the database if it can't be opened
+    db_health: DbHealth,
     docker_bridge: docker_bridge::DockerBridge,
     playit_keep_running: Arc<Mutex<Option<Arc<AtomicBool>>>>,
+    gateway: gateway::Gateway,
+    instance_sizes: disk_usage::InstanceSizeCache,
+    /// Early triggers of restarts that are counting down, by instance
+    pending_restarts: Arc<DashMap<InstanceUuid, Arc<tokio::sync::Notify>>>,
+    /// Instances running with config changes that only apply after a restart
+    restart_required: Arc<DashSet<InstanceUuid>>,
+    idempotency_keys: idempotency::IdempotencyCache,
+    confirmations: confirmation::ConfirmationTokens,
+    nat: nat::NatManager,
+    instance_states: instance_state::StateTracker,
+    tasks: tasks::TaskRegistry,
+    peers: peers::PeerRegistry,
+    secrets: secrets::SecretStore,
 }
 
 impl AppState {
     /// Kill all instances
     pub async fn cleanup(&mut self) {
         for instance in self.instances.iter() {
+            self.instance_states.request_stop(instance.key());
             let instance = instance.value().clone();
             tokio::task::spawn(async move {
                 if let Err(e) = instance.kill(CausedBy::System).await {
@@ -135,6 +197,118 @@ impl AppState {
     }
 }
 
+#[cfg(test)]
+impl AppState {
+    /// A state with default settings and no instances or users, stored under `path`
+    pub(crate) async fn for_tests(path: &std::path::Path) -> AppState {
+        let (tx, _rx) = EventBroadcaster::new(16);
+        let global_settings = GlobalSettings::new(
+            path.join("global_settings.json"),
+            tx.clone(),
+            GlobalSettingsData::default(),
+        );
+        let timezone = global_settings.subscribe_timezone();
+        let instances = Arc::new(DashMap::new());
+        AppState {
+            instances: instances.clone(),
+            users_manager: Arc::new(RwLock::new(UsersManager::new(
+                tx.clone(),
+                HashMap::new(),
+                path.join("users.json"),
+            ))),
+            events_buffer: Arc::new(Mutex::new(AllocRingBuffer::with_capacity(16))),
+            console_out_buffer: Arc::new(Mutex::new(HashMap::new())),
+            monitor_buffer: Arc::new(Mutex::new(HashMap::new())),
+            event_broadcaster: tx.clone(),
+            uuid: Uuid::new_v4().to_string(),
+            up_since: chrono::Utc::now().timestamp(),
+            global_settings: Arc::new(Mutex::new(global_settings)),
+            timezone,
+            system: Arc::new(Mutex::new(sysinfo::System::new())),
+            port_manager: Arc::new(Mutex::new(PortManager::new(HashSet::new()))),
+            first_time_setup_key: Arc::new(Mutex::new(None)),
+            playitgg_key: Arc::new(Mutex::new(None)),
+            download_urls: Arc::new(Mutex::new(HashMap::new())),
+            macro_executor: MacroExecutor::new(tx.clone(), tokio::runtime::Handle::current()),
+            sqlite_pool: db::health::lazy_pool(&path.join("data.db")).unwrap(),
+            db_health: DbHealth::default(),
+            docker_bridge: docker_bridge::DockerBridge::new(
+                tx.clone(),
+                path.join("docker_bridge.json"),
+            )
+            .await
+            .unwrap(),
+            playit_keep_running: Arc::new(Mutex::new(None)),
+            gateway: gateway::Gateway::new(path.join("gateway.json"), instances)
+                .await
+                .unwrap(),
+            instance_sizes: disk_usage::InstanceSizeCache::default(),
+            pending_restarts: Arc::new(DashMap::new()),
+            restart_required: Arc::new(DashSet::new()),
+            idempotency_keys: idempotency::IdempotencyCache::default(),
+            confirmations: confirmation::ConfirmationTokens::default(),
+            nat: nat::NatManager::default(),
+            instance_states: instance_state::StateTracker::default(),
+            tasks: tasks::TaskRegistry::default(),
+            peers: peers::PeerRegistry::default(),
+            secrets: secrets::SecretStore::load(path).await.unwrap(),
+        }
+    }
+}
+
+/// Console lines kept in memory per instance
+const CONSOLE_OUT_BUFFER_SIZE: usize = 1024;
+
+/// Fills the console buffers with the output stored before lodestone last stopped
+async fn restore_console_buffers(state: &AppState) {
+    let mut console_out_buffer = state.console_out_buffer.lock().await;
+    for entry in state.instances.iter() {
+        let events = match get_console_output(
+            &state.sqlite_pool,
+            entry.key(),
+            CONSOLE_OUT_BUFFER_SIZE as u32,
+            None,
+        )
+        .await
+        {
+            Ok(events) => events,
+            Err(e) => {
+                error!("Failed to restore console output of {}: {}", entry.key(), e);
+                continue;
+            }
+        };
+        let buffer = console_out_buffer
+            .entry(entry.key().to_owned())
+            .or_insert_with(|| AllocRingBuffer::with_capacity(CONSOLE_OUT_BUFFER_SIZE));
+        for event in events {
+            buffer.push(event);
+        }
+    }
+}
+
+/// Loads the instance stored in `path` from its `.lodestone_config`
+pub(crate) async fn restore_instance(
+    path: &Path,
+    event_broadcaster: EventBroadcaster,
+    macro_executor: MacroExecutor,
+) -> Result<(InstanceUuid, GameInstance), Error> {
+    let dot_lodestone_config = DotLodestoneConfig::read_from(path).await?;
+
+    debug!("restoring instance: {}", path.display());
+    let instance = implementations::registry::restore_by_game_type(
+        path.to_owned(),
+        dot_lodestone_config.clone(),
+        event_broadcaster,
+        macro_executor,
+    )
+    .await?;
+    debug!(
+        "Restored {:?} instance successfully",
+        dot_lodestone_config.game_type()
+    );
+    Ok((dot_lodestone_config.uuid().to_owned(), instance))
+}
+
 async fn restore_instances(
     instances_path: &Path,
     event_broadcaster: EventBroadcaster,
@@ -153,71 +327,19 @@ async fn restore_instances(
                 continue;
             }
         };
-        let dot_lodestone_config_file = match std::fs::File::open(path.join(".lodestone_config")) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Error while restoring instance {}, failed to read .lodestone_config file : {e}", path.display());
-                continue;
-            }
-        };
-        let dot_lodestone_config: DotLodestoneConfig = match serde_json::from_reader(
-            dot_lodestone_config_file,
-        ) {
+        let (uuid, instance) = match restore_instance(
+            &path,
+            event_broadcaster.clone(),
+            macro_executor.clone(),
+        )
+        .await
+        {
             Ok(v) => v,
             Err(e) => {
-                error!("Error while restoring instance {}, failed to parse .lodestone_config file : {e}", path.display());
+                error!("Error while restoring instance {} : {e}", path.display());
                 continue;
             }
         };
-
-        debug!("restoring instance: {}", path.display());
-        let uuid_instance: (InstanceUuid, GameInstance) = match dot_lodestone_config.game_type() {
-            GameType::MinecraftJava => {
-                let instance = match minecraft::MinecraftInstance::restore(
-                    path.to_owned(),
-                    dot_lodestone_config.clone(),
-                    event_broadcaster.clone(),
-                    macro_executor.clone(),
-                )
-                .await
-                {
-                    Ok(v) => v,
-                    Err(e) => {
-                        error!(
-                            "Error while restoring Minecraft Java instance {} : {e}",
-                            path.display()
-                        );
-                        continue;
-                    }
-                };
-                debug!("Restored Minecraft Java instance successfully");
-                (dot_lodestone_config.uuid().to_owned(), instance.into())
-            }
-            GameType::Generic => {
-                let instance = match generic::GenericInstance::restore(
-                    path.to_owned(),
-                    dot_lodestone_config.clone(),
-                    event_broadcaster.clone(),
-                    macro_executor.clone(),
-                )
-                .await
-                {
-                    Ok(v) => v,
-                    Err(e) => {
-                        error!(
-                            "Error while restoring atom instance {} : {e}",
-                            path.display()
-                        );
-                        continue;
-                    }
-                };
-                debug!("Restored Generic instance successfully");
-                (dot_lodestone_config.uuid().to_owned(), instance.into())
-            }
-            GameType::MinecraftBedrock => todo!(),
-        };
-        let uuid = uuid_instance.0;
-        let instance = uuid_instance.1;
         if ret.contains_key(&uuid) {
             warn!("UUID {} is repeated.", uuid.to_string());
         }
@@ -390,6 +512,65 @@ async fn check_for_core_update() {
     }
 }
 
+/// Generates a new first time setup key and prints it to the log, which is
+/// the only place it is ever shown
+pub(crate) fn generate_first_time_setup_key() -> String {
+    let key = rand_alphanumeric(16);
+    // log the first time setup key in green so it's easy to find
+    info!(
+        "First time setup key: {}",
+        ansi_term::Color::Green.paint(key.clone())
+    );
+    info!("This is a one-time, in-memory randomly generated key that allows you to create the owner account.");
+    info!(
+        "{}",
+        ansi_term::Color::Red.paint("DO NOT SHARE THIS KEY WITH ANYONE!")
+    );
+    key
+}
+
+/// Builds the CORS policy from the global settings, invalid entries are skipped.
+///
+/// Any origin is only allowed when `*` is explicitly listed
+fn cors_policy(origins: Vec<String>, headers: Vec<String>) -> (AllowOrigin, AllowHeaders) {
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        warn!("CORS allows any origin, only use this if you know what you are doing");
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().filter_map(|origin| {
+            header::HeaderValue::from_str(origin)
+                .map_err(|_| warn!("Ignoring invalid CORS origin {origin}"))
+                .ok()
+        }))
+    };
+    let allow_headers = AllowHeaders::list(headers.iter().filter_map(|name| {
+        header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| warn!("Ignoring invalid CORS header {name}"))
+            .ok()
+    }));
+    (allow_origin, allow_headers)
+}
+
+/// Resolves when the process is asked to terminate, which is how Docker and systemd stop
+/// lodestone. Never resolves on platforms without SIGTERM
+async fn sigterm() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM, only Ctrl+C will shut down cleanly: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    std::future::pending::<()>().await;
+}
+
 #[derive(Debug, Parser)]
 pub struct Args {
     #[arg(long, default_value = "false")]
@@ -400,6 +581,46 @@ pub struct Args {
     pub lodestone_path: Option<PathBuf>,
 }
 
+/// Where the core keeps its data, for embedding it or running it against another data directory
+#[derive(Debug, Clone)]
+pub struct CoreConfig {
+    pub data_dir: PathBuf,
+    /// Defaults to `bin` in the data directory
+    pub binaries_dir: Option<PathBuf>,
+    /// Defaults to `stores` in the data directory
+    pub stores_dir: Option<PathBuf>,
+    pub is_cli: bool,
+    pub is_desktop: bool,
+}
+
+impl CoreConfig {
+    /// The data directory is the first of `--lodestone-path`, `LODESTONE_DATA_DIR`,
+    /// `LODESTONE_PATH` and `~/.lodestone`
+    pub fn from_args(args: Args) -> Result<Self, Error> {
+        let data_dir = match args
+            .lodestone_path
+            .or_else(|| std::env::var_os("LODESTONE_DATA_DIR").map(PathBuf::from))
+            .or_else(|| std::env::var_os("LODESTONE_PATH").map(PathBuf::from))
+        {
+            Some(path) => path,
+            None => home::home_dir()
+                .or_else(|| std::env::current_dir().ok())
+                .ok_or_else(|| Error {
+                    kind: ErrorKind::Internal,
+                    source: Report::msg("Failed to get home dir"),
+                })?
+                .join(".lodestone"),
+        };
+        Ok(Self {
+            data_dir,
+            binaries_dir: None,
+            stores_dir: None,
+            is_cli: args.is_cli,
+            is_desktop: args.is_desktop,
+        })
+    }
+}
+
 pub async fn run(
     args: Args,
 ) -> Result<
@@ -410,61 +631,50 @@ pub async fn run(
         tokio::sync::oneshot::Sender<()>,
     ),
     Error,
+> {
+    run_with_config(CoreConfig::from_args(args)?).await
+}
+
+/// Starts the core with the data directory and paths in `config`
+///
+/// The paths are global to the process, so only the first core started in a process sets them
+pub async fn run_with_config(
+    config: CoreConfig,
+) -> Result<
+    (
+        impl Future<Output = ()>,
+        AppState,
+        tracing_appender::non_blocking::WorkerGuard,
+        tokio::sync::oneshot::Sender<()>,
+    ),
+    Error,
 > {
     let _ = color_eyre::install().map_err(|e| {
         error!("Failed to install color_eyre: {}", e);
     });
-    let lodestone_path = if let Some(path) = args.lodestone_path {
-        path
-    } else {
-        PathBuf::from(match std::env::var("LODESTONE_PATH") {
-            Ok(v) => v,
-            Err(_) => {
-                match home::home_dir()
-                    .unwrap_or_else(|| {
-                        std::env::current_dir()
-                            .expect("what kinda os are you running lodestone on???")
-                    })
-                    .join(".lodestone")
-                    .to_str()
-                {
-                    Some(p) => p.to_string(),
-                    None => {
-                        return Err(Error {
-                            kind: ErrorKind::Internal,
-                            source: Report::msg("Failed to get home dir"),
-                        })
-                    }
-                }
-            }
-        })
-    };
-    init_paths(lodestone_path.clone());
+    let lodestone_path = config.data_dir;
+    init_paths_with(
+        lodestone_path.clone(),
+        config.binaries_dir,
+        config.stores_dir,
+    );
     info!("Lodestone path: {}", lodestone_path.display());
     std::env::set_current_dir(&lodestone_path).map_err(|_| Error {
         kind: ErrorKind::Internal,
         source: Report::msg("Failed to set current dir"),
     })?;
     let guard = setup_tracing();
-    if args.is_desktop {
+    if config.is_desktop {
         info!("Lodestone Core running in Tauri");
     }
-    if !args.is_cli && !args.is_desktop {
+    if !config.is_cli && !config.is_desktop {
         warn!("Lodestone Core is not meant to be run as a standalone program. Please use Lodestone CLI instead.");
         warn!("Download it here: https://github.com/Lodestone-Team/lodestone_cli")
     }
     check_for_core_update().await;
     output_sys_info();
 
-    let lockfile_path = lodestone_path.join("lodestone.lock");
-    let lock_file =
-        std::fs::File::create(lockfile_path.as_path()).expect("failed to create lockfile");
-    if lock_file.try_lock_exclusive().is_err() {
-        return Err(Error {
-            kind: ErrorKind::Internal,
-            source: Report::msg("Another instance of lodestone is running"),
-        });
-    }
+    let lock_file = DataDirLock::acquire(&lodestone_path)?;
 
     let _ = migrate(&lodestone_path).map_err(|e| {
         error!("Error while migrating lodestone: {}. Lodestone will still start, but one or more instance may be in an erroneous state", e);
@@ -472,33 +682,32 @@ pub async fn run(
 
     let path_to_instances = lodestone_path.join("instances");
 
-    let (tx, _rx) = EventBroadcaster::new(512);
+    let global_settings_data =
+        GlobalSettingsData::read_from_file(path_to_global_settings()).await?;
+    let event_channel_capacity = match std::env::var("LODESTONE_EVENT_CHANNEL_CAPACITY") {
+        Ok(capacity) => capacity.parse().unwrap_or_else(|_| {
+            warn!("Invalid LODESTONE_EVENT_CHANNEL_CAPACITY {capacity}, using the global setting");
+            global_settings_data.event_channel_capacity
+        }),
+        Err(_) => global_settings_data.event_channel_capacity,
+    }
+    .max(1);
+    let (tx, _rx) = EventBroadcaster::new(event_channel_capacity);
 
     let mut users_manager = UsersManager::new(tx.clone(), HashMap::new(), path_to_users().clone());
 
     users_manager.load_users().await?;
+    users_manager.set_password_policy(global_settings_data.password_policy.clone());
 
-    let mut global_settings = GlobalSettings::new(
+    let global_settings = GlobalSettings::new(
         path_to_global_settings().clone(),
         tx.clone(),
-        GlobalSettingsData::default(),
+        global_settings_data,
     );
-
-    global_settings.load_from_file().await?;
+    let timezone = global_settings.subscribe_timezone();
 
     let first_time_setup_key = if !users_manager.as_ref().iter().any(|(_, user)| user.is_owner) {
-        let key = rand_alphanumeric(16);
-        // log the first time setup key in green so it's easy to find
-        info!(
-            "First time setup key: {}",
-            ansi_term::Color::Green.paint(key.clone())
-        );
-        info!("This is a one-time, in-memory randomly generated key that allows you to create the owner account.");
-        info!(
-            "{}",
-            ansi_term::Color::Red.paint("DO NOT SHARE THIS KEY WITH ANYONE!")
-        );
-        Some(key)
+        Some(generate_first_time_setup_key())
     } else {
         None
     };
@@ -522,6 +731,14 @@ pub async fn run(
     };
 
     let macro_executor = MacroExecutor::new(tx.clone(), tokio::runtime::Handle::current());
+    macro_executor.variables().set(global_settings.variables());
+    macro_executor.set_shell_hooks_allowed(global_settings.allow_shell_hooks());
+    let secrets = secrets::SecretStore::load(path_to_stores())
+        .await
+        .context("Failed to load secrets")?;
+    macro_executor
+        .variables()
+        .set_secrets(secrets.decrypt_all().await?);
     let instances = restore_instances(&path_to_instances, tx.clone(), macro_executor.clone())
         .await
         .map_err(|_| Error {
@@ -533,8 +750,11 @@ pub async fn run(
     for instance_entry in instances.iter() {
         allocated_ports.insert(instance_entry.value().port().await);
     }
+    let instances = Arc::new(instances);
+    let gateway =
+        gateway::Gateway::new(path_to_stores().join("gateway.json"), instances.clone()).await?;
     let shared_state = AppState {
-        instances: Arc::new(instances),
+        instances,
         users_manager: Arc::new(RwLock::new(users_manager)),
         events_buffer: Arc::new(Mutex::new(AllocRingBuffer::with_capacity(512))),
         console_out_buffer: Arc::new(Mutex::new(HashMap::new())),
@@ -549,50 +769,173 @@ pub async fn run(
         download_urls: Arc::new(Mutex::new(HashMap::new())),
         playit_keep_running: Arc::new(Mutex::new(None)),
         global_settings: Arc::new(Mutex::new(global_settings)),
+        timezone,
         macro_executor,
-        sqlite_pool: Pool::connect_with(
-            SqliteConnectOptions::from_str(&format!(
-                "sqlite://{}/data.db",
-                path_to_stores().display()
-            ))
-            .map_err(|_| Error {
-                kind: ErrorKind::Internal,
-                source: Report::msg("Failed to create sqlite connection options"),
-            })?
-            .create_if_missing(true),
-        )
-        .await
-        .map_err(|_| Error {
-            kind: ErrorKind::Internal,
-            source: Report::msg("Failed to create sqlite pool"),
-        })?,
+        sqlite_pool: db::health::lazy_pool(&path_to_stores().join("data.db"))?,
+        db_health: DbHealth::default(),
         docker_bridge: docker_bridge::DockerBridge::new(
             tx.clone(),
             path_to_stores().join("docker_bridge.json"),
         )
         .await
         .unwrap(),
+        gateway,
+        instance_sizes: disk_usage::InstanceSizeCache::default(),
+        pending_restarts: Arc::new(DashMap::new()),
+        restart_required: Arc::new(DashSet::new()),
+        idempotency_keys: idempotency::IdempotencyCache::default(),
+        confirmations: confirmation::ConfirmationTokens::default(),
+        nat: nat::NatManager::default(),
+        instance_states: instance_state::StateTracker::default(),
+        tasks: tasks::TaskRegistry::default(),
+        peers: peers::PeerRegistry::default(),
+        secrets,
     };
 
     command_console::init(shared_state.clone());
     init_app_state(shared_state.clone());
 
-    for mut entry in shared_state.instances.iter_mut() {
-        let instance = entry.value_mut();
-        if instance.auto_start().await {
-            info!("Auto starting instance {}", instance.name().await);
-            if let Err(e) = instance.start(CausedBy::System, false).await {
-                error!(
-                    "Failed to start instance {}: {:?}",
-                    entry.value().name().await,
-                    e
-                );
+    tokio::spawn({
+        let shared_state = shared_state.clone();
+        let delay = Duration::from_secs(
+            shared_state
+                .global_settings
+                .lock()
+                .await
+                .auto_start_delay_secs(),
+        );
+        auto_start::run_auto_start(shared_state, delay)
+    });
+
+    tokio::spawn(provision::provision_instances(shared_state.clone()));
+
+    // a successful start picks up every pending config change
+    tokio::spawn({
+        let restart_required = shared_state.restart_required.clone();
+        let event_broadcaster = tx.clone();
+        let mut event_receiver = tx.subscribe();
+        async move {
+            loop {
+                match event_receiver.recv().await {
+                    Ok(Event {
+                        event_inner:
+                            EventInner::InstanceEvent(InstanceEvent {
+                                instance_uuid,
+                                instance_event_inner:
+                                    InstanceEventInner::StateTransition { to: State::Running },
+                                ..
+                            }),
+                        ..
+                    }) => {
+                        restart_required.remove(&instance_uuid);
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        event_broadcaster.record_lag("Restart required tracker", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    });
+
+    tokio::spawn({
+        let instance_states = shared_state.instance_states.clone();
+        let event_broadcaster = tx.clone();
+        let mut event_receiver = tx.subscribe();
+        async move {
+            loop {
+                match event_receiver.recv().await {
+                    Ok(Event {
+                        event_inner:
+                            EventInner::InstanceEvent(InstanceEvent {
+                                instance_uuid,
+                                instance_event_inner: InstanceEventInner::StateTransition { to },
+                                ..
+                            }),
+                        ..
+                    }) => {
+                        let now = chrono::Utc::now().timestamp();
+                        instance_states.record(&instance_uuid, to, now);
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        event_broadcaster.record_lag("Instance state tracker", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
             }
         }
+    });
+
+    tokio::spawn({
+        let nat = shared_state.nat.clone();
+        let instances = shared_state.instances.clone();
+        let global_settings = shared_state.global_settings.clone();
+        let event_broadcaster = tx.clone();
+        let mut event_receiver = tx.subscribe();
+        async move {
+            loop {
+                match event_receiver.recv().await {
+                    Ok(Event {
+                        event_inner:
+                            EventInner::InstanceEvent(InstanceEvent {
+                                instance_uuid,
+                                instance_event_inner: InstanceEventInner::StateTransition { to },
+                                ..
+                            }),
+                        ..
+                    }) => match to {
+                        State::Running => {
+                            if !global_settings.lock().await.upnp_port_forwarding() {
+                                continue;
+                            }
+                            let instance = match instances.get(&instance_uuid) {
+                                Some(instance) => instance.value().clone(),
+                                None => continue,
+                            };
+                            match u16::try_from(instance.port().await) {
+                                Ok(port) => nat.map(&instance_uuid, port).await,
+                                Err(_) => warn!("Not forwarding invalid port of {}", instance_uuid),
+                            }
+                        }
+                        State::Stopped | State::Error => nat.unmap(&instance_uuid).await,
+                        _ => {}
+                    },
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        event_broadcaster.record_lag("UPnP port forwarding", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    });
+
+    tokio::spawn({
+        let tasks = shared_state.tasks.clone();
+        let event_broadcaster = tx.clone();
+        let mut event_receiver = tx.subscribe();
+        async move {
+            loop {
+                match event_receiver.recv().await {
+                    Ok(event) => tasks.observe(&event, chrono::Utc::now().timestamp()),
+                    Err(RecvError::Lagged(skipped)) => {
+                        event_broadcaster.record_lag("Task registry", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    });
+
+    if let Err(e) = shared_state.gateway.restart_listener().await {
+        error!("Failed to start gateway: {}", e);
     }
 
     let event_buffer_task = {
         let event_buffer = shared_state.events_buffer.clone();
+        let event_broadcaster = tx.clone();
         let console_out_buffer = shared_state.console_out_buffer.clone();
         let mut event_receiver = tx.subscribe();
         async move {
@@ -600,8 +943,8 @@ pub async fn run(
                 let result = event_receiver.recv().await;
                 if let Err(error) = result.as_ref() {
                     match error {
-                        RecvError::Lagged(_) => {
-                            warn!("Event buffer lagged");
+                        RecvError::Lagged(skipped) => {
+                            event_broadcaster.record_lag("Event buffer", *skipped);
                             continue;
                         }
                         RecvError::Closed => {
@@ -611,12 +954,15 @@ pub async fn run(
                     }
                 }
                 let event = result.unwrap();
+                if event.is_event_monitor_report() {
+                    continue;
+                }
                 if event.is_event_console_message() {
                     console_out_buffer
                         .lock()
                         .await
                         .entry(event.get_instance_uuid().unwrap())
-                        .or_insert_with(|| AllocRingBuffer::with_capacity(1024))
+                        .or_insert_with(|| AllocRingBuffer::with_capacity(CONSOLE_OUT_BUFFER_SIZE))
                         .push(event.clone());
                 } else {
                     event_buffer.lock().await.push(event.clone());
@@ -625,16 +971,58 @@ pub async fn run(
         }
     };
 
-    let write_to_db_task = write_event_to_db_task(tx.subscribe(), shared_state.sqlite_pool.clone());
+    db::health::open(&shared_state.sqlite_pool, &shared_state.db_health).await?;
+    if shared_state.db_health.is_available() {
+        let now = chrono::Utc::now().timestamp();
+        match get_sessions(&shared_state.sqlite_pool, now).await {
+            Ok(sessions) => shared_state
+                .users_manager
+                .read()
+                .await
+                .restore_sessions(sessions, now),
+            Err(e) => error!("Failed to restore sessions: {}", e),
+        }
+        restore_console_buffers(&shared_state).await;
+    }
+
+    // not part of the tasks raced at shutdown, it writes the events of instances stopping
+    let db_writer_shutdown = tokio_util::sync::CancellationToken::new();
+    let write_to_db_task = tokio::spawn(write_event_to_db_task(
+        tx.subscribe(),
+        tx.clone(),
+        shared_state.sqlite_pool.clone(),
+        shared_state.db_health.clone(),
+        db_writer_shutdown.clone(),
+    ));
+    let db_reconnect_task = db::health::reconnect_task(
+        shared_state.sqlite_pool.clone(),
+        shared_state.db_health.clone(),
+    );
 
     let monitor_report_task = {
         let monitor_buffer = shared_state.monitor_buffer.clone();
         let instances = shared_state.instances.clone();
+        let gateway = shared_state.gateway.clone();
+        let event_broadcaster = shared_state.event_broadcaster.clone();
+        let global_settings = shared_state.global_settings.clone();
         async move {
             let mut interval = tokio::time::interval(Duration::from_secs(1));
+            // last report broadcast for each instance, to skip reports that only differ by jitter
+            let mut last_broadcast: HashMap<InstanceUuid, MonitorReport> = HashMap::new();
             loop {
+                let threshold = global_settings.lock().await.monitor_event_threshold();
+                let mut changed = HashMap::new();
                 for entry in instances.iter() {
-                    let report = entry.value().monitor().await;
+                    let mut report = entry.value().monitor().await;
+                    report.gateway_stats = gateway.stats(entry.key());
+                    report.timestamp = Some(chrono::Utc::now().timestamp());
+                    if last_broadcast
+                        .get(entry.key())
+                        .map_or(true, |last| report.is_material_change(last, &threshold))
+                    {
+                        last_broadcast.insert(entry.key().to_owned(), report.clone());
+                        changed.insert(entry.key().to_owned(), report.clone());
+                    }
                     monitor_buffer
                         .lock()
                         .await
@@ -642,7 +1030,198 @@ pub async fn run(
                         .or_insert_with(|| AllocRingBuffer::with_capacity(64))
                         .push(report);
                 }
+                last_broadcast.retain(|uuid, _| instances.contains_key(uuid));
+                // one event per tick no matter how many instances are running
+                if !changed.is_empty() {
+                    event_broadcaster.send(Event::new_monitor_event(changed));
+                }
+                interval.tick().await;
+            }
+        }
+    };
+
+    let monitor_history_task = monitor_history::record_monitor_history(
+        shared_state.instances.clone(),
+        shared_state.monitor_buffer.clone(),
+        shared_state.global_settings.clone(),
+        shared_state.sqlite_pool.clone(),
+        shared_state.db_health.clone(),
+    );
+    let monitor_history_retention_task = monitor_history::compact_monitor_history(
+        shared_state.global_settings.clone(),
+        shared_state.sqlite_pool.clone(),
+        shared_state.db_health.clone(),
+    );
+    let event_retention_task = event_retention::prune_events_task(
+        shared_state.global_settings.clone(),
+        shared_state.sqlite_pool.clone(),
+        shared_state.db_health.clone(),
+    );
+    let peer_health_task = peers::monitor_peers(shared_state.peers.clone());
+
+    let nat_renew_task = {
+        let nat = shared_state.nat.clone();
+        async move {
+            let mut interval = tokio::time::interval(nat::LEASE_RENEW_INTERVAL);
+            loop {
+                interval.tick().await;
+                nat.renew().await;
+            }
+        }
+    };
+
+    let instance_size_task = {
+        let instance_sizes = shared_state.instance_sizes.clone();
+        let instances = shared_state.instances.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(300));
+            loop {
+                interval.tick().await;
+                instance_sizes.refresh(&instances).await;
+            }
+        }
+    };
+
+    let trash_sweep_task = {
+        let global_settings = shared_state.global_settings.clone();
+        let instances = shared_state.instances.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                let retention_days = global_settings.lock().await.trash_retention_days();
+                instance_trash::sweep_trash(retention_days).await;
+                let roots: Vec<PathBuf> = {
+                    let mut roots = Vec::new();
+                    for instance in instances.iter() {
+                        roots.push(instance.path().await);
+                    }
+                    roots
+                };
+                for root in roots {
+                    file_trash::sweep_trash(&root, retention_days).await;
+                }
+            }
+        }
+    };
+
+    // last seen times are only kept in memory in between
+    let session_sync_task = {
+        let users_manager = shared_state.users_manager.clone();
+        let sqlite_pool = shared_state.sqlite_pool.clone();
+        let db_health = shared_state.db_health.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(5 * 60));
+            loop {
                 interval.tick().await;
+                if !db_health.is_available() {
+                    continue;
+                }
+                let sessions = users_manager
+                    .read()
+                    .await
+                    .sessions(chrono::Utc::now().timestamp());
+                if let Err(e) = sync_sessions(&sqlite_pool, &sessions).await {
+                    error!("Failed to save sessions: {}", e);
+                }
+            }
+        }
+    };
+
+    let log_cleanup_task = {
+        let instances = shared_state.instances.clone();
+        let event_broadcaster = shared_state.event_broadcaster.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(6 * 60 * 60));
+            loop {
+                interval.tick().await;
+                let targets: Vec<GameInstance> = instances
+                    .iter()
+                    .map(|entry| entry.value().clone())
+                    .collect();
+                for instance in targets {
+                    let retention = instance.log_retention().await;
+                    if retention.is_unlimited() {
+                        continue;
+                    }
+                    match log_cleanup::cleanup_logs(&instance.path().await, &retention).await {
+                        Ok(report) if report.compressed > 0 || report.deleted > 0 => {
+                            event_broadcaster.send(Event::new_system_message(
+                                instance.uuid().await,
+                                instance.name().await,
+                                report.summary(),
+                            ));
+                        }
+                        Ok(_) => {}
+                        Err(e) => error!("Failed to clean up logs: {e}"),
+                    }
+                }
+            }
+        }
+    };
+
+    let scheduled_backup_task = {
+        let instances = shared_state.instances.clone();
+        let global_settings = shared_state.global_settings.clone();
+        let event_broadcaster = shared_state.event_broadcaster.clone();
+        let variables = shared_state.macro_executor.variables().clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+            loop {
+                interval.tick().await;
+                let targets: Vec<minecraft::MinecraftInstance> = instances
+                    .iter()
+                    .filter_map(|entry| match entry.value() {
+                        GameInstance::MinecraftInstance(instance) => Some(instance.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                let tz = global_settings.lock().await.timezone();
+                for instance in targets {
+                    let policy = instance.backup_policy().await;
+                    if !instance.world_backup_due(&policy, tz).await {
+                        continue;
+                    }
+                    let dirs = match instance.world_dirs().await {
+                        Ok(dirs) if !dirs.is_empty() => dirs,
+                        Ok(_) => continue,
+                        Err(e) => {
+                            error!("Failed to find the world to back up: {e}");
+                            continue;
+                        }
+                    };
+                    let timeout =
+                        Duration::from_secs(global_settings.lock().await.hot_backup_timeout_secs());
+                    let mut messages = Vec::new();
+                    match instance.hot_backup_worlds(&dirs, timeout, tz).await {
+                        Ok(backup) => {
+                            offsite_backup::spawn_upload(
+                                global_settings.lock().await.offsite_backup(),
+                                variables.clone(),
+                                event_broadcaster.clone(),
+                                instance.uuid().await,
+                                instance.name().await,
+                                backup.archive.clone(),
+                                tz,
+                                CausedBy::System,
+                            );
+                            messages.extend(backup.warning);
+                            match instance.prune_world_backups(&policy, tz).await {
+                                Ok(report) if report.deleted > 0 => messages.push(report.summary()),
+                                Ok(_) => {}
+                                Err(e) => messages.push(format!("Failed to prune backups: {e}")),
+                            }
+                        }
+                        Err(e) => messages.push(format!("Scheduled backup failed: {e}")),
+                    }
+                    for message in messages {
+                        event_broadcaster.send(Event::new_system_message(
+                            instance.uuid().await,
+                            instance.name().await,
+                            message,
+                        ));
+                    }
+                }
             }
         }
     };
@@ -654,6 +1233,19 @@ pub async fn run(
     .await;
     let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
 
+    let (allow_origin, allow_headers) = {
+        let global_settings = shared_state.global_settings.lock().await;
+        cors_policy(
+            global_settings.cors_allowed_origins(),
+            global_settings.cors_allowed_headers(),
+        )
+    };
+    let compress_responses = shared_state
+        .global_settings
+        .lock()
+        .await
+        .compress_responses();
+
     Ok((
         {
             let shared_state = shared_state.clone();
@@ -667,11 +1259,28 @@ pub async fn run(
                         Method::DELETE,
                         Method::OPTIONS,
                     ])
-                    .allow_headers([header::ORIGIN, header::CONTENT_TYPE, header::AUTHORIZATION]) // Note I can't find X-Auth-Token but it was in the original rocket version, hope it's fine
-                    .allow_origin(Any);
+                    .allow_headers(allow_headers)
+                    .expose_headers([
+                        header::HeaderName::from_static(correlation::CORRELATION_ID_HEADER),
+                        header::ETAG,
+                        header::HeaderName::from_static(timezone::TIMEZONE_HEADER),
+                        header::HeaderName::from_static(timezone::UTC_OFFSET_HEADER),
+                    ])
+                    .allow_origin(allow_origin);
 
                 let trace = TraceLayer::new_for_http();
 
+                // file downloads are served as is, most of them are archives or jars already
+                let compression = CompressionLayer::new()
+                    .gzip(compress_responses)
+                    .br(compress_responses)
+                    .compress_when(
+                        DefaultPredicate::new()
+                            .and(NotForContentType::const_new("application/octet-stream"))
+                            .and(NotForContentType::const_new("application/zip"))
+                            .and(NotForContentType::const_new("application/java-archive")),
+                    );
+
                 let api_routes = Router::new()
                     .merge(get_events_routes(shared_state.clone()))
                     .merge(get_instance_setup_config_routes(shared_state.clone()))
@@ -679,6 +1288,8 @@ pub async fn run(
                     .merge(get_instance_config_routes(shared_state.clone()))
                     .merge(get_instance_players_routes(shared_state.clone()))
                     .merge(get_instance_routes(shared_state.clone()))
+                    .merge(get_instance_archive_routes(shared_state.clone()))
+                    .merge(get_console_history_routes(shared_state.clone()))
                     .merge(get_system_routes(shared_state.clone()))
                     .merge(get_checks_routes(shared_state.clone()))
                     .merge(get_user_routes(shared_state.clone()))
@@ -692,8 +1303,28 @@ pub async fn run(
                     .merge(get_gateway_routes(shared_state.clone()))
                     .merge(get_extension_routes(shared_state.clone()))
                     .merge(get_playitgg_routes(shared_state.clone()))
+                    .merge(get_tasks_routes(shared_state.clone()))
+                    .merge(get_peers_routes(shared_state.clone()))
+                    .merge(get_secrets_routes(shared_state.clone()))
+                    .merge(get_instance_plugins_routes(shared_state.clone()))
+                    .merge(get_instance_crash_reports_routes(shared_state.clone()))
+                    .merge(get_instance_world_routes(shared_state.clone()))
+                    .layer(DefaultBodyLimit::max(body_limit::DEFAULT_BODY_LIMIT))
+                    .layer(axum::middleware::from_fn_with_state(
+                        body_limit::DEFAULT_BODY_LIMIT,
+                        body_limit::payload_too_large_middleware,
+                    ))
+                    .layer(axum::middleware::from_fn_with_state(
+                        shared_state.clone(),
+                        timezone::timezone_middleware,
+                    ))
+                    .layer(compression)
                     .layer(cors)
-                    .layer(trace);
+                    .layer(trace)
+                    .layer(axum::middleware::from_fn_with_state(
+                        shared_state.clone(),
+                        correlation::correlation_id_middleware,
+                    ));
                 let app = Router::new().nest("/api/v1", api_routes);
                 #[allow(unused_variables, unused_mut)]
                 let mut port = 16_662_u16;
@@ -719,7 +1350,7 @@ pub async fn run(
                                 info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
                                 axum_server::bind_rustls(addr, config)
                                     .handle(axum_server_handle)
-                                    .serve(app.into_make_service())
+                                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                                     .await
                             }
                             Err(e) => {
@@ -728,7 +1359,7 @@ pub async fn run(
                                 info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
                                 axum_server::bind(addr)
                                     .handle(axum_server_handle)
-                                    .serve(app.into_make_service())
+                                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                                     .await
                             }
                         }
@@ -736,13 +1367,24 @@ pub async fn run(
                     }
                 });
                 // capture file into the move block
-                let _lock_file = lock_file;
+                let lock_file = lock_file;
                 select! {
-                    _ = write_to_db_task => info!("Write to db task exited"),
+                    _ = db_reconnect_task => info!("Database reconnect task exited"),
                     _ = event_buffer_task => info!("Event buffer task exited"),
                     _ = monitor_report_task => info!("Monitor report task exited"),
+                    _ = monitor_history_task => info!("Monitor history task exited"),
+                    _ = monitor_history_retention_task => info!("Monitor history retention task exited"),
+                    _ = event_retention_task => info!("Event retention task exited"),
+                    _ = instance_size_task => info!("Instance size task exited"),
+                    _ = trash_sweep_task => info!("Trash sweep task exited"),
+                    _ = log_cleanup_task => info!("Log cleanup task exited"),
+                    _ = scheduled_backup_task => info!("Scheduled backup task exited"),
+                    _ = session_sync_task => info!("Session sync task exited"),
+                    _ = peer_health_task => info!("Peer health check task exited"),
+                    _ = nat_renew_task => info!("UPnP lease renewal task exited"),
                     _ = shutdown_rx => info!("Shutdown signal received"),
                     _ = tokio::signal::ctrl_c() => info!("Ctrl+C received"),
+                    _ = sigterm() => info!("SIGTERM received"),
                 }
                 info!("Shutting down web server");
                 axum_server_handle.shutdown();
@@ -758,6 +1400,7 @@ pub async fn run(
                     let instance = entry.value().clone();
                     match instance.state().await {
                         State::Starting => {
+                            shared_state.instance_states.request_stop(entry.key());
                             let handle = tokio::spawn({
                                 let instance = instance.clone();
                                 async move {
@@ -797,8 +1440,13 @@ pub async fn run(
                 for handle in handles {
                     let _ = handle.await;
                 }
+                shared_state.nat.unmap_all().await;
                 shared_state.instances.clear();
                 shared_state.macro_executor.shutdown_all();
+                info!("Writing buffered events to the database");
+                db_writer_shutdown.cancel();
+                let _ = write_to_db_task.await;
+                lock_file.release();
                 // exit
                 std::process::exit(0);
             }
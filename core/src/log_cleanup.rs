@@ -0,0 +1,291 @@
+//! Compresses and prunes old server logs and crash reports of an instance
+
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use color_eyre::eyre::Context;
+use color_eyre::Report;
+use flate2::{Compression, GzBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use ts_rs::TS;
+
+use crate::error::Error;
+
+/// Directories, relative to the instance root, that are cleaned up
+const LOG_DIRS: [&str; 2] = ["logs", "crash-reports"];
+
+/// Logs the server is still writing to, never touched
+const ACTIVE_LOGS: [&str; 2] = ["latest.log", "debug.log"];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct LogRetention {
+    /// Logs older than this many days are deleted
+    pub max_age_days: Option<u32>,
+    /// The oldest logs are deleted until the rest take up at most this many bytes
+    pub max_total_bytes: Option<u64>,
+}
+
+impl LogRetention {
+    /// Nothing is compressed or deleted when no limit is set
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age_days.is_none() && self.max_total_bytes.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct LogCleanupReport {
+    pub compressed: u32,
+    pub deleted: u32,
+    pub reclaimed_bytes: u64,
+}
+
+impl LogCleanupReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "Log cleanup compressed {} and deleted {} log file(s), reclaiming {:.1} MiB",
+            self.compressed,
+            self.deleted,
+            self.reclaimed_bytes as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LogFile {
+    path: PathBuf,
+    size: u64,
+    /// When the log was last written to, in seconds since the epoch
+    modified: i64,
+}
+
+fn is_active(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| ACTIVE_LOGS.contains(&name))
+        .unwrap_or(false)
+}
+
+fn is_compressed(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some("gz")
+}
+
+/// The modification time stored in a gzip header, which keeps the time of the
+/// original log after it is compressed
+fn gzip_mtime(path: &Path) -> Option<i64> {
+    let mut header = [0u8; 8];
+    let mut file = std::fs::File::open(path).ok()?;
+    std::io::Read::read_exact(&mut file, &mut header).ok()?;
+    if header[..2] != [0x1f, 0x8b] {
+        return None;
+    }
+    let mtime = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    (mtime != 0).then_some(mtime as i64)
+}
+
+fn collect_logs(instance_path: &Path) -> Vec<LogFile> {
+    let mut logs = Vec::new();
+    for dir in LOG_DIRS {
+        let read_dir = match std::fs::read_dir(instance_path.join(dir)) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        for entry in read_dir.filter_map(Result::ok) {
+            let path = entry.path();
+            let metadata = match entry.metadata() {
+                Ok(v) if v.is_file() => v,
+                _ => continue,
+            };
+            if is_active(&path) {
+                continue;
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            let modified = if is_compressed(&path) {
+                gzip_mtime(&path).unwrap_or(modified)
+            } else {
+                modified
+            };
+            logs.push(LogFile {
+                path,
+                size: metadata.len(),
+                modified,
+            });
+        }
+    }
+    logs
+}
+
+/// Compresses `log` next to itself and removes the original, returns the compressed log
+fn compress(log: &LogFile) -> Result<LogFile, Error> {
+    let mut file_name = log.path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".gz");
+    let destination = log.path.with_file_name(file_name);
+    let mut source =
+        std::fs::File::open(&log.path).context(format!("Failed to open {}", log.path.display()))?;
+    let file = std::fs::File::create(&destination)
+        .context(format!("Failed to create {}", destination.display()))?;
+    let mut encoder = GzBuilder::new()
+        .mtime(log.modified.clamp(0, u32::MAX as i64) as u32)
+        .write(file, Compression::default());
+    let res = std::io::copy(&mut source, &mut encoder).and_then(|_| encoder.finish().map(|_| ()));
+    if let Err(e) = res {
+        let _ = std::fs::remove_file(&destination);
+        return Err(Report::new(e)
+            .wrap_err(format!("Failed to compress {}", log.path.display()))
+            .into());
+    }
+    std::fs::remove_file(&log.path).context(format!("Failed to remove {}", log.path.display()))?;
+    let size = std::fs::metadata(&destination)
+        .map(|m| m.len())
+        .unwrap_or(log.size);
+    Ok(LogFile {
+        path: destination,
+        size,
+        modified: log.modified,
+    })
+}
+
+/// Logs past the maximum age, then the oldest logs until the rest fit in the maximum size
+fn select_for_deletion(mut logs: Vec<LogFile>, retention: &LogRetention, now: i64) -> Vec<LogFile> {
+    logs.sort_by_key(|log| log.modified);
+    let mut total: u64 = logs.iter().map(|log| log.size).sum();
+    let mut selected = Vec::new();
+    for log in logs {
+        let too_old = retention
+            .max_age_days
+            .map(|days| now - log.modified > days as i64 * 24 * 60 * 60)
+            .unwrap_or(false);
+        let too_large = retention
+            .max_total_bytes
+            .map(|max| total > max)
+            .unwrap_or(false);
+        if too_old || too_large {
+            total -= log.size;
+            selected.push(log);
+        }
+    }
+    selected
+}
+
+fn cleanup_logs_blocking(instance_path: &Path, retention: &LogRetention) -> LogCleanupReport {
+    let mut report = LogCleanupReport::default();
+    let mut logs = Vec::new();
+    for log in collect_logs(instance_path) {
+        if is_compressed(&log.path) {
+            logs.push(log);
+            continue;
+        }
+        match compress(&log) {
+            Ok(compressed) => {
+                report.compressed += 1;
+                report.reclaimed_bytes += log.size.saturating_sub(compressed.size);
+                logs.push(compressed);
+            }
+            Err(e) => {
+                error!("{e}");
+                logs.push(log);
+            }
+        }
+    }
+    for log in select_for_deletion(logs, retention, chrono::Utc::now().timestamp()) {
+        match std::fs::remove_file(&log.path) {
+            Ok(_) => {
+                report.deleted += 1;
+                report.reclaimed_bytes += log.size;
+            }
+            Err(e) => error!("Failed to remove {}: {e}", log.path.display()),
+        }
+    }
+    report
+}
+
+/// Compresses rotated logs and deletes the ones `retention` no longer keeps
+///
+/// The logs the server is writing to are left alone
+pub async fn cleanup_logs(
+    instance_path: &Path,
+    retention: &LogRetention,
+) -> Result<LogCleanupReport, Error> {
+    let instance_path = instance_path.to_owned();
+    let retention = retention.clone();
+    Ok(
+        tokio::task::spawn_blocking(move || cleanup_logs_blocking(&instance_path, &retention))
+            .await
+            .context("Log cleanup task panicked")?,
+    )
+}
+
+#[test]
+fn test_select_for_deletion() {
+    let day = 24 * 60 * 60;
+    let log = |name: &str, size, modified| LogFile {
+        path: PathBuf::from(name),
+        size,
+        modified,
+    };
+    let logs = vec![
+        log("new.log.gz", 100, 9 * day),
+        log("old.log.gz", 100, day),
+        log("middle.log.gz", 100, 5 * day),
+    ];
+    let names = |selected: Vec<LogFile>| {
+        selected
+            .into_iter()
+            .map(|log| log.path.display().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let by_age = LogRetention {
+        max_age_days: Some(7),
+        max_total_bytes: None,
+    };
+    assert_eq!(
+        names(select_for_deletion(logs.clone(), &by_age, 10 * day)),
+        vec!["old.log.gz"]
+    );
+
+    let by_size = LogRetention {
+        max_age_days: None,
+        max_total_bytes: Some(150),
+    };
+    assert_eq!(
+        names(select_for_deletion(logs, &by_size, 10 * day)),
+        vec!["old.log.gz", "middle.log.gz"]
+    );
+}
+
+#[tokio::test]
+async fn test_cleanup_keeps_active_log() {
+    let root = tempfile::tempdir().unwrap();
+    let logs = root.path().join("logs");
+    std::fs::create_dir_all(&logs).unwrap();
+    std::fs::write(logs.join("latest.log"), "x".repeat(4096)).unwrap();
+    std::fs::write(logs.join("2023-01-01-1.log"), "x".repeat(4096)).unwrap();
+
+    let retention = LogRetention {
+        max_age_days: None,
+        max_total_bytes: Some(1 << 20),
+    };
+    let report = cleanup_logs(root.path(), &retention).await.unwrap();
+    assert_eq!(report.compressed, 1);
+    assert_eq!(report.deleted, 0);
+    assert!(report.reclaimed_bytes > 0);
+    assert!(logs.join("latest.log").exists());
+    assert!(logs.join("2023-01-01-1.log.gz").exists());
+    assert!(gzip_mtime(&logs.join("2023-01-01-1.log.gz")).is_some());
+
+    let retention = LogRetention {
+        max_age_days: None,
+        max_total_bytes: Some(0),
+    };
+    let report = cleanup_logs(root.path(), &retention).await.unwrap();
+    assert_eq!(report.deleted, 1);
+    assert!(logs.join("latest.log").exists());
+}
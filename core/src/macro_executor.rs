@@ -4,7 +4,7 @@ use std::{
     path::PathBuf,
     rc::Rc,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
     time::Duration,
@@ -30,6 +30,7 @@ use crate::{
     events::{CausedBy, EventInner, MacroEvent, MacroEventInner},
     traits::t_macro::ExitStatus,
     types::InstanceUuid,
+    variables::Variables,
 };
 
 use color_eyre::eyre::eyre;
@@ -219,12 +220,16 @@ impl ModuleLoader for TypescriptModuleLoader {
 pub struct MacroExecutor {
     macro_process_table: Arc<DashMap<MacroPID, deno_core::v8::IsolateHandle>>,
     exit_status_table: Arc<DashMap<MacroPID, ExitStatus>>,
+    /// What started each running macro, so ops can act with the permissions of its user
+    caller_table: Arc<DashMap<MacroPID, CausedBy>>,
     #[allow(dead_code)]
     channel_table:
         Arc<DashMap<MacroPID, (mpsc::UnboundedSender<Value>, mpsc::UnboundedSender<Value>)>>,
     event_broadcaster: EventBroadcaster,
     next_process_id: Arc<AtomicUsize>,
     rt: tokio::runtime::Handle,
+    variables: Variables,
+    shell_hooks_allowed: Arc<AtomicBool>,
 }
 
 pub struct SpawnResult {
@@ -238,10 +243,12 @@ impl MacroExecutor {
         let process_table = Arc::new(DashMap::new());
         let process_id = Arc::new(AtomicUsize::new(0));
         let exit_status_table = Arc::new(DashMap::new());
+        let caller_table: Arc<DashMap<MacroPID, CausedBy>> = Arc::new(DashMap::new());
 
         // spawn a task to listen for exit events and update the exit status table
         tokio::task::spawn({
             let exit_status_table = exit_status_table.clone();
+            let caller_table = caller_table.clone();
             let mut rx = event_broadcaster.subscribe();
             async move {
                 loop {
@@ -253,6 +260,7 @@ impl MacroExecutor {
                         }) = event.try_macro_event()
                         {
                             exit_status_table.insert(*macro_pid, exit_status.clone());
+                            caller_table.remove(macro_pid);
                         }
                     }
                 }
@@ -264,11 +272,28 @@ impl MacroExecutor {
             event_broadcaster,
             channel_table: Arc::new(DashMap::new()),
             exit_status_table,
+            caller_table,
             next_process_id: process_id,
             rt,
+            variables: Variables::default(),
+            shell_hooks_allowed: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// The `${NAME}` variables substituted in macro arguments
+    pub fn variables(&self) -> &Variables {
+        &self.variables
+    }
+
+    /// Mirrors the global setting gating shell commands in instance hooks
+    pub fn set_shell_hooks_allowed(&self, allowed: bool) {
+        self.shell_hooks_allowed.store(allowed, Ordering::SeqCst);
+    }
+
+    pub fn shell_hooks_allowed(&self) -> bool {
+        self.shell_hooks_allowed.load(Ordering::SeqCst)
+    }
+
     fn add_default_permissions(
         perm: Option<PermissionsOptions>,
         path_to_main: PathBuf,
@@ -305,13 +330,18 @@ impl MacroExecutor {
         &self,
         path_to_main_module: PathBuf,
         args: Vec<String>,
-        _caused_by: CausedBy,
+        caused_by: CausedBy,
         worker_options_generator: Box<dyn WorkerOptionGenerator>,
         pre_injection_code: Option<String>,
         permissions: Option<PermissionsOptions>,
         instance_uuid: Option<InstanceUuid>,
     ) -> Result<SpawnResult, Error> {
+        let args = args
+            .iter()
+            .map(|arg| self.variables.substitute(arg))
+            .collect::<Result<Vec<_>, _>>()?;
         let pid = MacroPID(self.next_process_id.fetch_add(1, Ordering::SeqCst));
+        self.caller_table.insert(pid, caused_by);
         let exit_future = Box::pin({
             let __self = self.clone();
             async move { __self.wait_with_timeout(pid).await }
@@ -599,6 +629,13 @@ impl MacroExecutor {
         }
     }
 
+    /// What started the macro, `None` once it exited
+    pub fn caller(&self, pid: MacroPID) -> Option<CausedBy> {
+        self.caller_table
+            .get(&pid)
+            .map(|entry| entry.value().clone())
+    }
+
     pub async fn get_macro_status(&self, pid: MacroPID) -> Option<ExitStatus> {
         self.exit_status_table.get(&pid).map(|v| v.clone())
     }
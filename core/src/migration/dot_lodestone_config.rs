@@ -0,0 +1,121 @@
+//! Upgrades `.lodestone_config` files written by older versions to the current shape
+//!
+//! Each step is a pure function taking a config of version N to N + 1.
+//! Configs without a `config_version` field are version 0, which covers every release up to
+//! 0.5.1
+
+use color_eyre::eyre::eyre;
+use serde_json::{Map, Value};
+
+use crate::error::{Error, ErrorKind};
+
+pub const CURRENT_CONFIG_VERSION: u64 = 1;
+
+type Migration = fn(Map<String, Value>) -> Result<Map<String, Value>, Error>;
+
+/// `MIGRATIONS[n]` upgrades version n to n + 1
+const MIGRATIONS: [Migration; CURRENT_CONFIG_VERSION as usize] = [v0_to_v1];
+
+/// Fields of `.lodestone_config` before it was versioned
+const V0_FIELDS: [&str; 3] = ["game_type", "uuid", "creation_time"];
+
+/// Version 0 configs come in three shapes:
+/// - 0.4.2 stored the whole Minecraft config here with `"game_type": "minecraft"`. The legacy
+///   migration at boot moves it to `.lodestone_minecraft_config.json` before instances are
+///   restored, so only the fields `.lodestone_config` still has are kept
+/// - 0.4.3 added a `lodestone_version` field
+/// - 0.4.4 and later have only `game_type`, `uuid` and `creation_time`
+fn v0_to_v1(mut config: Map<String, Value>) -> Result<Map<String, Value>, Error> {
+    if config.get("game_type").and_then(Value::as_str) == Some("minecraft") {
+        // 0.4.2 only ran Minecraft Java servers
+        config.insert("game_type".to_string(), "MinecraftJava".into());
+    }
+    config.retain(|key, _| V0_FIELDS.contains(&key.as_str()));
+    Ok(config)
+}
+
+/// Runs every migration `config` needs, returns whether anything changed
+pub fn migrate_dot_lodestone_config(config: Value) -> Result<(Value, bool), Error> {
+    let mut config = match config {
+        Value::Object(config) => config,
+        _ => {
+            return Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!(".lodestone_config is not a JSON object"),
+            })
+        }
+    };
+    let version = config
+        .get("config_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    if version > CURRENT_CONFIG_VERSION {
+        return Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!(
+                ".lodestone_config version {} was written by a newer version of Lodestone Core",
+                version
+            ),
+        });
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        config = migration(config)?;
+    }
+    config.insert("config_version".to_string(), CURRENT_CONFIG_VERSION.into());
+    Ok((Value::Object(config), version < CURRENT_CONFIG_VERSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::t_configurable::GameType;
+    use crate::types::DotLodestoneConfig;
+
+    fn load(fixture: &str) -> (DotLodestoneConfig, bool) {
+        let (config, migrated) =
+            migrate_dot_lodestone_config(serde_json::from_str(fixture).unwrap()).unwrap();
+        (serde_json::from_value(config).unwrap(), migrated)
+    }
+
+    #[test]
+    fn test_migrate_v042_config() {
+        let (config, migrated) = load(include_str!("fixtures/dot_lodestone_config_v042.json"));
+        assert!(migrated);
+        assert_eq!(config.game_type(), &GameType::MinecraftJava);
+        assert_eq!(config.creation_time(), 1662508800);
+    }
+
+    #[test]
+    fn test_migrate_v043_config() {
+        let (config, migrated) = load(include_str!("fixtures/dot_lodestone_config_v043.json"));
+        assert!(migrated);
+        assert_eq!(config.creation_time(), 1668124800);
+        assert!(config.tags().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_v044_config() {
+        let (config, migrated) = load(include_str!("fixtures/dot_lodestone_config_v044.json"));
+        assert!(migrated);
+        assert_eq!(config.game_type(), &GameType::MinecraftBedrock);
+        assert_eq!(config.creation_time(), 1675209600);
+    }
+
+    #[test]
+    fn test_current_config_is_unchanged() {
+        let current = serde_json::to_value(DotLodestoneConfig::new(
+            "INSTANCE_1".to_string().into(),
+            GameType::Generic,
+        ))
+        .unwrap();
+        let (migrated_config, migrated) = migrate_dot_lodestone_config(current.clone()).unwrap();
+        assert!(!migrated);
+        assert_eq!(migrated_config, current);
+    }
+
+    #[test]
+    fn test_newer_config_is_rejected() {
+        let config = serde_json::json!({ "config_version": CURRENT_CONFIG_VERSION + 1 });
+        assert!(migrate_dot_lodestone_config(config).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+pub mod dot_lodestone_config;
 mod v042_to_v044;
 pub mod v043_to_v044;
 
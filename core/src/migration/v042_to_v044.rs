@@ -25,6 +25,11 @@ impl From<RestoreConfigV042> for RestoreConfig {
             jre_major_version: config.jre_major_version,
             has_started: config.has_started,
             java_cmd: None,
+            jvm_flags: Default::default(),
+            env: Default::default(),
+            hooks: Default::default(),
+            run_as: None,
+            ansi_handling: Default::default(),
         }
     }
 }
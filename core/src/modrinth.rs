@@ -0,0 +1,240 @@
+//! A thin client for the Modrinth API, used to search for and install plugins and mods
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    plugins::PluginDir,
+    traits::t_configurable::MinecraftVariant,
+};
+
+const MODRINTH_API: &str = "https://api.modrinth.com/v2";
+
+const MAX_SEARCH_LIMIT: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum ModrinthProjectType {
+    Plugin,
+    Mod,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ModrinthSearchHit {
+    pub project_id: String,
+    pub slug: String,
+    pub title: String,
+    pub description: String,
+    pub project_type: String,
+    pub downloads: u64,
+    pub icon_url: Option<String>,
+    pub versions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    hits: Vec<ModrinthSearchHit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ModrinthHashes {
+    pub sha512: String,
+    pub sha1: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ModrinthFile {
+    pub url: String,
+    pub filename: String,
+    pub primary: bool,
+    pub size: u64,
+    pub hashes: ModrinthHashes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ModrinthVersion {
+    pub id: String,
+    pub project_id: String,
+    pub name: String,
+    pub version_number: String,
+    pub game_versions: Vec<String>,
+    pub loaders: Vec<String>,
+    pub files: Vec<ModrinthFile>,
+}
+
+impl ModrinthVersion {
+    /// The file to install, the primary one if the version has several
+    pub fn primary_file(&self) -> Option<&ModrinthFile> {
+        self.files
+            .iter()
+            .find(|file| file.primary)
+            .or_else(|| self.files.first())
+    }
+}
+
+/// Where plugins of a server variant go, and the Modrinth loaders it can run
+pub fn plugin_target(
+    variant: &MinecraftVariant,
+) -> Option<(PluginDir, ModrinthProjectType, &'static [&'static str])> {
+    match variant {
+        MinecraftVariant::Paper => Some((
+            PluginDir::Plugins,
+            ModrinthProjectType::Plugin,
+            &["paper", "spigot", "bukkit", "purpur", "folia"],
+        )),
+        MinecraftVariant::Spigot => Some((
+            PluginDir::Plugins,
+            ModrinthProjectType::Plugin,
+            &["spigot", "bukkit"],
+        )),
+        MinecraftVariant::Fabric => Some((PluginDir::Mods, ModrinthProjectType::Mod, &["fabric"])),
+        MinecraftVariant::Forge => Some((PluginDir::Mods, ModrinthProjectType::Mod, &["forge"])),
+        MinecraftVariant::Vanilla | MinecraftVariant::Other { .. } => None,
+    }
+}
+
+/// Why `version` might not work on a server of `game_version` running one of `loaders`
+pub fn compatibility_warnings(
+    version: &ModrinthVersion,
+    game_version: &str,
+    loaders: &[&str],
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if !version.game_versions.iter().any(|v| v == game_version) {
+        warnings.push(format!(
+            "{} is made for Minecraft {}, the instance runs {}",
+            version.name,
+            version.game_versions.join(", "),
+            game_version
+        ));
+    }
+    if !version
+        .loaders
+        .iter()
+        .any(|loader| loaders.contains(&loader.as_str()))
+    {
+        warnings.push(format!(
+            "{} is made for {}, which the instance can't load",
+            version.name,
+            version.loaders.join(", ")
+        ));
+    }
+    warnings
+}
+
+fn client() -> Result<reqwest::Client, Error> {
+    // Modrinth asks API users to identify themselves
+    let user_agent = format!(
+        "Lodestone-Team/lodestone_core/{}",
+        crate::prelude::VERSION.with(|v| v.to_string())
+    );
+    Ok(reqwest::Client::builder()
+        .user_agent(user_agent)
+        .build()
+        .context("Failed to build HTTP client")?)
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(
+    url: &str,
+    query: &[(&str, String)],
+) -> Result<T, Error> {
+    let response = client()?
+        .get(url)
+        .query(query)
+        .send()
+        .await
+        .context("Failed to reach Modrinth")?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Not found on Modrinth"),
+        });
+    }
+    if !response.status().is_success() {
+        return Err(Error {
+            kind: ErrorKind::External,
+            source: eyre!("Modrinth responded with {}", response.status()),
+        });
+    }
+    Ok(response
+        .json()
+        .await
+        .context("Failed to parse Modrinth response")?)
+}
+
+pub async fn search(
+    query: &str,
+    project_type: ModrinthProjectType,
+    limit: u32,
+) -> Result<Vec<ModrinthSearchHit>, Error> {
+    let project_type = match project_type {
+        ModrinthProjectType::Plugin => "plugin",
+        ModrinthProjectType::Mod => "mod",
+    };
+    let response: SearchResponse = get_json(
+        &format!("{MODRINTH_API}/search"),
+        &[
+            ("query", query.to_string()),
+            ("facets", format!("[[\"project_type:{project_type}\"]]")),
+            ("limit", limit.clamp(1, MAX_SEARCH_LIMIT).to_string()),
+        ],
+    )
+    .await?;
+    Ok(response.hits)
+}
+
+/// Versions of a project, newest first
+pub async fn project_versions(project_id: &str) -> Result<Vec<ModrinthVersion>, Error> {
+    if !project_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid Modrinth project id"),
+        });
+    }
+    get_json(&format!("{MODRINTH_API}/project/{project_id}/version"), &[]).await
+}
+
+pub async fn version(version_id: &str) -> Result<ModrinthVersion, Error> {
+    if !version_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid Modrinth version id"),
+        });
+    }
+    get_json(&format!("{MODRINTH_API}/version/{version_id}"), &[]).await
+}
+
+#[test]
+fn test_compatibility_warnings() {
+    let version = ModrinthVersion {
+        id: "abc".to_string(),
+        project_id: "luckperms".to_string(),
+        name: "LuckPerms 5.4".to_string(),
+        version_number: "5.4.102".to_string(),
+        game_versions: vec!["1.19.4".to_string(), "1.20.1".to_string()],
+        loaders: vec!["bukkit".to_string()],
+        files: vec![],
+    };
+    let (_, _, paper_loaders) = plugin_target(&MinecraftVariant::Paper).unwrap();
+    assert!(compatibility_warnings(&version, "1.20.1", paper_loaders).is_empty());
+    assert_eq!(
+        compatibility_warnings(&version, "1.18.2", paper_loaders).len(),
+        1
+    );
+    let (_, _, fabric_loaders) = plugin_target(&MinecraftVariant::Fabric).unwrap();
+    assert_eq!(
+        compatibility_warnings(&version, "1.18.2", fabric_loaders).len(),
+        2
+    );
+    assert!(plugin_target(&MinecraftVariant::Vanilla).is_none());
+}
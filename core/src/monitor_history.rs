@@ -0,0 +1,136 @@
+//! Coarse monitor history stored in the database, downsampled from the 1s monitor buffer
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use ringbuffer::{AllocRingBuffer, RingBuffer, RingBufferExt};
+use sqlx::SqlitePool;
+use tokio::sync::Mutex;
+use tracing::error;
+
+use crate::{
+    db::{
+        health::DbHealth,
+        types::MonitorSample,
+        write::{compact_monitor_samples, write_monitor_sample},
+    },
+    global_settings::GlobalSettings,
+    prelude::GameInstance,
+    traits::{
+        t_player::TPlayerManagement,
+        t_server::{MonitorReport, State, TServer},
+    },
+    types::InstanceUuid,
+};
+
+/// Samples older than this are merged into hourly averages
+const DOWNSAMPLE_AFTER_SECS: i64 = 24 * 60 * 60;
+
+/// Averages the usage reported over the interval, ignoring reports that have no value
+fn downsample(reports: &[MonitorReport]) -> (Option<f32>, Option<u64>) {
+    let cpu: Vec<f32> = reports.iter().filter_map(|r| r.cpu_usage).collect();
+    let memory: Vec<u64> = reports.iter().filter_map(|r| r.memory_usage).collect();
+    (
+        (!cpu.is_empty()).then(|| cpu.iter().sum::<f32>() / cpu.len() as f32),
+        (!memory.is_empty()).then(|| memory.iter().sum::<u64>() / memory.len() as u64),
+    )
+}
+
+/// Writes a sample of every running instance to the database each sample interval
+///
+/// The live buffer only holds the last 64 reports, longer intervals average those
+pub async fn record_monitor_history(
+    instances: Arc<DashMap<InstanceUuid, GameInstance>>,
+    monitor_buffer: Arc<Mutex<HashMap<InstanceUuid, AllocRingBuffer<MonitorReport>>>>,
+    global_settings: Arc<Mutex<GlobalSettings>>,
+    sqlite_pool: SqlitePool,
+    db_health: DbHealth,
+) {
+    loop {
+        // read every time so a changed interval applies without a restart
+        let interval_secs = global_settings.lock().await.monitor_sample_interval_secs();
+        tokio::time::sleep(Duration::from_secs(interval_secs)).await;
+        if !db_health.is_available() {
+            continue;
+        }
+        let running: Vec<(InstanceUuid, GameInstance)> = {
+            let mut running = Vec::new();
+            for entry in instances.iter() {
+                if entry.value().state().await == State::Running {
+                    running.push((entry.key().clone(), entry.value().clone()));
+                }
+            }
+            running
+        };
+        let timestamp = chrono::Utc::now().timestamp();
+        for (uuid, instance) in running {
+            let (cpu_usage, memory_usage) = {
+                let monitor_buffer = monitor_buffer.lock().await;
+                let reports: Vec<MonitorReport> = match monitor_buffer.get(&uuid) {
+                    Some(buffer) => {
+                        let skip = buffer.len().saturating_sub(interval_secs as usize);
+                        buffer.iter().skip(skip).cloned().collect()
+                    }
+                    None => continue,
+                };
+                downsample(&reports)
+            };
+            let sample = MonitorSample {
+                instance_id: uuid,
+                timestamp,
+                cpu_usage,
+                memory_usage,
+                player_count: instance.get_player_count().await.ok(),
+            };
+            if let Err(e) = write_monitor_sample(&sqlite_pool, &sample).await {
+                error!("Failed to write monitor sample: {e}");
+            }
+        }
+    }
+}
+
+/// Applies the monitor history retention every hour
+pub async fn compact_monitor_history(
+    global_settings: Arc<Mutex<GlobalSettings>>,
+    sqlite_pool: SqlitePool,
+    db_health: DbHealth,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        if !db_health.is_available() {
+            continue;
+        }
+        let retention_days = global_settings
+            .lock()
+            .await
+            .monitor_history_retention_days();
+        let now = chrono::Utc::now().timestamp();
+        // whole hours only, so an hour is never merged while it is still getting samples
+        let downsample_before = (now - DOWNSAMPLE_AFTER_SECS) / 3600 * 3600;
+        let delete_before = now - retention_days as i64 * 24 * 60 * 60;
+        if let Err(e) =
+            compact_monitor_samples(&sqlite_pool, downsample_before, delete_before).await
+        {
+            error!("Failed to compact monitor history: {e}");
+        }
+    }
+}
+
+#[test]
+fn test_downsample() {
+    let report = |cpu_usage, memory_usage| MonitorReport {
+        cpu_usage,
+        memory_usage,
+        ..Default::default()
+    };
+    assert_eq!(
+        downsample(&[
+            report(Some(10.0), Some(100)),
+            report(None, None),
+            report(Some(30.0), Some(300)),
+        ]),
+        (Some(20.0), Some(200))
+    );
+    assert_eq!(downsample(&[report(None, None)]), (None, None));
+}
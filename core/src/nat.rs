@@ -0,0 +1,224 @@
+//! Port forwarding through the router with UPnP, so players outside the local network can
+//! join without the user setting up forwarding by hand
+//!
+//! Ports are mapped when an instance starts and unmapped when it stops, while
+//! `upnp_port_forwarding` is enabled in the global settings. Mappings are leased for a
+//! limited time and renewed while lodestone runs, so a crash doesn't leave ports open
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use igd::{PortMappingProtocol, SearchOptions};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use ts_rs::TS;
+
+use crate::types::InstanceUuid;
+
+/// Routers without UPnP don't answer at all, so the search gives up after this
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+const MAPPING_DESCRIPTION: &str = "Lodestone";
+
+/// How long the router keeps a mapping unless it is renewed
+const LEASE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Mappings are renewed well before their lease runs out
+pub const LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(20 * 60);
+
+/// How long the router's answer to a status request is reused
+const STATUS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, TS)]
+#[serde(tag = "status")]
+#[ts(export)]
+pub enum MappingStatus {
+    Mapped,
+    /// No UPnP router answered, the port has to be forwarded by hand
+    NotSupported,
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct PortMapping {
+    pub instance_uuid: InstanceUuid,
+    pub port: u16,
+    #[serde(flatten)]
+    pub status: MappingStatus,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct NatStatus {
+    pub enabled: bool,
+    /// Whether a UPnP router answered
+    pub supported: bool,
+    /// The address to share with players, if the router reported it
+    pub external_ip: Option<String>,
+    pub mappings: Vec<PortMapping>,
+}
+
+fn find_gateway() -> Option<igd::Gateway> {
+    igd::search_gateway(SearchOptions {
+        timeout: Some(SEARCH_TIMEOUT),
+        ..Default::default()
+    })
+    .ok()
+}
+
+fn add_mapping(port: u16) -> MappingStatus {
+    let gateway = match find_gateway() {
+        Some(gateway) => gateway,
+        None => return MappingStatus::NotSupported,
+    };
+    let local_ip = match local_ip_address::local_ip() {
+        Ok(IpAddr::V4(ip)) => ip,
+        Ok(IpAddr::V6(_)) => {
+            return MappingStatus::Failed {
+                reason: "UPnP needs a local IPv4 address".to_string(),
+            }
+        }
+        Err(e) => {
+            return MappingStatus::Failed {
+                reason: format!("Could not find the local address: {e}"),
+            }
+        }
+    };
+    match gateway.add_port(
+        PortMappingProtocol::TCP,
+        port,
+        SocketAddrV4::new(local_ip, port),
+        LEASE_DURATION.as_secs() as u32,
+        MAPPING_DESCRIPTION,
+    ) {
+        Ok(()) => MappingStatus::Mapped,
+        Err(e) => MappingStatus::Failed {
+            reason: e.to_string(),
+        },
+    }
+}
+
+/// The UPnP mappings lodestone made, by instance
+#[derive(Debug, Clone, Default)]
+pub struct NatManager {
+    mappings: Arc<DashMap<InstanceUuid, (u16, MappingStatus)>>,
+    /// When the router was last asked for its external address, and its answer. `None` if
+    /// no router answered
+    external_ip: Arc<Mutex<Option<(Instant, Option<Option<Ipv4Addr>>)>>>,
+}
+
+impl NatManager {
+    pub async fn map(&self, uuid: &InstanceUuid, port: u16) {
+        let status = tokio::task::spawn_blocking(move || add_mapping(port))
+            .await
+            .unwrap_or_else(|e| MappingStatus::Failed {
+                reason: e.to_string(),
+            });
+        match &status {
+            MappingStatus::Mapped => info!("Forwarded port {} with UPnP", port),
+            MappingStatus::NotSupported => {
+                warn!("Could not forward port {}, no UPnP router found", port)
+            }
+            MappingStatus::Failed { reason } => {
+                warn!("Could not forward port {} with UPnP: {}", port, reason)
+            }
+        }
+        self.mappings.insert(uuid.clone(), (port, status));
+    }
+
+    pub async fn unmap(&self, uuid: &InstanceUuid) {
+        let port = match self.mappings.remove(uuid) {
+            Some((_, (port, MappingStatus::Mapped))) => port,
+            _ => return,
+        };
+        let removed = tokio::task::spawn_blocking(move || {
+            find_gateway().map(|gateway| gateway.remove_port(PortMappingProtocol::TCP, port))
+        })
+        .await;
+        if !matches!(removed, Ok(Some(Ok(())))) {
+            warn!("Could not remove the UPnP mapping of port {}", port);
+        }
+    }
+
+    pub async fn unmap_all(&self) {
+        let uuids: Vec<InstanceUuid> = self
+            .mappings
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        for uuid in uuids {
+            self.unmap(&uuid).await;
+        }
+    }
+
+    /// Maps the ports of the mappings that succeeded again, before their lease runs out
+    pub async fn renew(&self) {
+        let mapped: Vec<(InstanceUuid, u16)> = self
+            .mappings
+            .iter()
+            .filter(|entry| entry.value().1 == MappingStatus::Mapped)
+            .map(|entry| (entry.key().clone(), entry.value().0))
+            .collect();
+        for (uuid, port) in mapped {
+            let status = tokio::task::spawn_blocking(move || add_mapping(port))
+                .await
+                .unwrap_or_else(|e| MappingStatus::Failed {
+                    reason: e.to_string(),
+                });
+            if status != MappingStatus::Mapped {
+                warn!("Could not renew the UPnP mapping of port {}", port);
+            }
+            // the instance may have stopped and been unmapped in the meantime
+            if let Some(mut entry) = self.mappings.get_mut(&uuid) {
+                if entry.0 == port {
+                    entry.1 = status;
+                }
+            }
+        }
+    }
+
+    /// The external address the router reports, asking it at most once per
+    /// [`STATUS_CACHE_TTL`] since searching for it takes seconds without a UPnP router
+    async fn external_ip(&self) -> Option<Option<Ipv4Addr>> {
+        let mut cached = self.external_ip.lock().await;
+        if let Some((fetched_at, external_ip)) = *cached {
+            if fetched_at.elapsed() < STATUS_CACHE_TTL {
+                return external_ip;
+            }
+        }
+        let external_ip = tokio::task::spawn_blocking(|| {
+            find_gateway().map(|gateway| gateway.get_external_ip().ok())
+        })
+        .await
+        .ok()
+        .flatten();
+        *cached = Some((Instant::now(), external_ip));
+        external_ip
+    }
+
+    pub async fn status(&self, enabled: bool) -> NatStatus {
+        let external_ip = self.external_ip().await;
+        NatStatus {
+            enabled,
+            supported: external_ip.is_some(),
+            external_ip: external_ip.flatten().map(|ip| ip.to_string()),
+            mappings: self
+                .mappings
+                .iter()
+                .map(|entry| PortMapping {
+                    instance_uuid: entry.key().clone(),
+                    port: entry.value().0,
+                    status: entry.value().1.clone(),
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Other cores this core knows about, so one frontend can manage several machines
+
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::{eyre, Context};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+/// How often registered peers are health checked
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Peers that neither re-registered nor answered a health check for this long are dropped
+const PEER_STALE_AFTER: Duration = Duration::from_secs(10 * 60);
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a core announces about itself when registering with another core
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct PeerRegistration {
+    pub uuid: String,
+    /// Base URL the core is reachable at, e.g. `https://10.0.0.2:16662`
+    pub address: String,
+    pub core_name: String,
+    pub instance_count: u32,
+    pub running_instance_count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct Peer {
+    pub uuid: String,
+    pub address: String,
+    pub core_name: String,
+    pub instance_count: u32,
+    pub running_instance_count: u32,
+    /// Unix time the peer last registered or passed a health check
+    pub last_seen: i64,
+    /// Whether the last health check succeeded
+    pub healthy: bool,
+}
+
+#[derive(Deserialize)]
+struct PeerInfo {
+    uuid: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PeerRegistry {
+    peers: Arc<DashMap<String, Peer>>,
+}
+
+impl PeerRegistry {
+    /// Adds the peer, or refreshes it if it registered before
+    pub fn register(
+        &self,
+        registration: PeerRegistration,
+        own_uuid: &str,
+        now: i64,
+    ) -> Result<Peer, Error> {
+        if registration.uuid == own_uuid {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A core cannot register with itself"),
+            });
+        }
+        let url = reqwest::Url::parse(&registration.address).context("Invalid peer address")?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Peer address must be an http or https URL"),
+            });
+        }
+        let peer = Peer {
+            uuid: registration.uuid,
+            address: registration.address.trim_end_matches('/').to_string(),
+            core_name: registration.core_name,
+            instance_count: registration.instance_count,
+            running_instance_count: registration.running_instance_count,
+            last_seen: now,
+            healthy: true,
+        };
+        self.peers.insert(peer.uuid.clone(), peer.clone());
+        Ok(peer)
+    }
+
+    pub fn remove(&self, uuid: &str) -> Option<Peer> {
+        self.peers.remove(uuid).map(|(_, peer)| peer)
+    }
+
+    /// Known peers, sorted by name
+    pub fn list(&self) -> Vec<Peer> {
+        let mut peers: Vec<Peer> = self.peers.iter().map(|peer| peer.clone()).collect();
+        peers.sort_by(|a, b| a.core_name.cmp(&b.core_name));
+        peers
+    }
+
+    fn record_health(&self, uuid: &str, healthy: bool, now: i64) {
+        if let Some(mut peer) = self.peers.get_mut(uuid) {
+            peer.healthy = healthy;
+            if healthy {
+                peer.last_seen = now;
+            }
+        }
+    }
+
+    fn drop_stale(&self, now: i64) {
+        self.peers.retain(|uuid, peer| {
+            let stale = now - peer.last_seen >= PEER_STALE_AFTER.as_secs() as i64;
+            if stale {
+                warn!(
+                    "Dropping peer {} ({uuid}), it has not been seen for a while",
+                    peer.core_name
+                );
+            }
+            !stale
+        });
+    }
+}
+
+/// Healthy if the peer answers its info endpoint with the uuid it registered with
+async fn check_peer(client: &reqwest::Client, peer: &Peer) -> bool {
+    let response = client
+        .get(format!("{}/api/v1/info", peer.address))
+        .timeout(HEALTH_CHECK_TIMEOUT)
+        .send()
+        .await;
+    match response {
+        Ok(response) if response.status().is_success() => response
+            .json::<PeerInfo>()
+            .await
+            .map(|info| info.uuid == peer.uuid)
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Health checks every peer periodically and drops the ones that went stale
+pub async fn monitor_peers(registry: PeerRegistry) {
+    let client = reqwest::Client::new();
+    let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        for peer in registry.list() {
+            let healthy = check_peer(&client, &peer).await;
+            registry.record_health(&peer.uuid, healthy, chrono::Utc::now().timestamp());
+        }
+        registry.drop_stale(chrono::Utc::now().timestamp());
+    }
+}
+
+#[test]
+fn test_peer_registry() {
+    let registry = PeerRegistry::default();
+    let registration = |uuid: &str, address: &str| PeerRegistration {
+        uuid: uuid.to_string(),
+        address: address.to_string(),
+        core_name: format!("core {uuid}"),
+        instance_count: 2,
+        running_instance_count: 1,
+    };
+    assert!(registry
+        .register(registration("self", "http://localhost:16662"), "self", 0)
+        .is_err());
+    assert!(registry
+        .register(registration("a", "ftp://localhost"), "self", 0)
+        .is_err());
+
+    let peer = registry
+        .register(registration("a", "http://10.0.0.2:16662/"), "self", 0)
+        .unwrap();
+    assert_eq!(peer.address, "http://10.0.0.2:16662");
+    registry
+        .register(registration("b", "http://10.0.0.3:16662"), "self", 0)
+        .unwrap();
+
+    // b keeps answering health checks, a does not
+    let later = PEER_STALE_AFTER.as_secs() as i64;
+    registry.record_health("a", false, later);
+    registry.record_health("b", true, later);
+    registry.drop_stale(later);
+    let peers = registry.list();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].uuid, "b");
+    assert_eq!(peers[0].last_seen, later);
+}
@@ -0,0 +1,366 @@
+//! Plugin and mod jars of an instance, read from `plugins/` and `mods/`
+//!
+//! A jar is disabled by renaming it to `<name>.jar.disabled`, which every server and
+//! mod loader skips
+
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+const DISABLED_SUFFIX: &str = ".disabled";
+
+/// Metadata files larger than this are ignored rather than read into memory
+const MAX_METADATA_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum PluginDir {
+    Plugins,
+    Mods,
+}
+
+impl PluginDir {
+    const ALL: [PluginDir; 2] = [PluginDir::Plugins, PluginDir::Mods];
+
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            PluginDir::Plugins => "plugins",
+            PluginDir::Mods => "mods",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum PluginLoader {
+    /// `plugin.yml` or `paper-plugin.yml`
+    Bukkit,
+    /// `fabric.mod.json`
+    Fabric,
+    /// `META-INF/mods.toml`
+    Forge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct PluginInfo {
+    pub dir: PluginDir,
+    /// Name of the jar when enabled, identifies the plugin in its directory
+    pub file_name: String,
+    pub enabled: bool,
+    pub size: u64,
+    /// The name from the metadata, or the file name if the jar has none
+    pub name: String,
+    pub version: Option<String>,
+    pub loader: Option<PluginLoader>,
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PluginMetadata {
+    loader: PluginLoader,
+    name: String,
+    version: Option<String>,
+    dependencies: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct BukkitPluginYml {
+    name: String,
+    version: Option<serde_yaml::Value>,
+    #[serde(default)]
+    depend: Vec<String>,
+    /// `paper-plugin.yml` lists dependencies as a map instead
+    #[serde(default)]
+    dependencies: Option<serde_yaml::Value>,
+}
+
+#[derive(Deserialize)]
+struct FabricModJson {
+    id: String,
+    name: Option<String>,
+    version: Option<String>,
+    #[serde(default)]
+    depends: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ForgeModsToml {
+    #[serde(default)]
+    mods: Vec<ForgeMod>,
+    #[serde(default)]
+    dependencies: toml::Table,
+}
+
+#[derive(Deserialize)]
+struct ForgeMod {
+    #[serde(rename = "modId")]
+    mod_id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    version: Option<String>,
+}
+
+/// A scalar YAML value as a string, versions are sometimes written as numbers
+fn yaml_to_string(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.clone()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn parse_bukkit(content: &str) -> Option<PluginMetadata> {
+    let yml: BukkitPluginYml = serde_yaml::from_str(content).ok()?;
+    let mut dependencies = yml.depend;
+    if let Some(serde_yaml::Value::Mapping(groups)) = yml.dependencies {
+        // paper: `dependencies: { server: { Vault: { required: true } } }`
+        for (_, group) in groups {
+            if let serde_yaml::Value::Mapping(group) = group {
+                dependencies.extend(group.keys().filter_map(yaml_to_string));
+            }
+        }
+    }
+    Some(PluginMetadata {
+        loader: PluginLoader::Bukkit,
+        name: yml.name,
+        version: yml.version.as_ref().and_then(yaml_to_string),
+        dependencies,
+    })
+}
+
+fn parse_fabric(content: &str) -> Option<PluginMetadata> {
+    let json: FabricModJson = serde_json::from_str(content).ok()?;
+    Some(PluginMetadata {
+        loader: PluginLoader::Fabric,
+        name: json.name.unwrap_or(json.id),
+        version: json.version,
+        dependencies: json.depends.keys().cloned().collect(),
+    })
+}
+
+fn parse_forge(content: &str) -> Option<PluginMetadata> {
+    let mods_toml: ForgeModsToml = toml::from_str(content).ok()?;
+    let first = mods_toml.mods.into_iter().next()?;
+    let dependencies = mods_toml
+        .dependencies
+        .get(&first.mod_id)
+        .and_then(|deps| deps.as_array())
+        .map(|deps| {
+            deps.iter()
+                .filter_map(|dep| dep.get("modId")?.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(PluginMetadata {
+        loader: PluginLoader::Forge,
+        name: first.display_name.unwrap_or(first.mod_id),
+        version: first.version,
+        dependencies,
+    })
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<File>, name: &str) -> Option<String> {
+    let entry = archive.by_name(name).ok()?;
+    if entry.size() > MAX_METADATA_BYTES {
+        return None;
+    }
+    let mut content = String::new();
+    entry
+        .take(MAX_METADATA_BYTES)
+        .read_to_string(&mut content)
+        .ok()?;
+    Some(content)
+}
+
+/// Metadata of the jar at `path`, `None` if it isn't a valid archive or has no known metadata
+fn read_metadata(path: &Path) -> Option<PluginMetadata> {
+    let mut archive = zip::ZipArchive::new(File::open(path).ok()?).ok()?;
+    let parsers: [(&str, fn(&str) -> Option<PluginMetadata>); 4] = [
+        ("plugin.yml", parse_bukkit),
+        ("paper-plugin.yml", parse_bukkit),
+        ("fabric.mod.json", parse_fabric),
+        ("META-INF/mods.toml", parse_forge),
+    ];
+    parsers
+        .iter()
+        .find_map(|(file, parse)| parse(&read_entry(&mut archive, file)?))
+}
+
+/// The enabled file name and whether the jar is enabled, `None` for files that aren't jars
+fn jar_file_name(file_name: &str) -> Option<(&str, bool)> {
+    let (name, enabled) = match file_name.strip_suffix(DISABLED_SUFFIX) {
+        Some(name) => (name, false),
+        None => (file_name, true),
+    };
+    name.to_lowercase()
+        .ends_with(".jar")
+        .then_some((name, enabled))
+}
+
+/// Every jar in `plugins/` and `mods/` of the instance at `root`, sorted by name
+///
+/// Reads the jars, call it from a blocking task
+pub fn list_plugins(root: &Path) -> Result<Vec<PluginInfo>, Error> {
+    let mut plugins = Vec::new();
+    for dir in PluginDir::ALL {
+        let entries = match std::fs::read_dir(root.join(dir.dir_name())) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(eyre!("Failed to read {}: {}", dir.dir_name(), e).into()),
+        };
+        for entry in entries.flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            let os_file_name = entry.file_name();
+            let (file_name, enabled) = match os_file_name.to_str().and_then(jar_file_name) {
+                Some(jar) => jar,
+                None => continue,
+            };
+            let plugin_metadata = read_metadata(&entry.path());
+            plugins.push(PluginInfo {
+                dir,
+                file_name: file_name.to_string(),
+                enabled,
+                size: metadata.len(),
+                name: plugin_metadata
+                    .as_ref()
+                    .map(|m| m.name.clone())
+                    .unwrap_or_else(|| file_name.to_string()),
+                version: plugin_metadata.as_ref().and_then(|m| m.version.clone()),
+                loader: plugin_metadata.as_ref().map(|m| m.loader),
+                dependencies: plugin_metadata.map(|m| m.dependencies).unwrap_or_default(),
+            });
+        }
+    }
+    plugins.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    Ok(plugins)
+}
+
+/// Rejects anything but the plain file name of an enabled jar
+pub fn validate_file_name(file_name: &str) -> Result<(), Error> {
+    if jar_file_name(file_name) != Some((file_name, true))
+        || file_name.contains(['/', '\\'])
+        || file_name.starts_with('.')
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid plugin file name"),
+        });
+    }
+    Ok(())
+}
+
+/// Current path of the jar named `file_name` in `dir`, whether it is enabled or not
+pub fn plugin_path(root: &Path, dir: PluginDir, file_name: &str) -> Result<PathBuf, Error> {
+    validate_file_name(file_name)?;
+    let dir = root.join(dir.dir_name());
+    let enabled = dir.join(file_name);
+    let disabled = dir.join(format!("{file_name}{DISABLED_SUFFIX}"));
+    if enabled.is_file() {
+        Ok(enabled)
+    } else if disabled.is_file() {
+        Ok(disabled)
+    } else {
+        Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Plugin {} not found", file_name),
+        })
+    }
+}
+
+/// Renames the jar to or from its `.disabled` name
+pub async fn set_plugin_enabled(
+    root: &Path,
+    dir: PluginDir,
+    file_name: &str,
+    enabled: bool,
+) -> Result<(), Error> {
+    let current = plugin_path(root, dir, file_name)?;
+    let target = root.join(dir.dir_name()).join(if enabled {
+        file_name.to_string()
+    } else {
+        format!("{file_name}{DISABLED_SUFFIX}")
+    });
+    if current == target {
+        return Ok(());
+    }
+    if target.exists() {
+        return Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!("{} already exists", target.display()),
+        });
+    }
+    crate::util::fs::rename(&current, &target).await
+}
+
+#[cfg(test)]
+fn write_jar(path: &Path, entries: &[(&str, &str)]) {
+    use std::io::Write;
+    let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+    for (name, content) in entries {
+        writer
+            .start_file(*name, zip::write::FileOptions::default())
+            .unwrap();
+        writer.write_all(content.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap();
+}
+
+#[tokio::test]
+async fn test_list_plugins() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(root.path().join("plugins")).unwrap();
+    std::fs::create_dir_all(root.path().join("mods")).unwrap();
+    write_jar(
+        &root.path().join("plugins/essentials.jar"),
+        &[(
+            "plugin.yml",
+            "name: Essentials\nversion: 2.20.1\ndepend: [Vault]\nmain: com.earth2me.Essentials\n",
+        )],
+    );
+    write_jar(
+        &root.path().join("mods/sodium.jar.disabled"),
+        &[(
+            "fabric.mod.json",
+            r#"{"id": "sodium", "name": "Sodium", "version": "0.4.10", "depends": {"fabricloader": ">=0.12"}}"#,
+        )],
+    );
+    std::fs::write(root.path().join("plugins/broken.jar"), "not a zip").unwrap();
+    std::fs::write(root.path().join("plugins/config.yml"), "").unwrap();
+
+    let plugins = list_plugins(root.path()).unwrap();
+    assert_eq!(plugins.len(), 3);
+    assert_eq!(plugins[0].name, "broken.jar");
+    assert_eq!(plugins[0].loader, None);
+    assert_eq!(plugins[1].name, "Essentials");
+    assert_eq!(plugins[1].version.as_deref(), Some("2.20.1"));
+    assert_eq!(plugins[1].dependencies, vec!["Vault".to_string()]);
+    assert_eq!(plugins[2].name, "Sodium");
+    assert_eq!(plugins[2].file_name, "sodium.jar");
+    assert!(!plugins[2].enabled);
+
+    set_plugin_enabled(root.path(), PluginDir::Mods, "sodium.jar", true)
+        .await
+        .unwrap();
+    assert!(root.path().join("mods/sodium.jar").is_file());
+    assert!(plugin_path(root.path(), PluginDir::Mods, "../plugins/essentials.jar").is_err());
+    assert_eq!(
+        plugin_path(root.path(), PluginDir::Mods, "missing.jar")
+            .unwrap_err()
+            .kind,
+        ErrorKind::NotFound
+    );
+}
@@ -1,10 +1,14 @@
-use std::{collections::HashSet, net::SocketAddrV4};
+use std::{collections::HashSet, net::SocketAddrV4, ops::RangeInclusive};
 
 use color_eyre::eyre::{eyre, Context};
 use serde::{Deserialize, Serialize};
 
 use crate::error::Error;
 
+/// Ports are allocated upwards from the one an instance asks for, there is no configured range,
+/// so this is every port an instance can get without privileges
+pub const ALLOCATABLE_PORTS: RangeInclusive<u32> = 1024..=65535;
+
 pub struct PortManager {
     allocated_ports: HashSet<u32>,
 }
@@ -43,6 +47,24 @@ impl PortManager {
         }
     }
 
+    /// Allocated ports in ascending order
+    pub fn allocated_ports(&self) -> Vec<u32> {
+        let mut ports: Vec<u32> = self.allocated_ports.iter().copied().collect();
+        ports.sort_unstable();
+        ports
+    }
+
+    /// Ports in [`ALLOCATABLE_PORTS`] that aren't allocated, whether or not something else is
+    /// bound to them
+    pub fn free_port_count(&self) -> u32 {
+        let allocated = self
+            .allocated_ports
+            .iter()
+            .filter(|port| ALLOCATABLE_PORTS.contains(port))
+            .count() as u32;
+        ALLOCATABLE_PORTS.end() - ALLOCATABLE_PORTS.start() + 1 - allocated
+    }
+
     pub fn add_port(&mut self, port: u32) {
         self.allocated_ports.insert(port);
     }
@@ -80,3 +102,10 @@ impl PortManager {
         .unwrap()
     }
 }
+
+#[test]
+fn test_free_port_count() {
+    let manager = PortManager::new(HashSet::from([25565, 25566, 80]));
+    assert_eq!(manager.allocated_ports(), vec![80, 25565, 25566]);
+    assert_eq!(manager.free_port_count(), 64512 - 2);
+}
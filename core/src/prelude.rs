@@ -45,6 +45,12 @@ pub fn path_to_tmp() -> &'static PathBuf {
     PATH_TO_TMP.get().unwrap()
 }
 
+static PATH_TO_TRASH: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn path_to_trash() -> &'static PathBuf {
+    PATH_TO_TRASH.get().unwrap()
+}
+
 static APP_STATE: OnceCell<AppState> = OnceCell::new();
 
 pub fn init_app_state(app_state: AppState) {
@@ -55,22 +61,34 @@ pub fn app_state() -> &'static AppState {
     APP_STATE.get().unwrap()
 }
 
-/// Initialize the paths for the lodestone instance.
+/// Initialize the paths for the lodestone instance, with binaries and stores in the
+/// default place inside `lodestone_path`.
 /// This function should only be called once.
 ///
 /// Also creates the directories if they don't exist.
 pub fn init_paths(lodestone_path: PathBuf) {
+    init_paths_with(lodestone_path, None, None)
+}
+
+/// Like `init_paths`, with binaries and stores optionally kept outside the data directory
+pub fn init_paths_with(
+    lodestone_path: PathBuf,
+    path_to_binaries: Option<PathBuf>,
+    path_to_stores: Option<PathBuf>,
+) {
     let path_to_instances = lodestone_path.join("instances");
-    let path_to_binaries = lodestone_path.join("bin");
-    let path_to_stores = lodestone_path.join("stores");
+    let path_to_binaries = path_to_binaries.unwrap_or_else(|| lodestone_path.join("bin"));
+    let path_to_stores = path_to_stores.unwrap_or_else(|| lodestone_path.join("stores"));
     let path_to_global_settings = lodestone_path.join("global_settings.json");
-    let path_to_users = lodestone_path.join("stores").join("users.json");
+    let path_to_users = path_to_stores.join("users.json");
     let path_to_tmp = lodestone_path.join("tmp");
+    let path_to_trash = lodestone_path.join("trash");
 
     std::fs::create_dir_all(&path_to_instances).unwrap();
     std::fs::create_dir_all(&path_to_binaries).unwrap();
     std::fs::create_dir_all(&path_to_stores).unwrap();
     std::fs::create_dir_all(&path_to_tmp).unwrap();
+    std::fs::create_dir_all(&path_to_trash).unwrap();
     // std::fs::File::create(&path_to_global_settings).unwrap();
     // std::fs::File::create(&path_to_users).unwrap();
     // std::fs::File::create(&path_to_tmp).unwrap();
@@ -82,6 +100,7 @@ pub fn init_paths(lodestone_path: PathBuf) {
     let _ = PATH_TO_GLOBAL_SETTINGS.set(path_to_global_settings);
     let _ = PATH_TO_USERS.set(path_to_users);
     let _ = PATH_TO_TMP.set(path_to_tmp);
+    let _ = PATH_TO_TRASH.set(path_to_trash);
 }
 
 thread_local! {
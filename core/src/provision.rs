@@ -0,0 +1,186 @@
+//! Declarative provisioning. On boot, the instances described by the JSON file at
+//! `LODESTONE_PROVISION` are set up unless one with the same uuid or name already exists, so
+//! the same file can be mounted on every start of a container
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use crate::error::{Error, ErrorKind};
+use crate::handlers::instance::setup_minecraft_instance;
+use crate::handlers::instance_setup_configs::HandlerGameType;
+use crate::traits::t_configurable::manifest::SetupValue;
+use crate::traits::t_configurable::TConfigurable;
+use crate::types::InstanceUuid;
+use crate::AppState;
+
+pub const PROVISION_ENV: &str = "LODESTONE_PROVISION";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ProvisionedInstance {
+    /// Pins the uuid of the instance, with or without the `INSTANCE_` prefix
+    #[serde(default)]
+    pub uuid: Option<String>,
+    pub game_type: HandlerGameType,
+    /// The same body the create instance endpoint takes, its name identifies the instance
+    pub setup: SetupValue,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ProvisionFile {
+    #[serde(default)]
+    pub instances: Vec<ProvisionedInstance>,
+}
+
+impl ProvisionedInstance {
+    /// The pinned uuid in its prefixed form
+    pub fn instance_uuid(&self) -> Result<Option<InstanceUuid>, Error> {
+        let uuid = match &self.uuid {
+            Some(uuid) => uuid.trim(),
+            None => return Ok(None),
+        };
+        let bare = uuid.strip_prefix("INSTANCE_").unwrap_or(uuid);
+        let parsed = uuid::Uuid::parse_str(bare).map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid uuid {uuid} for instance {}: {e}", self.setup.name),
+        })?;
+        Ok(Some(InstanceUuid::from(format!("INSTANCE_{parsed}"))))
+    }
+}
+
+pub async fn read_provision_file(path: &Path) -> Result<ProvisionFile, Error> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .context(format!("Failed to read provision file {}", path.display()))?;
+    serde_json::from_str(&content).map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid provision file {}: {e}", path.display()),
+    })
+}
+
+/// The entries of `file` that don't match any of the `existing` uuids and names, nor an earlier
+/// entry, paired with their pinned uuid
+pub fn missing_instances(
+    file: ProvisionFile,
+    existing: &[(InstanceUuid, String)],
+) -> Vec<(ProvisionedInstance, Option<InstanceUuid>)> {
+    let mut uuids: HashSet<InstanceUuid> = existing.iter().map(|(uuid, _)| uuid.clone()).collect();
+    let mut names: HashSet<String> = existing.iter().map(|(_, name)| name.clone()).collect();
+    let mut ret = Vec::new();
+    for instance in file.instances {
+        let uuid = match instance.instance_uuid() {
+            Ok(uuid) => uuid,
+            Err(e) => {
+                error!("Skipping provisioned instance: {e}");
+                continue;
+            }
+        };
+        if names.contains(&instance.setup.name)
+            || uuid.as_ref().map_or(false, |uuid| uuids.contains(uuid))
+        {
+            continue;
+        }
+        names.insert(instance.setup.name.clone());
+        if let Some(uuid) = &uuid {
+            uuids.insert(uuid.clone());
+        }
+        ret.push((instance, uuid));
+    }
+    ret
+}
+
+/// Sets up the instances in the provision file that don't exist yet. Failures are logged so
+/// one bad entry doesn't keep the core from starting
+pub async fn provision_instances(state: AppState) {
+    let path = match std::env::var_os(PROVISION_ENV) {
+        Some(path) => std::path::PathBuf::from(path),
+        None => return,
+    };
+    let file = match read_provision_file(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+    let mut existing = Vec::new();
+    for entry in state.instances.iter() {
+        existing.push((entry.key().clone(), entry.value().name().await));
+    }
+    let missing = missing_instances(file, &existing);
+    if missing.is_empty() {
+        info!("All provisioned instances already exist");
+        return;
+    }
+    for (instance, uuid) in missing {
+        let name = instance.setup.name.clone();
+        match setup_minecraft_instance(
+            state.clone(),
+            None,
+            uuid,
+            instance.game_type,
+            instance.setup,
+        )
+        .await
+        {
+            Ok(uuid) => info!("Provisioning instance {name} as {uuid}"),
+            Err(e) => warn!("Failed to provision instance {name}: {e}"),
+        }
+    }
+}
+
+#[test]
+fn test_missing_instances() {
+    let setup = |name: &str| SetupValue {
+        name: name.to_string(),
+        description: None,
+        auto_start: false,
+        restart_on_crash: false,
+        setting_sections: Default::default(),
+    };
+    let pinned = "5f0b3c1e-8d2a-4c47-9a8e-2f6d1b7c9e04";
+    let file = ProvisionFile {
+        instances: vec![
+            ProvisionedInstance {
+                uuid: None,
+                game_type: HandlerGameType::MinecraftPaper,
+                setup: setup("lobby"),
+            },
+            ProvisionedInstance {
+                uuid: Some(pinned.to_string()),
+                game_type: HandlerGameType::MinecraftPaper,
+                setup: setup("survival"),
+            },
+            ProvisionedInstance {
+                uuid: None,
+                game_type: HandlerGameType::MinecraftFabric,
+                setup: setup("creative"),
+            },
+            // repeated in the file
+            ProvisionedInstance {
+                uuid: None,
+                game_type: HandlerGameType::MinecraftFabric,
+                setup: setup("creative"),
+            },
+            ProvisionedInstance {
+                uuid: Some("../../etc".to_string()),
+                game_type: HandlerGameType::MinecraftPaper,
+                setup: setup("broken"),
+            },
+        ],
+    };
+    let existing = vec![
+        (InstanceUuid::default(), "lobby".to_string()),
+        (
+            InstanceUuid::from(format!("INSTANCE_{pinned}")),
+            "renamed survival".to_string(),
+        ),
+    ];
+    let missing = missing_instances(file, &existing);
+    assert_eq!(missing.len(), 1);
+    assert_eq!(missing[0].0.setup.name, "creative");
+    assert!(missing[0].1.is_none());
+}
@@ -0,0 +1,277 @@
+//! Sensitive values (webhook URLs, API keys...) kept encrypted at rest, apart from the global settings
+//!
+//! The key is derived from `LODESTONE_SECRETS_PASSPHRASE` when it is set, otherwise it is a
+//! random key stored in its own file, so a leaked settings or secrets file alone reveals nothing.
+//! Secrets can be set and deleted through the API but never read back, they are only
+//! substituted where referenced as `${secret.NAME}`
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use argon2::Argon2;
+use color_eyre::eyre::{eyre, Context};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+const PASSPHRASE_ENV: &str = "LODESTONE_SECRETS_PASSPHRASE";
+
+/// Encrypted with the key to tell a wrong passphrase apart from corrupted secrets
+const VERIFIER: &str = "lodestone";
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EncryptedValue {
+    /// Hex encoded nonce followed by the ciphertext
+    data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredSecret {
+    value: EncryptedValue,
+    updated_at: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SecretsFile {
+    /// Salt of the passphrase, `None` when the key is read from the key file
+    salt: Option<String>,
+    verifier: Option<EncryptedValue>,
+    secrets: BTreeMap<String, StoredSecret>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct SecretInfo {
+    pub name: String,
+    pub updated_at: i64,
+}
+
+#[derive(Clone)]
+pub struct SecretStore {
+    path: PathBuf,
+    cipher: Arc<Aes256Gcm>,
+    file: Arc<Mutex<SecretsFile>>,
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn encrypt(cipher: &Aes256Gcm, plaintext: &str) -> Result<EncryptedValue, Error> {
+    let nonce = random_bytes::<NONCE_LEN>();
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| eyre!("Failed to encrypt secret"))?;
+    Ok(EncryptedValue {
+        data: hex::encode([nonce.as_slice(), &ciphertext].concat()),
+    })
+}
+
+fn decrypt(cipher: &Aes256Gcm, value: &EncryptedValue) -> Result<String, Error> {
+    let data = hex::decode(&value.data).context("Secret is not valid hex")?;
+    if data.len() < NONCE_LEN {
+        return Err(eyre!("Secret is too short").into());
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| eyre!("Failed to decrypt secret, was the passphrase changed?"))?;
+    Ok(String::from_utf8(plaintext).context("Secret is not valid UTF-8")?)
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], Error> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| eyre!("Failed to derive the secrets key: {e}"))?;
+    Ok(key)
+}
+
+/// Reads the key file, creating it readable by the owner only if it doesn't exist
+async fn key_file_key(path: &Path) -> Result<[u8; 32], Error> {
+    if let Ok(content) = tokio::fs::read_to_string(path).await {
+        let key = hex::decode(content.trim()).context("Secrets key file is not valid hex")?;
+        return key
+            .try_into()
+            .map_err(|_| eyre!("Secrets key file does not hold a 32 byte key").into());
+    }
+    let key = random_bytes::<32>();
+    crate::util::fs::write_all(path, hex::encode(key)).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .await
+            .context("Failed to restrict the secrets key file")?;
+    }
+    Ok(key)
+}
+
+impl SecretStore {
+    pub async fn load(path_to_stores: &Path) -> Result<Self, Error> {
+        let path = path_to_stores.join("secrets.json");
+        let mut file: SecretsFile = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => serde_json::from_str(&content).context("Failed to parse secrets")?,
+            Err(_) => SecretsFile::default(),
+        };
+        let key = match std::env::var(PASSPHRASE_ENV) {
+            Ok(passphrase) => {
+                let salt = match &file.salt {
+                    Some(salt) => hex::decode(salt).context("Secrets salt is not valid hex")?,
+                    None if file.secrets.is_empty() => {
+                        let salt = random_bytes::<16>().to_vec();
+                        file.salt = Some(hex::encode(&salt));
+                        file.verifier = None;
+                        salt
+                    }
+                    None => {
+                        return Err(eyre!(
+                            "Secrets were stored without a passphrase, unset {PASSPHRASE_ENV}"
+                        )
+                        .into())
+                    }
+                };
+                passphrase_key(&passphrase, &salt)?
+            }
+            Err(_) if file.salt.is_some() => {
+                return Err(
+                    eyre!("Secrets are encrypted with a passphrase, set {PASSPHRASE_ENV}").into(),
+                )
+            }
+            Err(_) => key_file_key(&path_to_stores.join("secrets.key")).await?,
+        };
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| eyre!("Invalid secrets key"))?;
+        match &file.verifier {
+            Some(verifier) => {
+                if decrypt(&cipher, verifier)? != VERIFIER {
+                    return Err(eyre!("Secrets key does not match the stored secrets").into());
+                }
+            }
+            None => file.verifier = Some(encrypt(&cipher, VERIFIER)?),
+        }
+        let store = SecretStore {
+            path,
+            cipher: Arc::new(cipher),
+            file: Arc::new(Mutex::new(file)),
+        };
+        store.write(&*store.file.lock().await).await?;
+        Ok(store)
+    }
+
+    async fn write(&self, file: &SecretsFile) -> Result<(), Error> {
+        crate::util::fs::write_all(
+            &self.path,
+            serde_json::to_string_pretty(file).context("Failed to serialize secrets")?,
+        )
+        .await
+    }
+
+    /// Names of the stored secrets, never their values
+    pub async fn list(&self) -> Vec<SecretInfo> {
+        self.file
+            .lock()
+            .await
+            .secrets
+            .iter()
+            .map(|(name, secret)| SecretInfo {
+                name: name.clone(),
+                updated_at: secret.updated_at,
+            })
+            .collect()
+    }
+
+    pub async fn set(&self, name: &str, value: &str, now: i64) -> Result<(), Error> {
+        let mut file = self.file.lock().await;
+        file.secrets.insert(
+            name.to_string(),
+            StoredSecret {
+                value: encrypt(&self.cipher, value)?,
+                updated_at: now,
+            },
+        );
+        self.write(&file).await
+    }
+
+    pub async fn delete(&self, name: &str) -> Result<(), Error> {
+        let mut file = self.file.lock().await;
+        if file.secrets.remove(name).is_none() {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Secret {} not found", name),
+            });
+        }
+        self.write(&file).await
+    }
+
+    /// Encrypts a value kept outside of the store with the same key, e.g. a TOTP secret
+    pub fn seal(&self, plaintext: &str) -> Result<String, Error> {
+        Ok(encrypt(&self.cipher, plaintext)?.data)
+    }
+
+    pub fn unseal(&self, sealed: &str) -> Result<String, Error> {
+        decrypt(
+            &self.cipher,
+            &EncryptedValue {
+                data: sealed.to_string(),
+            },
+        )
+    }
+
+    /// Every secret in plaintext, for substituting `${secret.NAME}` references
+    pub async fn decrypt_all(&self) -> Result<BTreeMap<String, String>, Error> {
+        let file = self.file.lock().await;
+        file.secrets
+            .iter()
+            .map(|(name, secret)| Ok((name.clone(), decrypt(&self.cipher, &secret.value)?)))
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn test_secret_store() {
+    let stores = tempfile::tempdir().unwrap();
+    let store = SecretStore::load(stores.path()).await.unwrap();
+    store
+        .set("DISCORD_WEBHOOK", "https://discord.com/api/webhooks/1", 10)
+        .await
+        .unwrap();
+
+    // the value is not stored in plaintext
+    let on_disk = std::fs::read_to_string(stores.path().join("secrets.json")).unwrap();
+    assert!(on_disk.contains("DISCORD_WEBHOOK"));
+    assert!(!on_disk.contains("discord.com"));
+
+    // and can be read back after a restart
+    let store = SecretStore::load(stores.path()).await.unwrap();
+    assert_eq!(
+        store.list().await,
+        vec![SecretInfo {
+            name: "DISCORD_WEBHOOK".to_string(),
+            updated_at: 10
+        }]
+    );
+    assert_eq!(
+        store.decrypt_all().await.unwrap()["DISCORD_WEBHOOK"],
+        "https://discord.com/api/webhooks/1"
+    );
+
+    store.delete("DISCORD_WEBHOOK").await.unwrap();
+    assert_eq!(
+        store.delete("DISCORD_WEBHOOK").await.unwrap_err().kind,
+        ErrorKind::NotFound
+    );
+}
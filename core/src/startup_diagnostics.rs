@@ -0,0 +1,105 @@
+//! Pre-flight checks run when an instance fails to start, so the error response can say
+//! what to fix instead of only why the launch failed
+
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum StartupCheck {
+    Java,
+    Eula,
+    Port,
+    ServerJar,
+    Memory,
+    DiskSpace,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct DiagnosticCheck {
+    pub check: StartupCheck,
+    pub passed: bool,
+    /// What was found, and for a failed check what to do about it
+    pub message: String,
+}
+
+impl DiagnosticCheck {
+    pub fn passed(check: StartupCheck, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            passed: true,
+            message: message.into(),
+        }
+    }
+
+    pub fn failed(check: StartupCheck, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            passed: false,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct StartupDiagnostics {
+    pub checks: Vec<DiagnosticCheck>,
+}
+
+impl StartupDiagnostics {
+    pub fn failed(&self) -> impl Iterator<Item = &DiagnosticCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+
+    /// Attaches the diagnostics to `error`, the error response then lists them under
+    /// `diagnostics`
+    pub fn attach(self, error: Error) -> Error {
+        Error {
+            kind: error.kind,
+            source: error.source.wrap_err(self),
+        }
+    }
+}
+
+impl Display for StartupDiagnostics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let failed: Vec<&str> = self.failed().map(|check| check.message.as_str()).collect();
+        if failed.is_empty() {
+            write!(f, "Failed to start, all startup checks passed")
+        } else {
+            write!(f, "Failed to start: {}", failed.join("; "))
+        }
+    }
+}
+
+#[test]
+fn test_attach_startup_diagnostics() {
+    use crate::error::ErrorKind;
+    use color_eyre::eyre::eyre;
+
+    let diagnostics = StartupDiagnostics {
+        checks: vec![
+            DiagnosticCheck::passed(StartupCheck::Java, "Java 17 found"),
+            DiagnosticCheck::failed(StartupCheck::Eula, "The EULA has not been accepted"),
+        ],
+    };
+    let error = diagnostics.clone().attach(Error {
+        kind: ErrorKind::Internal,
+        source: eyre!("Server exited"),
+    });
+    assert_eq!(error.kind, ErrorKind::Internal);
+    assert_eq!(
+        error.source.downcast_ref::<StartupDiagnostics>(),
+        Some(&diagnostics)
+    );
+    assert_eq!(
+        error.source.to_string(),
+        "Failed to start: The EULA has not been accepted"
+    );
+}
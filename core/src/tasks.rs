@@ -0,0 +1,297 @@
+//! Long running operations (setup, backups, uploads, downloads...) tracked from the
+//! progression events they emit, so their progress can be queried instead of only followed live
+
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::eyre;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{
+        CausedBy, Event, EventInner, ProgressionEndValue, ProgressionEventID,
+        ProgressionEventInner, ProgressionStartValue,
+    },
+    types::{InstanceUuid, Snowflake},
+};
+
+/// How long a finished task can still be queried
+const FINISHED_TASK_RETENTION: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum TaskStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct TaskInfo {
+    /// The event id of the task's progression events
+    pub id: Snowflake,
+    pub name: String,
+    pub status: TaskStatus,
+    /// Amount of work done, in the same unit as `total`
+    pub progress: f64,
+    pub total: Option<f64>,
+    /// Latest progress message, or the end message once finished
+    pub message: Option<String>,
+    pub result: Option<ProgressionEndValue>,
+    pub caused_by: CausedBy,
+    /// The instance the task works on, `None` for tasks that aren't about one instance
+    pub instance_uuid: Option<InstanceUuid>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    /// Whether `DELETE /tasks/:id` can cancel the task
+    pub cancellable: bool,
+}
+
+/// The error an operation returns when it stops because its task was cancelled, a
+/// client error since it was asked for
+pub fn cancelled_error() -> Error {
+    Error {
+        kind: ErrorKind::Conflict,
+        source: eyre!("Cancelled"),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TaskRegistry {
+    tasks: Arc<DashMap<Snowflake, TaskInfo>>,
+    /// Tokens of running tasks that stop when cancelled
+    cancellation_tokens: Arc<DashMap<Snowflake, CancellationToken>>,
+}
+
+impl TaskRegistry {
+    /// Updates the task a progression event belongs to, other events are ignored
+    pub fn observe(&self, event: &Event, now: i64) {
+        let progression_event = match &event.event_inner {
+            EventInner::ProgressionEvent(progression_event) => progression_event,
+            _ => return,
+        };
+        let id = progression_event.event_id();
+        match progression_event.progression_event_inner() {
+            ProgressionEventInner::ProgressionStart {
+                progression_name,
+                total,
+                inner,
+            } => {
+                let instance_uuid = inner.as_ref().map(|inner| match inner {
+                    ProgressionStartValue::InstanceCreation { instance_uuid }
+                    | ProgressionStartValue::InstanceDelete { instance_uuid }
+                    | ProgressionStartValue::InstanceOperation { instance_uuid } => {
+                        instance_uuid.clone()
+                    }
+                });
+                self.collect_garbage(now);
+                self.tasks.insert(
+                    id,
+                    TaskInfo {
+                        id,
+                        name: progression_name.clone(),
+                        status: TaskStatus::Running,
+                        progress: 0.0,
+                        total: *total,
+                        message: None,
+                        result: None,
+                        caused_by: event.caused_by.clone(),
+                        instance_uuid,
+                        started_at: now,
+                        finished_at: None,
+                        cancellable: false,
+                    },
+                );
+            }
+            ProgressionEventInner::ProgressionUpdate {
+                progress_message,
+                progress,
+            } => {
+                if let Some(mut task) = self.tasks.get_mut(&id) {
+                    task.progress += progress;
+                    task.message = Some(progress_message.clone());
+                }
+            }
+            ProgressionEventInner::ProgressionEnd {
+                success,
+                message,
+                inner,
+            } => {
+                let cancelled = self
+                    .cancellation_tokens
+                    .remove(&id)
+                    .map(|(_, token)| token.is_cancelled())
+                    .unwrap_or(false);
+                if let Some(mut task) = self.tasks.get_mut(&id) {
+                    task.status = if *success {
+                        TaskStatus::Succeeded
+                    } else if cancelled {
+                        TaskStatus::Cancelled
+                    } else {
+                        TaskStatus::Failed
+                    };
+                    if *success {
+                        if let Some(total) = task.total {
+                            task.progress = total;
+                        }
+                    }
+                    task.message = message.clone();
+                    task.result = inner.clone();
+                    task.finished_at = Some(now);
+                }
+            }
+        }
+    }
+
+    /// Makes the task cancellable, the operation must stop when the returned token is cancelled
+    pub fn cancellation_token(&self, id: &ProgressionEventID) -> CancellationToken {
+        self.cancellation_tokens
+            .entry(id.inner())
+            .or_insert_with(CancellationToken::new)
+            .clone()
+    }
+
+    /// Asks a running task to stop, it ends as cancelled once the operation has cleaned up
+    pub fn cancel(&self, id: &Snowflake, now: i64) -> Result<(), Error> {
+        let task = self.get(id, now);
+        if let Some(task) = &task {
+            if task.status != TaskStatus::Running {
+                return Err(Error {
+                    kind: ErrorKind::Conflict,
+                    source: eyre!("Task has already finished"),
+                });
+            }
+        }
+        match (self.cancellation_tokens.get(id), task) {
+            (Some(token), _) => {
+                token.cancel();
+                Ok(())
+            }
+            (None, Some(_)) => Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("Task cannot be cancelled"),
+            }),
+            (None, None) => Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Task not found"),
+            }),
+        }
+    }
+
+    fn with_cancellable(&self, mut task: TaskInfo) -> TaskInfo {
+        task.cancellable = self.cancellation_tokens.contains_key(&task.id);
+        task
+    }
+
+    fn collect_garbage(&self, now: i64) {
+        self.tasks.retain(|_, task| match task.finished_at {
+            Some(finished_at) => now - finished_at < FINISHED_TASK_RETENTION.as_secs() as i64,
+            None => true,
+        });
+    }
+
+    /// Running and recently finished tasks, most recently started first
+    pub fn list(&self, now: i64) -> Vec<TaskInfo> {
+        self.collect_garbage(now);
+        let mut tasks: Vec<TaskInfo> = self
+            .tasks
+            .iter()
+            .map(|task| self.with_cancellable(task.clone()))
+            .collect();
+        tasks.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        tasks
+    }
+
+    pub fn get(&self, id: &Snowflake, now: i64) -> Option<TaskInfo> {
+        self.collect_garbage(now);
+        self.tasks
+            .get(id)
+            .map(|task| self.with_cancellable(task.clone()))
+    }
+}
+
+#[test]
+fn test_task_registry() {
+    let registry = TaskRegistry::default();
+    let (start, event_id) =
+        Event::new_progression_event_start("Downloading", Some(100.0), None, CausedBy::System);
+    registry.observe(&start, 0);
+    registry.observe(
+        &Event::new_progression_event_update(&event_id, "Downloading", 30.0),
+        1,
+    );
+    registry.observe(
+        &Event::new_progression_event_update(&event_id, "Downloading", 30.0),
+        2,
+    );
+    let task = &registry.list(2)[0];
+    assert_eq!(task.status, TaskStatus::Running);
+    assert_eq!(task.progress, 60.0);
+
+    registry.observe(
+        &Event::new_progression_event_end(event_id, true, Some("Done"), None),
+        3,
+    );
+    let task = registry.list(3).remove(0);
+    assert_eq!(task.status, TaskStatus::Succeeded);
+    assert_eq!(task.progress, 100.0);
+    assert_eq!(task.message.as_deref(), Some("Done"));
+    assert!(registry.get(&task.id, 3).is_some());
+
+    // finished tasks are dropped once the retention runs out
+    assert!(registry
+        .get(&task.id, 3 + FINISHED_TASK_RETENTION.as_secs() as i64)
+        .is_none());
+
+    let instance_uuid = InstanceUuid::from("INSTANCE_1".to_string());
+    let (start, event_id) = Event::new_progression_event_start(
+        "Exporting",
+        None,
+        Some(ProgressionStartValue::InstanceOperation {
+            instance_uuid: instance_uuid.clone(),
+        }),
+        CausedBy::System,
+    );
+    registry.observe(&start, 4);
+    assert_eq!(
+        registry.get(&event_id.inner(), 4).unwrap().instance_uuid,
+        Some(instance_uuid)
+    );
+}
+
+#[test]
+fn test_cancel_task() {
+    let registry = TaskRegistry::default();
+    let (start, event_id) =
+        Event::new_progression_event_start("Zipping", None, None, CausedBy::System);
+    registry.observe(&start, 0);
+    let id = event_id.inner();
+    assert_eq!(
+        registry.cancel(&id, 0).unwrap_err().kind,
+        ErrorKind::Conflict
+    );
+
+    let token = registry.cancellation_token(&event_id);
+    assert!(registry.get(&id, 0).unwrap().cancellable);
+    registry.cancel(&id, 0).unwrap();
+    assert!(token.is_cancelled());
+    registry.observe(
+        &Event::new_progression_event_end(event_id, false, Some("Cancelled"), None),
+        1,
+    );
+    assert_eq!(registry.get(&id, 1).unwrap().status, TaskStatus::Cancelled);
+    assert_eq!(
+        registry.cancel(&id, 1).unwrap_err().kind,
+        ErrorKind::Conflict
+    );
+    assert_eq!(
+        registry.cancel(&Snowflake::new(), 1).unwrap_err().kind,
+        ErrorKind::NotFound
+    );
+    assert_eq!(cancelled_error().kind, ErrorKind::Conflict);
+}
@@ -1,21 +1,31 @@
 use color_eyre::eyre::eyre;
+use tracing::error;
 
 use crate::{
     auth::{jwt_token::JwtToken, permission::UserPermission, user::User},
+    db::write::write_session,
     error::{Error, ErrorKind},
-    events::CausedBy,
+    events::{CausedBy, Event},
     AppState,
 };
 
+/// Starts a session for the owner, listed and revocable like any other login
 pub async fn get_owner_jwt(app_state: &AppState) -> Option<JwtToken> {
-    app_state
-        .users_manager
-        .read()
-        .await
+    let users_manager = app_state.users_manager.read().await;
+    let owner = users_manager
         .as_ref()
-        .iter()
-        .find(|(_, user)| user.is_owner)
-        .and_then(|(_, user)| user.create_jwt().ok())
+        .values()
+        .find(|user| user.is_owner)?
+        .clone();
+    let (token, session) = users_manager
+        .create_session(&owner, None, None, chrono::Utc::now().timestamp())
+        .ok()?;
+    if app_state.db_health.is_available() {
+        if let Err(e) = write_session(&app_state.sqlite_pool, &session).await {
+            error!("Failed to save session: {}", e);
+        }
+    }
+    Some(token)
 }
 
 pub async fn is_owner_account_present(app_state: &AppState) -> bool {
@@ -44,9 +54,12 @@ pub async fn setup_owner_account(
         .users_manager
         .write()
         .await
-        .add_user(user, CausedBy::System)
+        .add_user(user.clone(), CausedBy::System)
         .await?;
     app_state.first_time_setup_key.lock().await.take();
+    app_state
+        .event_broadcaster
+        .send(Event::new_setup_completed(user.uid));
     Ok(())
 }
 
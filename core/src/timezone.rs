@@ -0,0 +1,85 @@
+//! The timezone of the core. Scheduling that goes by the calendar, like keeping one backup a
+//! day, uses it, while timestamps in the API stay UTC seconds since the epoch with the offset
+//! sent alongside so clients don't have to guess it
+
+use axum::{
+    extract::State,
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{Offset, TimeZone};
+use chrono_tz::Tz;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    error::{Error, ErrorKind},
+    AppState,
+};
+
+/// IANA name of the core's timezone, on every API response
+pub const TIMEZONE_HEADER: &str = "x-lodestone-timezone";
+/// The core's current offset from UTC in seconds, on every API response
+pub const UTC_OFFSET_HEADER: &str = "x-lodestone-utc-offset";
+
+/// Parses an IANA timezone name like `Europe/Berlin`
+pub fn parse_timezone(name: &str) -> Result<Tz, Error> {
+    name.parse().map_err(|_| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Unknown timezone {name}, expected an IANA name like Europe/Berlin"),
+    })
+}
+
+/// The machine's timezone, UTC if it can't be determined or isn't a known IANA name
+pub fn local_timezone() -> Tz {
+    iana_time_zone::get_timezone()
+        .ok()
+        .and_then(|name| name.parse().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+/// The offset from UTC in seconds `tz` has at `timestamp`
+pub fn utc_offset_secs(tz: Tz, timestamp: i64) -> i32 {
+    match tz.timestamp_opt(timestamp, 0).single() {
+        Some(time) => time.offset().fix().local_minus_utc(),
+        None => 0,
+    }
+}
+
+/// `timestamp` as read off a wall clock in `tz`, counted in seconds since the epoch, so
+/// dividing it by a day gives the local day
+pub fn local_secs(tz: Tz, timestamp: i64) -> i64 {
+    timestamp + utc_offset_secs(tz, timestamp) as i64
+}
+
+/// Adds the timezone and its current offset to every response
+pub async fn timezone_middleware<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let tz = *state.timezone.borrow();
+    let mut response = next.run(request).await;
+    if let Ok(v) = HeaderValue::from_str(tz.name()) {
+        response.headers_mut().insert(TIMEZONE_HEADER, v);
+    }
+    let offset = utc_offset_secs(tz, chrono::Utc::now().timestamp());
+    response
+        .headers_mut()
+        .insert(UTC_OFFSET_HEADER, HeaderValue::from(offset));
+    response
+}
+
+#[test]
+fn test_timezone_offsets() {
+    assert!(parse_timezone("Mars/Olympus_Mons").is_err());
+    let berlin = parse_timezone("Europe/Berlin").unwrap();
+    // 2023-01-15 and 2023-07-15 at 12:00 UTC, either side of daylight saving
+    assert_eq!(utc_offset_secs(berlin, 1673784000), 3600);
+    assert_eq!(utc_offset_secs(berlin, 1689422400), 7200);
+    assert_eq!(utc_offset_secs(Tz::UTC, 1689422400), 0);
+    // 23:30 UTC on Jan 15 is already Jan 16 in Berlin
+    let late = 1673784000 + 11 * 3600 + 1800;
+    assert_eq!(local_secs(berlin, late).div_euclid(86400), 19373);
+    assert_eq!(local_secs(Tz::UTC, late).div_euclid(86400), 19372);
+}
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 
 use async_trait::async_trait;
 
@@ -35,6 +35,15 @@ pub struct InstanceInfo {
     pub player_count: Option<u32>,
     pub max_player_count: Option<u32>,
     pub player_list: Option<HashSet<Player>>,
+    pub tags: BTreeSet<String>,
+    #[serde(default)]
+    pub notes: String,
+    /// Config was changed while running and needs a restart to apply
+    #[serde(default)]
+    pub restart_required: bool,
+    /// Unix time the instance was moved to the trash, if it was
+    #[serde(default)]
+    pub deleted_at: Option<i64>,
 }
 use crate::generic::GenericInstance;
 use crate::minecraft::MinecraftInstance;
@@ -59,6 +68,10 @@ pub trait TInstance: TConfigurable + TMacro + TPlayerManagement + TServer + Clon
             player_count: self.get_player_count().await.ok(),
             max_player_count: self.get_max_player_count().await.ok(),
             player_list: self.get_player_list().await.ok(),
+            tags: self.tags().await,
+            notes: self.notes().await,
+            restart_required: false,
+            deleted_at: None,
         }
     }
 }
@@ -291,6 +291,9 @@ pub struct SettingManifest {
     is_secret: bool,                          // ??
     is_required: bool,                        // ??
     is_mutable: bool,                         // CAN change at runtime
+    /// Changing it only takes effect after the instance restarts
+    #[serde(default)]
+    requires_restart: bool,
 }
 
 impl SettingManifest {
@@ -327,6 +330,7 @@ impl SettingManifest {
             is_secret,
             is_required: true,
             is_mutable,
+            requires_restart: false,
         }
     }
     #[allow(clippy::too_many_arguments)]
@@ -355,6 +359,7 @@ impl SettingManifest {
             is_secret,
             is_required: false,
             is_mutable,
+            requires_restart: false,
         }
     }
 
@@ -383,6 +388,7 @@ impl SettingManifest {
                 is_secret,
                 is_required: true,
                 is_mutable,
+                requires_restart: false,
             }
         } else {
             Self {
@@ -395,10 +401,20 @@ impl SettingManifest {
                 default_value,
                 is_secret,
                 is_mutable,
+                requires_restart: false,
             }
         }
     }
 
+    pub fn with_requires_restart(mut self, requires_restart: bool) -> Self {
+        self.requires_restart = requires_restart;
+        self
+    }
+
+    pub fn requires_restart(&self) -> bool {
+        self.requires_restart
+    }
+
     fn set_value_type_safe(&mut self, value: ConfigurableValue) -> Result<(), Error> {
         self.value_type
             .type_check(&value)
@@ -783,6 +799,71 @@ impl SectionManifest {
     }
 }
 
+/// Describes a setting without its current value, for rendering a form
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct SettingSchema {
+    pub setting_id: String,
+    pub label: String,
+    pub help_text: String,
+    /// Carries the allowed range, regex or options
+    pub value_type: ConfigurableValueType,
+    pub default_value: Option<ConfigurableValue>,
+    pub is_secret: bool,
+    pub is_required: bool,
+    pub is_mutable: bool,
+    pub requires_restart: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct SectionSchema {
+    pub section_id: String,
+    pub label: String,
+    pub help_text: String,
+    pub settings: Vec<SettingSchema>,
+}
+
+/// The config fields an instance exposes, in display order
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ConfigSchema {
+    pub sections: Vec<SectionSchema>,
+}
+
+impl From<&SettingManifest> for SettingSchema {
+    fn from(setting: &SettingManifest) -> Self {
+        Self {
+            setting_id: setting.setting_id.clone(),
+            label: setting.name.clone(),
+            help_text: setting.description.clone(),
+            value_type: setting.value_type.clone(),
+            default_value: setting.default_value.clone(),
+            is_secret: setting.is_secret,
+            is_required: setting.is_required,
+            is_mutable: setting.is_mutable,
+            requires_restart: setting.requires_restart,
+        }
+    }
+}
+
+impl From<&ConfigurableManifest> for ConfigSchema {
+    fn from(manifest: &ConfigurableManifest) -> Self {
+        Self {
+            sections: manifest
+                .setting_sections
+                .values()
+                .map(|section| SectionSchema {
+                    section_id: section.section_id.clone(),
+                    label: section.name.clone(),
+                    help_text: section.description.clone(),
+                    settings: section.settings.values().map(SettingSchema::from).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[ts(export)]
 pub struct SettingLocalCache {
@@ -815,3 +896,44 @@ impl SettingLocalCache {
         &self.value
     }
 }
+
+#[test]
+fn test_config_schema_from_manifest() {
+    let setting = SettingManifest::new_value_with_type(
+        "server-port".to_string(),
+        "Server Port".to_string(),
+        "The port the server listens on".to_string(),
+        Some(ConfigurableValue::UnsignedInteger(25565)),
+        ConfigurableValueType::UnsignedInteger {
+            min: Some(0),
+            max: Some(65535),
+        },
+        None,
+        false,
+        true,
+    )
+    .with_requires_restart(true);
+    let mut settings = IndexMap::new();
+    settings.insert(setting.get_identifier().clone(), setting);
+    let mut sections = IndexMap::new();
+    sections.insert(
+        "section".to_string(),
+        SectionManifest::new(
+            "section".to_string(),
+            "Section".to_string(),
+            "A section".to_string(),
+            settings,
+        ),
+    );
+    let schema = ConfigSchema::from(&ConfigurableManifest::new(false, false, sections));
+    let setting = &schema.sections[0].settings[0];
+    assert_eq!(setting.label, "Server Port");
+    assert!(setting.requires_restart);
+    assert_eq!(
+        setting.value_type,
+        ConfigurableValueType::UnsignedInteger {
+            min: Some(0),
+            max: Some(65535),
+        }
+    );
+}
@@ -1,4 +1,5 @@
 pub mod manifest;
+use std::collections::BTreeSet;
 pub use std::path::PathBuf;
 
 use async_trait::async_trait;
@@ -8,16 +9,22 @@ pub use serde::{Deserialize, Serialize};
 pub use serde_json;
 use ts_rs::TS;
 
+use self::manifest::ConfigSchema;
 use self::manifest::ConfigurableManifest;
 use self::manifest::ConfigurableValue;
+use crate::auto_start::AutoStartOrder;
+use crate::backup_retention::BackupPolicy;
+use crate::console_filter::ConsoleFilters;
 use crate::error::Error;
 use crate::error::ErrorKind;
+use crate::gateway::MaintenanceMode;
 use crate::implementations::minecraft::Flavour;
+use crate::log_cleanup::LogRetention;
 use crate::traits::GameInstance;
 use crate::traits::GenericInstance;
 use crate::traits::MinecraftInstance;
 
-use crate::types::InstanceUuid;
+use crate::types::{DotLodestoneConfig, InstanceUuid};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
 #[serde(tag = "type")]
@@ -91,9 +98,101 @@ pub trait TConfigurable {
     /// does start when lodestone starts
     async fn auto_start(&self) -> bool;
     async fn restart_on_crash(&self) -> bool;
+    /// maximum memory the instance may use in MB, if it is capped
+    async fn max_memory(&self) -> Option<u32> {
+        None
+    }
+    /// tags are stored in the instance's .lodestone_config
+    async fn tags(&self) -> BTreeSet<String> {
+        DotLodestoneConfig::read_from(&self.path().await)
+            .await
+            .map(|config| config.tags().clone())
+            .unwrap_or_default()
+    }
+    async fn auto_start_order(&self) -> AutoStartOrder {
+        DotLodestoneConfig::read_from(&self.path().await)
+            .await
+            .map(|config| config.auto_start_order().clone())
+            .unwrap_or_default()
+    }
+    async fn maintenance(&self) -> MaintenanceMode {
+        DotLodestoneConfig::read_from(&self.path().await)
+            .await
+            .map(|config| config.maintenance().clone())
+            .unwrap_or_default()
+    }
+    async fn log_retention(&self) -> LogRetention {
+        DotLodestoneConfig::read_from(&self.path().await)
+            .await
+            .map(|config| config.log_retention().clone())
+            .unwrap_or_default()
+    }
+    async fn backup_policy(&self) -> BackupPolicy {
+        DotLodestoneConfig::read_from(&self.path().await)
+            .await
+            .map(|config| config.backup_policy().clone())
+            .unwrap_or_default()
+    }
+    async fn console_filters(&self) -> ConsoleFilters {
+        DotLodestoneConfig::read_from(&self.path().await)
+            .await
+            .map(|config| config.console_filters().clone())
+            .unwrap_or_default()
+    }
+    /// free-text notes operators keep about the instance
+    async fn notes(&self) -> String {
+        DotLodestoneConfig::read_from(&self.path().await)
+            .await
+            .map(|config| config.notes().to_string())
+            .unwrap_or_default()
+    }
     // setters
     async fn set_name(&self, name: String) -> Result<(), Error>;
     async fn set_description(&self, description: String) -> Result<(), Error>;
+    async fn set_tags(&self, tags: BTreeSet<String>) -> Result<(), Error> {
+        let path = self.path().await;
+        let mut config = DotLodestoneConfig::read_from(&path).await?;
+        config.set_tags(tags);
+        config.write_to(&path).await
+    }
+    async fn set_auto_start_order(&self, auto_start_order: AutoStartOrder) -> Result<(), Error> {
+        let path = self.path().await;
+        let mut config = DotLodestoneConfig::read_from(&path).await?;
+        config.set_auto_start_order(auto_start_order);
+        config.write_to(&path).await
+    }
+    async fn set_maintenance(&self, maintenance: MaintenanceMode) -> Result<(), Error> {
+        let path = self.path().await;
+        let mut config = DotLodestoneConfig::read_from(&path).await?;
+        config.set_maintenance(maintenance);
+        config.write_to(&path).await
+    }
+    async fn set_log_retention(&self, log_retention: LogRetention) -> Result<(), Error> {
+        let path = self.path().await;
+        let mut config = DotLodestoneConfig::read_from(&path).await?;
+        config.set_log_retention(log_retention);
+        config.write_to(&path).await
+    }
+    async fn set_backup_policy(&self, backup_policy: BackupPolicy) -> Result<(), Error> {
+        backup_policy.validate()?;
+        let path = self.path().await;
+        let mut config = DotLodestoneConfig::read_from(&path).await?;
+        config.set_backup_policy(backup_policy);
+        config.write_to(&path).await
+    }
+    async fn set_console_filters(&self, console_filters: ConsoleFilters) -> Result<(), Error> {
+        console_filters.validate()?;
+        let path = self.path().await;
+        let mut config = DotLodestoneConfig::read_from(&path).await?;
+        config.set_console_filters(console_filters);
+        config.write_to(&path).await
+    }
+    async fn set_notes(&self, notes: String) -> Result<(), Error> {
+        let path = self.path().await;
+        let mut config = DotLodestoneConfig::read_from(&path).await?;
+        config.set_notes(notes);
+        config.write_to(&path).await
+    }
     async fn set_port(&self, _port: u32) -> Result<(), Error> {
         Err(Error {
             kind: ErrorKind::UnsupportedOperation,
@@ -127,6 +226,10 @@ pub trait TConfigurable {
     }
 
     async fn configurable_manifest(&self) -> ConfigurableManifest;
+    /// Field types, ranges and labels for rendering a settings form, derived from the manifest
+    async fn config_schema(&self) -> ConfigSchema {
+        ConfigSchema::from(&self.configurable_manifest().await)
+    }
 
     async fn update_configurable(
         &self,
@@ -5,8 +5,10 @@ use serde::{Deserialize, Serialize};
 
 use ts_rs::TS;
 
+use crate::error::{Error, ErrorKind};
 use crate::events::CausedBy;
-use crate::Error;
+use crate::gateway::GatewayStatsReport;
+use crate::global_settings::MonitorEventThreshold;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, Copy)]
 #[serde(rename = "InstanceState")]
@@ -44,7 +46,7 @@ pub enum StateAction {
     InstanceStop,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
 #[ts(export)]
 pub struct DiskUsage {
     pub total_written_bytes: u64,
@@ -63,7 +65,7 @@ impl From<sysinfo::DiskUsage> for DiskUsage {
         }
     }
 }
-#[derive(Debug, Clone, Serialize, Deserialize, TS, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS, Default)]
 #[serde(rename = "PerformanceReport")]
 #[ts(export)]
 pub struct MonitorReport {
@@ -71,6 +73,47 @@ pub struct MonitorReport {
     pub disk_usage: Option<DiskUsage>,
     pub cpu_usage: Option<f32>,
     pub start_time: Option<u64>,
+    /// Connection stats if the instance is reachable through the gateway
+    #[serde(default)]
+    pub gateway_stats: Option<GatewayStatsReport>,
+    /// Unix time in seconds, set when the report is added to the live buffer
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+}
+
+impl MonitorReport {
+    /// Whether this report differs from `previous` by more than jitter
+    ///
+    /// Disk usage is cumulative and always changes, so it is not considered
+    pub fn is_material_change(
+        &self,
+        previous: &MonitorReport,
+        threshold: &MonitorEventThreshold,
+    ) -> bool {
+        if self.start_time != previous.start_time {
+            return true;
+        }
+        let cpu_changed = match (self.cpu_usage, previous.cpu_usage) {
+            (Some(now), Some(before)) => (now - before).abs() >= threshold.cpu_percent,
+            (now, before) => now.is_some() != before.is_some(),
+        };
+        let memory_changed = match (self.memory_usage, previous.memory_usage) {
+            (Some(now), Some(before)) => {
+                let before = before.max(1) as f64;
+                (now as f64 - before).abs() / before * 100.0 >= threshold.memory_percent as f64
+            }
+            (now, before) => now.is_some() != before.is_some(),
+        };
+        let connections_changed = self
+            .gateway_stats
+            .as_ref()
+            .map(|stats| stats.active_connections)
+            != previous
+                .gateway_stats
+                .as_ref()
+                .map(|stats| stats.active_connections);
+        cpu_changed || memory_changed || connections_changed
+    }
 }
 
 impl ToString for State {
@@ -86,36 +129,46 @@ impl ToString for State {
 }
 
 impl State {
+    /// The state `action` leads to, lifecycle actions that don't apply to the current
+    /// state, like starting an instance that is already starting, are a `Conflict`
     pub fn try_new_state(
         &self,
         action: StateAction,
         on_transit: Option<&dyn Fn(State)>,
     ) -> Result<State, Error> {
+        let conflict = |message: &str| {
+            Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!(message.to_string()),
+            })
+        };
         let state = match (*self, action) {
             (State::Starting, StateAction::UserStart) => {
-                Err(eyre!("Cannot start an instance that is already starting"))
+                conflict("Cannot start an instance that is already starting")
             }
             (State::Starting, StateAction::UserStop) => {
-                Err(eyre!("Cannot stop an instance that is starting"))
+                conflict("Cannot stop an instance that is starting")
             }
             (_, StateAction::InstanceStart) => Ok(State::Running),
             (_, StateAction::InstanceStop) => Ok(State::Stopped),
             (State::Running, StateAction::UserStart) => {
-                Err(eyre!("Cannot start an instance that is already running"))
+                conflict("Cannot start an instance that is already running")
             }
             (State::Running, StateAction::UserStop) => Ok(State::Stopping),
             (State::Stopping, StateAction::UserStart) => {
-                Err(eyre!("Cannot start an instance that is stopping"))
+                conflict("Cannot start an instance that is stopping")
             }
             (State::Stopping, StateAction::UserStop) => {
-                Err(eyre!("Cannot stop an instance that is already stopping"))
+                conflict("Cannot stop an instance that is already stopping")
             }
             (State::Stopped, StateAction::UserStart) => Ok(State::Starting),
             (State::Stopped, StateAction::UserStop) => {
-                Err(eyre!("Cannot stop an instance that is already stopped"))
+                conflict("Cannot stop an instance that is already stopped")
+            }
+            (State::Error, StateAction::UserStart) => Ok(State::Starting),
+            (State::Error, StateAction::UserStop) => {
+                conflict("Cannot stop an instance that is not running")
             }
-            (State::Error, StateAction::UserStart) => todo!(),
-            (State::Error, StateAction::UserStop) => todo!(),
         }?;
         if let Some(on_transit) = on_transit {
             on_transit(state);
@@ -147,3 +200,32 @@ pub trait TServer {
     async fn send_command(&self, command: &str, caused_by: CausedBy) -> Result<(), Error>;
     async fn monitor(&self) -> MonitorReport;
 }
+
+#[test]
+fn test_monitor_report_material_change() {
+    let threshold = MonitorEventThreshold::default();
+    let before = MonitorReport {
+        memory_usage: Some(1000),
+        cpu_usage: Some(20.0),
+        start_time: Some(1),
+        ..Default::default()
+    };
+    let jitter = MonitorReport {
+        memory_usage: Some(1020),
+        cpu_usage: Some(22.5),
+        ..before.clone()
+    };
+    assert!(!jitter.is_material_change(&before, &threshold));
+    let cpu_spike = MonitorReport {
+        cpu_usage: Some(40.0),
+        ..before.clone()
+    };
+    assert!(cpu_spike.is_material_change(&before, &threshold));
+    let restarted = MonitorReport {
+        start_time: Some(2),
+        ..before.clone()
+    };
+    assert!(restarted.is_material_change(&before, &threshold));
+    let stopped = MonitorReport::default();
+    assert!(stopped.is_material_change(&before, &threshold));
+}
@@ -1,5 +1,18 @@
+use std::collections::BTreeSet;
 use std::fmt::Display;
+use std::path::Path;
 
+use color_eyre::eyre::{eyre, Context};
+
+use crate::auto_start::AutoStartOrder;
+use crate::backup_retention::BackupPolicy;
+use crate::console_filter::ConsoleFilters;
+use crate::error::{Error, ErrorKind};
+use crate::gateway::MaintenanceMode;
+use crate::log_cleanup::LogRetention;
+use crate::migration::dot_lodestone_config::{
+    migrate_dot_lodestone_config, CURRENT_CONFIG_VERSION,
+};
 use crate::migration::DotLodestoneConfigV043;
 use crate::traits::t_configurable::GameType;
 use crate::{
@@ -127,9 +140,26 @@ pub struct LodestoneMetadata {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
 #[ts(export)]
 pub struct DotLodestoneConfig {
+    /// Bumped whenever the shape changes, see `migration::dot_lodestone_config`
+    #[serde(default)]
+    config_version: u64,
     game_type: GameType,
     uuid: InstanceUuid,
     creation_time: i64,
+    #[serde(default)]
+    tags: BTreeSet<String>,
+    #[serde(default)]
+    auto_start_order: AutoStartOrder,
+    #[serde(default)]
+    maintenance: MaintenanceMode,
+    #[serde(default)]
+    log_retention: LogRetention,
+    #[serde(default)]
+    console_filters: ConsoleFilters,
+    #[serde(default)]
+    backup_policy: BackupPolicy,
+    #[serde(default)]
+    notes: String,
 }
 
 impl From<RestoreConfigV042> for DotLodestoneConfig {
@@ -142,9 +172,17 @@ impl From<RestoreConfigV042> for DotLodestoneConfig {
             _ => panic!("Unknown game type: {}", config.game_type),
         };
         Self {
+            config_version: CURRENT_CONFIG_VERSION,
             game_type,
             uuid: config.uuid,
             creation_time: config.creation_time,
+            tags: BTreeSet::new(),
+            auto_start_order: AutoStartOrder::default(),
+            maintenance: MaintenanceMode::default(),
+            log_retention: LogRetention::default(),
+            console_filters: ConsoleFilters::default(),
+            backup_policy: BackupPolicy::default(),
+            notes: String::new(),
         }
     }
 }
@@ -152,9 +190,17 @@ impl From<RestoreConfigV042> for DotLodestoneConfig {
 impl From<DotLodestoneConfigV043> for DotLodestoneConfig {
     fn from(config: DotLodestoneConfigV043) -> Self {
         Self {
+            config_version: CURRENT_CONFIG_VERSION,
             game_type: config.game_type,
             uuid: config.uuid,
             creation_time: config.creation_time,
+            tags: BTreeSet::new(),
+            auto_start_order: AutoStartOrder::default(),
+            maintenance: MaintenanceMode::default(),
+            log_retention: LogRetention::default(),
+            console_filters: ConsoleFilters::default(),
+            backup_policy: BackupPolicy::default(),
+            notes: String::new(),
         }
     }
 }
@@ -162,9 +208,17 @@ impl From<DotLodestoneConfigV043> for DotLodestoneConfig {
 impl DotLodestoneConfig {
     pub fn new(uuid: InstanceUuid, game_type: GameType) -> Self {
         Self {
+            config_version: CURRENT_CONFIG_VERSION,
             game_type,
             uuid,
             creation_time: chrono::Utc::now().timestamp(),
+            tags: BTreeSet::new(),
+            auto_start_order: AutoStartOrder::default(),
+            maintenance: MaintenanceMode::default(),
+            log_retention: LogRetention::default(),
+            console_filters: ConsoleFilters::default(),
+            backup_policy: BackupPolicy::default(),
+            notes: String::new(),
         }
     }
 
@@ -178,6 +232,168 @@ impl DotLodestoneConfig {
     pub fn game_type(&self) -> &GameType {
         &self.game_type
     }
+
+    pub fn tags(&self) -> &BTreeSet<String> {
+        &self.tags
+    }
+
+    pub fn set_tags(&mut self, tags: BTreeSet<String>) {
+        self.tags = tags;
+    }
+
+    pub fn auto_start_order(&self) -> &AutoStartOrder {
+        &self.auto_start_order
+    }
+
+    pub fn set_auto_start_order(&mut self, auto_start_order: AutoStartOrder) {
+        self.auto_start_order = auto_start_order;
+    }
+
+    pub fn maintenance(&self) -> &MaintenanceMode {
+        &self.maintenance
+    }
+
+    pub fn set_maintenance(&mut self, maintenance: MaintenanceMode) {
+        self.maintenance = maintenance;
+    }
+
+    pub fn log_retention(&self) -> &LogRetention {
+        &self.log_retention
+    }
+
+    pub fn set_log_retention(&mut self, log_retention: LogRetention) {
+        self.log_retention = log_retention;
+    }
+
+    pub fn backup_policy(&self) -> &BackupPolicy {
+        &self.backup_policy
+    }
+
+    pub fn set_backup_policy(&mut self, backup_policy: BackupPolicy) {
+        self.backup_policy = backup_policy;
+    }
+
+    pub fn console_filters(&self) -> &ConsoleFilters {
+        &self.console_filters
+    }
+
+    pub fn set_console_filters(&mut self, console_filters: ConsoleFilters) {
+        self.console_filters = console_filters;
+    }
+
+    pub fn notes(&self) -> &str {
+        &self.notes
+    }
+
+    pub fn set_notes(&mut self, notes: String) {
+        self.notes = notes;
+    }
+
+    /// Upgrades configs written by older versions, and writes the upgraded config back
+    pub async fn read_from(path_to_instance: &Path) -> Result<Self, Error> {
+        let path = path_to_instance.join(".lodestone_config");
+        let config: serde_json::Value =
+            serde_json::from_str(&crate::util::fs::read_to_string(&path).await?)
+                .context(format!("Failed to parse {}", path.display()))?;
+        let (config, migrated) = migrate_dot_lodestone_config(config).map_err(|e| Error {
+            kind: e.kind,
+            source: e
+                .source
+                .wrap_err(format!("Failed to migrate {}", path.display())),
+        })?;
+        let config: Self = serde_json::from_value(config)
+            .context(format!("Failed to parse {}", path.display()))?;
+        if migrated {
+            config.write_to(path_to_instance).await?;
+        }
+        Ok(config)
+    }
+
+    pub async fn write_to(&self, path_to_instance: &Path) -> Result<(), Error> {
+        crate::util::fs::write_all(
+            path_to_instance.join(".lodestone_config"),
+            serde_json::to_string_pretty(self).context("Failed to serialize .lodestone_config")?,
+        )
+        .await
+    }
+}
+
+/// Trims and lowercases a tag so that "Production " and "production" are the same tag
+pub fn normalize_tag(tag: &str) -> Result<String, Error> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Tag cannot be empty"),
+        });
+    }
+    if tag.len() > 32 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Tag cannot be longer than 32 characters"),
+        });
+    }
+    Ok(tag)
+}
+
+/// Trims an instance name and rejects names that are empty, too long or contain control characters
+pub fn validate_instance_name(name: &str) -> Result<String, Error> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Name cannot be empty"),
+        });
+    }
+    if name.chars().count() > 100 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Name cannot be longer than 100 characters"),
+        });
+    }
+    if name.chars().any(char::is_control) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Name cannot contain control characters"),
+        });
+    }
+    Ok(name.to_string())
+}
+
+/// Notes can be at most this many characters
+pub const MAX_INSTANCE_NOTES_LENGTH: usize = 4000;
+
+/// Trims trailing whitespace off instance notes and rejects notes that are too long
+pub fn validate_instance_notes(notes: &str) -> Result<String, Error> {
+    let notes = notes.trim_end();
+    if notes.chars().count() > MAX_INSTANCE_NOTES_LENGTH {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Notes cannot be longer than {} characters",
+                MAX_INSTANCE_NOTES_LENGTH
+            ),
+        });
+    }
+    Ok(notes.to_string())
+}
+
+#[test]
+fn test_validate_instance_name() {
+    assert_eq!(
+        validate_instance_name(" Survival (1.20) ").unwrap(),
+        "Survival (1.20)"
+    );
+    assert!(validate_instance_name("  ").is_err());
+    assert!(validate_instance_name("new\nline").is_err());
+    assert!(validate_instance_name(&"a".repeat(101)).is_err());
+}
+
+#[test]
+fn test_normalize_tag() {
+    assert_eq!(normalize_tag("  Production ").unwrap(), "production");
+    assert!(normalize_tag("   ").is_err());
+    assert!(normalize_tag(&"a".repeat(33)).is_err());
 }
 
 #[test]
@@ -190,3 +406,14 @@ fn test_instance_uuid() {
     let uuid2: InstanceUuid = serde_json::from_str(&uuid_str).unwrap();
     assert_eq!(uuid1, uuid2);
 }
+
+#[test]
+fn test_validate_instance_notes() {
+    assert_eq!(
+        validate_instance_notes("prod server, do not wipe\n\n").unwrap(),
+        "prod server, do not wipe"
+    );
+    assert_eq!(validate_instance_notes("").unwrap(), "");
+    assert!(validate_instance_notes(&"a".repeat(MAX_INSTANCE_NOTES_LENGTH)).is_ok());
+    assert!(validate_instance_notes(&"a".repeat(MAX_INSTANCE_NOTES_LENGTH + 1)).is_err());
+}
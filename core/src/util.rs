@@ -11,6 +11,7 @@ use tokio::io::AsyncWriteExt;
 use futures_util::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
 use ts_rs::TS;
 
 use flate2::read::GzDecoder;
@@ -38,12 +39,14 @@ pub struct DownloadProgress {
     pub step: u64,
     pub download_name: String,
 }
+/// Stops with `tasks::cancelled_error` and removes the partial download if `cancel` is cancelled
 pub async fn download_file(
     url: &str,
     path: &Path,
     name_override: Option<&str>,
     on_download: &(dyn Fn(DownloadProgress) + Send + Sync),
     overwrite_old: bool,
+    cancel: Option<&CancellationToken>,
 ) -> Result<PathBuf, Error> {
     let lodestone_tmp = path_to_tmp().clone();
     tokio::fs::create_dir_all(&lodestone_tmp)
@@ -103,8 +106,26 @@ pub async fn download_file(
     let mut new_downloaded: u64 = 0;
     let threshold = total_size.unwrap_or(500000) / 100;
     let mut stream = response.bytes_stream();
-    while let Some(item) = stream.next().await {
-        let chunk = item.context("Failed to read response")?;
+    let cancelled = async {
+        match cancel {
+            Some(cancel) => cancel.cancelled().await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(cancelled);
+    loop {
+        let item = tokio::select! {
+            item = stream.next() => item,
+            _ = &mut cancelled => {
+                drop(temp_file);
+                let _ = tokio::fs::remove_file(&temp_file_path).await;
+                return Err(crate::tasks::cancelled_error());
+            }
+        };
+        let chunk = match item {
+            Some(item) => item.context("Failed to read response")?,
+            None => break,
+        };
         temp_file
             .write_all(&chunk)
             .await
@@ -127,6 +148,49 @@ pub async fn download_file(
     Ok(path.join(&file_name))
 }
 
+/// Size of the file at `url` according to a HEAD request, without downloading it
+pub async fn content_length(url: &str) -> Option<u64> {
+    let response = Client::new().head(url).send().await.ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    // `Response::content_length` is the size of the body, which a HEAD response doesn't have
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|len| *len > 0)
+}
+
+/// Hex encoded SHA-256 digest of a file
+pub async fn sha256_file(path: &Path) -> Result<String, Error> {
+    digest_file::<sha2::Sha256>(path).await
+}
+
+/// Hex encoded SHA-512 digest of a file
+pub async fn sha512_file(path: &Path) -> Result<String, Error> {
+    digest_file::<sha2::Sha512>(path).await
+}
+
+async fn digest_file<D: sha2::Digest + std::io::Write + Send + 'static>(
+    path: &Path,
+) -> Result<String, Error> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)
+            .context(format!("Failed to open {} for hashing", path.display()))?;
+        let mut hasher = D::new();
+        std::io::copy(&mut file, &mut hasher)
+            .context(format!("Failed to hash {}", path.display()))?;
+        Ok(hex::encode(hasher.finalize()))
+    })
+    .await
+    .context("Hashing task panicked")?
+}
+
 /// List all files in a directory
 /// files_or_dir = 0 -> files, 1 -> directories
 pub async fn list_dir(
@@ -312,11 +376,18 @@ pub async fn unzip_file_async(
         ))?
 }
 
+/// Stops with `tasks::cancelled_error` between files if `cancel` is cancelled, the partial
+/// archive is a temporary file that is removed when it stops
 pub fn zip_files(
     files: &[impl AsRef<Path>],
     dest: impl AsRef<Path>,
     overwrite_dest: bool,
+    cancel: Option<&CancellationToken>,
 ) -> Result<PathBuf, Error> {
+    let check_cancelled = || match cancel {
+        Some(cancel) if cancel.is_cancelled() => Err(crate::tasks::cancelled_error()),
+        _ => Ok(()),
+    };
     let dest = dest.as_ref();
     std::fs::create_dir_all(dest.parent().context("Failed to get destination parent")?)
         .context(format!("Failed to create directory {}", dest.display()))?;
@@ -332,6 +403,7 @@ pub fn zip_files(
     let mut writer = zip::ZipWriter::new(&tmp_archive);
     let options = zip::write::FileOptions::default().unix_permissions(0o775);
     for entry_path in files.iter().map(|f| f.as_ref()) {
+        check_cancelled()?;
         if entry_path.is_dir() {
             writer
                 .add_directory(
@@ -351,6 +423,7 @@ pub fn zip_files(
                 .into_iter()
                 .filter_map(|e| e.ok())
             {
+                check_cancelled()?;
                 let child_entry_path = child_entry.path();
                 let child_entry_dest =
                     child_entry_path
@@ -440,13 +513,14 @@ pub async fn zip_files_async(
     files: &[impl AsRef<Path>],
     dest: impl AsRef<Path>,
     overwrite_dest: bool,
+    cancel: Option<CancellationToken>,
 ) -> Result<PathBuf, Error> {
     let _files = files
         .iter()
         .map(|f| f.as_ref().to_owned())
         .collect::<Vec<_>>();
     let _dest = dest.as_ref().to_owned();
-    tokio::task::spawn_blocking(move || zip_files(&_files, &_dest, overwrite_dest))
+    tokio::task::spawn_blocking(move || zip_files(&_files, &_dest, overwrite_dest, cancel.as_ref()))
         .await
         .context("Failed to spawn blocking task")?
 }
@@ -645,6 +719,7 @@ mod tests {
     use std::io::Read;
     use std::path::PathBuf;
     use tokio;
+    use tokio_util::sync::CancellationToken;
 
     #[tokio::test]
     async fn test_unzip_file() {
@@ -750,6 +825,7 @@ mod tests {
                 &["testdata/zip_test/test1.txt", "testdata/zip_test/test2"],
                 dest_path.join("test_dest.zip"),
                 false,
+                None,
             )
             .unwrap(),
             dest_path.join("test_dest.zip")
@@ -759,6 +835,7 @@ mod tests {
                 &["testdata/zip_test/test1.txt", "testdata/zip_test/test2"],
                 dest_path.join("test_dest.zip"),
                 false,
+                None,
             )
             .unwrap(),
             dest_path.join("test_dest_1.zip")
@@ -768,6 +845,7 @@ mod tests {
                 &["testdata/zip_test/test1.txt", "testdata/zip_test/test2"],
                 dest_path.join("test_dest.zip"),
                 false,
+                None,
             )
             .unwrap(),
             dest_path.join("test_dest_2.zip")
@@ -830,5 +908,16 @@ mod tests {
         let mut contents = String::new();
         buf_reader.read_to_string(&mut contents).unwrap();
         assert_eq!(contents.trim(), "test2_test2_test1");
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(zip_files(
+            &["testdata/zip_test/test1.txt", "testdata/zip_test/test2"],
+            dest_path.join("cancelled.zip"),
+            false,
+            Some(&cancel),
+        )
+        .is_err());
+        assert!(!dest_path.join("cancelled.zip").exists());
     }
 }
@@ -0,0 +1,151 @@
+//! `${NAME}` substitution from the variables defined in the global settings, and
+//! `${secret.NAME}` from the secret store
+//!
+//! Only those are substituted, never the environment of the core, so a
+//! macro or launch argument can't read anything it wasn't explicitly given.
+//! Secrets are only resolved in settings only the owner can change, anywhere
+//! else `${secret.NAME}` is kept as written
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use color_eyre::eyre::eyre;
+
+use crate::error::{Error, ErrorKind};
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+const SECRET_PREFIX: &str = "secret.";
+
+/// Replaces every `${NAME}` in `input`, and `${secret.NAME}` if `secrets` are given. `$${`
+/// is kept as a literal `${`
+///
+/// A reference to a variable that isn't defined, or a `${` that is never closed, is kept as
+/// written, so arguments that already contained `${` from before variables existed still work.
+/// Referencing a secret that doesn't exist is an error
+pub fn substitute(
+    input: &str,
+    variables: &BTreeMap<String, String>,
+    secrets: Option<&BTreeMap<String, String>>,
+) -> Result<String, Error> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with("$${") {
+            output.push_str("${");
+            rest = &rest[3..];
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = match after.find('}') {
+                Some(end) => end,
+                None => break,
+            };
+            let name = &after[..end];
+            match (name.strip_prefix(SECRET_PREFIX), secrets) {
+                (Some(secret), Some(secrets)) => {
+                    let value = secrets.get(secret).ok_or_else(|| Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Secret {} is not defined", secret),
+                    })?;
+                    output.push_str(value);
+                }
+                (Some(_), None) => output.push_str(&rest[..end + 3]),
+                (None, _) => match variables.get(name) {
+                    Some(value) => output.push_str(value),
+                    None => output.push_str(&rest[..end + 3]),
+                },
+            }
+            rest = &after[end + 1..];
+        } else {
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// The variables and secrets in effect, kept in sync with the global settings and secret store
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    variables: Arc<RwLock<BTreeMap<String, String>>>,
+    secrets: Arc<RwLock<BTreeMap<String, String>>>,
+}
+
+impl Variables {
+    pub fn set(&self, variables: BTreeMap<String, String>) {
+        *self.variables.write().unwrap() = variables;
+    }
+
+    pub fn set_secrets(&self, secrets: BTreeMap<String, String>) {
+        *self.secrets.write().unwrap() = secrets;
+    }
+
+    /// Substitutes variables, for values anyone who can edit an instance or macro can set
+    pub fn substitute(&self, input: &str) -> Result<String, Error> {
+        substitute(input, &self.variables.read().unwrap(), None)
+    }
+
+    /// Substitutes variables and secrets, only for settings the owner alone can change
+    pub fn substitute_with_secrets(&self, input: &str) -> Result<String, Error> {
+        substitute(
+            input,
+            &self.variables.read().unwrap(),
+            Some(&self.secrets.read().unwrap()),
+        )
+    }
+}
+
+#[test]
+fn test_substitute() {
+    let variables = BTreeMap::from([
+        (
+            "WEBHOOK".to_string(),
+            "https://example.com/hook".to_string(),
+        ),
+        ("MAX_RAM".to_string(), "4096".to_string()),
+    ]);
+    let secrets = BTreeMap::from([("API_KEY".to_string(), "hunter2".to_string())]);
+    assert_eq!(
+        substitute(
+            "curl ${WEBHOOK} -Xmx${MAX_RAM}M",
+            &variables,
+            Some(&secrets)
+        )
+        .unwrap(),
+        "curl https://example.com/hook -Xmx4096M"
+    );
+    assert_eq!(
+        substitute("cost: $5, $${WEBHOOK}", &variables, Some(&secrets)).unwrap(),
+        "cost: $5, ${WEBHOOK}"
+    );
+    // left as written, like arguments from before variables existed
+    assert_eq!(
+        substitute("-Dpath=${user.home}/mods", &variables, Some(&secrets)).unwrap(),
+        "-Dpath=${user.home}/mods"
+    );
+    assert_eq!(
+        substitute("${WEBHOOK", &variables, Some(&secrets)).unwrap(),
+        "${WEBHOOK"
+    );
+    assert_eq!(
+        substitute("key=${secret.API_KEY}", &variables, Some(&secrets)).unwrap(),
+        "key=hunter2"
+    );
+    assert!(substitute("${secret.WEBHOOK}", &variables, Some(&secrets)).is_err());
+    // secrets are only resolved where asked for
+    assert_eq!(
+        substitute("key=${secret.API_KEY}", &variables, None).unwrap(),
+        "key=${secret.API_KEY}"
+    );
+    assert!(is_valid_name("DISCORD_WEBHOOK_2"));
+    assert!(!is_valid_name("2FAST"));
+    assert!(!is_valid_name("with-dash"));
+}
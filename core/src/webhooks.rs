@@ -0,0 +1,147 @@
+//! Webhook endpoints configured in the global settings, e.g. a Discord channel webhook
+//!
+//! The URL may reference `${VAR}` and `${secret.NAME}`, so the token part of a Discord
+//! webhook can be kept in the secret store
+
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest response body returned from a test delivery
+const MAX_RESPONSE_BODY_BYTES: usize = 4096;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct Webhook {
+    pub id: String,
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct WebhookTestResult {
+    pub status: u16,
+    /// The response body, cut at a few kilobytes
+    pub body: String,
+    pub body_truncated: bool,
+}
+
+/// Cuts `body` to at most `max` bytes on a character boundary
+fn truncate_body(mut body: Vec<u8>, max: usize) -> (String, bool) {
+    let truncated = body.len() > max;
+    body.truncate(max);
+    let body = match String::from_utf8(body) {
+        Ok(body) => body,
+        Err(e) => {
+            let valid_up_to = e.utf8_error().valid_up_to();
+            let mut bytes = e.into_bytes();
+            bytes.truncate(valid_up_to);
+            String::from_utf8(bytes).unwrap_or_default()
+        }
+    };
+    (body, truncated)
+}
+
+/// Turns a failed request into a message that says what went wrong
+fn describe_request_error(e: &reqwest::Error) -> String {
+    let mut causes = Vec::new();
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+    let detail = causes.join(": ");
+    let lowercase = detail.to_lowercase();
+    if e.is_timeout() {
+        format!(
+            "The webhook did not respond within {} seconds",
+            WEBHOOK_TIMEOUT.as_secs()
+        )
+    } else if lowercase.contains("certificate")
+        || lowercase.contains("tls")
+        || lowercase.contains("ssl")
+    {
+        format!("TLS handshake with the webhook failed: {detail}")
+    } else if e.is_connect() {
+        format!("Could not connect to the webhook: {detail}")
+    } else if detail.is_empty() {
+        format!("Failed to send the webhook: {e}")
+    } else {
+        format!("Failed to send the webhook: {detail}")
+    }
+}
+
+/// Posts a sample notification to `url` and reports how the endpoint answered
+///
+/// The payload has a `content` field so Discord webhooks display it as a message
+pub async fn send_test(url: &str, core_name: &str) -> Result<WebhookTestResult, Error> {
+    let url = reqwest::Url::parse(url).map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid webhook URL: {e}"),
+    })?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Webhook URL must be an http or https URL"),
+        });
+    }
+    let payload = json!({
+        "content": format!("Test notification from Lodestone core {core_name}"),
+        "event": "test",
+        "core_name": core_name,
+        "timestamp": chrono::Utc::now().timestamp(),
+    });
+    let mut response = reqwest::Client::new()
+        .post(url)
+        .timeout(WEBHOOK_TIMEOUT)
+        .json(&payload)
+        .send()
+        .await
+        .map_err(|e| Error {
+            kind: ErrorKind::External,
+            source: eyre!(describe_request_error(&e)),
+        })?;
+    let status = response.status().as_u16();
+    let mut body = Vec::new();
+    // stop reading once past the limit, the rest would be thrown away anyway
+    while body.len() <= MAX_RESPONSE_BODY_BYTES {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) => break,
+            Err(e) => {
+                return Err(Error {
+                    kind: ErrorKind::External,
+                    source: eyre!(describe_request_error(&e)),
+                })
+            }
+        }
+    }
+    let (body, body_truncated) = truncate_body(body, MAX_RESPONSE_BODY_BYTES);
+    Ok(WebhookTestResult {
+        status,
+        body,
+        body_truncated,
+    })
+}
+
+#[test]
+fn test_truncate_body() {
+    assert_eq!(truncate_body(b"ok".to_vec(), 10), ("ok".to_string(), false));
+    assert_eq!(
+        truncate_body(b"0123456789ab".to_vec(), 10),
+        ("0123456789".to_string(), true)
+    );
+    // never splits a character in half
+    assert_eq!(
+        truncate_body("aé".as_bytes().to_vec(), 2),
+        ("a".to_string(), true)
+    );
+}
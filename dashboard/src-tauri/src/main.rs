@@ -5,7 +5,7 @@
 
 use lodestone_core::AppState;
 
-use lodestone_core::error::Error;
+use lodestone_core::Error;
 
 use lodestone_core::auth::jwt_token::JwtToken;
 use lodestone_core::tauri_export::is_owner_account_present;
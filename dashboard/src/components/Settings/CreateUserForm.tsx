@@ -48,13 +48,13 @@ export const CreateUserForm = ({
       username: values.username,
       password: values.password,
     })
-      .then((loginReply) => {
+      .then((user) => {
         queryClient.setQueryData(
           ['user', 'list'],
           (oldData: { [uid: string]: PublicUser } | undefined) => {
             return {
               ...oldData,
-              [loginReply.user.uid]: loginReply.user,
+              [user.uid]: user,
             };
           }
         );
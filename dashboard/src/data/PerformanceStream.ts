@@ -26,7 +26,7 @@ export const usePerformanceStream = (uuid: string) => {
   const [lastPing, setLastPing] = useState(Date.now());
   const [latency_s, setLatency_s] = useState(0);
   const [counter, setCounter] = useState(-1);
-  const { core } = useContext(LodestoneContext);
+  const { core, token } = useContext(LodestoneContext);
   const { address, port, apiVersion, protocol } = core;
 
   useInterval(() => {
@@ -38,7 +38,7 @@ export const usePerformanceStream = (uuid: string) => {
       const websocket = new WebSocket(
         `${protocol === 'https' ? 'wss' : 'ws'}://${address}:${
           port ?? LODESTONE_PORT
-        }/api/${apiVersion}/monitor/${uuid}`
+        }/api/${apiVersion}/monitor/${uuid}?token=Bearer ${token}`
       );
 
       websocket.onmessage = (messageEvent) => {
@@ -61,7 +61,7 @@ export const usePerformanceStream = (uuid: string) => {
       console.error(e);
     }
     // eslint-disable-next-line react-hooks/exhaustive-deps
-  }, [address, port, apiVersion, uuid]);
+  }, [address, port, apiVersion, uuid, token]);
 
   return {
     buffer,
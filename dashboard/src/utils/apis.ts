@@ -4,6 +4,7 @@ import { ClientError } from 'bindings/ClientError';
 import { ClientFile } from 'bindings/ClientFile';
 import { MacroEntry } from 'bindings/MacroEntry';
 import { LoginReply } from 'bindings/LoginReply';
+import { PublicUser } from 'bindings/PublicUser';
 import { UserPermission } from 'bindings/UserPermission';
 import { Base64 } from 'js-base64';
 import { LoginValues } from 'pages/login/UserLogin';
@@ -361,13 +362,13 @@ export async function loginToCore(
 
 /**
  * @throws string if error
- * @returns LoginReply if success
+ * @returns PublicUser if success
  */
 export const createNewUser = async (values: {
   username: string;
   password: string;
 }) => {
-  return await axiosWrapper<LoginReply>({
+  return await axiosWrapper<PublicUser>({
     method: 'post',
     url: '/user',
     data: values,